@@ -0,0 +1,61 @@
+/*
+ * Track bank select control changes to know the bank of subsequent program changes
+ */
+
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+/// Highest bank number, which combines the 7-bit MSB and LSB
+pub const MAX_BANK: u16 = 16383;
+
+pub struct BankTracker {
+    /// Last bank select MSB and LSB by channel
+    bank_selects: [(Option<u8>, Option<u8>); CHANNEL_COUNT],
+}
+
+impl BankTracker {
+    pub fn new() -> Self {
+        BankTracker {
+            bank_selects: [(None, None); CHANNEL_COUNT],
+        }
+    }
+
+    /// Records bank select control changes and returns the bank of the event's channel, which is
+    /// `MSB * 128 + LSB`. An omitted MSB or LSB counts as 0, and the bank is unknown until a bank
+    /// select was received on the channel.
+    pub fn track(&mut self, midi_event: &MidiEvent) -> Option<u16> {
+        let channel = midi_event.channel()?;
+        let (msb, lsb) = self.bank_selects.get_mut((channel as usize).checked_sub(1)?)?;
+        match *midi_event {
+            MidiEvent::ControlChange { control_no: BANK_SELECT_MSB, value, .. } => *msb = Some(value),
+            MidiEvent::ControlChange { control_no: BANK_SELECT_LSB, value, .. } => *lsb = Some(value),
+            _ => {},
+        }
+        if msb.is_none() && lsb.is_none() {
+            return None;
+        }
+        Some(msb.unwrap_or(0) as u16 * 128 + lsb.unwrap_or(0) as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bank_tracker() {
+        let mut bank_tracker = BankTracker::new();
+        let program_change = |channel| MidiEvent::ProgramChange { channel, program: 5 };
+        let bank_select = |control_no, value| MidiEvent::ControlChange { channel: 1, control_no, value };
+
+        assert_eq!(bank_tracker.track(&program_change(1)), None);
+        bank_tracker.track(&bank_select(BANK_SELECT_MSB, 2));
+        assert_eq!(bank_tracker.track(&program_change(1)), Some(256));
+        bank_tracker.track(&bank_select(BANK_SELECT_LSB, 3));
+        assert_eq!(bank_tracker.track(&program_change(1)), Some(259));
+        assert_eq!(bank_tracker.track(&program_change(2)), None);
+        assert_eq!(bank_tracker.track(&MidiEvent::TimingClock {}), None);
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * Conversion between JACK frames and milliseconds
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const MS_PER_SECOND: u64 = 1000;
+
+/// Sample rate of the JACK server, which the notification handler changes while the clock of the
+/// process handler uses it
+#[derive(Clone, Debug)]
+pub struct SampleRate(Arc<AtomicU64>);
+
+impl SampleRate {
+    /// Changes the sample rate, e.g. when the JACK server was restarted with another one
+    pub fn set(&self, sample_rate: usize) {
+        self.0.store(valid_sample_rate(sample_rate), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Converts times given in frames, i.e. samples, to milliseconds at the sample rate
+/// of the JACK server. All time-based features use it to get consistent timestamps.
+#[derive(Debug)]
+pub struct Clock {
+    shared_sample_rate: SampleRate,
+    /// Sample rate which applies to the frames from `base_frames` on
+    sample_rate: u64,
+    /// Frame count at the last change of the sample rate
+    base_frames: u64,
+    /// Time in milliseconds at the last change of the sample rate
+    base_ms: u64,
+}
+
+impl Clock {
+    pub fn new(sample_rate: usize) -> Self {
+        let sample_rate = valid_sample_rate(sample_rate);
+        Clock {
+            shared_sample_rate: SampleRate(Arc::new(AtomicU64::new(sample_rate))),
+            sample_rate,
+            base_frames: 0,
+            base_ms: 0,
+        }
+    }
+
+    /// Handle to change the sample rate of this clock from another thread
+    pub fn shared_sample_rate(&self) -> SampleRate {
+        self.shared_sample_rate.clone()
+    }
+
+    /// Applies a changed sample rate to the frames from `frames` on only, so that the time does not
+    /// jump when the sample rate changes. Called at the start of every process cycle.
+    pub fn update_sample_rate(&mut self, frames: u64) {
+        let sample_rate = self.shared_sample_rate.get();
+        if sample_rate != self.sample_rate {
+            self.base_ms = self.frames_to_ms(frames);
+            self.base_frames = frames;
+            self.sample_rate = sample_rate;
+        }
+    }
+
+    pub fn frames_to_ms(&self, frames: u64) -> u64 {
+        self.base_ms + frames.saturating_sub(self.base_frames) * MS_PER_SECOND / self.sample_rate
+    }
+}
+
+fn valid_sample_rate(sample_rate: usize) -> u64 {
+    sample_rate.max(1) as u64
+}
+
+/// Counts the frames since the first process cycle in 64 bits, since the 32-bit frame time of
+/// JACK wraps around after about a day, e.g. after 24.8 hours at 48 kHz
+#[derive(Debug, Default)]
+pub struct FrameCounter {
+    last_frame_time: Option<u32>,
+    frames: u64,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the frame time at the start of the current cycle and returns it as a frame count
+    /// which never decreases
+    pub fn advance(&mut self, frame_time: u32) -> u64 {
+        self.frames += match self.last_frame_time.replace(frame_time) {
+            Some(last_frame_time) => frame_time.wrapping_sub(last_frame_time) as u64,
+            None => frame_time as u64,
+        };
+        self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_44100() {
+        let clock = Clock::new(44100);
+
+        assert_eq!(clock.frames_to_ms(44100), 1000);
+        assert_eq!(clock.frames_to_ms(22050), 500);
+        assert_eq!(clock.frames_to_ms(440), 9);
+    }
+
+    #[test]
+    fn test_clock_48000() {
+        let clock = Clock::new(48000);
+
+        assert_eq!(clock.frames_to_ms(48000), 1000);
+        assert_eq!(clock.frames_to_ms(1024), 21);
+        assert_eq!(clock.frames_to_ms(5_925_888_000), 123_456_000);
+    }
+
+    #[test]
+    fn test_clock_invalid_sample_rate() {
+        assert_eq!(Clock::new(0).frames_to_ms(5), 5000);
+
+        let mut clock = Clock::new(44100);
+        clock.shared_sample_rate().set(0);
+        clock.update_sample_rate(0);
+        assert_eq!(clock.frames_to_ms(5), 5000);
+    }
+
+    #[test]
+    fn test_clock_sample_rate_change_continues_time() {
+        let mut clock = Clock::new(44100);
+        assert_eq!(clock.frames_to_ms(441_000), 10_000);
+
+        clock.shared_sample_rate().set(96000);
+        clock.update_sample_rate(441_000);
+        assert_eq!(clock.frames_to_ms(441_000), 10_000);
+        assert_eq!(clock.frames_to_ms(441_000 + 96000), 11_000);
+
+        clock.shared_sample_rate().set(22050);
+        clock.update_sample_rate(441_000 + 96000);
+        assert_eq!(clock.frames_to_ms(441_000 + 96000 + 22050), 12_000);
+    }
+
+    #[test]
+    fn test_frame_counter_wraps_around() {
+        let mut frame_counter = FrameCounter::new();
+
+        assert_eq!(frame_counter.advance(u32::MAX - 1023), u32::MAX as u64 - 1023);
+        assert_eq!(frame_counter.advance(u32::MAX - 511), u32::MAX as u64 - 511);
+        assert_eq!(frame_counter.advance(512), u32::MAX as u64 + 513);
+        assert_eq!(frame_counter.advance(1536), u32::MAX as u64 + 1537);
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * Generate timing clock, start, and stop events, so that the router can originate the transport
+ * of connected devices
+ */
+
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use log::{debug, info};
+use crate::midi::MidiEvent;
+
+/// Timing clock events are sent 24 times per quarter note
+const CLOCKS_PER_BEAT: u32 = 24;
+const SECONDS_PER_MINUTE: f64 = 60.0;
+
+/// Times of the timing clocks at a fixed tempo relative to the start of the clock. They are
+/// computed from the number of the tick, so rounding errors do not add up over time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TickSchedule {
+    bpm: f64,
+}
+
+impl TickSchedule {
+    pub fn new(bpm: f64) -> Self {
+        TickSchedule { bpm }
+    }
+
+    /// Time between two timing clocks
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(SECONDS_PER_MINUTE / (self.bpm * CLOCKS_PER_BEAT as f64))
+    }
+
+    /// Time of the given tick after the start of the clock, starting at 0 for the first tick
+    pub fn tick_offset(&self, tick_no: u64) -> Duration {
+        Duration::from_secs_f64(tick_no as f64 * SECONDS_PER_MINUTE / (self.bpm * CLOCKS_PER_BEAT as f64))
+    }
+}
+
+/// Sends timing clocks at a fixed tempo to the process handler, which writes them to the clock
+/// port in its next cycle
+pub struct ClockGenerator {
+    schedule: TickSchedule,
+    sender: Sender<MidiEvent>,
+}
+
+impl ClockGenerator {
+    pub fn new(bpm: f64, sender: Sender<MidiEvent>) -> Self {
+        ClockGenerator {
+            schedule: TickSchedule::new(bpm),
+            sender,
+        }
+    }
+
+    /// Sends a start event, which makes connected devices play from the beginning
+    pub fn start(&self) {
+        info!("Starting the MIDI clock");
+        self.sender.send(MidiEvent::Start {}).unwrap_or_default();
+    }
+
+    /// Sends a stop event, while timing clocks are still sent to keep devices at the tempo
+    pub fn stop(&self) {
+        info!("Stopping the MIDI clock");
+        self.sender.send(MidiEvent::Stop {}).unwrap_or_default();
+    }
+
+    /// Starts a thread which sends timing clocks until the receiver is dropped, e.g. when the
+    /// router is stopped
+    pub fn spawn_ticker(&self) -> JoinHandle<()> {
+        let schedule = self.schedule;
+        let sender = self.sender.clone();
+        debug!("Sending timing clocks every {:?}", schedule.interval());
+        thread::spawn(move || {
+            let clock_start = Instant::now();
+            for tick_no in 0.. {
+                let tick_time = clock_start + schedule.tick_offset(tick_no);
+                thread::sleep(tick_time.saturating_duration_since(Instant::now()));
+                if sender.send(MidiEvent::TimingClock {}).is_err() {
+                    return;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use super::*;
+
+    #[test]
+    fn test_tick_schedule_interval() {
+        assert_eq!(TickSchedule::new(120.0).interval().as_micros(), 20_833);
+        assert_eq!(TickSchedule::new(60.0).interval().as_micros(), 41_666);
+        assert_eq!(TickSchedule::new(125.0).interval(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_tick_schedule_tick_offset() {
+        let schedule = TickSchedule::new(120.0);
+
+        assert_eq!(schedule.tick_offset(0), Duration::ZERO);
+        assert_eq!(schedule.tick_offset(24), Duration::from_millis(500));
+        assert_eq!(schedule.tick_offset(24 * 120), Duration::from_secs(60));
+        for tick_no in 1..100 {
+            let interval = schedule.tick_offset(tick_no) - schedule.tick_offset(tick_no - 1);
+            assert!(interval.abs_diff(schedule.interval()) <= Duration::from_micros(1), "tick {}", tick_no);
+        }
+    }
+
+    #[test]
+    fn test_clock_generator() {
+        let (sender, receiver) = mpsc::channel();
+        let clock_generator = ClockGenerator::new(300.0, sender);
+
+        clock_generator.start();
+        let ticks_start = Instant::now();
+        let ticker = clock_generator.spawn_ticker();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(MidiEvent::Start {}));
+        for _ in 0..5 {
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(MidiEvent::TimingClock {}));
+        }
+        assert!(ticks_start.elapsed() >= TickSchedule::new(300.0).tick_offset(4));
+        clock_generator.stop();
+        let events: Vec<MidiEvent> = receiver.try_iter().collect();
+        assert!(events.contains(&MidiEvent::Stop {}));
+        drop(receiver);
+        ticker.join().unwrap();
+    }
+}
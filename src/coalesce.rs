@@ -0,0 +1,66 @@
+/*
+ * Drop control changes which would not change the value of a controller
+ */
+
+use std::collections::HashMap;
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const CONTROLLER_COUNT: usize = 128;
+
+type ControllerValues = [[Option<u8>; CONTROLLER_COUNT]; CHANNEL_COUNT];
+
+pub struct CcCoalescer {
+    /// Value of the last control change sent by output port, channel and controller
+    last_values: HashMap<String, Box<ControllerValues>>,
+}
+
+impl CcCoalescer {
+    /// Creates the state of all output ports in advance to avoid allocations while routing
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(port_names: I) -> Self {
+        CcCoalescer {
+            last_values: port_names.into_iter()
+                .map(|port_name| (port_name.to_string(), Box::new([[None; CONTROLLER_COUNT]; CHANNEL_COUNT])))
+                .collect(),
+        }
+    }
+
+    /// Checks whether the event may be sent to the port, which is not the case for control changes
+    /// repeating the last value sent for the controller. Other events always pass.
+    pub fn should_send(&mut self, port_name: &str, midi_event: &MidiEvent) -> bool {
+        let MidiEvent::ControlChange { channel, control_no, value } = *midi_event else {
+            return true;
+        };
+        let last_value = self.last_values.get_mut(port_name)
+            .and_then(|last_values| last_values.get_mut((channel as usize).checked_sub(1)?))
+            .and_then(|last_values| last_values.get_mut(control_no as usize));
+        match last_value {
+            Some(last_value) => last_value.replace(value) != Some(value),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cc_coalescer() {
+        let mut coalescer = CcCoalescer::new(["out1", "out2"]);
+        let control_change = |control_no, value| MidiEvent::ControlChange { channel: 1, control_no, value };
+
+        assert!(coalescer.should_send("out1", &control_change(7, 100)));
+        assert!(!coalescer.should_send("out1", &control_change(7, 100)));
+        assert!(!coalescer.should_send("out1", &control_change(7, 100)));
+        assert!(coalescer.should_send("out1", &control_change(7, 101)));
+        assert!(coalescer.should_send("out1", &control_change(7, 100)));
+        assert!(coalescer.should_send("out1", &control_change(8, 100)));
+        assert!(coalescer.should_send("out2", &control_change(7, 100)));
+        assert!(coalescer.should_send("unknown", &control_change(7, 100)));
+        assert!(coalescer.should_send("unknown", &control_change(7, 100)));
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert!(coalescer.should_send("out1", &note_on));
+        assert!(coalescer.should_send("out1", &note_on));
+    }
+}
@@ -0,0 +1,105 @@
+/*
+ * Export the routing table as Graphviz diagram
+ */
+
+use std::fmt::Write;
+use crate::routing::RoutingTable;
+
+const INPUT_NODE: &str = "input";
+
+/// Creates a DOT graph with edges from the input via each rule to its output ports
+pub fn export_dot(routing_table: &RoutingTable) -> String {
+    let mut dot = String::from("digraph midi_router {\n    rankdir=LR;\n");
+    writeln!(dot, "    \"{}\" [shape=box];", INPUT_NODE).unwrap();
+
+    let mut output_ports: Vec<&str> = routing_table.get_all_output_ports().into_iter().collect();
+    output_ports.sort();
+    for output_port in output_ports {
+        writeln!(dot, "    \"port:{}\" [shape=box, label=\"{}\"];", escape(output_port), escape(output_port)).unwrap();
+    }
+
+    for (rule_no, rule) in routing_table.rules.iter().enumerate() {
+        let rule_node = format!("rule{}", rule_no + 1);
+        writeln!(dot, "    \"{}\" [label=\"{}\"];", rule_node, escape(&rule.label(rule_no + 1))).unwrap();
+        writeln!(
+            dot,
+            "    \"{}\" -> \"{}\" [label=\"{}\"];",
+            INPUT_NODE,
+            rule_node,
+            escape(&rule.describe_condition()),
+        ).unwrap();
+        for output_port in rule.actions.iter().filter_map(|action| action.output_port()) {
+            writeln!(dot, "    \"{}\" -> \"port:{}\";", rule_node, escape(output_port)).unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use crate::routing::{Action, Condition, NumericRange, Rule};
+    use crate::transform::RemapController;
+    use super::*;
+
+    #[test]
+    fn test_export_dot() {
+        let routing_table = RoutingTable {
+            rules: vec![
+                Rule {
+                    condition: Condition {
+                        event_pattern: Some(Regex::new("note-.*").unwrap()),
+                        value_pattern: Some(NumericRange { start: i16::MIN, end: 59 }),
+                        ..Default::default()
+                    },
+                    actions: vec![Action::ForwardTo { output_port: "pads".into() }],
+                    description: Some("Low notes".into()),
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
+                },
+                Rule {
+                    condition: Condition {
+                        event_pattern: Some(Regex::new("control-change").unwrap()),
+                        channel_pattern: Some(NumericRange { start: 1, end: 1 }),
+                        ..Default::default()
+                    },
+                    actions: vec![
+                        Action::ForwardTo { output_port: "pads".into() },
+                        Action::Transform {
+                            transforms: vec![Box::new(RemapController { from: 1, to: 74 })],
+                            output_port: "lead \"A\"".into(),
+                        },
+                    ],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
+                },
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(export_dot(&routing_table), r#"digraph midi_router {
+    rankdir=LR;
+    "input" [shape=box];
+    "port:lead \"A\"" [shape=box, label="lead \"A\""];
+    "port:pads" [shape=box, label="pads"];
+    "rule1" [label="Rule 1: Low notes"];
+    "input" -> "rule1" [label="note-.* <60"];
+    "rule1" -> "port:pads";
+    "rule2" [label="Rule 2"];
+    "input" -> "rule2" [label="control-change ch1"];
+    "rule2" -> "port:pads";
+    "rule2" -> "port:lead \"A\"";
+}
+"#);
+    }
+}
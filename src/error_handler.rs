@@ -1,32 +1,199 @@
-/* 
+/*
  * Top-level error handling methods
  */
 use std::io;
+use std::io::IsTerminal;
 use std::path::Path;
 use crate::jack_router::JackRouterError;
-use crate::parser::RuleConfigError;
+use crate::parser::{describe_position, InvalidUtf8Error, IsDirectoryError, RuleConfigError, RuleParseError};
+use crate::rule_test::RuleTestError;
+use crate::utils::indent;
 
-pub fn handle_io_error<P: AsRef<Path>>(filepath: &P, e: &io::Error) -> String {
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Styling of error messages printed to the terminal
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorStyle {
+    pub colored: bool,
+}
+
+impl ErrorStyle {
+    /// Enables colors if stderr is a terminal, unless `no_color` is set
+    pub fn detect(no_color: bool) -> Self {
+        ErrorStyle {
+            colored: !no_color && io::stderr().is_terminal(),
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        self.paint(RED, msg)
+    }
+
+    fn highlight(&self, msg: &str) -> String {
+        self.paint(YELLOW, msg)
+    }
+
+    fn paint(&self, color: &str, msg: &str) -> String {
+        if self.colored {
+            format!("{}{}{}", color, msg, RESET)
+        } else {
+            msg.to_string()
+        }
+    }
+}
+
+pub fn handle_io_error<P: AsRef<Path>>(filepath: &P, e: &io::Error, style: ErrorStyle) -> String {
     let filepath_str = filepath.as_ref().display().to_string();
-    match e.kind() {
+    if let Some(invalid_utf8) = e.get_ref().and_then(|inner| inner.downcast_ref::<InvalidUtf8Error>()) {
+        let position = describe_position(invalid_utf8.line_no, None);
+        return style.error(&format!("The file '{}' is not valid UTF-8 in {}. Save it with UTF-8 encoding.", filepath_str, position));
+    }
+    let is_directory = e.get_ref().is_some_and(|inner| inner.is::<IsDirectoryError>());
+    let msg = match e.kind() {
+        _ if is_directory => format!("'{}' is a directory, expected a config file.", filepath_str),
         io::ErrorKind::NotFound => format!("The file '{}' was not found.", filepath_str),
         io::ErrorKind::PermissionDenied => format!("Permission denied. You may not have the necessary permissions to access the file '{}'.", filepath_str),
         io::ErrorKind::AlreadyExists => format!("The file '{}' already exists.", filepath_str),
         io::ErrorKind::WriteZero => format!("An attempt was made to write zero bytes to '{}'.", filepath_str),
         io::ErrorKind::UnexpectedEof => format!("An unexpected end of file was encountered at '{}'.", filepath_str),
         _ => format!("An unknown I/O error occurred when accessing '{}': {}", filepath_str, e),
-    }
+    };
+    style.error(&msg)
 }
 
-pub fn handle_config_error<P: AsRef<Path>>(filepath: &P, e: &RuleConfigError) -> String {
+pub fn handle_config_error<P: AsRef<Path>>(filepath: &P, e: &RuleConfigError, style: ErrorStyle) -> String {
     let filepath_str = filepath.as_ref().display().to_string();
-    format!("Error in config file '{}': {}", filepath_str, e)
+    let error_strs = e.errors.iter()
+        .map(|error| format_rule_parse_error(error, style))
+        .map(|msg| indent(msg, 4))
+        .collect::<Vec<String>>()
+        .join("\n  - ");
+    let header = format!(
+        "Error in config file '{}': Rule parsing failed. {} errors were found: ",
+        filepath_str,
+        e.errors.len(),
+    );
+    format!("{}\n  - {}", style.error(&header), error_strs)
 }
 
-pub fn handle_jack_router_error(e: &JackRouterError) -> String {
+fn format_rule_parse_error(e: &RuleParseError, style: ErrorStyle) -> String {
+    match e {
+        RuleParseError::InvalidFields { line_no, rule_no, invalid_fields } => {
+            let invalid_fields_strs = invalid_fields.iter()
+                .map(|field| {
+                    let reason_str = match field.reason {
+                        Some(ref reason) => format!("{}", reason),
+                        None => "Reason unknown".into(),
+                    };
+                    format!(
+                        "Parsing '{}' in field {} failed: {}",
+                        style.highlight(&field.content),
+                        field.field_id,
+                        reason_str,
+                    )
+                })
+                .map(|msg| indent(msg, 4))
+                .collect::<Vec<String>>()
+                .join("\n  - ");
+            format!("Invalid field in {}:\n  - {}", describe_position(*line_no, *rule_no), invalid_fields_strs)
+        },
+    }
+}
+
+pub fn handle_rule_test_error(e: &RuleTestError, style: ErrorStyle) -> String {
+    match e {
+        RuleTestError::InvalidRule(error) => format!(
+            "{}\n  - {}",
+            style.error("The rule is invalid:"),
+            indent(format_rule_parse_error(error, style), 4),
+        ),
+        RuleTestError::InvalidEvent(error) => style.error(&format!("The event is invalid: {}", error)),
+    }
+}
+
+pub fn handle_jack_router_error(e: &JackRouterError, style: ErrorStyle) -> String {
     let error_msgs = e.reasons.iter()
         .map(|jack_error| format!("{}", jack_error))
         .collect::<Vec<String>>()
         .join("\n  - ");
-    format!("The following Jack-related errors occurred:\n  - {}", error_msgs)
-}
\ No newline at end of file
+    format!("{}\n  - {}", style.error("The following Jack-related errors occurred:"), error_msgs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{load_rules_with_report, FieldParseError};
+    use super::*;
+
+    const COLORED: ErrorStyle = ErrorStyle { colored: true };
+    const PLAIN: ErrorStyle = ErrorStyle { colored: false };
+
+    fn create_config_error() -> RuleConfigError {
+        RuleConfigError {
+            errors: vec![
+                RuleParseError::InvalidFields {
+                    line_no: 1,
+                    rule_no: None,
+                    invalid_fields: vec![
+                        FieldParseError {
+                            field_id: 2,
+                            content: "ch300".into(),
+                            reason: None,
+                        },
+                    ],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_handle_config_error_colored() {
+        let msg = handle_config_error(&"rules.conf", &create_config_error(), COLORED);
+
+        assert!(msg.starts_with(RED), "Unexpected message: {}", msg);
+        assert!(msg.contains(&format!("'{}ch300{}'", YELLOW, RESET)), "Unexpected message: {}", msg);
+    }
+
+    #[test]
+    fn test_handle_config_error_plain() {
+        let msg = handle_config_error(&"rules.conf", &create_config_error(), PLAIN);
+
+        assert!(!msg.contains('\x1b'), "Unexpected message: {}", msg);
+        assert_eq!(msg, "Error in config file 'rules.conf': Rule parsing failed. 1 errors were found: \n  \
+            - Invalid field in line 2:\n      - Parsing 'ch300' in field 2 failed: Reason unknown");
+    }
+
+    #[test]
+    fn test_handle_io_error_colored() {
+        let error = io::Error::from(io::ErrorKind::NotFound);
+
+        let colored_msg = handle_io_error(&"rules.conf", &error, COLORED);
+        assert_eq!(colored_msg, format!("{}The file 'rules.conf' was not found.{}", RED, RESET));
+
+        let plain_msg = handle_io_error(&"rules.conf", &error, PLAIN);
+        assert_eq!(plain_msg, "The file 'rules.conf' was not found.");
+    }
+
+    #[test]
+    fn test_handle_io_error_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = load_rules_with_report(&dir.path()).unwrap_err();
+
+        let msg = handle_io_error(&dir.path(), &error, PLAIN);
+        assert_eq!(msg, format!("'{}' is a directory, expected a config file.", dir.path().display()));
+    }
+
+    #[test]
+    fn test_handle_io_error_invalid_utf8() {
+        let error = io::Error::new(io::ErrorKind::InvalidData, InvalidUtf8Error { line_no: 2 });
+
+        let msg = handle_io_error(&"rules.conf", &error, PLAIN);
+        assert_eq!(msg, "The file 'rules.conf' is not valid UTF-8 in line 3. Save it with UTF-8 encoding.");
+    }
+
+    #[test]
+    fn test_error_style_detect_no_color() {
+        assert!(!ErrorStyle::detect(true).colored);
+    }
+}
@@ -0,0 +1,51 @@
+/*
+ * Create an example configuration for new users
+ */
+
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+pub const EXAMPLE_CONFIG: &str = include_str!("../res/example-config.txt");
+
+/// Writes the example configuration to `file_path`, failing if the file already exists
+pub fn write_example_config<P: AsRef<Path>>(file_path: &P) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(file_path)?;
+    file.write_all(EXAMPLE_CONFIG.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::TempDir;
+    use crate::parser::load_rules_from_file;
+    use super::*;
+
+    #[test]
+    fn test_write_example_config() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("midi-router.config");
+
+        write_example_config(&file_path).unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), EXAMPLE_CONFIG);
+
+        let result = write_example_config(&file_path);
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), EXAMPLE_CONFIG);
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("midi-router.config");
+        write_example_config(&file_path).unwrap();
+
+        let rules = load_rules_from_file(&file_path).unwrap();
+        assert_eq!(rules.len(), 5);
+    }
+}
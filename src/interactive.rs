@@ -0,0 +1,114 @@
+/*
+ * Commands entered in the terminal while the router is running
+ */
+
+use std::io::BufRead;
+
+pub const HELP_TEXT: &str = "Press ENTER to quit, enter 'p' to silence all notes, 's <N>' to solo rule N, or 's' to unsolo";
+const SOLO_COMMAND: &str = "s";
+const CLOCK_START_COMMAND: &str = "start";
+const CLOCK_STOP_COMMAND: &str = "stop";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Quit,
+    Panic,
+    /// Index of the rule to solo, or `None` to apply all rules
+    Solo(Option<usize>),
+    /// Send a start event to the clock port
+    ClockStart,
+    /// Send a stop event to the clock port
+    ClockStop,
+    Unknown(String),
+}
+
+pub fn parse_command(input: &str) -> Command {
+    match input.trim() {
+        "" | "q" => Command::Quit,
+        "p" => Command::Panic,
+        SOLO_COMMAND => Command::Solo(None),
+        CLOCK_START_COMMAND => Command::ClockStart,
+        CLOCK_STOP_COMMAND => Command::ClockStop,
+        other => parse_solo_command(other).unwrap_or(Command::Unknown(other.to_string())),
+    }
+}
+
+/// Parses `s <N>`, where `N` is the 1-based number of the rule as shown to users
+fn parse_solo_command(input: &str) -> Option<Command> {
+    let (command, rule_no) = input.split_once(char::is_whitespace)?;
+    if command != SOLO_COMMAND {
+        return None;
+    }
+    let rule_no = rule_no.trim().parse::<usize>().ok()?;
+    Some(Command::Solo(Some(rule_no.checked_sub(1)?)))
+}
+
+/// Reads commands line by line and passes them to `on_command` until a quit command is entered or
+/// the input ends
+pub fn run_command_loop<R: BufRead, F: FnMut(&Command)>(input: R, mut on_command: F) {
+    for line in input.lines() {
+        let command = match line {
+            Ok(line) => parse_command(&line),
+            Err(_) => Command::Quit,
+        };
+        on_command(&command);
+        if command == Command::Quit {
+            return;
+        }
+    }
+    on_command(&Command::Quit);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("p"), Command::Panic);
+        assert_eq!(parse_command(" p \n"), Command::Panic);
+        assert_eq!(parse_command(""), Command::Quit);
+        assert_eq!(parse_command("q"), Command::Quit);
+        assert_eq!(parse_command("x"), Command::Unknown("x".into()));
+    }
+
+    #[test]
+    fn test_parse_command_solo() {
+        assert_eq!(parse_command("s"), Command::Solo(None));
+        assert_eq!(parse_command("s 1"), Command::Solo(Some(0)));
+        assert_eq!(parse_command("s  12\n"), Command::Solo(Some(11)));
+        assert_eq!(parse_command("s 0"), Command::Unknown("s 0".into()));
+        assert_eq!(parse_command("s x"), Command::Unknown("s x".into()));
+        assert_eq!(parse_command("x 1"), Command::Unknown("x 1".into()));
+    }
+
+    #[test]
+    fn test_parse_command_clock() {
+        assert_eq!(parse_command("start"), Command::ClockStart);
+        assert_eq!(parse_command(" stop\n"), Command::ClockStop);
+        assert_eq!(parse_command("starts"), Command::Unknown("starts".into()));
+    }
+
+    #[test]
+    fn test_run_command_loop() {
+        let mut commands = Vec::new();
+        run_command_loop(Cursor::new("p\nx\np\n\np\n"), |command| match command {
+            Command::Quit => commands.push("quit"),
+            Command::Panic => commands.push("panic"),
+            Command::Solo(_) => commands.push("solo"),
+            Command::ClockStart | Command::ClockStop => commands.push("clock"),
+            Command::Unknown(_) => commands.push("unknown"),
+        });
+
+        assert_eq!(commands, vec!["panic", "unknown", "panic", "quit"]);
+    }
+
+    #[test]
+    fn test_run_command_loop_end_of_input() {
+        let mut commands = Vec::new();
+        run_command_loop(Cursor::new("p"), |command| commands.push(format!("{:?}", command)));
+
+        assert_eq!(commands, vec!["Panic", "Quit"]);
+    }
+}
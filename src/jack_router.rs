@@ -5,61 +5,309 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use jack::{AsyncClient, Client, ClientOptions, ClientStatus, Control, Error as JackError, MidiIn, MidiOut, MidiWriter, Port, ProcessHandler, ProcessScope, RawMidi};
-use log::{debug, error, info};
-use crate::midi::decode_raw_midi;
-use crate::routing::RoutingTable;
-use crate::utils::indent;
+use std::io;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+use jack::{AsyncClient, Client, ClientOptions, ClientStatus, Control, Error as JackError, Frames, MidiIn, MidiOut, MidiWriter, NotificationHandler, Port, ProcessHandler, ProcessScope, RawMidi, TransportState as JackTransportState, Unowned};
+use log::{debug, error, info, warn};
+use crate::midi::{decode_raw_midi, encode_midi_event, panic_events, trim_to_expected_len, MidiEvent, RunningStatusDecoder, SysExAssembler};
+use crate::clock::{Clock, FrameCounter, SampleRate};
+use crate::preset::PresetTracker;
+use crate::profile::ProcessProfile;
+use crate::retry_queue::RetryQueue;
+use crate::stats::PortStats;
+use crate::bank::BankTracker;
+use crate::coalesce::CcCoalescer;
+use crate::note_cap::{CapDecision, NoteLimiter};
+use crate::note_pairing::NotePairing;
+use crate::note_tracker::NoteTracker;
+use crate::slew::SlewTracker;
+use crate::stuck_notes::StuckNoteFixer;
+use crate::sustain::SustainTracker;
+use crate::text_sink::{TextSink, TEXT_SINK_PORT};
+use crate::tempo::TempoTracker;
+use crate::time_window::MinuteOfDayClock;
+use crate::voice::VoiceTracker;
+use crate::routing::{MatchContext, RoutedEvent, RoutingTable, TransportState};
+use crate::utils::{indent, now_ms};
+
+/// Maximum number of ports of a single JACK client (see `PORT_NUM_FOR_CLIENT` in JACK2)
+const MAX_PORTS_PER_CLIENT: usize = 768;
+const PORT_REGISTRATION_PROGRESS_STEP: usize = 100;
+pub const DEFAULT_INPUT_NAME: &str = "midi_in";
+pub const DEFAULT_CLOCK_PORT: &str = "clock";
+
+/// Request to silence all notes on every output port in the next process cycle
+#[derive(Clone, Default)]
+pub struct PanicSwitch {
+    requested: Arc<AtomicBool>,
+}
+
+impl PanicSwitch {
+    pub fn trigger(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether a panic was requested and resets the request
+    fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Names under which the router is visible in JACK
+pub struct JackRouterOptions {
+    pub router_name: String,
+    pub input_name: String,
+    /// Further names of the input port, e.g. for auto-connect scripts
+    pub input_aliases: Vec<String>,
+    /// Measure the duration of the process callback
+    pub profile: bool,
+    /// Name of an output port receiving the raw bytes of events which cannot be decoded
+    pub error_port: Option<String>,
+    /// Drop control changes repeating the last value sent to a port
+    pub coalesce_cc: bool,
+    /// Send a note-off before a note-on of a note which is still playing on a port
+    pub fix_stuck_notes: bool,
+    /// Silence all output ports after an xrun, which may have lost note-off events
+    pub panic_on_xrun: bool,
+    /// Name of an output port receiving the events sent with `clock_sender`
+    pub clock_port: Option<String>,
+    /// Send events which do not fit into the buffer of their output port in the next cycle
+    /// instead of dropping them
+    pub retry_next_cycle: bool,
+}
+
+impl Default for JackRouterOptions {
+    fn default() -> Self {
+        JackRouterOptions {
+            router_name: "midi_router".to_string(),
+            input_name: DEFAULT_INPUT_NAME.to_string(),
+            input_aliases: Vec::new(),
+            profile: false,
+            error_port: None,
+            coalesce_cc: false,
+            fix_stuck_notes: false,
+            panic_on_xrun: false,
+            clock_port: None,
+            retry_next_cycle: false,
+        }
+    }
+}
 
 pub struct JackRouter {
-    client: AsyncClient<(), JackRouterProcessHandler>,
+    client: AsyncClient<JackRouterNotificationHandler, JackRouterProcessHandler>,
+    /// Full name of the MIDI input port, e.g. `midi_router:midi_in`
+    input_port_name: String,
+    last_event_ms: Arc<AtomicU64>,
+    panic_switch: PanicSwitch,
+    port_stats: Arc<PortStats>,
+    profile: Option<Arc<ProcessProfile>>,
+    clock_sender: Option<Sender<MidiEvent>>,
 }
 
 impl JackRouter {
     pub fn new(routing_table: RoutingTable,
-               router_name: &str) -> Result<JackRouter, JackRouterError> {
-        let (client, _status) = Self::create_client(router_name)?;
-        let midi_input_port = Self::register_midi_input_port(&client)?;
+               options: &JackRouterOptions) -> Result<JackRouter, JackRouterError> {
+        let (client, _status) = Self::create_client(&options.router_name)?;
+        let midi_input_port = Self::register_input_port(
+            &options.input_name,
+            &options.input_aliases,
+            |port_name| client.register_port(port_name, MidiIn::default()),
+            |port, alias| port.set_alias(alias),
+        )?;
+        let input_port_name = midi_input_port.name().map_err(|err| JackRouterError { reasons: vec![err] })?;
         let midi_output_ports = Self::register_midi_output_ports(&client, &routing_table)?;
+        let error_port = match &options.error_port {
+            Some(port_name) => {
+                info!("Registering midi error port {}", port_name);
+                let port = client.register_port(port_name, MidiOut::default())
+                    .map_err(|err| JackRouterError { reasons: vec![err] })?;
+                Some(port)
+            },
+            None => None,
+        };
+        let (clock_sender, clock_output) = match &options.clock_port {
+            Some(port_name) => {
+                info!("Registering midi clock port {}", port_name);
+                let port = client.register_port(port_name, MidiOut::default())
+                    .map_err(|err| JackRouterError { reasons: vec![err] })?;
+                let (sender, receiver) = mpsc::channel();
+                (Some(sender), Some((port, receiver)))
+            },
+            None => (None, None),
+        };
+        let last_event_ms = Arc::new(AtomicU64::new(now_ms()));
+        let panic_switch = PanicSwitch::default();
+        let port_stats = Arc::new(PortStats::new(midi_output_ports.keys().map(String::as_str)));
+        let profile = options.profile.then(|| Arc::new(ProcessProfile::new()));
+        let output_guards = OutputGuards {
+            note_limiters: routing_table.note_caps.iter()
+                .map(|(port_name, note_cap)| (port_name.clone(), NoteLimiter::new(note_cap.clone())))
+                .collect(),
+            cc_coalescer: options.coalesce_cc.then(|| CcCoalescer::new(midi_output_ports.keys().map(String::as_str))),
+            stuck_note_fixer: options.fix_stuck_notes
+                .then(|| StuckNoteFixer::new(midi_output_ports.keys().map(String::as_str))),
+            note_pairing: NotePairing::new(midi_output_ports.keys().map(String::as_str)),
+            retry_queue: options.retry_next_cycle.then(RetryQueue::new),
+            text_sink: routing_table.get_all_output_ports().contains(TEXT_SINK_PORT)
+                .then(|| TextSink::spawn(io::stdout()).0),
+        };
+        let routing_table_uses_time_windows = routing_table.uses_time_windows();
         let process_handler = JackRouterProcessHandler {
             midi_input_port,
             midi_output_ports,
+            error_port,
+            clock_output,
             routing_table,
+            last_event_ms: Arc::clone(&last_event_ms),
+            panic_switch: panic_switch.clone(),
+            panic_messages: panic_events().iter()
+                .filter_map(|event| encode_midi_event(event).ok())
+                .collect(),
+            port_stats: Arc::clone(&port_stats),
+            sysex_assembler: SysExAssembler::new(),
+            running_status_decoder: RunningStatusDecoder::new(),
+            profile: profile.clone(),
+            clock: Clock::new(client.sample_rate()),
+            frame_counter: FrameCounter::new(),
+            last_event_clock_ms: None,
+            note_tracker: NoteTracker::new(),
+            tempo_tracker: TempoTracker::new(),
+            bank_tracker: BankTracker::new(),
+            preset_tracker: PresetTracker::new(),
+            sustain_tracker: SustainTracker::new(),
+            slew_tracker: SlewTracker::new(),
+            voice_tracker: VoiceTracker::new(),
+            minute_of_day_clock: routing_table_uses_time_windows.then(MinuteOfDayClock::spawn),
+            output_guards,
+        };
+        let notification_handler = JackRouterNotificationHandler {
+            panic_switch: options.panic_on_xrun.then(|| panic_switch.clone()),
+            sample_rate: process_handler.clock.shared_sample_rate(),
         };
-        let async_client = JackRouter::create_active_client(client, process_handler)?;
+        let async_client = JackRouter::create_active_client(client, notification_handler, process_handler)?;
 
         Ok(JackRouter {
             client: async_client,
+            input_port_name,
+            last_event_ms,
+            panic_switch,
+            port_stats,
+            profile,
+            clock_sender,
         })
     }
 
+    /// Switch to send All-Notes-Off and All-Sound-Off to all output ports
+    pub fn panic_switch(&self) -> PanicSwitch {
+        self.panic_switch.clone()
+    }
+
+    /// Durations of the process callback if profiling is enabled
+    pub fn profile(&self) -> Option<Arc<ProcessProfile>> {
+        self.profile.clone()
+    }
+
+    /// Number of events sent to each output port
+    pub fn port_stats(&self) -> Arc<PortStats> {
+        Arc::clone(&self.port_stats)
+    }
+
+    /// Sender of events to write to the clock port in the next process cycle, if there is one
+    pub fn clock_sender(&self) -> Option<Sender<MidiEvent>> {
+        self.clock_sender.clone()
+    }
+
+    /// Time (see `now_ms`) at which the last MIDI event was received
+    pub fn last_event_ms(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.last_event_ms)
+    }
+
+    /// Handle of the MIDI input port, e.g. to query its connections from another thread. It must
+    /// not be used after the router is stopped.
+    pub fn input_port(&self) -> Option<Port<Unowned>> {
+        self.client.as_client().port_by_name(&self.input_port_name)
+    }
+
     fn create_client(router_name: &str) -> Result<(Client, ClientStatus), JackRouterError> {
         info!("Creating Jack client {}", router_name);
         Client::new(router_name, ClientOptions::default())
             .map_err(|err| JackRouterError { reasons: vec![err] })
     }
 
-    fn register_midi_input_port(client: &Client) -> Result<Port<MidiIn>, JackRouterError> {
-        let port_name = "midi_in";
+    /// Registers the input port with `register_port` and adds all aliases with `set_alias`
+    fn register_input_port<T, F, A>(port_name: &str,
+                                    aliases: &[String],
+                                    register_port: F,
+                                    mut set_alias: A) -> Result<T, JackRouterError>
+    where F: FnOnce(&str) -> Result<T, JackError>,
+          A: FnMut(&mut T, &str) -> Result<(), JackError> {
         info!("Registering midi input port {}", port_name);
-        client.register_port(port_name, MidiIn::default())
-            .map_err(|err| JackRouterError { reasons: vec![err] })
+        let mut port = register_port(port_name)
+            .map_err(|err| JackRouterError { reasons: vec![err] })?;
+        let errors: Vec<JackError> = aliases.iter()
+            .filter_map(|alias| {
+                debug!("Adding alias {} to midi input port {}", alias, port_name);
+                set_alias(&mut port, alias).err()
+            })
+            .collect();
+        if !errors.is_empty() {
+            Err(JackRouterError { reasons: errors })?
+        }
+        Ok(port)
     }
 
     fn register_midi_output_ports(client: &Client, routing_table: &RoutingTable) -> Result<HashMap<String, Port<MidiOut>>, JackRouterError> {
-        let output_port_names = routing_table.get_all_output_ports();
+        let dynamic_port_names = routing_table.rules.iter()
+            .flat_map(|rule| &rule.actions)
+            .filter(|action| action.has_dynamic_output_port())
+            .filter_map(|action| action.output_port());
+        for port_name in dynamic_port_names {
+            warn!("Output port {} refers to capture groups and is not registered", port_name);
+        }
+        let mut output_port_names = routing_table.get_all_output_ports();
+        output_port_names.remove(TEXT_SINK_PORT);
+        let max_port_count = MAX_PORTS_PER_CLIENT - 1;  // one port is taken by the input
+        Self::register_ports(
+            output_port_names.into_iter().collect(),
+            max_port_count,
+            |port_name| client.register_port(port_name, MidiOut::default()),
+        )
+    }
+
+    /// Registers all given ports with `register_port`, reporting progress and collecting all errors
+    fn register_ports<T, F>(mut port_names: Vec<&str>,
+                            max_port_count: usize,
+                            mut register_port: F) -> Result<HashMap<String, T>, JackRouterError>
+    where F: FnMut(&str) -> Result<T, JackError> {
+        if port_names.len() > max_port_count {
+            Err(JackRouterError {
+                reasons: vec![JackError::PortRegistrationError(format!(
+                    "{} output ports are required, but JACK supports at most {} ports per client",
+                    port_names.len(),
+                    max_port_count,
+                ))],
+            })?
+        }
 
-        let mut midi_output_ports = HashMap::with_capacity(output_port_names.len());
+        port_names.sort_unstable();
+        let port_count = port_names.len();
+        let mut ports = HashMap::with_capacity(port_count);
         let mut errors = Vec::new();
 
-        for port_name in output_port_names {
-            info!("Registering midi output port {}", port_name);
-            match client.register_port(port_name.as_str(), MidiOut::default()) {
-                Ok(output_port) => {
-                    midi_output_ports.insert(port_name.into(), output_port);
+        for (port_idx, port_name) in port_names.into_iter().enumerate() {
+            debug!("Registering midi output port {}", port_name);
+            match register_port(port_name) {
+                Ok(port) => {
+                    ports.insert(port_name.into(), port);
                 },
                 Err(error) => errors.push(error),
             }
+            if (port_idx + 1) % PORT_REGISTRATION_PROGRESS_STEP == 0 || port_idx + 1 == port_count {
+                info!("Registered {}/{} midi output ports", port_idx + 1, port_count);
+            }
         }
 
         if !errors.is_empty() {
@@ -68,12 +316,15 @@ impl JackRouter {
             })?
         }
 
-        Ok(midi_output_ports)
+        Ok(ports)
     }
 
-    fn create_active_client(client: Client, process_handler: JackRouterProcessHandler) -> Result<AsyncClient<(), JackRouterProcessHandler>, JackRouterError> {
+    fn create_active_client(client: Client,
+                            notification_handler: JackRouterNotificationHandler,
+                            process_handler: JackRouterProcessHandler)
+        -> Result<AsyncClient<JackRouterNotificationHandler, JackRouterProcessHandler>, JackRouterError> {
         info!("Activating Jack client {}", client.name());
-        client.activate_async((), process_handler)
+        client.activate_async(notification_handler, process_handler)
             .map_err(|err| JackRouterError { reasons: vec![err] })
     }
 
@@ -86,26 +337,251 @@ impl JackRouter {
     }
 }
 
+pub struct JackRouterNotificationHandler {
+    /// Switch to trigger on xruns, or `None` to only log them
+    panic_switch: Option<PanicSwitch>,
+    /// Sample rate of the clock of the process handler, which must convert at the current one
+    sample_rate: SampleRate,
+}
+
+impl JackRouterNotificationHandler {
+    fn on_xrun(&self) {
+        match &self.panic_switch {
+            Some(panic_switch) => {
+                warn!("Xrun detected, silencing all output ports");
+                panic_switch.trigger();
+            },
+            None => debug!("Xrun detected"),
+        }
+    }
+
+    fn on_sample_rate(&self, sample_rate: usize) {
+        info!("Sample rate changed to {} Hz", sample_rate);
+        self.sample_rate.set(sample_rate);
+    }
+}
+
+impl NotificationHandler for JackRouterNotificationHandler {
+    fn xrun(&mut self, _: &Client) -> Control {
+        self.on_xrun();
+        Control::Continue
+    }
+
+    fn sample_rate(&mut self, _: &Client, sample_rate: Frames) -> Control {
+        self.on_sample_rate(sample_rate as usize);
+        Control::Continue
+    }
+}
+
 pub struct JackRouterProcessHandler {
     midi_input_port: Port<MidiIn>,
     midi_output_ports: HashMap<String, Port<MidiOut>>,
+    /// Port receiving events which cannot be decoded
+    error_port: Option<Port<MidiOut>>,
+    /// Port receiving generated clock events and the queue of these events
+    clock_output: Option<(Port<MidiOut>, Receiver<MidiEvent>)>,
     routing_table: RoutingTable,
+    last_event_ms: Arc<AtomicU64>,
+    panic_switch: PanicSwitch,
+    panic_messages: Vec<Vec<u8>>,
+    port_stats: Arc<PortStats>,
+    sysex_assembler: SysExAssembler,
+    running_status_decoder: RunningStatusDecoder,
+    profile: Option<Arc<ProcessProfile>>,
+    clock: Clock,
+    /// Frames since the first cycle, which unlike the frame time of JACK do not wrap around
+    frame_counter: FrameCounter,
+    /// Time of the last MIDI event according to `clock`
+    last_event_clock_ms: Option<u64>,
+    note_tracker: NoteTracker,
+    tempo_tracker: TempoTracker,
+    bank_tracker: BankTracker,
+    preset_tracker: PresetTracker,
+    sustain_tracker: SustainTracker,
+    slew_tracker: SlewTracker,
+    voice_tracker: VoiceTracker,
+    /// Local time of day if any rule matches it, which is never read from libc in the cycle
+    minute_of_day_clock: Option<MinuteOfDayClock>,
+    output_guards: OutputGuards,
+}
+
+/// State of the output ports deciding whether an event may be sent
+struct OutputGuards {
+    /// Notes playing on each output port with a note cap
+    note_limiters: HashMap<String, NoteLimiter>,
+    cc_coalescer: Option<CcCoalescer>,
+    stuck_note_fixer: Option<StuckNoteFixer>,
+    /// Notes sent for each held note, which its note-off must release
+    note_pairing: NotePairing,
+    /// Events to send in the next cycle because the buffer of their output port was full
+    retry_queue: Option<RetryQueue>,
+    /// Prints the events routed to the port `-` if any rule sends there
+    text_sink: Option<TextSink>,
 }
 
 impl JackRouterProcessHandler {
+    fn send_panic(panic_messages: &[Vec<u8>], output_port_writers: &mut HashMap<String, MidiWriter>) {
+        info!("Sending All-Notes-Off and All-Sound-Off to all output ports");
+        for (port_name, writer) in output_port_writers.iter_mut() {
+            for bytes in panic_messages {
+                if let Err(err) = writer.write(&RawMidi { time: 0, bytes }) {
+                    error!("Could not send panic message to port {}: {}", port_name, err);
+                }
+            }
+        }
+    }
+
+    /// Decodes the event or passes it to `on_error` if it is malformed
+    fn decode_or_report<F: FnOnce(RawMidi)>(raw_event: RawMidi, on_error: F) -> Option<MidiEvent> {
+        match decode_raw_midi(raw_event) {
+            Ok(event) => {
+                debug!("Decoded raw event to {:?}", event);
+                Some(event)
+            },
+            Err(err) => {
+                error!("Error decoding midi event: {}", err);
+                on_error(raw_event);
+                None
+            },
+        }
+    }
+
+    /// Sends the routed events in their order, so that events for the same port keep the order of
+    /// the rules and actions they result from
     fn send_event_out(raw_event: RawMidi,
-                      output_port_names: Vec<&str>,
+                      midi_event: &MidiEvent,
+                      routed_events: Vec<RoutedEvent>,
+                      routing_table: &RoutingTable,
+                      port_stats: &PortStats,
+                      output_guards: &mut OutputGuards,
                       output_port_writers: &mut HashMap<String, MidiWriter>) {
-        for port_name in output_port_names {
-            if let Some(writer) = output_port_writers.get_mut(port_name) {
-                debug!("Send signal {:?} to port {}", raw_event, port_name);
-                writer.write(&raw_event).unwrap()
-            } else {
+        let orphan_port = Self::orphan_port(
+            routing_table.orphan_port.as_deref(),
+            &routed_events,
+            |port_name| output_port_writers.contains_key(port_name),
+        );
+        let orphan_writer = orphan_port.and_then(|port_name| Some((port_name, output_port_writers.get_mut(port_name)?)));
+        if let Some((port_name, writer)) = orphan_writer {
+            debug!("Send orphaned signal {:?} to port {}", raw_event, port_name);
+            let bytes = trim_to_expected_len(raw_event.bytes);
+            if Self::write_or_retry(writer, port_name, raw_event.time, bytes, output_guards.retry_queue.as_mut()) {
+                port_stats.record_sent(port_name);
+            }
+        }
+        for RoutedEvent { output_port: port_name, event } in routed_events {
+            let port_name = port_name.as_ref();
+            if !routing_table.is_allowed_on_port(port_name, event.as_ref().unwrap_or(midi_event)) {
+                debug!("Event {:?} blocked by filter of port {}", event.as_ref().unwrap_or(midi_event), port_name);
+                continue;
+            }
+            if port_name == TEXT_SINK_PORT {
+                if let Some(text_sink) = &output_guards.text_sink {
+                    text_sink.write(event.as_ref().unwrap_or(midi_event));
+                }
+                continue;
+            }
+            let Some(writer) = output_port_writers.get_mut(port_name) else {
                 error!("Could not find output port writer: {}. Ignore this rule.", port_name);
+                continue;
+            };
+            if let Some(cc_coalescer) = output_guards.cc_coalescer.as_mut() {
+                if !cc_coalescer.should_send(port_name, event.as_ref().unwrap_or(midi_event)) {
+                    debug!("Event {:?} coalesced on port {}", event.as_ref().unwrap_or(midi_event), port_name);
+                    continue;
+                }
+            }
+            let cap_decision = output_guards.note_limiters.get_mut(port_name)
+                .map(|note_limiter| note_limiter.check(event.as_ref().unwrap_or(midi_event)))
+                .unwrap_or(CapDecision::Send);
+            match cap_decision {
+                CapDecision::Send => {},
+                CapDecision::Drop => {
+                    debug!("Event {:?} dropped by note cap of port {}", event.as_ref().unwrap_or(midi_event), port_name);
+                    continue;
+                },
+                CapDecision::StealThenSend(note_off) => match encode_midi_event(&note_off) {
+                    Ok(bytes) => {
+                        debug!("Steal note with {:?} on port {}", note_off, port_name);
+                        Self::write_or_retry(writer, port_name, raw_event.time, &bytes, output_guards.retry_queue.as_mut());
+                    },
+                    Err(err) => error!("Error encoding midi event: {}", err),
+                },
+            }
+            let stuck_note_off = output_guards.stuck_note_fixer.as_mut()
+                .and_then(|stuck_note_fixer| stuck_note_fixer.check(port_name, event.as_ref().unwrap_or(midi_event)));
+            if let Some(note_off) = stuck_note_off {
+                match encode_midi_event(&note_off) {
+                    Ok(bytes) => {
+                        debug!("Release stuck note with {:?} on port {}", note_off, port_name);
+                        Self::write_or_retry(writer, port_name, raw_event.time, &bytes, output_guards.retry_queue.as_mut());
+                    },
+                    Err(err) => error!("Error encoding midi event: {}", err),
+                }
+            }
+            match event {
+                None => {
+                    debug!("Send signal {:?} to port {}", raw_event, port_name);
+                    let bytes = trim_to_expected_len(raw_event.bytes);
+                    if Self::write_or_retry(writer, port_name, raw_event.time, bytes, output_guards.retry_queue.as_mut()) {
+                        port_stats.record_sent(port_name);
+                    }
+                },
+                Some(event) => match encode_midi_event(&event) {
+                    Ok(bytes) => {
+                        debug!("Send event {:?} to port {}", event, port_name);
+                        if Self::write_or_retry(writer, port_name, raw_event.time, &bytes, output_guards.retry_queue.as_mut()) {
+                            port_stats.record_sent(port_name);
+                        }
+                    },
+                    Err(err) => error!("Error encoding midi event: {}", err),
+                },
             }
         }
     }
 
+    /// Writes the event, or queues it for the next cycle if the buffer of the port is full or
+    /// earlier events of the port are still queued. Returns whether the event was written.
+    fn write_or_retry(writer: &mut MidiWriter,
+                      port_name: &str,
+                      time: u32,
+                      bytes: &[u8],
+                      retry_queue: Option<&mut RetryQueue>) -> bool {
+        Self::write_with_retry(port_name, bytes, retry_queue, || writer.write(&RawMidi { time, bytes }))
+    }
+
+    fn write_with_retry<F>(port_name: &str, bytes: &[u8], retry_queue: Option<&mut RetryQueue>, write: F) -> bool
+    where F: FnOnce() -> Result<(), JackError> {
+        match retry_queue {
+            Some(retry_queue) if retry_queue.has_pending(port_name) => {
+                debug!("Queue event {:?} for port {} behind earlier events", bytes, port_name);
+                retry_queue.push(port_name, bytes);
+                false
+            },
+            retry_queue => match (write(), retry_queue) {
+                (Ok(()), _) => true,
+                (Err(JackError::NotEnoughSpace), Some(retry_queue)) => {
+                    debug!("Buffer of port {} is full, sending {:?} in the next cycle", port_name, bytes);
+                    retry_queue.push(port_name, bytes);
+                    false
+                },
+                (Err(err), _) => {
+                    error!("Could not send event {:?} to port {}: {}", bytes, port_name, err);
+                    false
+                },
+            },
+        }
+    }
+
+    /// Returns the orphan port if the event was routed, but none of its output ports is available
+    fn orphan_port<'a, F: Fn(&str) -> bool>(orphan_port: Option<&'a str>,
+                                             routed_events: &[RoutedEvent],
+                                             has_writer: F) -> Option<&'a str> {
+        if routed_events.is_empty() || routed_events.iter().any(|routed_event| has_writer(&routed_event.output_port)) {
+            return None;
+        }
+        orphan_port
+    }
+
     fn create_output_port_writers<'a>(ps: &'a ProcessScope, output_ports: &'a mut HashMap<String, Port<MidiOut>>) -> HashMap<String, MidiWriter<'a>> {
         let mut output_writers = HashMap::with_capacity(output_ports.len());
         for (port_name, port) in output_ports {
@@ -117,25 +593,116 @@ impl JackRouterProcessHandler {
 }
 
 impl ProcessHandler for JackRouterProcessHandler {
-    fn process(&mut self, _: &Client, ps: &ProcessScope) -> Control {
-        let mut output_port_writers = Self::create_output_port_writers(ps, &mut self.midi_output_ports);
-        for raw_event in self.midi_input_port.iter(ps) {
-            debug!("Received raw event {:?}", raw_event);
-            let midi_event = match decode_raw_midi(raw_event) {
-                Ok(event) => {
-                    debug!("Decoded raw event to {:?}", event);
-                    event
-                },
-                Err(err) => {
-                    error!("Error decoding midi event: {}", err);
-                    continue;
+    fn process(&mut self, client: &Client, ps: &ProcessScope) -> Control {
+        let process_start = Instant::now();
+        let transport = match client.transport().query_state() {
+            Ok(JackTransportState::Rolling) => Some(TransportState::Rolling),
+            Ok(_) => Some(TransportState::Stopped),
+            Err(_) => None,
+        };
+        self.route_events(ps, transport);
+        self.send_clock_events(ps);
+        if let Some(profile) = &self.profile {
+            profile.record(process_start.elapsed());
+        }
+        Control::Continue
+    }
+}
+
+impl JackRouterProcessHandler {
+    /// Writes the clock events queued since the last cycle at its start
+    fn send_clock_events(&mut self, ps: &ProcessScope) {
+        let Some((clock_port, clock_receiver)) = self.clock_output.as_mut() else {
+            return;
+        };
+        let mut writer = clock_port.writer(ps);
+        for event in clock_receiver.try_iter() {
+            match encode_midi_event(&event) {
+                Ok(bytes) => {
+                    if let Err(err) = writer.write(&RawMidi { time: 0, bytes: &bytes }) {
+                        error!("Could not send clock event {:?}: {}", event, err);
+                    }
                 },
+                Err(err) => error!("Error encoding midi event: {}", err),
+            }
+        }
+    }
+
+    fn route_events(&mut self, ps: &ProcessScope, transport: Option<TransportState>) {
+        let mut output_port_writers = Self::create_output_port_writers(ps, &mut self.midi_output_ports);
+        let mut error_writer = self.error_port.as_mut().map(|port| port.writer(ps));
+        if self.panic_switch.take() {
+            Self::send_panic(&self.panic_messages, &mut output_port_writers);
+            self.output_guards.note_limiters.values_mut().for_each(NoteLimiter::reset);
+        }
+        if let Some(retry_queue) = self.output_guards.retry_queue.as_mut() {
+            let written_ports = retry_queue.flush(|port_name, bytes| match output_port_writers.get_mut(port_name) {
+                Some(writer) => writer.write(&RawMidi { time: 0, bytes }),
+                None => Err(JackError::PortRegistrationError(port_name.to_string())),
+            });
+            for port_name in written_ports {
+                self.port_stats.record_sent(&port_name);
+            }
+        }
+        let minute_of_day = self.minute_of_day_clock.as_ref().map(MinuteOfDayClock::minute_of_day);
+        let cycle_frames = self.frame_counter.advance(ps.last_frame_time());
+        self.clock.update_sample_rate(cycle_frames);
+        for raw_fragment in self.midi_input_port.iter(ps) {
+            debug!("Received raw event {:?}", raw_fragment);
+            let Some(bytes) = self.sysex_assembler.assemble(raw_fragment.bytes) else {
+                continue;
+            };
+            let (bytes, running_status) = self.running_status_decoder.expand(bytes);
+            let raw_event = RawMidi { time: raw_fragment.time, bytes: &bytes };
+            self.last_event_ms.store(now_ms(), Ordering::Relaxed);
+            let event_ms = self.clock.frames_to_ms(cycle_frames + raw_event.time as u64);
+            let silence_ms = self.last_event_clock_ms.replace(event_ms)
+                .map(|last_event_ms| event_ms.saturating_sub(last_event_ms))
+                .unwrap_or(u64::MAX);
+            let report_error = |raw_event: RawMidi| {
+                let Some(error_writer) = error_writer.as_mut() else {
+                    return;
+                };
+                if let Err(err) = error_writer.write(&raw_event) {
+                    error!("Could not send malformed event to error port: {}", err);
+                }
             };
-            let output_port_names = self.routing_table.get_output_ports(midi_event);
+            let Some(midi_event) = Self::decode_or_report(raw_event, report_error) else {
+                continue;
+            };
+            self.tempo_tracker.track(&midi_event, event_ms);
+            let repeated_note_on = self.note_tracker.is_repeated_note_on(&midi_event);
+            if repeated_note_on {
+                warn!("Note-on {:?} repeats a note which was not released, the note may be stuck", midi_event);
+            }
+            let context = MatchContext {
+                silence_ms,
+                held_ms: self.note_tracker.track(&midi_event, event_ms),
+                since_program_change_ms: self.preset_tracker.track(&midi_event, event_ms),
+                tempo_bpm: self.tempo_tracker.bpm(),
+                bank: self.bank_tracker.track(&midi_event),
+                sustain_pedal_down: self.sustain_tracker.track(&midi_event),
+                repeated_note_on,
+                slew: self.slew_tracker.track(&midi_event),
+                raw_len: Some(raw_event.bytes.len()),
+                running_status,
+                voice: self.voice_tracker.track(&midi_event),
+                minute_of_day,
+                transport,
+            };
+            let mut routed_events = self.routing_table.get_outputs_in_context(&midi_event, &context);
+            self.output_guards.note_pairing.pair(&midi_event, &mut routed_events);
 
-            Self::send_event_out(raw_event, output_port_names, &mut output_port_writers);
+            Self::send_event_out(
+                raw_event,
+                &midi_event,
+                routed_events,
+                &self.routing_table,
+                &self.port_stats,
+                &mut self.output_guards,
+                &mut output_port_writers,
+            );
         }
-        Control::Continue
     }
 }
 
@@ -160,3 +727,210 @@ impl Display for JackRouterError {
 }
 
 impl Error for JackRouterError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_switch() {
+        let panic_switch = PanicSwitch::default();
+        assert!(!panic_switch.take());
+
+        panic_switch.clone().trigger();
+        assert!(panic_switch.take());
+        assert!(!panic_switch.take());
+    }
+
+    #[test]
+    fn test_notification_handler_xrun_triggers_panic() {
+        let panic_switch = PanicSwitch::default();
+        let notification_handler = JackRouterNotificationHandler {
+            panic_switch: Some(panic_switch.clone()),
+            sample_rate: Clock::new(44100).shared_sample_rate(),
+        };
+
+        notification_handler.on_xrun();
+
+        assert!(panic_switch.take());
+        assert!(!panic_switch.take());
+    }
+
+    #[test]
+    fn test_notification_handler_sample_rate_updates_clock() {
+        let mut clock = Clock::new(44100);
+        let notification_handler = JackRouterNotificationHandler {
+            panic_switch: None,
+            sample_rate: clock.shared_sample_rate(),
+        };
+        assert_eq!(clock.frames_to_ms(44100), 1000);
+
+        notification_handler.on_sample_rate(48000);
+
+        clock.update_sample_rate(44100);
+        assert_eq!(clock.frames_to_ms(44100), 1000);
+        assert_eq!(clock.frames_to_ms(44100 + 48000), 2000);
+    }
+
+    #[test]
+    fn test_decode_or_report() {
+        let mut reported = Vec::new();
+        let malformed_event = RawMidi { time: 5, bytes: &[0x90, 60] };
+
+        let result = JackRouterProcessHandler::decode_or_report(malformed_event, |raw_event| {
+            reported.push((raw_event.time, raw_event.bytes.to_vec()));
+        });
+        assert_eq!(result, None);
+        assert_eq!(reported, vec![(5, vec![0x90, 60])]);
+
+        let note_on = RawMidi { time: 0, bytes: &[0x90, 60, 100] };
+        let result = JackRouterProcessHandler::decode_or_report(note_on, |_| panic!("Unexpected error"));
+        assert_eq!(result, Some(MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+    }
+
+    #[test]
+    fn test_write_with_retry() {
+        let mut retry_queue = RetryQueue::new();
+        let buffer_full = || Err(JackError::NotEnoughSpace);
+
+        assert!(JackRouterProcessHandler::write_with_retry("synth", &[0x90, 60, 100], Some(&mut retry_queue), || Ok(())));
+        assert!(!JackRouterProcessHandler::write_with_retry("synth", &[0x90, 62, 100], Some(&mut retry_queue), buffer_full));
+        assert!(retry_queue.has_pending("synth"));
+        assert!(!JackRouterProcessHandler::write_with_retry(
+            "synth",
+            &[0x90, 64, 100],
+            Some(&mut retry_queue),
+            || panic!("Event must be queued behind the earlier one"),
+        ));
+        assert!(JackRouterProcessHandler::write_with_retry("drums", &[0x99, 36, 100], Some(&mut retry_queue), || Ok(())));
+        assert!(!retry_queue.has_pending("drums"));
+
+        let mut written = Vec::new();
+        retry_queue.flush(|_, bytes| {
+            written.push(bytes.to_vec());
+            Ok(())
+        });
+        assert_eq!(written, vec![vec![0x90, 62, 100], vec![0x90, 64, 100]]);
+    }
+
+    #[test]
+    fn test_write_with_retry_disabled() {
+        assert!(!JackRouterProcessHandler::write_with_retry("synth", &[0x90, 60, 100], None, || Err(JackError::NotEnoughSpace)));
+        assert!(JackRouterProcessHandler::write_with_retry("synth", &[0x90, 60, 100], None, || Ok(())));
+    }
+
+    #[test]
+    fn test_orphan_port() {
+        let routed_events = vec![
+            RoutedEvent { output_port: "drums-1".into(), event: None },
+            RoutedEvent { output_port: "drums-2".into(), event: None },
+        ];
+        let orphan_port = |available_port: &'static str| JackRouterProcessHandler::orphan_port(
+            Some("orphans"),
+            &routed_events,
+            |port_name| port_name == available_port,
+        );
+
+        assert_eq!(orphan_port("orphans"), Some("orphans"));
+        assert_eq!(orphan_port("drums-2"), None);
+        assert_eq!(JackRouterProcessHandler::orphan_port(Some("orphans"), &[], |_| false), None);
+        assert_eq!(JackRouterProcessHandler::orphan_port(None, &routed_events, |_| false), None);
+    }
+
+    #[test]
+    fn test_register_ports() {
+        let port_names: Vec<String> = (0..250).map(|idx| format!("out{}", idx)).collect();
+        let mut registered = Vec::new();
+        let result = JackRouter::register_ports(
+            port_names.iter().map(String::as_str).collect(),
+            300,
+            |port_name| {
+                registered.push(port_name.to_string());
+                Ok(port_name.len())
+            },
+        );
+
+        assert!(result.is_ok());
+        let ports = result.unwrap();
+        assert_eq!(ports.len(), 250);
+        assert_eq!(ports["out123"], 6);
+        assert_eq!(registered.len(), 250);
+    }
+
+    #[test]
+    fn test_register_ports_collects_errors() {
+        let result = JackRouter::register_ports(
+            vec!["a", "b", "c"],
+            300,
+            |port_name| match port_name {
+                "a" => Ok(()),
+                _ => Err(JackError::PortRegistrationError(port_name.to_string())),
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap().reasons.len(), 2);
+    }
+
+    #[test]
+    fn test_register_ports_limit_exceeded() {
+        let port_names: Vec<String> = (0..1000).map(|idx| format!("out{}", idx)).collect();
+        let mut register_calls = 0;
+        let result = JackRouter::register_ports(
+            port_names.iter().map(String::as_str).collect(),
+            767,
+            |_| {
+                register_calls += 1;
+                Ok(())
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(register_calls, 0);
+        let error = result.err().unwrap();
+        assert_eq!(error.reasons.len(), 1);
+        if let JackError::PortRegistrationError(message) = &error.reasons[0] {
+            assert!(message.contains("1000 output ports are required"), "Unexpected message: {}", message);
+            assert!(message.contains("at most 767 ports"), "Unexpected message: {}", message);
+        } else {
+            panic!("Unexpected error {:?}", error.reasons[0]);
+        }
+    }
+
+    #[test]
+    fn test_register_input_port() {
+        let mut registered = Vec::new();
+        let aliases = vec!["keyboard".to_string(), "system:capture_midi".to_string()];
+        let result = JackRouter::register_input_port(
+            "controller_in",
+            &aliases,
+            |port_name| {
+                registered.push(port_name.to_string());
+                Ok(Vec::new())
+            },
+            |port: &mut Vec<String>, alias| {
+                port.push(alias.to_string());
+                Ok(())
+            },
+        );
+
+        assert_eq!(registered, vec!["controller_in"]);
+        assert_eq!(result.unwrap(), aliases);
+    }
+
+    #[test]
+    fn test_register_input_port_alias_error() {
+        let result = JackRouter::register_input_port(
+            DEFAULT_INPUT_NAME,
+            &["a".to_string(), "b".to_string(), "c".to_string()],
+            |_| Ok(()),
+            |_, alias| match alias {
+                "a" => Ok(()),
+                _ => Err(JackError::PortAliasError),
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap().reasons.len(), 2);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,325 @@
+/*
+ * Check a config file for errors and likely mistakes without starting the router
+ */
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use crate::midi::EVENT_NAMES;
+use crate::parser::{load_rules_with_report, ParseReport};
+use crate::routing::{Condition, NumericRange, Rule};
+
+/// Exit code if the config file passes all checks
+pub const EXIT_OK: i32 = 0;
+/// Exit code if there are warnings and warnings are treated as failures
+pub const EXIT_WARNINGS: i32 = 1;
+/// Exit code if the config file has errors, which is the same as when loading it fails
+pub const EXIT_ERRORS: i32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct LintReport {
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|finding| finding.severity == severity).count()
+    }
+
+    /// Exit code for CI jobs, which only fails on warnings if `warnings_fail` is set
+    pub fn exit_code(&self, warnings_fail: bool) -> i32 {
+        if self.count(Severity::Error) > 0 {
+            EXIT_ERRORS
+        } else if warnings_fail && self.count(Severity::Warning) > 0 {
+            EXIT_WARNINGS
+        } else {
+            EXIT_OK
+        }
+    }
+
+    /// One finding per line followed by a summary, e.g. `warning: Rule 2 duplicates rule 1`
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for finding in &self.findings {
+            text.push_str(&format!("{}: {}\n", finding.severity.as_str(), finding.message));
+        }
+        text.push_str(&format!(
+            "{} errors, {} warnings\n",
+            self.count(Severity::Error),
+            self.count(Severity::Warning),
+        ));
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let findings = self.findings.iter()
+            .map(|finding| format!(
+                "{{\"severity\":\"{}\",\"message\":\"{}\"}}",
+                finding.severity.as_str(),
+                escape_json(&finding.message),
+            ))
+            .collect::<Vec<String>>()
+            .join(",");
+        format!(
+            "{{\"errors\":{},\"warnings\":{},\"findings\":[{}]}}\n",
+            self.count(Severity::Error),
+            self.count(Severity::Warning),
+            findings,
+        )
+    }
+
+    fn push(&mut self, severity: Severity, message: String) {
+        self.findings.push(Finding { severity, message });
+    }
+}
+
+pub fn lint_config_file<P: AsRef<Path>>(file_path: &P) -> Result<LintReport, io::Error> {
+    load_rules_with_report(file_path).map(|report| lint(&report))
+}
+
+/// Runs all checks on a parsed config file. Parse errors are errors, everything else is a warning
+/// because the router still starts with such a config.
+pub fn lint(report: &ParseReport) -> LintReport {
+    let mut lint_report = LintReport::default();
+    for error in &report.errors {
+        lint_report.push(Severity::Error, error.to_string());
+    }
+    for warning in &report.warnings {
+        lint_report.push(Severity::Warning, warning.to_string());
+    }
+    check_never_matching(&report.rules, &mut lint_report);
+    check_duplicates(&report.rules, &mut lint_report);
+    check_unreferenced_ports(report, &mut lint_report);
+    check_velocity_partitions(&report.rules, &mut lint_report);
+    lint_report
+}
+
+/// Rules whose event name patterns do not match any MIDI event. Negated rules match all events
+/// then, which is intended.
+fn check_never_matching(rules: &[Rule], lint_report: &mut LintReport) {
+    for (rule_no, rule) in rules.iter().enumerate() {
+        if !rule.negated && !matches_any_event_name(&rule.condition) {
+            lint_report.push(
+                Severity::Warning,
+                format!("Rule {} never matches because no event name matches '{}'", rule_no + 1, rule.condition.describe()),
+            );
+        }
+    }
+}
+
+fn matches_any_event_name(condition: &Condition) -> bool {
+    let matches_self = match &condition.event_pattern {
+        Some(event_pattern) => EVENT_NAMES.iter().any(|event_name| event_pattern.is_match(event_name)),
+        None => true,
+    };
+    matches_self || condition.alternatives.iter().any(matches_any_event_name)
+}
+
+/// Rules repeating an earlier rule, which sends each matching event twice
+fn check_duplicates(rules: &[Rule], lint_report: &mut LintReport) {
+    // Rule number by condition and actions of the first rule with them
+    let mut first_rules: HashMap<String, usize> = HashMap::new();
+    for (rule_no, rule) in rules.iter().enumerate() {
+        let key = format!("{} => {:?}", rule.describe_condition(), rule.actions);
+        match first_rules.get(&key) {
+            Some(first_rule_no) => lint_report.push(
+                Severity::Warning,
+                format!("Rule {} duplicates rule {}, so matching events are sent twice", rule_no + 1, first_rule_no + 1),
+            ),
+            None => {
+                first_rules.insert(key, rule_no);
+            },
+        }
+    }
+}
+
+/// Output filters and note caps of ports no rule sends to
+fn check_unreferenced_ports(report: &ParseReport, lint_report: &mut LintReport) {
+    let is_referenced = |port: &str| report.rules.iter()
+        .flat_map(|rule| &rule.actions)
+        .any(|action| action.output_port() == Some(port));
+    for (output_port, _) in &report.output_filters {
+        if !is_referenced(output_port) {
+            lint_report.push(Severity::Warning, format!("Filter refers to port '{}', which no rule sends to", output_port));
+        }
+    }
+    for (output_port, _) in &report.note_caps {
+        if !is_referenced(output_port) {
+            lint_report.push(Severity::Warning, format!("Cap refers to port '{}', which no rule sends to", output_port));
+        }
+    }
+}
+
+/// Rules with a `velcomp` velocity range whose complement is not fully matched by rules with the
+/// same condition otherwise, so that some velocities match none of them
+fn check_velocity_partitions(rules: &[Rule], lint_report: &mut LintReport) {
+    let partition_keys: Vec<Option<(String, &NumericRange<u8>)>> = rules.iter()
+        .map(|rule| Some(&rule.condition).filter(|_| !rule.negated).and_then(velocity_partition_key))
+        .collect();
+    let mut checked_keys: Vec<&str> = Vec::new();
+    for (rule, partition_key) in rules.iter().zip(&partition_keys) {
+        let Some((key, _)) = partition_key.as_ref().filter(|_| rule.condition.velocity_complement) else {
+            continue;
+        };
+        if checked_keys.contains(&key.as_str()) {
+            continue;
+        }
+        checked_keys.push(key);
+        let (rule_nos, velocity_ranges): (Vec<String>, Vec<&NumericRange<u8>>) = partition_keys.iter()
+            .enumerate()
+            .filter_map(|(rule_no, partition_key)| match partition_key {
+                Some((other_key, velocity_range)) if other_key == key => Some(((rule_no + 1).to_string(), *velocity_range)),
+                _ => None,
+            })
+            .unzip();
+        if let Some((gap_start, gap_end)) = first_velocity_gap(&velocity_ranges) {
+            lint_report.push(
+                Severity::Warning,
+                format!(
+                    "Velocities {}-{} match none of the complementary rules {} with '{}'",
+                    gap_start,
+                    gap_end,
+                    rule_nos.join(", "),
+                    key,
+                ),
+            );
+        }
+    }
+}
+
+/// Condition without its velocity range, which complementary rules share, and the velocity range
+fn velocity_partition_key(condition: &Condition) -> Option<(String, &NumericRange<u8>)> {
+    let velocity_range = condition.velocity_pattern.as_ref()?;
+    let velocity_field = velocity_range.describe("vel", u8::MIN.into(), u8::MAX.into());
+    let key = condition.describe()
+        .split_whitespace()
+        .filter(|field| *field != velocity_field)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    Some((key, velocity_range))
+}
+
+/// First range of note velocities between 0 and 127 which is in none of the given ranges
+fn first_velocity_gap(velocity_ranges: &[&NumericRange<u8>]) -> Option<(u8, u8)> {
+    let is_covered = |velocity: u8| velocity_ranges.iter().any(|range| range.is_within(velocity));
+    let gap_start = (0..=127).find(|velocity| !is_covered(*velocity))?;
+    let gap_end = (gap_start..=127).take_while(|velocity| !is_covered(*velocity)).last()?;
+    Some((gap_start, gap_end))
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use super::*;
+
+    fn lint_config(config: &str) -> LintReport {
+        let mut config_file = NamedTempFile::new().unwrap();
+        config_file.write_all(config.as_bytes()).unwrap();
+        lint_config_file(&config_file.path()).unwrap()
+    }
+
+    #[test]
+    fn test_lint_clean_config() {
+        let lint_report = lint_config("note-.* ch1 => synth\nfilter synth ch1\n");
+
+        assert!(lint_report.findings.is_empty());
+        assert_eq!(lint_report.exit_code(true), EXIT_OK);
+    }
+
+    #[test]
+    fn test_lint_warnings() {
+        let lint_report = lint_config("note-.* => synth\nnote-.* => synth\nnote_on => pads\ncap drums 4\n");
+
+        assert_eq!(lint_report.findings, vec![
+            Finding { severity: Severity::Warning, message: "Rule 3 never matches because no event name matches 'note_on'".into() },
+            Finding { severity: Severity::Warning, message: "Rule 2 duplicates rule 1, so matching events are sent twice".into() },
+            Finding { severity: Severity::Warning, message: "Cap refers to port 'drums', which no rule sends to".into() },
+        ]);
+        assert_eq!(lint_report.exit_code(false), EXIT_OK);
+        assert_eq!(lint_report.exit_code(true), EXIT_WARNINGS);
+    }
+
+    #[test]
+    fn test_lint_velocity_partition() {
+        let lint_report = lint_config("note-on vel>=100 => loud\nnote-on velcomp>=100 => soft\n");
+
+        assert!(lint_report.findings.is_empty());
+    }
+
+    #[test]
+    fn test_lint_velocity_partition_with_gap() {
+        let lint_report = lint_config("note-on vel>=100 => loud\nnote-on velcomp>=80 => soft\nnote-off => soft\n");
+
+        assert_eq!(lint_report.findings, vec![Finding {
+            severity: Severity::Warning,
+            message: "Velocities 80-99 match none of the complementary rules 1, 2 with 'note-on'".into(),
+        }]);
+    }
+
+    #[test]
+    fn test_lint_velocity_complement_without_partner() {
+        let lint_report = lint_config("note-on ch1 velcomp<40 => loud\nnote-on ch2 vel<40 => soft\n");
+
+        assert_eq!(lint_report.findings, vec![Finding {
+            severity: Severity::Warning,
+            message: "Velocities 0-39 match none of the complementary rules 1 with 'note-on ch1'".into(),
+        }]);
+    }
+
+    #[test]
+    fn test_lint_errors() {
+        let lint_report = lint_config("note-.* ch300 => synth\nnote-.* => synth\nnote-.* => synth\n");
+
+        assert_eq!(lint_report.count(Severity::Error), 1);
+        assert_eq!(lint_report.count(Severity::Warning), 1);
+        assert_eq!(lint_report.exit_code(false), EXIT_ERRORS);
+    }
+
+    #[test]
+    fn test_lint_report_to_json() {
+        let lint_report = LintReport {
+            findings: vec![Finding { severity: Severity::Error, message: "Parsing 'a\"b' failed".into() }],
+        };
+
+        assert_eq!(
+            lint_report.to_json(),
+            "{\"errors\":1,\"warnings\":0,\"findings\":[{\"severity\":\"error\",\"message\":\"Parsing 'a\\\"b' failed\"}]}\n",
+        );
+    }
+}
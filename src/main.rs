@@ -4,19 +4,197 @@ mod routing;
 mod jack_router;
 mod error_handler;
 mod utils;
+mod watchdog;
+mod interactive;
+mod init;
+mod rule_test;
+mod dot;
+mod note_tracker;
+mod clock;
+mod stats;
+mod tempo;
+mod version;
+mod profile;
+mod bank;
+mod note_cap;
+mod sustain;
+mod slew;
+mod coalesce;
+mod lint;
+mod time_window;
+mod transform;
+mod scale;
+mod stuck_notes;
+mod voice;
+mod clock_generator;
+mod retry_queue;
+mod text_sink;
+mod note_pairing;
+mod preset;
 
-use crate::jack_router::JackRouter;
-use crate::parser::{load_rules_from_file, RuleConfigError};
-use crate::routing::RoutingTable;
+use crate::clock_generator::ClockGenerator;
+use crate::init::write_example_config;
+use crate::interactive::{run_command_loop, Command, HELP_TEXT};
+use crate::jack_router::{JackRouter, JackRouterOptions, PanicSwitch, DEFAULT_CLOCK_PORT, DEFAULT_INPUT_NAME};
+use crate::parser::{format_config_file, load_routing_table_with_options, LoadOptions, RuleConfigError};
+use crate::routing::SoloSwitch;
+use crate::stats::{rule_stats_csv, RuleStats};
+use crate::text_sink::TEXT_SINK_PORT;
 use std::error::Error;
-use std::io;
-use clap::Parser;
+use std::{fs, io};
+use std::sync::{mpsc, Arc};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use clap::{Parser, Subcommand, ValueEnum};
 use log::{debug, info};
-use crate::error_handler::{handle_config_error, handle_io_error, handle_jack_router_error};
+use crate::error_handler::{handle_config_error, handle_io_error, handle_jack_router_error, handle_rule_test_error, ErrorStyle};
+use crate::rule_test::test_rule;
+use crate::dot::export_dot;
+use crate::lint::lint_config_file;
+use crate::version::version_info;
+use crate::watchdog::{IdleWatchdog, InputConnectionCheck};
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    config_file: std::path::PathBuf,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
+    #[arg(required_unless_present_any = ["test_rule", "version"])]
+    config_file: Option<std::path::PathBuf>,
+
+    /// Print version, supported config versions, and enabled features
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// Write an example configuration to CONFIG_FILE and exit
+    #[arg(long)]
+    init: bool,
+
+    /// Rewrite CONFIG_FILE in canonical form with aligned rules and exit
+    #[arg(long)]
+    format: bool,
+
+    /// Reject output ports which are not declared in a `ports:` header of CONFIG_FILE
+    #[arg(long)]
+    strict_ports: bool,
+
+    /// Exit if the input port has no connections shortly after the start
+    #[arg(long)]
+    require_input: bool,
+
+    /// Exit if no MIDI events arrive for the given number of seconds
+    #[arg(long, value_name = "SECONDS")]
+    idle_timeout: Option<u64>,
+
+    /// Stop matching rules against an event after the given time
+    #[arg(long, value_name = "MICROSECONDS")]
+    match_budget: Option<u64>,
+
+    /// Name of the MIDI input port
+    #[arg(long, value_name = "NAME", default_value = DEFAULT_INPUT_NAME)]
+    input_name: String,
+
+    /// Additional name of the MIDI input port, can be given twice
+    #[arg(long, value_name = "ALIAS")]
+    input_alias: Vec<String>,
+
+    /// Disable colored error messages
+    #[arg(long)]
+    no_color: bool,
+
+    /// Check a single rule against the event given by --event and exit
+    #[arg(long, value_name = "RULE", requires = "event")]
+    test_rule: Option<String>,
+
+    /// Event to check with --test-rule, e.g. 'note-on ch1 note60 vel100'
+    #[arg(long, value_name = "EVENT", requires = "test_rule")]
+    event: Option<String>,
+
+    /// Write the routing of CONFIG_FILE as Graphviz diagram to the given file and exit
+    #[arg(long, value_name = "DOT_FILE")]
+    export_dot: Option<std::path::PathBuf>,
+
+    /// Forward all events to the given port unless a matching rule drops or redirects them
+    #[arg(long, value_name = "NAME")]
+    passthrough_to: Option<String>,
+
+    /// Forward events unchanged to the ports of all actions instead of transforming them
+    #[arg(long)]
+    no_transforms: bool,
+
+    /// Forward events of matching rules whose output ports are all unavailable to the given port
+    #[arg(long, value_name = "NAME")]
+    orphan_port: Option<String>,
+
+    /// Send the raw bytes of events which cannot be decoded to an output port with the given name
+    #[arg(long, value_name = "NAME")]
+    error_port: Option<String>,
+
+    /// Drop control changes repeating the last value sent to the same port
+    #[arg(long)]
+    coalesce_cc: bool,
+
+    /// Send a note-off before a note-on of a note which is still playing on the same output port
+    #[arg(long)]
+    fix_stuck_notes: bool,
+
+    /// Send events which do not fit into the buffer of their output port in the next cycle
+    #[arg(long)]
+    retry_next_cycle: bool,
+
+    /// Send All-Notes-Off and All-Sound-Off to all output ports after an xrun
+    #[arg(long)]
+    panic_on_xrun: bool,
+
+    /// Send timing clocks at the given tempo to the clock port, started and stopped with 'start' and 'stop'
+    #[arg(long, value_name = "BPM", value_parser = clap::value_parser!(u16).range(1..=999))]
+    emit_clock: Option<u16>,
+
+    /// Name of the output port receiving the clock of --emit-clock
+    #[arg(long, value_name = "NAME", default_value = DEFAULT_CLOCK_PORT, requires = "emit_clock")]
+    clock_port: String,
+
+    /// Write the number of events matched by each rule as CSV to the given file when stopping
+    #[arg(long, value_name = "CSV_FILE")]
+    stats_out: Option<std::path::PathBuf>,
+
+    /// Print the duration of the process callback every few seconds
+    #[arg(long)]
+    profile: bool,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Check CONFIG_FILE for errors and likely mistakes, and exit with 2 on errors
+    Lint {
+        config_file: std::path::PathBuf,
+
+        /// Output format of the findings
+        #[arg(long, value_enum, default_value_t = LintFormat::Text)]
+        format: LintFormat,
+
+        /// Exit with 1 if there are warnings but no errors
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LintFormat {
+    Text,
+    Json,
+}
+
+const PROFILE_INTERVAL: Duration = Duration::from_secs(5);
+/// Time to wait for a connection to the input port before warning about it
+const INPUT_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+enum StopReason {
+    KeyPressed,
+    IdleTimeout,
+    NoInput,
 }
 
 
@@ -25,15 +203,59 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     info!("Starting Jack MIDI router");
     let args = Cli::parse();
+    let error_style = ErrorStyle::detect(args.no_color);
+
+    if args.version {
+        println!("{}", version_info());
+        return Ok(());
+    }
+
+    if let Some(CliCommand::Lint { config_file, format, deny_warnings }) = &args.command {
+        let lint_report = match lint_config_file(config_file) {
+            Ok(lint_report) => lint_report,
+            Err(err) => {
+                eprintln!("{}", handle_io_error(config_file, &err, error_style));
+                std::process::exit(3);
+            },
+        };
+        match format {
+            LintFormat::Text => print!("{}", lint_report.to_text()),
+            LintFormat::Json => print!("{}", lint_report.to_json()),
+        }
+        std::process::exit(lint_report.exit_code(*deny_warnings));
+    }
+
+    if let (Some(rule), Some(event)) = (&args.test_rule, &args.event) {
+        match test_rule(rule, event) {
+            Ok(outputs) if outputs.is_empty() => println!("No match, the event is dropped"),
+            Ok(outputs) => outputs.iter().for_each(|output| println!("{}", output)),
+            Err(err) => {
+                eprintln!("{}", handle_rule_test_error(&err, error_style));
+                std::process::exit(2);
+            },
+        }
+        return Ok(());
+    }
+
+    let config_file = args.config_file.expect("CONFIG_FILE is required by the argument parser");
+    if args.init {
+        if let Err(err) = write_example_config(&config_file) {
+            eprintln!("{}", handle_io_error(&config_file, &err, error_style));
+            std::process::exit(3);
+        }
+        println!("Example configuration written to '{}'", config_file.display());
+        return Ok(());
+    }
 
-    let rules = match load_rules_from_file(&args.config_file) {
-        Ok(rules) => rules,
+    let load_options = LoadOptions { strict_ports: args.strict_ports };
+    let mut routing_table = match load_routing_table_with_options(&config_file, &load_options) {
+        Ok(routing_table) => routing_table,
         Err(err) => {
             if let Some(io_error) = err.downcast_ref::<io::Error>() {
-                eprintln!("{}", handle_io_error(&args.config_file, io_error));
+                eprintln!("{}", handle_io_error(&config_file, io_error, error_style));
                 std::process::exit(3);
             } else if let Some(rule_config_error) = err.downcast_ref::<RuleConfigError>() {
-                eprintln!("{}", handle_config_error(&args.config_file, rule_config_error));
+                eprintln!("{}", handle_config_error(&config_file, rule_config_error, error_style));
                 std::process::exit(2);
             } else {
                 eprintln!("An unknown error occurred: {}", err);
@@ -42,25 +264,149 @@ fn main() -> Result<(), Box<dyn Error>> {
         },
     };
 
-    debug!("Rules: {:?}", rules);
+    if args.format {
+        if let Err(err) = format_config_file(&config_file) {
+            eprintln!("{}", handle_io_error(&config_file, &err, error_style));
+            std::process::exit(3);
+        }
+        println!("Config file '{}' formatted", config_file.display());
+        return Ok(());
+    }
+
+    if let Some(dot_file) = args.export_dot {
+        if let Err(err) = fs::write(&dot_file, export_dot(&routing_table)) {
+            eprintln!("{}", handle_io_error(&dot_file, &err, error_style));
+            std::process::exit(3);
+        }
+        println!("Routing diagram written to '{}'", dot_file.display());
+        return Ok(());
+    }
 
-    let routing_table = RoutingTable { rules, };
-    let router = match JackRouter::new(routing_table, "midi_router") {
+    routing_table.match_budget = args.match_budget.map(Duration::from_micros);
+    routing_table.passthrough_port = args.passthrough_to;
+    routing_table.orphan_port = args.orphan_port;
+    routing_table.bypass_transforms = args.no_transforms;
+    debug!("Rules: {:?}", routing_table.rules);
+    debug!("Output filters: {:?}", routing_table.output_filters);
+
+    let solo_switch = routing_table.solo_switch();
+    let rule_stats = Arc::new(RuleStats::new(routing_table.rules.len()));
+    routing_table.rule_stats = Arc::clone(&rule_stats);
+    let rule_summaries: Vec<(Option<String>, String)> = routing_table.rules.iter()
+        .map(|rule| (rule.description.clone(), rule.describe_condition()))
+        .collect();
+    let rule_labels: Vec<String> = routing_table.rules.iter()
+        .enumerate()
+        .map(|(rule_no, rule)| rule.label(rule_no + 1))
+        .collect();
+    // Keep stdout free for the events printed by the port `-`
+    let print_status: fn(&str) = if routing_table.get_all_output_ports().contains(TEXT_SINK_PORT) {
+        |message| eprintln!("{}", message)
+    } else {
+        |message| println!("{}", message)
+    };
+    let router_options = JackRouterOptions {
+        input_name: args.input_name,
+        input_aliases: args.input_alias,
+        profile: args.profile,
+        error_port: args.error_port,
+        coalesce_cc: args.coalesce_cc,
+        fix_stuck_notes: args.fix_stuck_notes,
+        panic_on_xrun: args.panic_on_xrun,
+        retry_next_cycle: args.retry_next_cycle,
+        clock_port: args.emit_clock.map(|_| args.clock_port),
+        ..Default::default()
+    };
+    let router = match JackRouter::new(routing_table, &router_options) {
         Ok(router) => router,
         Err(err) => {
-            eprintln!("{}", handle_jack_router_error(&err));
+            eprintln!("{}", handle_jack_router_error(&err, error_style));
             std::process::exit(4);
         }
     };
 
-    wait_for_keypress();
+    let (stop_sender, stop_receiver) = mpsc::channel();
+    if let Some(idle_timeout) = args.idle_timeout {
+        let idle_stop_sender = stop_sender.clone();
+        IdleWatchdog::new(router.last_event_ms(), Duration::from_secs(idle_timeout))
+            .spawn(move || idle_stop_sender.send(StopReason::IdleTimeout).unwrap_or_default());
+    }
+    let input_check = router.input_port().map(|input_port| {
+        let require_input = args.require_input;
+        let no_input_stop_sender = stop_sender.clone();
+        InputConnectionCheck::new(move || input_port.connected_count().unwrap_or(0), INPUT_GRACE_PERIOD)
+            .spawn(move || if require_input {
+                no_input_stop_sender.send(StopReason::NoInput).unwrap_or_default()
+            })
+    });
+    if let Some(profile) = router.profile() {
+        profile.spawn_reporter(PROFILE_INTERVAL, move |summary| print_status(&summary.to_string()));
+    }
+    let clock_generator = args.emit_clock.zip(router.clock_sender())
+        .map(|(bpm, clock_sender)| ClockGenerator::new(bpm.into(), clock_sender));
+    if let Some(clock_generator) = &clock_generator {
+        clock_generator.spawn_ticker();
+    }
+    let panic_switch = router.panic_switch();
+    thread::spawn(move || {
+        run_interactive_commands(stop_sender, panic_switch, solo_switch, clock_generator, rule_labels, print_status)
+    });
+
+    let stop_reason = stop_receiver.recv();
+    match stop_reason {
+        Ok(StopReason::IdleTimeout) => info!("Idle timeout reached"),
+        Ok(StopReason::NoInput) => eprintln!("The MIDI input port has no connections, exiting because of --require-input"),
+        Ok(StopReason::KeyPressed) | Err(_) => info!("Stop requested by user"),
+    }
+    if let Some(input_check) = input_check {
+        input_check.cancel();
+    }
+    let port_stats = router.port_stats();
     router.stop()?;
 
+    let unused_ports = port_stats.unused_ports();
+    if !unused_ports.is_empty() {
+        print_status(&format!("No events were sent to the following output ports: {}", unused_ports.join(", ")));
+    }
+    if let Some(stats_file) = args.stats_out {
+        if let Err(err) = fs::write(&stats_file, rule_stats_csv(&rule_summaries, &rule_stats.snapshot())) {
+            eprintln!("{}", handle_io_error(&stats_file, &err, error_style));
+            std::process::exit(3);
+        }
+        print_status(&format!("Rule statistics written to '{}'", stats_file.display()));
+    }
+    if let Ok(StopReason::NoInput) = stop_reason {
+        std::process::exit(5);
+    }
+
     Ok(())
 }
 
-fn wait_for_keypress() {
-    println!("Press any key to quit");
-    let mut user_input = String::new();
-    io::stdin().read_line(&mut user_input).ok();
+fn run_interactive_commands(stop_sender: Sender<StopReason>,
+                            panic_switch: PanicSwitch,
+                            solo_switch: SoloSwitch,
+                            clock_generator: Option<ClockGenerator>,
+                            rule_labels: Vec<String>,
+                            print_status: fn(&str)) {
+    print_status(HELP_TEXT);
+    run_command_loop(io::stdin().lock(), |command| match command {
+        Command::Quit => stop_sender.send(StopReason::KeyPressed).unwrap_or_default(),
+        Command::Panic => panic_switch.trigger(),
+        Command::Solo(Some(index)) if *index >= rule_labels.len() => {
+            print_status(&format!("Rule {} does not exist, there are {} rules", index + 1, rule_labels.len()));
+        },
+        Command::Solo(index) => {
+            solo_switch.solo(*index);
+            match index {
+                Some(index) => print_status(&format!("Soloing {}", rule_labels[*index])),
+                None => print_status("Applying all rules"),
+            }
+        },
+        Command::ClockStart | Command::ClockStop if clock_generator.is_none() => {
+            print_status("No clock is sent, use --emit-clock to send one");
+        },
+        Command::ClockStart => clock_generator.iter().for_each(ClockGenerator::start),
+        Command::ClockStop => clock_generator.iter().for_each(ClockGenerator::stop),
+        Command::Unknown(input) => print_status(&format!("Unknown command '{}'. {}", input, HELP_TEXT)),
+    });
 }
@@ -2,8 +2,10 @@
  * Decode MIDI events
  */
 
+use std::borrow::Cow;
 use std::error::Error;
 use jack::RawMidi;
+use log::warn;
 use strum_macros::IntoStaticStr;
 
 /*
@@ -11,8 +13,10 @@ use strum_macros::IntoStaticStr;
  */
 
 const MIN_PITCHWHEEL: i16 = -8192;
+/// Data bytes carry 7 bits, the most significant bit is reserved for status bytes
+const DATA_BYTE_MASK: u8 = 0x7f;
 
-#[derive(Debug, IntoStaticStr, PartialEq)]
+#[derive(Clone, Debug, IntoStaticStr, PartialEq)]
 pub enum MidiEvent {
     #[strum(serialize = "note-off")]
     NoteOff {
@@ -54,7 +58,9 @@ pub enum MidiEvent {
         value: i16,
     }, // Pitch bend event
     #[strum(serialize = "system-exclusive")]
-    SystemExclusive {},
+    SystemExclusive {
+        data: Vec<u8>,
+    }, // Complete message including the leading 0xF0 and the trailing 0xF7
     #[strum(serialize = "midi-time-code-qtr-frame")]
     MidiTimeCodeQtrFrame {},
     #[strum(serialize = "song-position-pointer")]
@@ -83,10 +89,199 @@ pub enum MidiEvent {
     Undefined {},
 }
 
+/// Names of all MIDI events as used in event name patterns
+pub const EVENT_NAMES: [&str; 20] = [
+    "note-off", "note-on", "polyphonic-aftertouch", "control-change", "program-change", "channel-aftertouch",
+    "pitch-bend-change", "system-exclusive", "midi-time-code-qtr-frame", "song-position-pointer", "song-select",
+    "tone-request", "end-of-sys-ex", "timing-clock", "start", "continue", "stop", "active-sensing", "system-reset",
+    "undefined",
+];
+
+/// Set of MIDI event types, where each bit stands for the event name at the same position in
+/// `EVENT_NAMES`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EventTypeMask(u32);
+
+impl EventTypeMask {
+    pub const ALL: EventTypeMask = EventTypeMask((1 << EVENT_NAMES.len()) - 1);
+
+    /// Types of all events whose names satisfy `predicate`, e.g. the names matching a pattern
+    pub fn matching<F: Fn(&str) -> bool>(predicate: F) -> Self {
+        let bits = EVENT_NAMES.iter()
+            .enumerate()
+            .filter(|(_, event_name)| predicate(event_name))
+            .fold(0, |bits, (index, _)| bits | 1 << index);
+        EventTypeMask(bits)
+    }
+
+    /// Type of the given event only
+    pub fn of_event(midi_event: &MidiEvent) -> Self {
+        let event_name: &'static str = midi_event.into();
+        EventTypeMask::matching(|name| name == event_name)
+    }
+
+    pub fn union(self, other: EventTypeMask) -> Self {
+        EventTypeMask(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: EventTypeMask) -> Self {
+        EventTypeMask(self.0 & other.0)
+    }
+
+    pub fn intersects(self, other: EventTypeMask) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+const UNIVERSAL_NON_REAL_TIME: u8 = 0x7e;
+const UNIVERSAL_REAL_TIME: u8 = 0x7f;
+
+/// Identification of a Universal System Exclusive message, e.g. 0x7F/0x06/0x01 for MMC stop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UniversalSysExId {
+    /// 0x7E (non-real time) or 0x7F (real time)
+    pub universal_id: u8,
+    pub sub_id1: u8,
+    pub sub_id2: Option<u8>,
+}
+
+impl UniversalSysExId {
+    /// Checks whether `other` is this ID or a more specific one, i.e., `sub_id2` matches any value if unset
+    pub fn includes(&self, other: &UniversalSysExId) -> bool {
+        self.universal_id == other.universal_id
+            && self.sub_id1 == other.sub_id1
+            && self.sub_id2.map(|sub_id2| other.sub_id2 == Some(sub_id2)).unwrap_or(true)
+    }
+}
+
+impl MidiEvent {
+    /// Universal SysEx sub-IDs of a message of the form `F0 <7E|7F> <DEVICE> <SUB_ID1> [<SUB_ID2>] ...`
+    pub fn universal_sysex_id(&self) -> Option<UniversalSysExId> {
+        let MidiEvent::SystemExclusive { data } = self else {
+            return None;
+        };
+        let data_bytes: Vec<u8> = data.iter()
+            .skip(1)
+            .take_while(|byte| **byte != 0xf7)
+            .copied()
+            .collect();
+        match data_bytes.as_slice() {
+            [universal_id @ (UNIVERSAL_NON_REAL_TIME | UNIVERSAL_REAL_TIME), _device_id, sub_id1, rest @ ..] => {
+                Some(UniversalSysExId {
+                    universal_id: *universal_id,
+                    sub_id1: *sub_id1,
+                    sub_id2: rest.first().copied(),
+                })
+            },
+            _ => None,
+        }
+    }
+
+    /// Channel of channel messages, or `None` for system messages
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiEvent::NoteOff { channel, .. } |
+            MidiEvent::NoteOn { channel, .. } |
+            MidiEvent::PolyphonicAftertouch { channel, .. } |
+            MidiEvent::ControlChange { channel, .. } |
+            MidiEvent::ProgramChange { channel, .. } |
+            MidiEvent::ChannelAftertouch { channel, .. } |
+            MidiEvent::PitchBendChange { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
+    /// Least and most significant 7 bits of the value of pitch bends, which are sent as separate
+    /// data bytes, e.g. by controllers with a coarse and a fine bend control
+    pub fn pitch_bend_bytes(&self) -> Option<(u8, u8)> {
+        match *self {
+            MidiEvent::PitchBendChange { value, .. } => {
+                let unsigned_value = (value - MIN_PITCHWHEEL) as u16;
+                Some(((unsigned_value & 0x7f) as u8, ((unsigned_value >> 7) & 0x7f) as u8))
+            },
+            _ => None,
+        }
+    }
+
+    /// Checks whether the event releases a note, which note-on events with velocity 0 also do by
+    /// convention if `zero_velocity_is_off` is set
+    pub fn is_release(&self, zero_velocity_is_off: bool) -> bool {
+        match *self {
+            MidiEvent::NoteOff { .. } => true,
+            MidiEvent::NoteOn { velocity: 0, .. } => zero_velocity_is_off,
+            _ => false,
+        }
+    }
+}
+
+/// Group of related MIDI event types
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventCategory {
+    /// Events referring to a single note: note-on, note-off and polyphonic aftertouch
+    Note,
+}
+
+impl EventCategory {
+    pub fn event_types(&self) -> EventTypeMask {
+        match self {
+            EventCategory::Note => {
+                EventTypeMask::matching(|name| ["note-on", "note-off", "polyphonic-aftertouch"].contains(&name))
+            },
+        }
+    }
+
+    pub fn contains(&self, midi_event: &MidiEvent) -> bool {
+        match self {
+            EventCategory::Note => matches!(
+                midi_event,
+                MidiEvent::NoteOn { .. } | MidiEvent::NoteOff { .. } | MidiEvent::PolyphonicAftertouch { .. }
+            ),
+        }
+    }
+}
+
+/// Number of bytes of a MIDI message with the given status byte, or `None` if the length is variable
+/// (SysEx)
+pub fn expected_len(status: u8) -> Option<usize> {
+    match status >> 4 {
+        0xc | 0xd => Some(2),
+        0x8..=0xe => Some(3),
+        0xf => match status & 0x0f {
+            0x0 => None,
+            0x1 | 0x3 => Some(2),
+            0x2 => Some(3),
+            _ => Some(1),
+        },
+        _ => Some(1),  // data byte without status, e.g. running status
+    }
+}
+
+/// Cuts off excess bytes after a complete MIDI message
+pub fn trim_to_expected_len(bytes: &[u8]) -> &[u8] {
+    let len = bytes.first()
+        .and_then(|status| expected_len(*status))
+        .unwrap_or(bytes.len());
+    &bytes[..len.min(bytes.len())]
+}
+
 pub fn decode_raw_midi(raw_midi: RawMidi) -> Result<MidiEvent, Box<dyn Error>> {
-    let event_type = raw_midi.bytes[0] >> 4;
-    let channel = (raw_midi.bytes[0] & 0x0f) + 1;  // channel number is 1-based in standard
+    let Some(&status) = raw_midi.bytes.first() else {
+        Err("Empty MIDI event")?
+    };
+    if let Some(len) = expected_len(status) {
+        if raw_midi.bytes.len() < len {
+            Err(format!(
+                "MIDI event with status {:#04x} too short: expected {} bytes, got {}",
+                status,
+                len,
+                raw_midi.bytes.len(),
+            ))?
+        }
+    }
+    let event_type = (status >> 4) & 0x0f;
+    let channel = (status & 0x0f) + 1;  // channel number is 1-based in standard
     let event = match event_type {
+        0x0..=0x7 => Err(format!("MIDI event starts with data byte {:#04x} instead of a status byte", status))?,
         0x8 => MidiEvent::NoteOff {
             channel,
             note: raw_midi.bytes[1],
@@ -116,13 +311,17 @@ pub fn decode_raw_midi(raw_midi: RawMidi) -> Result<MidiEvent, Box<dyn Error>> {
             pressure: raw_midi.bytes[1],
         },
         0xe => {
+            let lsb = (raw_midi.bytes[1] & DATA_BYTE_MASK) as i16;
+            let msb = (raw_midi.bytes[2] & DATA_BYTE_MASK) as i16;
             MidiEvent::PitchBendChange {
                 channel,
-                value: ((raw_midi.bytes[2] as i16) << 7) + (raw_midi.bytes[1] as i16) + MIN_PITCHWHEEL,
+                value: (msb << 7) + lsb + MIN_PITCHWHEEL,
             }
         },
-        0xf => match channel {
-            0x0 => MidiEvent::SystemExclusive {},
+        0xf => match status & 0x0f {
+            0x0 => MidiEvent::SystemExclusive {
+                data: raw_midi.bytes.to_vec(),
+            },
             0x1 => MidiEvent::MidiTimeCodeQtrFrame {},
             0x2 => MidiEvent::SongPositionPointer {},
             0x3 => MidiEvent::SongSelect {
@@ -136,15 +335,182 @@ pub fn decode_raw_midi(raw_midi: RawMidi) -> Result<MidiEvent, Box<dyn Error>> {
             0xc => MidiEvent::Stop {},
             0xe => MidiEvent::ActiveSensing {},
             0xf => MidiEvent::SystemReset {},
-            _ => MidiEvent::Undefined {},
+            // reserved by the MIDI standard, devices should ignore them
+            0x4 | 0x5 | 0x9 | 0xd => MidiEvent::Undefined {},
+            0x10..=u8::MAX => unreachable!("status byte {:#04x} masked to more than 4 bits", status),
         },
-        _ => Err(format!("Unknown MIDI event ID: {}", event_type))?
+        0x10..=u8::MAX => unreachable!("status byte {:#04x} shifted to more than 4 bits", status),
     };
     Ok(event)
 }
 
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+const SYSEX_BUFFER_CAPACITY: usize = 1024;
+
+/// Reassembles SysEx messages which are split into several fragments, e.g. across process cycles
+#[derive(Debug)]
+pub struct SysExAssembler {
+    buffer: Vec<u8>,
+    is_active: bool,
+    /// Whether the current SysEx message exceeded the buffer and its remaining fragments are dropped
+    is_overflowing: bool,
+}
+
+impl SysExAssembler {
+    pub fn new() -> Self {
+        SysExAssembler {
+            buffer: Vec::with_capacity(SYSEX_BUFFER_CAPACITY),
+            is_active: false,
+            is_overflowing: false,
+        }
+    }
+
+    /// Returns complete messages, either as is or reassembled from previous fragments, or `None` if
+    /// the SysEx message is not complete yet. Real-time messages may appear in between fragments.
+    /// Messages longer than `SYSEX_BUFFER_CAPACITY` bytes are dropped if they are fragmented.
+    pub fn assemble<'a>(&'a mut self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        let Some(&first_byte) = bytes.first() else {
+            return Some(bytes);
+        };
+        let is_continuation = first_byte < 0x80 || first_byte == SYSEX_END;
+        if first_byte == SYSEX_START {
+            if self.is_active && !self.is_overflowing {
+                warn!("Dropping incomplete SysEx message of {} bytes", self.buffer.len());
+            }
+            self.buffer.clear();
+            self.is_active = true;
+            self.is_overflowing = false;
+        } else if self.is_active && is_continuation {
+            // fragment of the current SysEx message
+        } else {
+            if self.is_active && first_byte < 0xf8 {
+                if !self.is_overflowing {
+                    warn!("Dropping incomplete SysEx message of {} bytes", self.buffer.len());
+                }
+                self.buffer.clear();
+                self.is_active = false;
+                self.is_overflowing = false;
+            }
+            return Some(bytes);
+        }
+
+        let end = bytes.iter().position(|byte| *byte == SYSEX_END);
+        let fragment = match end {
+            Some(end) => &bytes[..=end],
+            None => bytes,
+        };
+        if self.is_overflowing {
+            self.is_active = end.is_none();
+            self.is_overflowing = end.is_none();
+            return None;
+        }
+        match end {
+            Some(_) if self.buffer.is_empty() => {
+                self.is_active = false;
+                Some(fragment)
+            },
+            _ if self.buffer.len() + fragment.len() > SYSEX_BUFFER_CAPACITY => {
+                warn!("Dropping SysEx message longer than {} bytes", SYSEX_BUFFER_CAPACITY);
+                self.buffer.clear();
+                self.is_active = end.is_none();
+                self.is_overflowing = end.is_none();
+                None
+            },
+            Some(_) => {
+                self.buffer.extend_from_slice(fragment);
+                self.is_active = false;
+                Some(&self.buffer)
+            },
+            None => {
+                self.buffer.extend_from_slice(fragment);
+                None
+            },
+        }
+    }
+}
+
+/// Restores the status byte of channel messages sent with running status, i.e. only with the data
+/// bytes because the status byte is the same as the one of the previous message
+#[derive(Debug, Default)]
+pub struct RunningStatusDecoder {
+    status: Option<u8>,
+}
+
+impl RunningStatusDecoder {
+    pub fn new() -> Self {
+        RunningStatusDecoder::default()
+    }
+
+    /// Returns the complete message and whether its status byte was restored from the running
+    /// status. Messages with a status byte are returned as is.
+    pub fn expand<'a>(&mut self, bytes: &'a [u8]) -> (Cow<'a, [u8]>, bool) {
+        match bytes.first() {
+            Some(&status @ 0x80..=0xef) => self.status = Some(status),
+            // system common messages cancel the running status, real-time messages keep it
+            Some(0xf0..=0xf7) => self.status = None,
+            Some(0x00..=0x7f) if bytes.iter().all(|byte| *byte <= DATA_BYTE_MASK) => {
+                if let Some(status) = self.status {
+                    let mut expanded = Vec::with_capacity(bytes.len() + 1);
+                    expanded.push(status);
+                    expanded.extend_from_slice(bytes);
+                    return (Cow::Owned(expanded), true);
+                }
+            },
+            _ => {},
+        }
+        (Cow::Borrowed(bytes), false)
+    }
+}
+
+const ALL_SOUND_OFF: u8 = 120;
+const ALL_NOTES_OFF: u8 = 123;
+
+/// Events silencing all channels: All-Notes-Off and All-Sound-Off on channels 1 to 16
+pub fn panic_events() -> Vec<MidiEvent> {
+    (1..=16)
+        .flat_map(|channel| [ALL_NOTES_OFF, ALL_SOUND_OFF].map(|control_no| MidiEvent::ControlChange {
+            channel,
+            control_no,
+            value: 0,
+        }))
+        .collect()
+}
+
+pub fn encode_midi_event(event: &MidiEvent) -> Result<Vec<u8>, Box<dyn Error>> {
+    let status = |event_type: u8, channel: u8| (event_type << 4) | (channel.wrapping_sub(1) & 0x0f);
+    let bytes = match *event {
+        MidiEvent::NoteOff { channel, note, velocity } => vec![status(0x8, channel), note, velocity],
+        MidiEvent::NoteOn { channel, note, velocity } => vec![status(0x9, channel), note, velocity],
+        MidiEvent::PolyphonicAftertouch { channel, note, pressure } => vec![status(0xa, channel), note, pressure],
+        MidiEvent::ControlChange { channel, control_no, value } => vec![status(0xb, channel), control_no, value],
+        MidiEvent::ProgramChange { channel, program } => vec![status(0xc, channel), program],
+        MidiEvent::ChannelAftertouch { channel, pressure } => vec![status(0xd, channel), pressure],
+        MidiEvent::PitchBendChange { channel, .. } => {
+            let (lsb, msb) = event.pitch_bend_bytes().unwrap_or_default();
+            vec![status(0xe, channel), lsb, msb]
+        },
+        MidiEvent::SystemExclusive { ref data } => data.clone(),
+        MidiEvent::SongSelect { song_num } => vec![0xf3, song_num],
+        MidiEvent::TuneRequest {} => vec![0xf6],
+        MidiEvent::EndOfSysEx {} => vec![0xf7],
+        MidiEvent::TimingClock {} => vec![0xf8],
+        MidiEvent::Start {} => vec![0xfa],
+        MidiEvent::Continue {} => vec![0xfb],
+        MidiEvent::Stop {} => vec![0xfc],
+        MidiEvent::ActiveSensing {} => vec![0xfe],
+        MidiEvent::SystemReset {} => vec![0xff],
+        _ => {
+            let event_name: &'static str = event.into();
+            Err(format!("Cannot encode MIDI event without payload: {}", event_name))?
+        },
+    };
+    Ok(bytes)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::ptr;
     use super::*;
 
     #[test]
@@ -256,6 +622,33 @@ mod tests {
         assert_eq!(expected, result.unwrap());
     }
 
+    #[test]
+    fn test_decode_raw_midi_pitch_bend_change_high_bits_set() {
+        let bytes = vec![230, 0xff, 0xff];
+        let raw_midi = RawMidi { time: 0, bytes: &bytes};
+
+        let result = decode_raw_midi(raw_midi);
+
+        let expected = MidiEvent::PitchBendChange {
+            channel: 7,
+            value: 8191,
+        };
+        assert!(result.is_ok());
+        assert_eq!(expected, result.unwrap());
+
+        let bytes = vec![230, 66 | 0x80, 28 | 0x80];
+        let raw_midi = RawMidi { time: 0, bytes: &bytes};
+
+        let result = decode_raw_midi(raw_midi);
+
+        let expected = MidiEvent::PitchBendChange {
+            channel: 7,
+            value: -4542,
+        };
+        assert!(result.is_ok());
+        assert_eq!(expected, result.unwrap());
+    }
+
     #[test]
     fn test_decode_raw_midi_pitch_bend_change_min() {
         let bytes = vec![230, 0, 0];
@@ -285,4 +678,341 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(expected, result.unwrap());
     }
-}
+
+    #[test]
+    fn test_encode_midi_event_program_change() {
+        let event = MidiEvent::ProgramChange {
+            channel: 16,
+            program: 5,
+        };
+
+        let result = encode_midi_event(&event);
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![207, 5]);
+    }
+
+    #[test]
+    fn test_encode_midi_event_pitch_bend_change() {
+        let event = MidiEvent::PitchBendChange {
+            channel: 7,
+            value: -4542,
+        };
+
+        let result = encode_midi_event(&event);
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![230, 66, 28]);
+    }
+
+    #[test]
+    fn test_pitch_bend_bytes() {
+        let bend = |value| MidiEvent::PitchBendChange { channel: 7, value };
+
+        assert_eq!(bend(7618).pitch_bend_bytes(), Some((66, 123)));
+        assert_eq!(bend(-4542).pitch_bend_bytes(), Some((66, 28)));
+        assert_eq!(bend(-8192).pitch_bend_bytes(), Some((0, 0)));
+        assert_eq!(bend(0).pitch_bend_bytes(), Some((0, 64)));
+        assert_eq!(bend(8191).pitch_bend_bytes(), Some((127, 127)));
+        assert_eq!(MidiEvent::ProgramChange { channel: 7, program: 5 }.pitch_bend_bytes(), None);
+    }
+
+    #[test]
+    fn test_encode_midi_event_round_trip() {
+        let events = vec![
+            MidiEvent::NoteOff { channel: 6, note: 123, velocity: 25 },
+            MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 },
+            MidiEvent::PolyphonicAftertouch { channel: 16, note: 127, pressure: 127 },
+            MidiEvent::ControlChange { channel: 15, control_no: 5, value: 5 },
+            MidiEvent::ChannelAftertouch { channel: 3, pressure: 64 },
+            MidiEvent::PitchBendChange { channel: 7, value: 8191 },
+            MidiEvent::PitchBendChange { channel: 7, value: -8192 },
+        ];
+        for event in events {
+            let bytes = encode_midi_event(&event).unwrap();
+            let decoded = decode_raw_midi(RawMidi { time: 0, bytes: &bytes }).unwrap();
+            assert_eq!(decoded, event);
+        }
+    }
+
+    #[test]
+    fn test_encode_midi_event_system_messages() {
+        assert_eq!(encode_midi_event(&MidiEvent::SongSelect { song_num: 3 }).unwrap(), vec![0xf3, 3]);
+        assert_eq!(encode_midi_event(&MidiEvent::TimingClock {}).unwrap(), vec![0xf8]);
+        assert_eq!(encode_midi_event(&MidiEvent::SystemReset {}).unwrap(), vec![0xff]);
+    }
+
+    #[test]
+    fn test_encode_midi_event_without_payload() {
+        let result = encode_midi_event(&MidiEvent::MidiTimeCodeQtrFrame {});
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_event_category_note() {
+        let category = EventCategory::Note;
+        assert!(category.contains(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert!(category.contains(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert!(category.contains(&MidiEvent::PolyphonicAftertouch { channel: 1, note: 60, pressure: 10 }));
+        assert!(!category.contains(&MidiEvent::ControlChange { channel: 1, control_no: 1, value: 0 }));
+        assert!(!category.contains(&MidiEvent::ChannelAftertouch { channel: 1, pressure: 10 }));
+        assert!(!category.contains(&MidiEvent::TimingClock {}));
+    }
+
+    #[test]
+    fn test_panic_events() {
+        let events = panic_events();
+
+        assert_eq!(events.len(), 32);
+        assert_eq!(events[0], MidiEvent::ControlChange { channel: 1, control_no: 123, value: 0 });
+        assert_eq!(events[1], MidiEvent::ControlChange { channel: 1, control_no: 120, value: 0 });
+        assert_eq!(events[31], MidiEvent::ControlChange { channel: 16, control_no: 120, value: 0 });
+    }
+
+    #[test]
+    fn test_midi_event_channel() {
+        assert_eq!(MidiEvent::NoteOn { channel: 3, note: 60, velocity: 100 }.channel(), Some(3));
+        assert_eq!(MidiEvent::PitchBendChange { channel: 16, value: 0 }.channel(), Some(16));
+        assert_eq!(MidiEvent::TimingClock {}.channel(), None);
+    }
+
+    #[test]
+    fn test_event_type_mask() {
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+        let note_on_types = EventTypeMask::of_event(&note_on);
+
+        assert_eq!(note_on_types, EventTypeMask(0b10));
+        assert_eq!(EventTypeMask::of_event(&MidiEvent::Undefined {}), EventTypeMask(1 << 19));
+        assert_eq!(EventTypeMask::matching(|name| name.starts_with("note-")), note_on_types.union(EventTypeMask::of_event(&note_off)));
+        assert!(EventCategory::Note.event_types().intersects(note_on_types));
+        assert!(!EventCategory::Note.event_types().intersects(EventTypeMask::of_event(&MidiEvent::Stop {})));
+        assert_eq!(EventTypeMask::ALL.intersection(note_on_types), note_on_types);
+    }
+
+    #[test]
+    fn test_midi_event_is_release() {
+        assert!(MidiEvent::NoteOff { channel: 1, note: 60, velocity: 64 }.is_release(false));
+        assert!(MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 }.is_release(true));
+        assert!(!MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 }.is_release(false));
+        assert!(!MidiEvent::NoteOn { channel: 1, note: 60, velocity: 1 }.is_release(true));
+        assert!(!MidiEvent::ControlChange { channel: 1, control_no: 64, value: 0 }.is_release(true));
+        assert_eq!(MidiEvent::SongSelect { song_num: 1 }.channel(), None);
+    }
+
+    #[test]
+    fn test_decode_raw_midi_system_exclusive() {
+        let bytes = vec![0xf0, 0x43, 0x10, 0x4c, 0xf7];
+        let raw_midi = RawMidi { time: 0, bytes: &bytes};
+
+        let result = decode_raw_midi(raw_midi);
+
+        let expected = MidiEvent::SystemExclusive {
+            data: bytes.clone(),
+        };
+        assert!(result.is_ok());
+        assert_eq!(expected, result.unwrap());
+    }
+
+    #[test]
+    fn test_decode_raw_midi_song_select() {
+        let bytes = vec![0xf3, 12];
+        let raw_midi = RawMidi { time: 0, bytes: &bytes};
+
+        let result = decode_raw_midi(raw_midi);
+
+        assert!(result.is_ok());
+        assert_eq!(MidiEvent::SongSelect { song_num: 12 }, result.unwrap());
+    }
+
+    #[test]
+    fn test_universal_sysex_id_mmc_stop() {
+        let bytes = vec![0xf0, 0x7f, 0x7f, 0x06, 0x01, 0xf7];
+        let event = decode_raw_midi(RawMidi { time: 0, bytes: &bytes }).unwrap();
+
+        let sysex_id = event.universal_sysex_id();
+
+        assert_eq!(sysex_id, Some(UniversalSysExId {
+            universal_id: 0x7f,
+            sub_id1: 0x06,
+            sub_id2: Some(0x01),
+        }));
+    }
+
+    #[test]
+    fn test_universal_sysex_id_non_universal() {
+        let manufacturer_specific = MidiEvent::SystemExclusive { data: vec![0xf0, 0x43, 0x10, 0x4c, 0xf7] };
+        assert_eq!(manufacturer_specific.universal_sysex_id(), None);
+
+        let too_short = MidiEvent::SystemExclusive { data: vec![0xf0, 0x7e, 0x00, 0xf7] };
+        assert_eq!(too_short.universal_sysex_id(), None);
+
+        assert_eq!(MidiEvent::TimingClock {}.universal_sysex_id(), None);
+    }
+
+    #[test]
+    fn test_universal_sysex_id_includes() {
+        let mmc = UniversalSysExId { universal_id: 0x7f, sub_id1: 0x06, sub_id2: None };
+        let mmc_stop = UniversalSysExId { universal_id: 0x7f, sub_id1: 0x06, sub_id2: Some(0x01) };
+        let mmc_play = UniversalSysExId { universal_id: 0x7f, sub_id1: 0x06, sub_id2: Some(0x02) };
+
+        assert!(mmc.includes(&mmc_stop));
+        assert!(mmc_stop.includes(&mmc_stop));
+        assert!(!mmc_stop.includes(&mmc_play));
+        assert!(!mmc_stop.includes(&mmc));
+    }
+
+    #[test]
+    fn test_decode_raw_midi_two_byte_messages() {
+        let bytes = vec![0xc3, 5];
+        let result = decode_raw_midi(RawMidi { time: 0, bytes: &bytes });
+        assert_eq!(result.unwrap(), MidiEvent::ProgramChange { channel: 4, program: 5 });
+
+        let bytes = vec![0xd0, 99];
+        let result = decode_raw_midi(RawMidi { time: 0, bytes: &bytes });
+        assert_eq!(result.unwrap(), MidiEvent::ChannelAftertouch { channel: 1, pressure: 99 });
+    }
+
+    #[test]
+    fn test_decode_raw_midi_every_event_type() {
+        for event_type in 0x0..=0xfu8 {
+            let bytes = [event_type << 4, 60, 100];
+            let result = decode_raw_midi(RawMidi { time: 0, bytes: &bytes });
+            let event_name: Option<&'static str> = result.as_ref().ok().map(|event| event.into());
+            let expected_name = match event_type {
+                0x0..=0x7 => None,
+                0xf => Some("system-exclusive"),
+                _ => Some(EVENT_NAMES[event_type as usize - 0x8]),
+            };
+            assert_eq!(event_name, expected_name, "event type {:#x}", event_type);
+        }
+    }
+
+    #[test]
+    fn test_decode_raw_midi_every_system_message() {
+        let expected_names = [
+            "system-exclusive", "midi-time-code-qtr-frame", "song-position-pointer", "song-select",
+            "undefined", "undefined", "tone-request", "end-of-sys-ex",
+            "timing-clock", "undefined", "start", "continue",
+            "stop", "undefined", "active-sensing", "system-reset",
+        ];
+        for (message_type, expected_name) in (0x0..=0xfu8).zip(expected_names) {
+            let bytes = [0xf0 | message_type, 1, 2];
+            let event = decode_raw_midi(RawMidi { time: 0, bytes: &bytes }).unwrap();
+            let event_name: &'static str = (&event).into();
+            assert_eq!(event_name, expected_name, "message type {:#x}", message_type);
+        }
+    }
+
+    #[test]
+    fn test_decode_raw_midi_too_short() {
+        for bytes in [vec![], vec![0xc3], vec![0xd0], vec![0x90, 60], vec![0xe0, 0], vec![0xf3]] {
+            let result = decode_raw_midi(RawMidi { time: 0, bytes: &bytes });
+            assert!(result.is_err(), "Unexpectedly decoded {:?} to {:?}", bytes, result);
+        }
+    }
+
+    #[test]
+    fn test_expected_len() {
+        assert_eq!(expected_len(0x80), Some(3));
+        assert_eq!(expected_len(0xbf), Some(3));
+        assert_eq!(expected_len(0xc0), Some(2));
+        assert_eq!(expected_len(0xdf), Some(2));
+        assert_eq!(expected_len(0xe5), Some(3));
+        assert_eq!(expected_len(0xf0), None);
+        assert_eq!(expected_len(0xf2), Some(3));
+        assert_eq!(expected_len(0xf3), Some(2));
+        assert_eq!(expected_len(0xf8), Some(1));
+    }
+
+    #[test]
+    fn test_trim_to_expected_len() {
+        assert_eq!(trim_to_expected_len(&[0xc3, 5, 7]), &[0xc3, 5]);
+        assert_eq!(trim_to_expected_len(&[0x90, 60, 100]), &[0x90, 60, 100]);
+        assert_eq!(trim_to_expected_len(&[0xf0, 0x7e, 0x01, 0xf7]), &[0xf0, 0x7e, 0x01, 0xf7]);
+        assert_eq!(trim_to_expected_len(&[0x90, 60]), &[0x90, 60]);
+        assert_eq!(trim_to_expected_len(&[]), &[] as &[u8]);
+    }
+
+    #[test]
+    fn test_running_status_decoder() {
+        let mut decoder = RunningStatusDecoder::new();
+
+        assert_eq!(decoder.expand(&[60, 100]), (Cow::Borrowed(&[60, 100][..]), false));
+        assert_eq!(decoder.expand(&[0x91, 60, 100]), (Cow::Borrowed(&[0x91, 60, 100][..]), false));
+        let (bytes, is_running_status) = decoder.expand(&[62, 0]);
+        assert_eq!(bytes.as_ref(), &[0x91, 62, 0]);
+        assert!(is_running_status);
+        let result = decode_raw_midi(RawMidi { time: 0, bytes: &bytes });
+        assert_eq!(result.unwrap(), MidiEvent::NoteOn { channel: 2, note: 62, velocity: 0 });
+
+        assert_eq!(decoder.expand(&[0xf8]), (Cow::Borrowed(&[0xf8][..]), false));
+        assert_eq!(decoder.expand(&[64, 90]).0.as_ref(), &[0x91, 64, 90]);
+        assert_eq!(decoder.expand(&[0xf3, 2]), (Cow::Borrowed(&[0xf3, 2][..]), false));
+        assert_eq!(decoder.expand(&[64, 90]), (Cow::Borrowed(&[64, 90][..]), false));
+    }
+
+    #[test]
+    fn test_sysex_assembler_fragments() {
+        let mut assembler = SysExAssembler::new();
+
+        assert_eq!(assembler.assemble(&[0xf0, 0x43, 0x10]), None);
+        assert_eq!(assembler.assemble(&[0xf8]), Some(&[0xf8][..]));
+        let bytes = assembler.assemble(&[0x4c, 0x00, 0xf7]).unwrap();
+        assert_eq!(bytes, &[0xf0, 0x43, 0x10, 0x4c, 0x00, 0xf7]);
+
+        let result = decode_raw_midi(RawMidi { time: 0, bytes });
+        assert_eq!(result.unwrap(), MidiEvent::SystemExclusive { data: vec![0xf0, 0x43, 0x10, 0x4c, 0x00, 0xf7] });
+    }
+
+    #[test]
+    fn test_sysex_assembler_complete_messages() {
+        let mut assembler = SysExAssembler::new();
+
+        assert_eq!(assembler.assemble(&[0x90, 60, 100]), Some(&[0x90, 60, 100][..]));
+        assert_eq!(assembler.assemble(&[0xf0, 0x7e, 0x09, 0xf7]), Some(&[0xf0, 0x7e, 0x09, 0xf7][..]));
+        let message = [0xf0, 0x7e, 0x09, 0xf7];
+        assert!(ptr::eq(assembler.assemble(&message).unwrap(), &message[..]));
+    }
+
+    #[test]
+    fn test_sysex_assembler_keeps_buffer() {
+        let mut assembler = SysExAssembler::new();
+
+        for _ in 0..3 {
+            assert_eq!(assembler.assemble(&[0xf0, 0x43]), None);
+            assert_eq!(assembler.assemble(&[0x10, 0xf7]), Some(&[0xf0, 0x43, 0x10, 0xf7][..]));
+            assert!(assembler.buffer.capacity() >= SYSEX_BUFFER_CAPACITY);
+        }
+    }
+
+    #[test]
+    fn test_sysex_assembler_overflow() {
+        let mut assembler = SysExAssembler::new();
+        let data = vec![0x01; SYSEX_BUFFER_CAPACITY / 2];
+
+        assert_eq!(assembler.assemble(&[0xf0, 0x43]), None);
+        assert_eq!(assembler.assemble(&data), None);
+        assert_eq!(assembler.assemble(&data), None);
+        assert_eq!(assembler.assemble(&data), None);
+        assert_eq!(assembler.assemble(&[0x01, 0xf7]), None);
+        assert_eq!(assembler.buffer.capacity(), SYSEX_BUFFER_CAPACITY);
+
+        assert_eq!(assembler.assemble(&[0x90, 60, 100]), Some(&[0x90, 60, 100][..]));
+        assert_eq!(assembler.assemble(&[0xf0, 0x43]), None);
+        assert_eq!(assembler.assemble(&[0x10, 0xf7]), Some(&[0xf0, 0x43, 0x10, 0xf7][..]));
+    }
+
+    #[test]
+    fn test_sysex_assembler_interrupted() {
+        let mut assembler = SysExAssembler::new();
+
+        assert_eq!(assembler.assemble(&[0xf0, 0x43]), None);
+        assert_eq!(assembler.assemble(&[0x90, 60, 100]), Some(&[0x90, 60, 100][..]));
+        assert_eq!(assembler.assemble(&[0x10, 0xf7]), Some(&[0x10, 0xf7][..]));
+
+        assert_eq!(assembler.assemble(&[0xf0, 0x43]), None);
+        assert_eq!(assembler.assemble(&[0xf0, 0x7e]), None);
+        assert_eq!(assembler.assemble(&[0x01, 0xf7]), Some(&[0xf0, 0x7e, 0x01, 0xf7][..]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,133 @@
+/*
+ * Limit the number of notes played simultaneously on an output port
+ */
+
+use std::collections::VecDeque;
+use crate::midi::MidiEvent;
+
+/// What happens to a note-on event if the maximum number of notes is already playing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoteCapPolicy {
+    /// Drops the new note
+    Drop,
+    /// Releases the oldest note to play the new one
+    Steal,
+}
+
+/// Maximum number of simultaneous notes on an output port
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteCap {
+    pub max_notes: usize,
+    pub policy: NoteCapPolicy,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CapDecision {
+    Send,
+    Drop,
+    /// Sends the given note-off event releasing the oldest note before the event
+    StealThenSend(MidiEvent),
+}
+
+/// Notes currently playing on an output port with a note cap
+pub struct NoteLimiter {
+    cap: NoteCap,
+    /// Channel and note of the playing notes, oldest first
+    active_notes: VecDeque<(u8, u8)>,
+}
+
+impl NoteLimiter {
+    pub fn new(cap: NoteCap) -> Self {
+        NoteLimiter {
+            active_notes: VecDeque::with_capacity(cap.max_notes + 1),
+            cap,
+        }
+    }
+
+    /// Decides whether the event may be sent and records the notes played by it. Note-off events
+    /// of dropped or stolen notes are dropped as well.
+    pub fn check(&mut self, midi_event: &MidiEvent) -> CapDecision {
+        match *midi_event {
+            MidiEvent::NoteOn { channel, note, velocity } if velocity > 0 => {
+                if self.release(channel, note) {
+                    // the note is retriggered, so it counts as the newest note
+                    self.active_notes.push_back((channel, note));
+                    return CapDecision::Send;
+                }
+                if self.active_notes.len() < self.cap.max_notes {
+                    self.active_notes.push_back((channel, note));
+                    return CapDecision::Send;
+                }
+                if self.cap.policy == NoteCapPolicy::Drop {
+                    return CapDecision::Drop;
+                }
+                let Some((stolen_channel, stolen_note)) = self.active_notes.pop_front() else {
+                    return CapDecision::Drop;
+                };
+                self.active_notes.push_back((channel, note));
+                CapDecision::StealThenSend(MidiEvent::NoteOff { channel: stolen_channel, note: stolen_note, velocity: 0 })
+            },
+            MidiEvent::NoteOn { channel, note, .. } |
+            MidiEvent::NoteOff { channel, note, .. } => match self.release(channel, note) {
+                true => CapDecision::Send,
+                false => CapDecision::Drop,
+            },
+            _ => CapDecision::Send,
+        }
+    }
+
+    /// Forgets all playing notes, e.g. after All-Notes-Off was sent
+    pub fn reset(&mut self) {
+        self.active_notes.clear();
+    }
+
+    fn release(&mut self, channel: u8, note: u8) -> bool {
+        match self.active_notes.iter().position(|active_note| *active_note == (channel, note)) {
+            Some(index) => {
+                self.active_notes.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(note: u8) -> MidiEvent {
+        MidiEvent::NoteOn { channel: 1, note, velocity: 100 }
+    }
+
+    fn play_notes(limiter: &mut NoteLimiter, count: u8) {
+        for note in 0..count {
+            assert_eq!(limiter.check(&note_on(note)), CapDecision::Send);
+        }
+    }
+
+    #[test]
+    fn test_note_limiter_steal() {
+        let mut limiter = NoteLimiter::new(NoteCap { max_notes: 8, policy: NoteCapPolicy::Steal });
+        play_notes(&mut limiter, 8);
+
+        assert_eq!(
+            limiter.check(&note_on(8)),
+            CapDecision::StealThenSend(MidiEvent::NoteOff { channel: 1, note: 0, velocity: 0 }),
+        );
+        assert_eq!(limiter.check(&MidiEvent::NoteOff { channel: 1, note: 0, velocity: 0 }), CapDecision::Drop);
+        assert_eq!(limiter.check(&MidiEvent::NoteOff { channel: 1, note: 8, velocity: 0 }), CapDecision::Send);
+    }
+
+    #[test]
+    fn test_note_limiter_drop() {
+        let mut limiter = NoteLimiter::new(NoteCap { max_notes: 8, policy: NoteCapPolicy::Drop });
+        play_notes(&mut limiter, 8);
+
+        assert_eq!(limiter.check(&note_on(8)), CapDecision::Drop);
+        assert_eq!(limiter.check(&MidiEvent::NoteOn { channel: 1, note: 8, velocity: 0 }), CapDecision::Drop);
+        assert_eq!(limiter.check(&MidiEvent::NoteOff { channel: 1, note: 0, velocity: 0 }), CapDecision::Send);
+        assert_eq!(limiter.check(&note_on(8)), CapDecision::Send);
+        assert_eq!(limiter.check(&MidiEvent::ProgramChange { channel: 1, program: 1 }), CapDecision::Send);
+    }
+}
@@ -0,0 +1,182 @@
+/*
+ * Pair note-offs with the notes sent for their note-on, so that a note-off releases the right
+ * note even if the transposition of its rule changed while the note was held
+ */
+
+use std::collections::HashMap;
+use crate::midi::MidiEvent;
+use crate::routing::RoutedEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const NOTE_COUNT: usize = 128;
+/// Most notes remembered per port for one note-on, e.g. when a rule layers several octaves
+const MAX_LAYERS: usize = 4;
+
+/// Channel and note of each note sent to a port for one incoming note-on, in the order of the
+/// routed events
+#[derive(Clone, Copy, Debug, Default)]
+struct SentNotes {
+    notes: [(u8, u8); MAX_LAYERS],
+    len: usize,
+    /// Index of the note released by the next note-off
+    next: usize,
+}
+
+impl SentNotes {
+    fn push(&mut self, channel: u8, note: u8) {
+        if self.len < MAX_LAYERS {
+            self.notes[self.len] = (channel, note);
+            self.len += 1;
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<(u8, u8)> {
+        let note = self.notes[..self.len].get(self.next).copied();
+        self.next += 1;
+        note
+    }
+}
+
+type SentNotesByInput = [[SentNotes; NOTE_COUNT]; CHANNEL_COUNT];
+
+pub struct NotePairing {
+    /// Notes sent to each output port by channel and note of the incoming note-on
+    sent_notes: HashMap<String, Box<SentNotesByInput>>,
+}
+
+impl NotePairing {
+    /// Creates the state of all output ports in advance to avoid allocations while routing
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(port_names: I) -> Self {
+        NotePairing {
+            sent_notes: port_names.into_iter()
+                .map(|port_name| (port_name.to_string(), Box::new([[SentNotes::default(); NOTE_COUNT]; CHANNEL_COUNT])))
+                .collect(),
+        }
+    }
+
+    /// Records the notes sent for a note-on, and changes the notes sent for a note-off to those
+    /// sent for its note-on on the same port
+    pub fn pair(&mut self, midi_event: &MidiEvent, routed_events: &mut [RoutedEvent]) {
+        let (channel, note, is_note_on) = match *midi_event {
+            MidiEvent::NoteOn { channel, note, velocity } if velocity > 0 => (channel, note, true),
+            MidiEvent::NoteOn { channel, note, .. } | MidiEvent::NoteOff { channel, note, .. } => (channel, note, false),
+            _ => return,
+        };
+        let (channel_idx, note_idx) = ((channel as usize).wrapping_sub(1), note as usize);
+        if channel_idx >= CHANNEL_COUNT || note_idx >= NOTE_COUNT {
+            return;
+        }
+        if is_note_on {
+            self.forget(channel_idx, note_idx);
+        }
+        for routed_event in routed_events.iter_mut() {
+            let Some(sent_notes) = self.sent_notes.get_mut(routed_event.output_port.as_ref()) else {
+                continue;
+            };
+            let sent_notes = &mut sent_notes[channel_idx][note_idx];
+            match *routed_event.event.as_ref().unwrap_or(midi_event) {
+                MidiEvent::NoteOn { channel, note, velocity } if is_note_on && velocity > 0 => {
+                    sent_notes.push(channel, note);
+                },
+                MidiEvent::NoteOff { velocity, .. } if !is_note_on => {
+                    if let Some((channel, note)) = sent_notes.pop_front() {
+                        routed_event.event = Some(MidiEvent::NoteOff { channel, note, velocity });
+                    }
+                },
+                MidiEvent::NoteOn { velocity: 0, .. } if !is_note_on => {
+                    if let Some((channel, note)) = sent_notes.pop_front() {
+                        routed_event.event = Some(MidiEvent::NoteOn { channel, note, velocity: 0 });
+                    }
+                },
+                _ => {},
+            }
+        }
+        if !is_note_on {
+            self.forget(channel_idx, note_idx);
+        }
+    }
+
+    /// Forgets the notes sent for the incoming note on all ports
+    fn forget(&mut self, channel_idx: usize, note_idx: usize) {
+        for sent_notes in self.sent_notes.values_mut() {
+            sent_notes[channel_idx][note_idx] = SentNotes::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_rule;
+    use crate::routing::RoutingTable;
+    use super::*;
+
+    fn routing_table(rule: &str) -> RoutingTable {
+        RoutingTable {
+            rules: vec![parse_rule(0, rule.into()).unwrap()],
+            ..Default::default()
+        }
+    }
+
+    fn route(routing_table: &RoutingTable, note_pairing: &mut NotePairing, midi_event: &MidiEvent) -> Vec<(String, MidiEvent)> {
+        let mut routed_events = routing_table.get_outputs(midi_event);
+        note_pairing.pair(midi_event, &mut routed_events);
+        routed_events.into_iter()
+            .map(|routed_event| (routed_event.output_port.to_string(), routed_event.event.unwrap_or(midi_event.clone())))
+            .collect()
+    }
+
+    #[test]
+    fn test_note_pairing_transpose_changed_while_held() {
+        let mut note_pairing = NotePairing::new(["synth"]);
+        let octave_up = routing_table("note-.* => synth:transpose(+12)");
+        let fifth_up = routing_table("note-.* => synth:transpose(+7)");
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 64 };
+
+        assert_eq!(route(&octave_up, &mut note_pairing, &note_on), vec![
+            ("synth".to_string(), MidiEvent::NoteOn { channel: 1, note: 72, velocity: 100 }),
+        ]);
+        assert_eq!(route(&fifth_up, &mut note_pairing, &note_off), vec![
+            ("synth".to_string(), MidiEvent::NoteOff { channel: 1, note: 72, velocity: 64 }),
+        ]);
+
+        assert_eq!(route(&fifth_up, &mut note_pairing, &note_on), vec![
+            ("synth".to_string(), MidiEvent::NoteOn { channel: 1, note: 67, velocity: 100 }),
+        ]);
+        let zero_velocity_note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 };
+        assert_eq!(route(&octave_up, &mut note_pairing, &zero_velocity_note_on), vec![
+            ("synth".to_string(), MidiEvent::NoteOn { channel: 1, note: 67, velocity: 0 }),
+        ]);
+        assert_eq!(route(&octave_up, &mut note_pairing, &note_off), vec![
+            ("synth".to_string(), MidiEvent::NoteOff { channel: 1, note: 72, velocity: 64 }),
+        ]);
+    }
+
+    #[test]
+    fn test_note_pairing_layered_notes() {
+        let mut note_pairing = NotePairing::new(["synth", "pad"]);
+        let layered = routing_table("note-.* => synth synth:transpose(+12) pad@2");
+        let transposed = routing_table("note-.* => synth:transpose(-12) synth:transpose(-24) pad@2:transpose(+5)");
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+
+        route(&layered, &mut note_pairing, &note_on);
+        assert_eq!(route(&transposed, &mut note_pairing, &note_off), vec![
+            ("synth".to_string(), MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }),
+            ("synth".to_string(), MidiEvent::NoteOff { channel: 1, note: 72, velocity: 0 }),
+            ("pad".to_string(), MidiEvent::NoteOff { channel: 2, note: 60, velocity: 0 }),
+        ]);
+    }
+
+    #[test]
+    fn test_note_pairing_unpaired_note_off() {
+        let mut note_pairing = NotePairing::new(["synth"]);
+        let octave_up = routing_table("note-.* => synth:transpose(+12) other:transpose(+12)");
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+
+        assert_eq!(route(&octave_up, &mut note_pairing, &note_off), vec![
+            ("synth".to_string(), MidiEvent::NoteOff { channel: 1, note: 72, velocity: 0 }),
+            ("other".to_string(), MidiEvent::NoteOff { channel: 1, note: 72, velocity: 0 }),
+        ]);
+    }
+}
@@ -0,0 +1,107 @@
+/*
+ * Track held notes to compute how long a note was held when it is released
+ */
+
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const NOTE_COUNT: usize = 128;
+
+pub struct NoteTracker {
+    /// Time (see `now_ms`) of the note-on event of each held note by channel and note
+    note_on_ms: [[Option<u64>; NOTE_COUNT]; CHANNEL_COUNT],
+}
+
+impl NoteTracker {
+    pub fn new() -> Self {
+        NoteTracker {
+            note_on_ms: [[None; NOTE_COUNT]; CHANNEL_COUNT],
+        }
+    }
+
+    /// Records note-on events and returns how long the note was held for note-off events.
+    /// A note-on event with velocity 0 releases the note as well.
+    pub fn track(&mut self, midi_event: &MidiEvent, event_ms: u64) -> Option<u64> {
+        match *midi_event {
+            MidiEvent::NoteOn { channel, note, velocity } if velocity > 0 => {
+                *self.held_note(channel, note)? = Some(event_ms);
+                None
+            },
+            MidiEvent::NoteOn { channel, note, .. } |
+            MidiEvent::NoteOff { channel, note, .. } => {
+                let note_on_ms = self.held_note(channel, note)?.take()?;
+                Some(event_ms.saturating_sub(note_on_ms))
+            },
+            _ => None,
+        }
+    }
+
+    /// Checks whether the event is a note-on of a note which is held, i.e. the previous note-on
+    /// of the same note was not followed by a note-off
+    pub fn is_repeated_note_on(&self, midi_event: &MidiEvent) -> bool {
+        match *midi_event {
+            MidiEvent::NoteOn { channel, note, velocity } if velocity > 0 => (channel as usize).checked_sub(1)
+                .and_then(|channel_index| self.note_on_ms.get(channel_index))
+                .and_then(|notes| notes.get(note as usize))
+                .is_some_and(Option::is_some),
+            _ => false,
+        }
+    }
+
+    fn held_note(&mut self, channel: u8, note: u8) -> Option<&mut Option<u64>> {
+        self.note_on_ms
+            .get_mut((channel as usize).checked_sub(1)?)?
+            .get_mut(note as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_tracker_short_and_long_holds() {
+        let mut tracker = NoteTracker::new();
+        let note_on = |note| MidiEvent::NoteOn { channel: 1, note, velocity: 100 };
+        let note_off = |note| MidiEvent::NoteOff { channel: 1, note, velocity: 0 };
+
+        assert_eq!(tracker.track(&note_on(60), 1000), None);
+        assert_eq!(tracker.track(&note_on(64), 1100), None);
+        assert_eq!(tracker.track(&note_off(64), 1250), Some(150));
+        assert_eq!(tracker.track(&note_off(60), 2000), Some(1000));
+        assert_eq!(tracker.track(&note_off(60), 2100), None);
+    }
+
+    #[test]
+    fn test_note_tracker_note_on_with_zero_velocity() {
+        let mut tracker = NoteTracker::new();
+
+        tracker.track(&MidiEvent::NoteOn { channel: 16, note: 127, velocity: 1 }, 0);
+        let held_ms = tracker.track(&MidiEvent::NoteOn { channel: 16, note: 127, velocity: 0 }, 700);
+
+        assert_eq!(held_ms, Some(700));
+    }
+
+    #[test]
+    fn test_note_tracker_repeated_note_on() {
+        let mut tracker = NoteTracker::new();
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+
+        assert!(!tracker.is_repeated_note_on(&note_on));
+        tracker.track(&note_on, 0);
+        assert!(tracker.is_repeated_note_on(&note_on));
+        assert!(!tracker.is_repeated_note_on(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 }));
+        assert!(!tracker.is_repeated_note_on(&MidiEvent::NoteOn { channel: 1, note: 61, velocity: 100 }));
+        tracker.track(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }, 10);
+        assert!(!tracker.is_repeated_note_on(&note_on));
+    }
+
+    #[test]
+    fn test_note_tracker_invalid_note() {
+        let mut tracker = NoteTracker::new();
+
+        assert_eq!(tracker.track(&MidiEvent::NoteOn { channel: 0, note: 60, velocity: 1 }, 0), None);
+        assert_eq!(tracker.track(&MidiEvent::NoteOn { channel: 1, note: 200, velocity: 1 }, 0), None);
+        assert_eq!(tracker.track(&MidiEvent::NoteOff { channel: 1, note: 200, velocity: 1 }, 10), None);
+    }
+}
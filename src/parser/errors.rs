@@ -37,6 +37,8 @@ impl Error for RuleConfigError {}
 pub enum RuleParseError {
     InvalidFields {
         line_no: usize,
+        /// Position of the rule within its line if the line contains several rules
+        rule_no: Option<usize>,
         invalid_fields: Vec<FieldParseError>,
     },
 }
@@ -44,13 +46,13 @@ pub enum RuleParseError {
 impl Display for RuleParseError {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            RuleParseError::InvalidFields { line_no, invalid_fields } => {
+            RuleParseError::InvalidFields { line_no, rule_no, invalid_fields } => {
                 let invalid_fields_strs = invalid_fields.iter()
                     .map(|field| format!("{}", field))
                     .map(|msg| indent(msg, 4))
                     .collect::<Vec<String>>()
                     .join("\n  - ");
-                write!(formatter, "Invalid field in line {}:\n  - {}", line_no + 1, invalid_fields_strs)
+                write!(formatter, "Invalid field in {}:\n  - {}", describe_position(*line_no, *rule_no), invalid_fields_strs)
             }
         }
     }
@@ -58,6 +60,75 @@ impl Display for RuleParseError {
 
 impl Error for RuleParseError {}
 
+/// Describes the position of a rule in the config file, e.g. `line 3, rule 2`
+pub fn describe_position(line_no: usize, rule_no: Option<usize>) -> String {
+    match rule_no {
+        Some(rule_no) => format!("line {}, rule {}", line_no + 1, rule_no + 1),
+        None => format!("line {}", line_no + 1),
+    }
+}
+
+
+/*
+ * Config file path which refers to a directory, which can be opened on some platforms, but not
+ * read line by line
+ */
+
+#[derive(Debug)]
+pub struct IsDirectoryError;
+
+impl Display for IsDirectoryError {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "Is a directory")
+    }
+}
+
+impl Error for IsDirectoryError {}
+
+
+/*
+ * Config file with bytes which are not valid UTF-8, e.g. because it was saved in another encoding
+ */
+
+#[derive(Debug)]
+pub struct InvalidUtf8Error {
+    pub line_no: usize,
+}
+
+impl Display for InvalidUtf8Error {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "Invalid UTF-8 in {}", describe_position(self.line_no, None))
+    }
+}
+
+impl Error for InvalidUtf8Error {}
+
+
+/*
+ * Non-fatal issue in a rule, which is loaded nevertheless
+ */
+
+#[derive(Debug, PartialEq)]
+pub enum RuleWarning {
+    /// The rule only drops the matching events without sending anything
+    DropsAllEvents {
+        line_no: usize,
+        rule_no: Option<usize>,
+    },
+}
+
+impl Display for RuleWarning {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleWarning::DropsAllEvents { line_no, rule_no } => write!(
+                formatter,
+                "Rule in {} has no output port and drops all matching events",
+                describe_position(*line_no, *rule_no),
+            ),
+        }
+    }
+}
+
 
 /*
  * Error in a field within a rule
@@ -97,6 +168,14 @@ impl Error for FieldParseError {}
 pub enum FieldFormatError {
     InvalidFormat,
     NumberOutOfRange { min: i16, max: i16 },
+    UnknownEventName,
+    UnknownAction,
+    UnknownDynamic,
+    UnknownBus,
+    DuplicateBus,
+    UndeclaredPort,
+    UnknownOption,
+    CaptureInNegatedRule,
 }
 
 
@@ -109,6 +188,14 @@ impl Display for FieldFormatError {
                 min + 1, 
                 max - 1,
             ),
+            FieldFormatError::UnknownEventName => "Unknown MIDI event name".to_string(),
+            FieldFormatError::UnknownAction => "Unknown action".to_string(),
+            FieldFormatError::UnknownDynamic => "Unknown dynamic, expected one of ppp, pp, p, mp, mf, f, ff, fff".to_string(),
+            FieldFormatError::UnknownBus => "Unknown bus".to_string(),
+            FieldFormatError::DuplicateBus => "Bus is already defined".to_string(),
+            FieldFormatError::UndeclaredPort => "Port is not declared in a 'ports:' header".to_string(),
+            FieldFormatError::UnknownOption => "Unknown option, expected one of label, enabled, dryrun, priority, group".to_string(),
+            FieldFormatError::CaptureInNegatedRule => "Rules negated with 'not' have no capture groups".to_string(),
         };
         write!(formatter, "{}", reason_str)
     }
@@ -0,0 +1,206 @@
+/*
+ * Parse and format textual MIDI event literals, e.g. "program-change ch1 5"
+ */
+
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use crate::midi::MidiEvent;
+use crate::parser::FieldFormatError;
+
+lazy_static! {
+    static ref EVENT_FIELD_PAT: Regex = RegexBuilder::new(r"^(?P<type>ch|vel|ctrl|note)?(?P<value>-?\d+)$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+}
+
+#[derive(Debug)]
+struct EventFields {
+    channel: u8,
+    value: i16,
+    velocity: u8,
+    control_no: u8,
+}
+
+/// Parses an event literal of the form `<EVENT_NAME> [ch<N>] [<N>] [vel<N>] [ctrl<N>]`.
+/// Omitted fields default to channel 1 and 0 for all other values.
+pub fn parse_event(text: &str) -> Result<MidiEvent, FieldFormatError> {
+    let mut tokens = text.split_whitespace();
+    let name = tokens.next().ok_or(FieldFormatError::InvalidFormat)?;
+
+    let mut fields = EventFields {
+        channel: 1,
+        value: 0,
+        velocity: 0,
+        control_no: 0,
+    };
+    for token in tokens {
+        let captures = EVENT_FIELD_PAT.captures(token).ok_or(FieldFormatError::InvalidFormat)?;
+        let value_type_str = captures.name("type").map_or("", |m| m.as_str());
+        let value = captures["value"].parse::<i16>()
+            .map_err(|_| FieldFormatError::InvalidFormat)?;
+        match value_type_str.to_lowercase().as_str() {
+            "ch" => fields.channel = to_u8_in_range(value, 1, 16)?,
+            "vel" => fields.velocity = to_u8_in_range(value, 0, 127)?,
+            "ctrl" => fields.control_no = to_u8_in_range(value, 0, 127)?,
+            _ => fields.value = value,
+        }
+    }
+
+    create_event(name, fields)
+}
+
+fn to_u8_in_range(value: i16, min: i16, max: i16) -> Result<u8, FieldFormatError> {
+    if min <= value && value <= max {
+        Ok(value as u8)
+    } else {
+        Err(FieldFormatError::NumberOutOfRange { min: min - 1, max: max + 1 })
+    }
+}
+
+fn create_event(name: &str, fields: EventFields) -> Result<MidiEvent, FieldFormatError> {
+    let EventFields { channel, value, velocity, control_no } = fields;
+    let data_byte = || to_u8_in_range(value, 0, 127);
+    let event = match name {
+        "note-off" => MidiEvent::NoteOff { channel, note: data_byte()?, velocity },
+        "note-on" => MidiEvent::NoteOn { channel, note: data_byte()?, velocity },
+        "polyphonic-aftertouch" => MidiEvent::PolyphonicAftertouch { channel, note: data_byte()?, pressure: velocity },
+        "control-change" => MidiEvent::ControlChange { channel, control_no, value: data_byte()? },
+        "program-change" => MidiEvent::ProgramChange { channel, program: data_byte()? },
+        "channel-aftertouch" => MidiEvent::ChannelAftertouch { channel, pressure: data_byte()? },
+        "pitch-bend-change" => {
+            if !(-8192..=8191).contains(&value) {
+                Err(FieldFormatError::NumberOutOfRange { min: -8193, max: 8192 })?
+            }
+            MidiEvent::PitchBendChange { channel, value }
+        },
+        "song-select" => MidiEvent::SongSelect { song_num: data_byte()? },
+        "tune-request" | "tone-request" => MidiEvent::TuneRequest {},
+        "timing-clock" => MidiEvent::TimingClock {},
+        "start" => MidiEvent::Start {},
+        "continue" => MidiEvent::Continue {},
+        "stop" => MidiEvent::Stop {},
+        "active-sensing" => MidiEvent::ActiveSensing {},
+        "system-reset" => MidiEvent::SystemReset {},
+        _ => Err(FieldFormatError::UnknownEventName)?,
+    };
+    Ok(event)
+}
+
+/// Formats an event as literal which `parse_event` reads back, e.g. `note-on ch1 60 vel100`.
+/// The bytes of SysEx messages are appended in hex.
+pub fn format_event(midi_event: &MidiEvent) -> String {
+    let name: &'static str = midi_event.into();
+    match midi_event {
+        MidiEvent::NoteOff { channel, note, velocity } |
+        MidiEvent::NoteOn { channel, note, velocity } |
+        MidiEvent::PolyphonicAftertouch { channel, note, pressure: velocity } => {
+            format!("{} ch{} {} vel{}", name, channel, note, velocity)
+        },
+        MidiEvent::ControlChange { channel, control_no, value } => {
+            format!("{} ch{} ctrl{} {}", name, channel, control_no, value)
+        },
+        MidiEvent::ProgramChange { channel, program: value } |
+        MidiEvent::ChannelAftertouch { channel, pressure: value } => format!("{} ch{} {}", name, channel, value),
+        MidiEvent::PitchBendChange { channel, value } => format!("{} ch{} {}", name, channel, value),
+        MidiEvent::SongSelect { song_num } => format!("{} {}", name, song_num),
+        MidiEvent::SystemExclusive { data } => {
+            let bytes: Vec<String> = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{} {}", name, bytes.join(" "))
+        },
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event_program_change() {
+        let result = parse_event("program-change ch1 5");
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), MidiEvent::ProgramChange {
+            channel: 1,
+            program: 5,
+        });
+    }
+
+    #[test]
+    fn test_parse_event_note_on() {
+        let result = parse_event("note-on ch10 note60 vel100");
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), MidiEvent::NoteOn {
+            channel: 10,
+            note: 60,
+            velocity: 100,
+        });
+    }
+
+    #[test]
+    fn test_parse_event_defaults() {
+        let result = parse_event("control-change ctrl7");
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), MidiEvent::ControlChange {
+            channel: 1,
+            control_no: 7,
+            value: 0,
+        });
+    }
+
+    #[test]
+    fn test_parse_event_pitch_bend_negative() {
+        let result = parse_event("pitch-bend-change ch2 -100");
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), MidiEvent::PitchBendChange {
+            channel: 2,
+            value: -100,
+        });
+    }
+
+    #[test]
+    fn test_parse_event_unknown_name() {
+        let result = parse_event("note-pikachu ch1 5");
+        assert!(matches!(result, Err(FieldFormatError::UnknownEventName)));
+    }
+
+    #[test]
+    fn test_parse_event_out_of_range() {
+        assert!(parse_event("note-on ch17 60").is_err());
+        assert!(parse_event("note-on ch1 128").is_err());
+        assert!(parse_event("note-on ch1 60 vel200").is_err());
+    }
+
+    #[test]
+    fn test_format_event() {
+        let events = [
+            MidiEvent::NoteOn { channel: 10, note: 60, velocity: 100 },
+            MidiEvent::PolyphonicAftertouch { channel: 1, note: 60, pressure: 30 },
+            MidiEvent::ControlChange { channel: 2, control_no: 7, value: 90 },
+            MidiEvent::ProgramChange { channel: 16, program: 5 },
+            MidiEvent::PitchBendChange { channel: 1, value: -100 },
+            MidiEvent::SongSelect { song_num: 3 },
+            MidiEvent::TimingClock {},
+        ];
+        for event in events {
+            assert_eq!(parse_event(&format_event(&event)).unwrap(), event);
+        }
+
+        assert_eq!(format_event(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }), "note-on ch1 60 vel100");
+        assert_eq!(format_event(&MidiEvent::ControlChange { channel: 1, control_no: 7, value: 5 }), "control-change ch1 ctrl7 5");
+        assert_eq!(
+            format_event(&MidiEvent::SystemExclusive { data: vec![0xf0, 0x7e, 0x09, 0xf7] }),
+            "system-exclusive f0 7e 09 f7",
+        );
+    }
+
+    #[test]
+    fn test_parse_event_invalid_field() {
+        assert!(parse_event("note-on ch1 x60").is_err());
+        assert!(parse_event("").is_err());
+    }
+}
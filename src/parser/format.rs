@@ -0,0 +1,139 @@
+/*
+ * Rewrite config files in a canonical form, e.g. "note-on   ch>=3 =>out" as "note-on ch>2 => out"
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::parser::parser::{
+    check_not_directory, is_directive, normalize_value_field, split_fields, BUS_KEYWORD, CAP_KEYWORD, COMMENT_PREFIX, FILTER_KEYWORD,
+    FORWARD_SYMBOL, MATCH_MODE_KEYWORD, OR_KEYWORD, PORTS_KEYWORD, SECTION_END, SECTION_KEYWORD,
+};
+
+const INDENT: &str = "    ";
+
+/// Formats the config file in place. The file must be valid, otherwise rules may be garbled.
+pub fn format_config_file<P: AsRef<Path>>(file_path: &P) -> Result<(), io::Error> {
+    check_not_directory(file_path)?;
+    let config = fs::read_to_string(file_path)?;
+    fs::write(file_path, format_config(&config))
+}
+
+/// Normalizes the whitespace and value ranges of all lines, indents rules within sections, and
+/// aligns the `=>` of consecutive rules. Comments and empty lines are kept.
+pub fn format_config(config: &str) -> String {
+    let mut lines = Vec::new();
+    // Left- and right-hand sides of consecutive rules, which are aligned with each other
+    let mut rule_block: Vec<(String, String)> = Vec::new();
+    let mut depth = 0usize;
+    for line in config.lines().map(str::trim) {
+        let fields = split_fields(line);
+        let forward_id = fields.iter().position(|field| *field == FORWARD_SYMBOL);
+        if let Some(forward_id) = forward_id.filter(|_| !line.starts_with(COMMENT_PREFIX)) {
+            let lhs = format_condition_fields(&fields[..forward_id], true);
+            rule_block.push((format!("{}{}", INDENT.repeat(depth), lhs), fields[forward_id + 1..].join(" ")));
+            continue;
+        }
+        lines.extend(align_rules(&mut rule_block));
+
+        if line == SECTION_END {
+            depth = depth.saturating_sub(1);
+        }
+        let formatted_line = if line.starts_with(COMMENT_PREFIX) || line.is_empty() {
+            line.to_string()
+        } else if is_directive(line, SECTION_KEYWORD) {
+            format!("{} {}", SECTION_KEYWORD, format_condition_fields(&fields[1..], false))
+        } else if is_directive(line, FILTER_KEYWORD) {
+            format!("{} {}", fields[..2].join(" "), format_condition_fields(&fields[2..], false))
+        } else if is_directive(line, BUS_KEYWORD) || is_directive(line, CAP_KEYWORD)
+            || is_directive(line, PORTS_KEYWORD) || is_directive(line, MATCH_MODE_KEYWORD) {
+            fields.join(" ")
+        } else {
+            line.to_string()
+        };
+        if formatted_line.is_empty() {
+            lines.push(formatted_line);
+        } else {
+            lines.push(format!("{}{}", INDENT.repeat(depth), formatted_line));
+        }
+        if is_directive(line, SECTION_KEYWORD) {
+            depth += 1;
+        }
+    }
+    lines.extend(align_rules(&mut rule_block));
+
+    let mut formatted = lines.join("\n");
+    formatted.push('\n');
+    formatted
+}
+
+/// Normalizes all value fields, but keeps event name patterns, which start each condition
+fn format_condition_fields(fields: &[&str], starts_with_name: bool) -> String {
+    let mut is_name_field = starts_with_name;
+    let mut formatted_fields = Vec::with_capacity(fields.len());
+    for field in fields {
+        if *field == OR_KEYWORD {
+            is_name_field = starts_with_name;
+            formatted_fields.push(field.to_string());
+        } else if is_name_field {
+            is_name_field = false;
+            formatted_fields.push(field.to_string());
+        } else {
+            formatted_fields.push(normalize_value_field(field).unwrap_or(field.to_string()));
+        }
+    }
+    formatted_fields.join(" ")
+}
+
+/// Pads the left-hand sides of the rules so that their `=>` are in the same column
+fn align_rules(rule_block: &mut Vec<(String, String)>) -> Vec<String> {
+    let width = rule_block.iter().map(|(lhs, _)| lhs.chars().count()).max().unwrap_or(0);
+    rule_block.drain(..)
+        .map(|(lhs, rhs)| format!("{:width$} {} {}", lhs, FORWARD_SYMBOL, rhs).trim_end().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# Drums => synth
+note-.*   ch10 vel>=1   =>  drums
+control-change ctrl7-7 =>   mixer   synth:cc(7->11)
+
+bus synths  =  synthA synthB
+filter  synthA ch1-1
+with  ch>=2 {
+# Keys
+note-on 0-59 or note-off <=59 => @synths:transpose(+12)
+program-change velmf  =>
+}
+";
+
+    #[test]
+    fn test_format_config() {
+        let formatted = format_config(CONFIG);
+
+        assert_eq!(formatted, "\
+# Drums => synth
+note-.* ch10 vel>0   => drums
+control-change ctrl7 => mixer synth:cc(7->11)
+
+bus synths = synthA synthB
+filter synthA ch1
+with ch>1 {
+    # Keys
+    note-on 0-59 or note-off <60 => @synths:transpose(+12)
+    program-change velmf         =>
+}
+");
+    }
+
+    #[test]
+    fn test_format_config_is_idempotent() {
+        let formatted = format_config(CONFIG);
+
+        assert_eq!(format_config(&formatted), formatted);
+    }
+}
@@ -1,5 +1,9 @@
 pub mod errors;
+pub mod event;
+pub mod format;
 pub mod parser;
 
 pub use errors::*;
+pub use event::*;
+pub use format::*;
 pub use parser::*;
\ No newline at end of file
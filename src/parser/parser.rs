@@ -3,45 +3,382 @@
  */
 
 use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicU64;
 use std::error::Error;
-use std::fs::File;
-use std::{io, mem};
+use std::fs::{self, File};
+use std::{io, iter, mem};
 use std::io::BufRead;
 use std::path::Path;
 use lazy_static::lazy_static;
+use log::warn;
 use regex::{Captures, Match, Regex, RegexBuilder};
-use crate::parser::{FieldFormatError, FieldParseError, RuleConfigError, RuleParseError};
-use crate::routing::{Action, Condition, NumericRange, Rule};
+use crate::parser::{parse_event, FieldFormatError, FieldParseError, InvalidUtf8Error, IsDirectoryError, RuleConfigError, RuleParseError, RuleWarning};
+use crate::bank::MAX_BANK;
+use crate::midi::{EventCategory, UniversalSysExId};
+use crate::note_cap::{NoteCap, NoteCapPolicy};
+use crate::scale::Scale;
+use crate::text_sink::{TEXT_SINK_ALIAS, TEXT_SINK_PORT};
+use crate::time_window::TimeWindow;
+use crate::transform::{BendToCc, CcToBend, ClampVelocity, QuantizeToScale, QuantizeValue, RemapController, PickupState, SetChannel, ToggleState, ToggleSwitch, Transform, Transpose};
+use crate::routing::{Action, Condition, ControllerKind, ControllerSet, NumericRange, TransportState, MAX_LENGTH, OutputFilter, Rule, RoutingTable};
 
 lazy_static! {
-    static ref FIELD_PAT: Regex = RegexBuilder::new(r"^(?P<type>ch|vel|ctrl)?(?:(?P<wildcard>[*])|(?P<start>-?\d+)-(?P<end>-?\d+)|>(?P<lower_bound>-?\d+)|<(?P<upper_bound>-?\d+)|(?P<exact_value>-?\d+))$")
+    static ref FIELD_PAT: Regex = RegexBuilder::new(r"^(?P<type>ch|vel|relvel|ctrl|slew|tempo|bank|len|voice|pbmsb|pblsb)?(?:(?P<wildcard>[*])|(?P<start>-?\d+)-(?P<end>-?\d+)|>=(?P<inclusive_lower_bound>-?\d+)|<=(?P<inclusive_upper_bound>-?\d+)|>(?P<lower_bound>-?\d+)|<(?P<upper_bound>-?\d+)|(?P<exact_value>-?\d+)|(?P<dynamic>[a-z]+))$")
         .case_insensitive(true)
         .build()
         .unwrap();
+    static ref CONTROLLER_SET_PAT: Regex = RegexBuilder::new(r"^ctrl(?P<controllers>\d+(?:,\d+)+)$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+}
+
+/// Velocity ranges of musical dynamics from pianississimo to fortississimo
+const DYNAMICS: [(&str, u8, u8); 8] = [
+    ("ppp", 1, 15),
+    ("pp", 16, 31),
+    ("p", 32, 47),
+    ("mp", 48, 63),
+    ("mf", 64, 79),
+    ("f", 80, 95),
+    ("ff", 96, 111),
+    ("fff", 112, 127),
+];
+
+/// Versions of the config file format which this router can read
+pub const SUPPORTED_CONFIG_VERSIONS: [&str; 1] = ["1"];
+
+/// Maximum tempo in beats per minute which can be given in a condition
+const MAX_TEMPO_BPM: i16 = 999;
+
+pub(super) const COMMENT_PREFIX: char = '#';
+pub(super) const FILTER_KEYWORD: &str = "filter";
+pub(super) const BUS_KEYWORD: &str = "bus";
+pub(super) const CAP_KEYWORD: &str = "cap";
+pub(super) const PORTS_KEYWORD: &str = "ports:";
+pub(super) const MATCH_MODE_KEYWORD: &str = "match-mode";
+const ANCHORED_MODE: &str = "anchored";
+const CASE_INSENSITIVE_MODE: &str = "icase";
+const CAP_POLICY_DROP: &str = "drop";
+const CAP_POLICY_STEAL: &str = "steal";
+pub(super) const SECTION_KEYWORD: &str = "with";
+const SECTION_START: &str = "{";
+/// Start of the options block at the end of a rule, e.g. `{ label=Drums, enabled=false }`
+const OPTIONS_START: &str = " {";
+const OPTIONS_END: char = '}';
+const OPTIONS_SEPARATOR: char = ',';
+const LABEL_OPTION: &str = "label";
+const ENABLED_OPTION: &str = "enabled";
+const DRY_RUN_OPTION: &str = "dryrun";
+const PRIORITY_OPTION: &str = "priority";
+const GROUP_OPTION: &str = "group";
+pub(super) const SECTION_END: &str = "}";
+/// Separates several rules written on one line, e.g. `note-on => synth; note-off => synth`
+const RULE_SEPARATOR: char = ';';
+const BUS_PREFIX: char = '@';
+const ASSIGN_SYMBOL: &str = "=";
+pub(super) const FORWARD_SYMBOL: &str = "=>";
+pub(super) const OR_KEYWORD: &str = "or";
+/// Negates the condition of the whole rule if given before it, e.g. `not note-on ch1 => out`
+const NOT_KEYWORD: &str = "not";
+const ANY_NOTE_KEYWORD: &str = "anynote";
+const DROP_KEYWORD: &str = "drop";
+const REDIRECT_ACTION: &str = "redirect";
+const LOG_MODIFIER: &str = ":log";
+const PICKUP_MODIFIER: &str = ":pickup";
+const ZERO_VELOCITY_OFF_KEYWORD: &str = "vel0off";
+const SUSTAINED_KEYWORD: &str = "sustained";
+const STUCK_KEYWORD: &str = "stuck";
+const RUNNING_STATUS_KEYWORD: &str = "running-status";
+const RELEASE_KEYWORD: &str = "release";
+const SWITCH_KEYWORD: &str = "switch";
+const CONTINUOUS_KEYWORD: &str = "continuous";
+const LITERAL_NAME_PREFIX: char = '=';
+const SYSEX_PREFIX: &str = "sysex=";
+const SCALE_PREFIX: &str = "scale=";
+const TRANSPORT_PREFIX: &str = "transport=";
+const AFTER_QUIET_PREFIX: &str = "afterquiet";
+const HELD_OVER_PREFIX: &str = "heldover";
+const HELD_UNDER_PREFIX: &str = "heldunder";
+const SINCE_PROGRAM_CHANGE_PREFIX: &str = "sincepc";
+const EVERY_PREFIX: &str = "every";
+const ALTERNATE_PREFIX: &str = "alt";
+const TIME_PREFIX: &str = "time";
+const VELOCITY_COMPLEMENT_PREFIX: &str = "velcomp";
+const ACTION_SEPARATOR: char = ':';
+/// Start of config files saved as UTF-8 with byte order mark, which is not part of the first line
+const BYTE_ORDER_MARK: char = '\u{feff}';
+/// Separates the channel which events are moved to from the output port, e.g. `out1@3`
+const CHANNEL_SEPARATOR: char = '@';
+
+/// Output ports of each bus by bus name
+pub type BusTable = HashMap<String, Vec<String>>;
+
+lazy_static! {
+    static ref EMPTY_BUS_TABLE: BusTable = BusTable::new();
+}
+
+/// Result of parsing a config file, including all rules that were parsed successfully
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    pub rules: Vec<Rule>,
+    pub output_filters: Vec<(String, OutputFilter)>,
+    pub note_caps: Vec<(String, NoteCap)>,
+    pub errors: Vec<RuleParseError>,
+    /// Non-fatal issues found while parsing
+    pub warnings: Vec<RuleWarning>,
+}
+
+/// How event name patterns are matched, which is set for the whole file by `match-mode` directives
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MatchMode {
+    /// Patterns must match the whole event name instead of any part of it
+    pub anchored: bool,
+    pub case_insensitive: bool,
 }
 
-const FORWARD_SYMBOL: &str = "=>";
+/// Options for loading a config file
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    /// Require every output port to be declared in a `ports:` header
+    pub strict_ports: bool,
+}
 
+#[cfg(test)]
 pub fn load_rules_from_file<P: AsRef<Path>>(file_path: &P) -> Result<Vec<Rule>, Box<dyn Error>> {
+    load_routing_table_from_file(file_path).map(|routing_table| routing_table.rules)
+}
+
+/// Loads rules and directives, e.g. output filters, of a config file
+#[cfg(test)]
+pub fn load_routing_table_from_file<P: AsRef<Path>>(file_path: &P) -> Result<RoutingTable, Box<dyn Error>> {
+    load_routing_table_with_options(file_path, &LoadOptions::default())
+}
+
+pub fn load_routing_table_with_options<P: AsRef<Path>>(
+    file_path: &P,
+    options: &LoadOptions,
+) -> Result<RoutingTable, Box<dyn Error>> {
+    let report = load_rules_with_options(file_path, options)?;
+    for warning in &report.warnings {
+        warn!("{}", warning);
+    }
+    if report.errors.is_empty() {
+        let mut routing_table = RoutingTable {
+            rules: report.rules,
+            output_filters: report.output_filters.into_iter().collect(),
+            note_caps: report.note_caps.into_iter().collect(),
+            ..Default::default()
+        };
+        routing_table.order_rules_by_priority();
+        Ok(routing_table)
+    } else {
+        Err(RuleConfigError { errors: report.errors }.into())
+    }
+}
+
+pub fn load_rules_with_report<P: AsRef<Path>>(file_path: &P) -> Result<ParseReport, io::Error> {
+    load_rules_with_options(file_path, &LoadOptions::default())
+}
+
+pub fn load_rules_with_options<P: AsRef<Path>>(file_path: &P, options: &LoadOptions) -> Result<ParseReport, io::Error> {
+    check_not_directory(file_path)?;
     let file = File::open(file_path)?;
-    let mut rules = Vec::new();
-    let mut errors = Vec::new();
-    for (line_no, line_result) in io::BufReader::new(file).lines().enumerate() {
-        let line = line_result?.trim().to_owned();
-        if line.is_empty() {
+    let lines = read_lines(io::BufReader::new(file))?;
+    let mut report = ParseReport::default();
+    let buses = parse_buses(&lines, &mut report.errors);
+    let declared_ports = parse_declared_ports(&lines, &mut report.errors);
+    let match_mode = parse_match_mode(&lines, &mut report.errors);
+    // Line number and shared condition of all enclosing sections
+    let mut sections: Vec<(usize, Condition)> = Vec::new();
+    // Comment lines since the last empty line or statement, which describe the next rule
+    let mut comment_block: Vec<String> = Vec::new();
+    for (line_no, line) in lines.into_iter().enumerate() {
+        if let Some(comment) = line.strip_prefix(COMMENT_PREFIX) {
+            comment_block.push(comment.trim().to_string());
+            continue;
+        }
+        let comments = mem::take(&mut comment_block);
+        if line.is_empty() || is_directive(&line, BUS_KEYWORD) || is_directive(&line, PORTS_KEYWORD)
+            || is_directive(&line, MATCH_MODE_KEYWORD) {
+            continue;
+        }
+        if is_directive(&line, SECTION_KEYWORD) {
+            match parse_section_start(line_no, &line) {
+                Ok(condition) => sections.push((line_no, condition)),
+                Err(error) => {
+                    report.errors.push(error);
+                    sections.push((line_no, Condition::default()));
+                },
+            }
+            continue;
+        }
+        if line == SECTION_END {
+            if sections.pop().is_none() {
+                report.errors.push(invalid_line(line_no, 0, &line));
+            }
+            continue;
+        }
+        if is_directive(&line, FILTER_KEYWORD) {
+            match parse_filter_directive(line_no, &line) {
+                Ok(output_filter) => report.output_filters.push(output_filter),
+                Err(error) => report.errors.push(error),
+            }
+            continue;
+        }
+        if is_directive(&line, CAP_KEYWORD) {
+            match parse_cap_directive(line_no, &line) {
+                Ok(note_cap) => report.note_caps.push(note_cap),
+                Err(error) => report.errors.push(error),
+            }
+            continue;
+        }
+        let rule_strs = split_rules(&line);
+        let has_several_rules = rule_strs.len() > 1;
+        for (rule_no, rule_str) in rule_strs.into_iter().enumerate() {
+            let mut parser = RuleParser::new(&buses);
+            parser.match_mode = match_mode;
+            if options.strict_ports {
+                parser.declared_ports = Some(&declared_ports);
+            }
+            match parser.parse(line_no, rule_str.to_string()) {
+                Ok(_) if !parser.enabled => {},
+                Ok(mut rule) => {
+                    for (_, section_condition) in sections.iter().rev() {
+                        rule.condition.inherit(section_condition);
+                    }
+                    if rule.description.is_none() && rule_no == 0 && !comments.is_empty() {
+                        rule.description = Some(comments.join(" "));
+                    }
+                    if rule.actions.iter().all(|action| matches!(action, Action::Drop)) {
+                        report.warnings.push(RuleWarning::DropsAllEvents {
+                            line_no,
+                            rule_no: Some(rule_no).filter(|_| has_several_rules),
+                        });
+                    }
+                    report.rules.push(rule);
+                },
+                Err(RuleParseError::InvalidFields { line_no, invalid_fields, .. }) => {
+                    report.errors.push(RuleParseError::InvalidFields {
+                        line_no,
+                        rule_no: Some(rule_no).filter(|_| has_several_rules),
+                        invalid_fields,
+                    });
+                },
+            }
+        }
+    }
+    for (line_no, _) in sections {
+        report.errors.push(invalid_line(line_no, 0, SECTION_KEYWORD));
+    }
+    Ok(report)
+}
+
+/// Fails with an `IsDirectoryError` if the config file is a directory. Other special files, e.g.
+/// pipes, can be read like regular files.
+pub(super) fn check_not_directory<P: AsRef<Path>>(file_path: &P) -> Result<(), io::Error> {
+    if fs::metadata(file_path)?.is_dir() {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, IsDirectoryError))?
+    }
+    Ok(())
+}
+
+/// Reads the trimmed lines of a config file without the byte order mark some editors put at its
+/// start. Fails with an `InvalidUtf8Error` naming the line if the file is not valid UTF-8.
+fn read_lines<R: BufRead>(reader: R) -> Result<Vec<String>, io::Error> {
+    reader.split(b'\n').enumerate()
+        .map(|(line_no, bytes)| {
+            let line = String::from_utf8(bytes?)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, InvalidUtf8Error { line_no }))?;
+            let line = line.strip_prefix(BYTE_ORDER_MARK).filter(|_| line_no == 0).unwrap_or(&line);
+            Ok(line.trim().to_owned())
+        })
+        .collect()
+}
+
+/// Splits a line into the rules separated by `;`, ignoring a trailing separator
+fn split_rules(line: &str) -> Vec<&str> {
+    let line = line.strip_suffix(RULE_SEPARATOR).unwrap_or(line);
+    line.split(RULE_SEPARATOR).map(str::trim).collect()
+}
+
+/// Parses the start of a section of the form `with <CONDITION> {`, whose condition is shared by
+/// all enclosed rules
+fn parse_section_start(line_no: usize, line: &str) -> Result<Condition, RuleParseError> {
+    let fields = split_fields(line);
+    if fields.len() < 3 || fields[fields.len() - 1] != SECTION_START {
+        Err(invalid_line(line_no, fields.len() - 1, fields[fields.len() - 1]))?
+    }
+
+    let mut parser = RuleParser::new(&EMPTY_BUS_TABLE);
+    parser.name_field_id = usize::MAX;  // all fields are condition fields
+    for (field_id, value) in fields.iter().enumerate().take(fields.len() - 1).skip(1) {
+        if *value == OR_KEYWORD {
+            Err(invalid_line(line_no, field_id, value))?
+        }
+        parser.parse_lhs(field_id, value);
+    }
+    if !parser.errors.is_empty() {
+        Err(RuleParseError::InvalidFields { line_no, rule_no: None, invalid_fields: mem::take(&mut parser.errors) })?
+    }
+    Ok(parser.build_condition())
+}
+
+fn invalid_line(line_no: usize, field_id: usize, content: &str) -> RuleParseError {
+    RuleParseError::InvalidFields {
+        line_no,
+        rule_no: None,
+        invalid_fields: vec![FieldParseError {
+            field_id,
+            content: content.to_string(),
+            reason: Some(FieldFormatError::InvalidFormat.into()),
+        }],
+    }
+}
+
+/// Collects all bus definitions in advance, so that buses can be used before their definition
+fn parse_buses(lines: &[String], errors: &mut Vec<RuleParseError>) -> BusTable {
+    let mut buses = BusTable::new();
+    for (line_no, line) in lines.iter().enumerate() {
+        if !is_directive(line, BUS_KEYWORD) {
             continue;
         }
-        match parse_rule(line_no, line) {
-            Ok(rule) => rules.push(rule),
+        match parse_bus_directive(line_no, line) {
+            Ok((name, _)) if buses.contains_key(&name) => errors.push(RuleParseError::InvalidFields {
+                line_no,
+                rule_no: None,
+                invalid_fields: vec![FieldParseError {
+                    field_id: 1,
+                    content: name,
+                    reason: Some(FieldFormatError::DuplicateBus.into()),
+                }],
+            }),
+            Ok((name, output_ports)) => {
+                buses.insert(name, output_ports);
+            },
             Err(error) => errors.push(error),
         }
     }
+    buses
+}
 
-    if errors.is_empty() {
-        Ok(rules)
-    } else {
-        Err(RuleConfigError { errors }.into())
+/// Collects the port names of all `ports: <PORT>...` headers, which declare the output ports
+/// that rules may send to in strict mode
+fn parse_declared_ports(lines: &[String], errors: &mut Vec<RuleParseError>) -> HashSet<String> {
+    let mut declared_ports = HashSet::new();
+    for (line_no, line) in lines.iter().enumerate() {
+        if !is_directive(line, PORTS_KEYWORD) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 2 {
+            errors.push(invalid_line(line_no, 1, ""));
+        }
+        declared_ports.extend(fields[1..].iter().map(|port| port.to_string()));
     }
+    declared_ports
 }
 
 fn _parse_version(line_no: usize, line: &String) -> Option<String> {
@@ -55,30 +392,188 @@ fn _parse_version(line_no: usize, line: &String) -> Option<String> {
     }
 }
 
-fn parse_rule(line_no: usize, line: String) -> Result<Rule, RuleParseError> {
-    RuleParser::new().parse(line_no, line)
+/// Parses all `match-mode <MODE> [<MODE>]` directives, which apply to all rules of the file
+fn parse_match_mode(lines: &[String], errors: &mut Vec<RuleParseError>) -> MatchMode {
+    let mut match_mode = MatchMode::default();
+    for (line_no, line) in lines.iter().enumerate() {
+        if !is_directive(line, MATCH_MODE_KEYWORD) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 2 {
+            errors.push(invalid_line(line_no, 1, ""));
+        }
+        for (field_id, mode) in fields.iter().enumerate().skip(1) {
+            match *mode {
+                ANCHORED_MODE => match_mode.anchored = true,
+                CASE_INSENSITIVE_MODE => match_mode.case_insensitive = true,
+                _ => errors.push(invalid_line(line_no, field_id, mode)),
+            }
+        }
+    }
+    match_mode
+}
+
+pub(super) fn is_directive(line: &str, keyword: &str) -> bool {
+    line.split_whitespace().next() == Some(keyword)
+}
+
+/// Parses a note cap of the form `cap <PORT> <N> [drop|steal]`, which drops new notes by default
+fn parse_cap_directive(line_no: usize, line: &str) -> Result<(String, NoteCap), RuleParseError> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if !(3..=4).contains(&fields.len()) {
+        Err(invalid_line(line_no, fields.len().min(4), fields.get(4).unwrap_or(&"")))?
+    }
+    let max_notes = fields[2].parse::<usize>().map_err(|err| RuleParseError::InvalidFields {
+        line_no,
+        rule_no: None,
+        invalid_fields: vec![FieldParseError {
+            field_id: 2,
+            content: fields[2].to_string(),
+            reason: Some(err.into()),
+        }],
+    })?;
+    let policy = match fields.get(3) {
+        None | Some(&CAP_POLICY_DROP) => NoteCapPolicy::Drop,
+        Some(&CAP_POLICY_STEAL) => NoteCapPolicy::Steal,
+        Some(policy) => Err(invalid_line(line_no, 3, policy))?,
+    };
+    Ok((fields[1].to_string(), NoteCap { max_notes, policy }))
+}
+
+/// Parses an output filter of the form `filter <PORT> ch<N>`
+fn parse_filter_directive(line_no: usize, line: &str) -> Result<(String, OutputFilter), RuleParseError> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let invalid_field = |field_id: usize, reason: Box<dyn Error>| RuleParseError::InvalidFields {
+        line_no,
+        rule_no: None,
+        invalid_fields: vec![FieldParseError {
+            field_id,
+            content: fields.get(field_id).unwrap_or(&"").to_string(),
+            reason: Some(reason),
+        }],
+    };
+
+    if fields.len() != 3 {
+        Err(invalid_field(fields.len().min(3), FieldFormatError::InvalidFormat.into()))?
+    }
+    let channel_field = FIELD_PAT.captures(fields[2])
+        .ok_or(FieldParseError {
+            field_id: 2,
+            content: fields[2].to_string(),
+            reason: Some(FieldFormatError::InvalidFormat.into()),
+        })
+        .and_then(|captures| parse_value_field(2, fields[2], captures));
+    match channel_field {
+        Ok(Field::ChannelField { start, end }) => Ok((
+            fields[1].to_string(),
+            OutputFilter { channel_pattern: NumericRange { start, end } },
+        )),
+        Ok(_) => Err(invalid_field(2, FieldFormatError::InvalidFormat.into())),
+        Err(error) => Err(RuleParseError::InvalidFields { line_no, rule_no: None, invalid_fields: vec![error] }),
+    }
+}
+
+/// Parses a bus definition of the form `bus <NAME> = <PORT> [<PORT> ...]`
+fn parse_bus_directive(line_no: usize, line: &str) -> Result<(String, Vec<String>), RuleParseError> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let invalid_field = |field_id: usize| RuleParseError::InvalidFields {
+        line_no,
+        rule_no: None,
+        invalid_fields: vec![FieldParseError {
+            field_id,
+            content: fields.get(field_id).unwrap_or(&"").to_string(),
+            reason: Some(FieldFormatError::InvalidFormat.into()),
+        }],
+    };
+    let is_port_name = |name: &str| !name.starts_with(BUS_PREFIX) && !name.contains(ACTION_SEPARATOR);
+
+    if fields.len() < 4 {
+        Err(invalid_field(fields.len()))?
+    }
+    if !is_port_name(fields[1]) {
+        Err(invalid_field(1))?
+    }
+    if fields[2] != ASSIGN_SYMBOL {
+        Err(invalid_field(2))?
+    }
+    if let Some(field_id) = (3..fields.len()).find(|&field_id| !is_port_name(fields[field_id])) {
+        Err(invalid_field(field_id))?
+    }
+    Ok((
+        fields[1].to_string(),
+        fields[3..].iter().map(|output_port| output_port.to_string()).collect(),
+    ))
+}
+
+pub fn parse_rule(line_no: usize, line: String) -> Result<Rule, RuleParseError> {
+    parse_rule_with_buses(line_no, line, &EMPTY_BUS_TABLE)
 }
 
-struct RuleParser {
+fn parse_rule_with_buses(line_no: usize, line: String, buses: &BusTable) -> Result<Rule, RuleParseError> {
+    RuleParser::new(buses).parse(line_no, line)
+}
+
+struct RuleParser<'a> {
+    buses: &'a BusTable,
+    /// Output ports which actions may send to, or `None` to allow all ports
+    declared_ports: Option<&'a HashSet<String>>,
+    match_mode: MatchMode,
     condition_builder: ConditionBuilder,
+    alternatives: Vec<Condition>,
+    name_field_id: usize,
     errors: Vec<FieldParseError>,
-    output_names: Vec<String>,
+    actions: Vec<Action>,
+    /// Description given by the `label` option of the rule
+    label: Option<String>,
+    /// Whether the rule is applied, which is given by the `enabled` option of the rule
+    enabled: bool,
+    /// Whether the rule only logs what it would send, which is given by the `dryrun` option
+    dry_run: bool,
+    /// Order in which the rule is applied, which is given by the `priority` option
+    priority: i16,
+    /// Rules of which only the first matching one is applied, which is given by the `group` option
+    group: Option<String>,
+    /// Whether the rule starts with `not`, which negates its condition
+    negated: bool,
     state: RuleParserState,
 }
 
-impl RuleParser {
-    fn new() -> Self {
+impl<'a> RuleParser<'a> {
+    fn new(buses: &'a BusTable) -> Self {
         RuleParser {
+            buses,
+            declared_ports: None,
+            match_mode: MatchMode::default(),
             condition_builder: ConditionBuilder::new(),
+            alternatives: Vec::new(),
+            name_field_id: 0,
             errors: Vec::new(),
-            output_names: Vec::new(),
+            actions: Vec::new(),
+            label: None,
+            enabled: true,
+            dry_run: false,
+            priority: 0,
+            group: None,
+            negated: false,
             state: RuleParserState::ParseLeftHandSide,
         }
     }
 
     fn parse(&mut self, line_no: usize, line: String) -> Result<Rule, RuleParseError> {
-        for (field_id, value) in line.trim().split_whitespace().enumerate() {
+        let (line, options) = split_options(line.trim());
+        let fields = split_fields(line);
+        let field_count = fields.len();
+        if let Some(options) = options {
+            self.parse_options(field_count, options);
+        }
+        self.negated = fields.first() == Some(&NOT_KEYWORD);
+        if self.negated {
+            self.name_field_id = 1;
+        }
+        for (field_id, value) in fields.into_iter().enumerate().skip(self.negated as usize) {
             if value == FORWARD_SYMBOL {
+                self.check_alternative_complete(field_id);
                 self.state = RuleParserState::ParseRightHandSide;
                 continue;
             }
@@ -87,38 +582,218 @@ impl RuleParser {
                 RuleParserState::ParseRightHandSide => self.parse_rhs(field_id, value),
             }
         }
+        if let RuleParserState::ParseLeftHandSide = self.state {
+            self.check_alternative_complete(field_count);
+        }
 
         if self.errors.len() > 0 {
             Err(RuleParseError::InvalidFields {
                 line_no,
+                rule_no: None,
                 invalid_fields: mem::take(&mut self.errors),
             })?
         }
 
-        let actions = mem::take(&mut self.output_names).into_iter()
-            .map(|name| Action::ForwardTo { output_port: name })
-            .collect();
-
         Ok(Rule {
-            condition: self.condition_builder.build(),
-            actions,
+            condition: self.build_condition(),
+            actions: mem::take(&mut self.actions),
+            description: self.label.take(),
+            negated: self.negated,
+            dry_run: self.dry_run,
+            priority: self.priority,
+            group: self.group.take(),
         })
     }
 
+    /// Applies the options of an options block given without braces, e.g. `label=Drums, enabled=false`
+    fn parse_options(&mut self, field_id: usize, options: &str) {
+        for option in options.split(OPTIONS_SEPARATOR).map(str::trim).filter(|option| !option.is_empty()) {
+            let create_error = |reason: FieldFormatError| FieldParseError {
+                field_id,
+                content: option.to_string(),
+                reason: Some(reason.into()),
+            };
+            let Some((key, value)) = option.split_once(ASSIGN_SYMBOL) else {
+                self.errors.push(create_error(FieldFormatError::InvalidFormat));
+                continue;
+            };
+            match (key.trim(), value.trim()) {
+                (LABEL_OPTION, label) if !label.is_empty() => self.label = Some(label.to_string()),
+                (ENABLED_OPTION, "true") => self.enabled = true,
+                (ENABLED_OPTION, "false") => self.enabled = false,
+                (DRY_RUN_OPTION, "true") => self.dry_run = true,
+                (DRY_RUN_OPTION, "false") => self.dry_run = false,
+                (PRIORITY_OPTION, priority) => match priority.parse::<i16>() {
+                    Ok(priority) => self.priority = priority,
+                    Err(_) => self.errors.push(create_error(FieldFormatError::InvalidFormat)),
+                },
+                (GROUP_OPTION, group) if !group.is_empty() => self.group = Some(group.to_string()),
+                (LABEL_OPTION | ENABLED_OPTION | DRY_RUN_OPTION | GROUP_OPTION, _) => {
+                    self.errors.push(create_error(FieldFormatError::InvalidFormat))
+                },
+                _ => self.errors.push(create_error(FieldFormatError::UnknownOption)),
+            }
+        }
+    }
+
+    fn build_condition(&mut self) -> Condition {
+        self.alternatives.push(self.condition_builder.build());
+        let mut conditions = mem::take(&mut self.alternatives).into_iter();
+        let mut condition = conditions.next().unwrap_or_default();
+        condition.alternatives = conditions.collect();
+        condition
+    }
+
+    /// Reports an `or` which is not followed by another condition
+    fn check_alternative_complete(&mut self, field_id: usize) {
+        if self.name_field_id == field_id && field_id > 0 {
+            let keyword = match self.negated && field_id == 1 {
+                true => NOT_KEYWORD,
+                false => OR_KEYWORD,
+            };
+            self.errors.push(FieldParseError {
+                field_id: field_id - 1,
+                content: keyword.to_string(),
+                reason: Some(FieldFormatError::InvalidFormat.into()),
+            });
+        }
+    }
+
     fn parse_lhs(&mut self, field_id: usize, value: &str) {
-        match parse_field_lhs(field_id, value) {
+        if value == OR_KEYWORD && field_id == self.name_field_id {
+            // an `or` must follow a condition
+            self.errors.push(FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(FieldFormatError::InvalidFormat.into()),
+            });
+            self.name_field_id = field_id + 1;
+            return;
+        }
+        if value == OR_KEYWORD {
+            self.alternatives.push(self.condition_builder.build());
+            self.name_field_id = field_id + 1;
+            return;
+        }
+
+        let field = if value == ANY_NOTE_KEYWORD {
+            Ok(Field::CategoryField { category: EventCategory::Note })
+        } else if value == ZERO_VELOCITY_OFF_KEYWORD {
+            Ok(Field::ZeroVelocityOffField)
+        } else if value == SUSTAINED_KEYWORD {
+            Ok(Field::SustainedField)
+        } else if value == STUCK_KEYWORD {
+            Ok(Field::StuckField)
+        } else if value == RELEASE_KEYWORD {
+            Ok(Field::ReleaseField)
+        } else if value == RUNNING_STATUS_KEYWORD {
+            Ok(Field::RunningStatusField)
+        } else if value == SWITCH_KEYWORD {
+            Ok(Field::ControllerKindField { controller_kind: ControllerKind::Switch })
+        } else if value == CONTINUOUS_KEYWORD {
+            Ok(Field::ControllerKindField { controller_kind: ControllerKind::Continuous })
+        } else if field_id == self.name_field_id {
+            parse_name_pattern_field(field_id, value, self.match_mode)
+        } else {
+            parse_field_lhs(field_id, value)
+        };
+        match field {
             Ok(Field::NameField { name_pattern }) => {
                 self.condition_builder.event_pattern = Some(name_pattern);
             },
+            Ok(Field::CategoryField { category }) => {
+                self.condition_builder.category_pattern = Some(category);
+            },
+            Ok(Field::ZeroVelocityOffField) => {
+                self.condition_builder.treat_zero_vel_as_off = true;
+            },
+            Ok(Field::SustainedField) => {
+                self.condition_builder.sustained = true;
+            },
+            Ok(Field::StuckField) => {
+                self.condition_builder.stuck = true;
+            },
+            Ok(Field::ReleaseField) => {
+                self.condition_builder.release = true;
+            },
+            Ok(Field::RunningStatusField) => {
+                self.condition_builder.running_status = true;
+            },
+            Ok(Field::TransportField { transport_state }) => {
+                self.condition_builder.transport_state = Some(transport_state);
+            },
+            Ok(Field::ScaleField { scale }) => {
+                self.condition_builder.scale = Some(scale);
+            },
+            Ok(Field::TimeWindowField { time_window }) => {
+                self.condition_builder.time_window = Some(time_window);
+            },
+            Ok(Field::ControllerSetField { controller_set }) => {
+                self.condition_builder.controller_set = Some(controller_set);
+            },
+            Ok(Field::ControllerKindField { controller_kind }) => {
+                self.condition_builder.controller_kind = Some(controller_kind);
+            },
+            Ok(Field::SysExField { sysex_id }) => {
+                self.condition_builder.sysex_sub_id = Some(sysex_id);
+            },
+            Ok(Field::SilenceField { min_silence_ms }) => {
+                self.condition_builder.min_silence_ms = Some(min_silence_ms);
+            },
+            Ok(Field::EveryField { every }) => {
+                self.condition_builder.every = Some(every);
+            },
+            Ok(Field::AlternateField { alternate }) => {
+                self.condition_builder.alternate = Some(alternate);
+            },
+            Ok(Field::HeldField { start, end }) => {
+                let held_pattern = self.condition_builder.held_pattern.get_or_insert(NumericRange { start, end: u64::MAX });
+                held_pattern.start = max(held_pattern.start, start);
+                held_pattern.end = min(held_pattern.end, end);
+            },
+            Ok(Field::SinceProgramChangeField { start, end }) => {
+                let pattern = self.condition_builder.since_program_change_pattern
+                    .get_or_insert(NumericRange { start, end: u64::MAX });
+                pattern.start = max(pattern.start, start);
+                pattern.end = min(pattern.end, end);
+            },
             Ok(Field::ValueField {start, end}) => {
                 self.condition_builder.value_pattern = Some(NumericRange { start, end });
             },
             Ok(Field::ChannelField {start, end}) => {
                 self.condition_builder.channel_pattern = Some(NumericRange {start, end });
             },
+            Ok(Field::TempoField {start, end}) => {
+                self.condition_builder.tempo_pattern = Some(NumericRange {start, end });
+            },
+            Ok(Field::LengthField {start, end}) => {
+                self.condition_builder.length_pattern = Some(NumericRange {start, end });
+            },
+            Ok(Field::VoiceField {start, end}) => {
+                self.condition_builder.voice_pattern = Some(NumericRange {start, end });
+            },
+            Ok(Field::PitchBendMsbField {start, end}) => {
+                self.condition_builder.pitch_bend_msb_pattern = Some(NumericRange {start, end });
+            },
+            Ok(Field::PitchBendLsbField {start, end}) => {
+                self.condition_builder.pitch_bend_lsb_pattern = Some(NumericRange {start, end });
+            },
+            Ok(Field::BankField {start, end}) => {
+                self.condition_builder.bank_pattern = Some(NumericRange {start, end });
+            },
             Ok(Field::VelocityField {start, end}) => {
                 self.condition_builder.velocity_pattern = Some(NumericRange {start, end });
             },
+            Ok(Field::VelocityComplementField {start, end}) => {
+                self.condition_builder.velocity_pattern = Some(NumericRange {start, end });
+                self.condition_builder.velocity_complement = true;
+            },
+            Ok(Field::ReleaseVelocityField {start, end}) => {
+                self.condition_builder.release_velocity_pattern = Some(NumericRange {start, end });
+            },
+            Ok(Field::SlewField {start, end}) => {
+                self.condition_builder.slew_pattern = Some(NumericRange {start, end });
+            },
             Ok(Field::ControlNoField {start, end}) => {
                 self.condition_builder.control_no_pattern = Some(NumericRange {start, end });
             },
@@ -126,38 +801,213 @@ impl RuleParser {
         }
     }
 
-    fn parse_rhs(&mut self, _: usize, value: &str) {
-        self.output_names.push(value.to_string());
+    fn parse_rhs(&mut self, field_id: usize, value: &str) {
+        if let Some(bus_ref) = value.strip_prefix(BUS_PREFIX) {
+            return self.parse_bus_ref(field_id, value, bus_ref);
+        }
+        match parse_field_rhs(field_id, value) {
+            Ok(action) => self.push_action(field_id, value, action),
+            Err(error) => self.errors.push(error),
+        }
+    }
+
+    fn push_action(&mut self, field_id: usize, value: &str, action: Action) {
+        let is_declared = |output_port: &str| match self.declared_ports {
+            Some(declared_ports) => declared_ports.contains(output_port),
+            None => true,
+        };
+        match action.output_port() {
+            Some(_) if action.has_dynamic_output_port() && self.negated => {
+                self.errors.push(FieldParseError {
+                    field_id,
+                    content: value.to_string(),
+                    reason: Some(FieldFormatError::CaptureInNegatedRule.into()),
+                })
+            },
+            Some(output_port) if !action.has_dynamic_output_port() && !is_declared(output_port) => {
+                self.errors.push(FieldParseError {
+                    field_id,
+                    content: value.to_string(),
+                    reason: Some(FieldFormatError::UndeclaredPort.into()),
+                })
+            },
+            _ => self.actions.push(action),
+        }
+    }
+
+    /// Expands a bus reference `@<NAME>[:<ACTION>]` to the same action on each port of the bus
+    fn parse_bus_ref(&mut self, field_id: usize, value: &str, bus_ref: &str) {
+        let (bus_name, action_spec) = match bus_ref.split_once(ACTION_SEPARATOR) {
+            Some((bus_name, action_spec)) => (bus_name, Some(action_spec)),
+            None => (bus_ref, None),
+        };
+        let Some(output_ports) = self.buses.get(bus_name) else {
+            return self.errors.push(FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(FieldFormatError::UnknownBus.into()),
+            });
+        };
+        for output_port in output_ports {
+            let port_value = match action_spec {
+                Some(action_spec) => format!("{}{}{}", output_port, ACTION_SEPARATOR, action_spec),
+                None => output_port.clone(),
+            };
+            match parse_field_rhs(field_id, &port_value) {
+                Ok(action) => self.push_action(field_id, value, action),
+                Err(error) => {
+                    self.errors.push(FieldParseError { content: value.to_string(), ..error });
+                    return;
+                },
+            }
+        }
+    }
+}
+
+/// Splits the options block off the end of a rule and returns the options without braces. The
+/// block must be separated by whitespace, so that capture references like `${name}` are kept.
+fn split_options(line: &str) -> (&str, Option<&str>) {
+    let options_block = line.strip_suffix(OPTIONS_END)
+        .and_then(|line| line.rsplit_once(OPTIONS_START));
+    match options_block {
+        Some((rule, options)) if !options.contains(OPTIONS_END) => (rule.trim_end(), Some(options)),
+        _ => (line, None),
+    }
+}
+
+/// Splits a rule into whitespace-separated fields, keeping parenthesized groups together
+pub(super) fn split_fields(line: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut depth = 0usize;
+    let mut field_start = None;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = field_start.take() {
+                    fields.push(&line[start..idx]);
+                }
+                continue;
+            },
+            _ => {},
+        }
+        field_start.get_or_insert(idx);
     }
+    if let Some(start) = field_start {
+        fields.push(&line[start..]);
+    }
+    fields
 }
 
 #[derive(Debug)]
 struct ConditionBuilder {
     pub event_pattern: Option<Regex>,
+    pub category_pattern: Option<EventCategory>,
+    pub treat_zero_vel_as_off: bool,
+    pub sustained: bool,
+    pub stuck: bool,
+    pub release: bool,
+    pub running_status: bool,
+    pub sysex_sub_id: Option<UniversalSysExId>,
+    pub min_silence_ms: Option<u64>,
+    pub held_pattern: Option<NumericRange<u64>>,
+    pub since_program_change_pattern: Option<NumericRange<u64>>,
+    pub tempo_pattern: Option<NumericRange<u16>>,
+    pub bank_pattern: Option<NumericRange<u16>>,
+    pub time_window: Option<TimeWindow>,
+    pub scale: Option<Scale>,
+    pub transport_state: Option<TransportState>,
+    pub length_pattern: Option<NumericRange<u16>>,
+    pub voice_pattern: Option<NumericRange<u8>>,
+    pub every: Option<u64>,
+    pub alternate: Option<u8>,
     pub channel_pattern: Option<NumericRange<u8>>,
     pub value_pattern: Option<NumericRange<i16>>,
+    pub pitch_bend_msb_pattern: Option<NumericRange<u8>>,
+    pub pitch_bend_lsb_pattern: Option<NumericRange<u8>>,
     pub velocity_pattern: Option<NumericRange<u8>>,
+    pub velocity_complement: bool,
+    pub release_velocity_pattern: Option<NumericRange<u8>>,
     pub control_no_pattern: Option<NumericRange<u8>>,
+    pub controller_set: Option<ControllerSet>,
+    pub controller_kind: Option<ControllerKind>,
+    pub slew_pattern: Option<NumericRange<u8>>,
 }
 
 impl ConditionBuilder {
     fn new() -> Self {
         ConditionBuilder {
             event_pattern: None,
+            category_pattern: None,
+            treat_zero_vel_as_off: false,
+            sustained: false,
+            stuck: false,
+            release: false,
+            running_status: false,
+            sysex_sub_id: None,
+            min_silence_ms: None,
+            held_pattern: None,
+            since_program_change_pattern: None,
+            tempo_pattern: None,
+            bank_pattern: None,
+            time_window: None,
+            scale: None,
+            transport_state: None,
+            length_pattern: None,
+            voice_pattern: None,
+            every: None,
+            alternate: None,
             channel_pattern: None,
             value_pattern: None,
+            pitch_bend_msb_pattern: None,
+            pitch_bend_lsb_pattern: None,
             velocity_pattern: None,
+            velocity_complement: false,
+            release_velocity_pattern: None,
             control_no_pattern: None,
+            controller_set: None,
+            controller_kind: None,
+            slew_pattern: None,
         }
     }
 
     fn build(&mut self) -> Condition {
         Condition {
             event_pattern: mem::take(&mut self.event_pattern),
+            category_pattern: mem::take(&mut self.category_pattern),
+            treat_zero_vel_as_off: mem::take(&mut self.treat_zero_vel_as_off),
+            sustained: mem::take(&mut self.sustained),
+            stuck: mem::take(&mut self.stuck),
+            release: mem::take(&mut self.release),
+            running_status: mem::take(&mut self.running_status),
+            sysex_sub_id: mem::take(&mut self.sysex_sub_id),
+            min_silence_ms: mem::take(&mut self.min_silence_ms),
+            held_pattern: mem::take(&mut self.held_pattern),
+            since_program_change_pattern: mem::take(&mut self.since_program_change_pattern),
+            tempo_pattern: mem::take(&mut self.tempo_pattern),
+            bank_pattern: mem::take(&mut self.bank_pattern),
+            time_window: mem::take(&mut self.time_window),
+            scale: mem::take(&mut self.scale),
+            transport_state: mem::take(&mut self.transport_state),
+            length_pattern: mem::take(&mut self.length_pattern),
+            voice_pattern: mem::take(&mut self.voice_pattern),
+            every: mem::take(&mut self.every),
+            alternate: mem::take(&mut self.alternate),
+            match_count: AtomicU64::new(0),
+            event_types: OnceLock::new(),
             channel_pattern: mem::take(&mut self.channel_pattern),
             value_pattern: mem::take(&mut self.value_pattern),
+            pitch_bend_msb_pattern: mem::take(&mut self.pitch_bend_msb_pattern),
+            pitch_bend_lsb_pattern: mem::take(&mut self.pitch_bend_lsb_pattern),
             velocity_pattern: mem::take(&mut self.velocity_pattern),
+            velocity_complement: mem::take(&mut self.velocity_complement),
+            release_velocity_pattern: mem::take(&mut self.release_velocity_pattern),
             controller_pattern: mem::take(&mut self.control_no_pattern),
+            controller_set: mem::take(&mut self.controller_set),
+            controller_kind: mem::take(&mut self.controller_kind),
+            slew_pattern: mem::take(&mut self.slew_pattern),
+            alternatives: Vec::new(),
         }
     }
 }
@@ -169,7 +1019,78 @@ enum RuleParserState {
 
 fn parse_field_lhs(field_id: usize, value: &str) -> Result<Field, FieldParseError> {
     if field_id == 0 {
-        parse_name_pattern_field(field_id, value)
+        parse_name_pattern_field(field_id, value, MatchMode::default())
+    } else if let Some(sysex_id) = value.strip_prefix(SYSEX_PREFIX) {
+        parse_sysex_field(sysex_id).map_err(|reason| FieldParseError {
+            field_id,
+            content: value.to_string(),
+            reason: Some(reason.into()),
+        })
+    } else if let Some(transport_state) = value.strip_prefix(TRANSPORT_PREFIX) {
+        match transport_state {
+            "rolling" => Ok(Field::TransportField { transport_state: TransportState::Rolling }),
+            "stopped" => Ok(Field::TransportField { transport_state: TransportState::Stopped }),
+            _ => Err(FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(FieldFormatError::InvalidFormat.into()),
+            }),
+        }
+    } else if let Some(velocity_range) = value.strip_prefix(VELOCITY_COMPLEMENT_PREFIX) {
+        parse_velocity_complement_field(field_id, value, velocity_range)
+    } else if let Some(scale) = value.strip_prefix(SCALE_PREFIX) {
+        scale.parse::<Scale>()
+            .map(|scale| Field::ScaleField { scale })
+            .map_err(|_| FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(FieldFormatError::InvalidFormat.into()),
+            })
+    } else if let Some(min_silence_ms) = value.strip_prefix(AFTER_QUIET_PREFIX) {
+        parse_duration_ms(field_id, value, min_silence_ms)
+            .map(|min_silence_ms| Field::SilenceField { min_silence_ms })
+    } else if let Some(min_held_ms) = value.strip_prefix(HELD_OVER_PREFIX) {
+        parse_duration_ms(field_id, value, min_held_ms)
+            .map(|min_held_ms| Field::HeldField { start: min_held_ms, end: u64::MAX })
+    } else if let Some(max_held_ms) = value.strip_prefix(HELD_UNDER_PREFIX) {
+        parse_duration_ms(field_id, value, max_held_ms)
+            .and_then(|max_held_ms| max_held_ms.checked_sub(1).ok_or(FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(FieldFormatError::InvalidFormat.into()),
+            }))
+            .map(|max_held_ms| Field::HeldField { start: 0, end: max_held_ms })
+    } else if let Some(bound) = value.strip_prefix(SINCE_PROGRAM_CHANGE_PREFIX) {
+        parse_since_program_change_field(field_id, value, bound)
+    } else if let Some(every) = value.strip_prefix(EVERY_PREFIX) {
+        match every.parse::<u64>() {
+            Ok(every) if every > 0 => Ok(Field::EveryField { every }),
+            _ => Err(FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(FieldFormatError::InvalidFormat.into()),
+            }),
+        }
+    } else if let Some(alternate) = value.strip_prefix(ALTERNATE_PREFIX) {
+        match alternate {
+            "0" => Ok(Field::AlternateField { alternate: 0 }),
+            "1" => Ok(Field::AlternateField { alternate: 1 }),
+            _ => Err(FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(FieldFormatError::InvalidFormat.into()),
+            }),
+        }
+    } else if let Some(time_window) = value.strip_prefix(TIME_PREFIX) {
+        parse_time_window(time_window)
+            .map(|time_window| Field::TimeWindowField { time_window })
+            .map_err(|reason| FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(reason.into()),
+            })
+    } else if let Some(captures) = CONTROLLER_SET_PAT.captures(value) {
+        parse_controller_set_field(field_id, value, captures)
     } else if let Some(captures) = FIELD_PAT.captures(value) {
         parse_value_field(field_id, value, captures)
     } else {
@@ -181,61 +1102,403 @@ fn parse_field_lhs(field_id: usize, value: &str) -> Result<Field, FieldParseErro
     }
 }
 
-fn parse_name_pattern_field(field_id: usize, value: &str) -> Result<Field, FieldParseError> {
-    match Regex::new(value) {
-        Ok(name_pattern) => Ok(Field::NameField { name_pattern }),
-        Err(err) => Err(FieldParseError {
-            field_id,
-            content: value.to_string(),
-            reason: Some(err.into()),
+fn parse_duration_ms(field_id: usize, value: &str, duration_ms: &str) -> Result<u64, FieldParseError> {
+    duration_ms.parse::<u64>().map_err(|err| FieldParseError {
+        field_id,
+        content: value.to_string(),
+        reason: Some(err.into()),
+    })
+}
+
+/// Parses the bound of the time since the last program change, e.g. `>2000` for more than two
+/// seconds or `<500`
+fn parse_since_program_change_field(field_id: usize, value: &str, bound: &str) -> Result<Field, FieldParseError> {
+    let invalid_format = || FieldParseError {
+        field_id,
+        content: value.to_string(),
+        reason: Some(FieldFormatError::InvalidFormat.into()),
+    };
+    if let Some(lower_bound) = bound.strip_prefix('>') {
+        parse_duration_ms(field_id, value, lower_bound)?
+            .checked_add(1)
+            .map(|start| Field::SinceProgramChangeField { start, end: u64::MAX })
+            .ok_or_else(invalid_format)
+    } else if let Some(upper_bound) = bound.strip_prefix('<') {
+        parse_duration_ms(field_id, value, upper_bound)?
+            .checked_sub(1)
+            .map(|end| Field::SinceProgramChangeField { start: 0, end })
+            .ok_or_else(invalid_format)
+    } else {
+        Err(invalid_format())
+    }
+}
+
+/// Parses the hexadecimal IDs of a universal SysEx message, e.g. `7f,06,01`
+fn parse_sysex_field(sysex_id: &str) -> Result<Field, FieldFormatError> {
+    let ids = sysex_id.split(',')
+        .map(|id| u8::from_str_radix(id, 16).map_err(|_| FieldFormatError::InvalidFormat))
+        .collect::<Result<Vec<u8>, FieldFormatError>>()?;
+    match ids.as_slice() {
+        [universal_id @ (0x7e | 0x7f), sub_id1, rest @ ..] if rest.len() <= 1 => Ok(Field::SysExField {
+            sysex_id: UniversalSysExId {
+                universal_id: *universal_id,
+                sub_id1: *sub_id1,
+                sub_id2: rest.first().copied(),
+            },
         }),
+        _ => Err(FieldFormatError::InvalidFormat),
     }
 }
 
-fn parse_value_field(field_id: usize, value: &str, captures: Captures) -> Result<Field, FieldParseError> {
-    let value_type_str = captures.name("type").map_or("", |m| m.as_str());
+/// Returns the actions following the alias `stdout:` of the port `-`, e.g. `:transpose(+12)`
+fn strip_text_sink_alias(value: &str) -> Option<&str> {
+    value.strip_prefix(TEXT_SINK_ALIAS)
+        .filter(|action_spec| action_spec.is_empty() || action_spec.starts_with(ACTION_SEPARATOR))
+}
 
-    let match_to_i16 = |m: Match| m.as_str()
-        .parse::<i16>()
-        .map_err(|err| FieldParseError {
-            field_id,
-            content: value.into(),
-            reason: Some(err.into()),
-        });
-    let get_match_as_i16 = |name: &str| {
-        let opt_value = captures.name(name).map(match_to_i16);
-        switch_option_and_result(opt_value)
+fn parse_field_rhs(field_id: usize, value: &str) -> Result<Action, FieldParseError> {
+    let create_error = |reason: FieldFormatError| FieldParseError {
+        field_id,
+        content: value.to_string(),
+        reason: Some(reason.into()),
     };
 
-    let default_start = if value_type_str == "" { i16::MIN } else { u8::MIN as i16 };
-    let default_end = if value_type_str == "" { i16::MAX } else { u8::MAX as i16 };
+    if let Some(action_value) = value.strip_suffix(LOG_MODIFIER) {
+        let action = parse_field_rhs(field_id, action_value).map_err(|error| FieldParseError {
+            content: value.to_string(),
+            ..error
+        })?;
+        return Ok(Action::Logged { action: Box::new(action) });
+    }
+    if let Some(action_value) = value.strip_suffix(PICKUP_MODIFIER) {
+        let action = parse_field_rhs(field_id, action_value).map_err(|error| FieldParseError {
+            content: value.to_string(),
+            ..error
+        })?;
+        return Ok(Action::Pickup { action: Box::new(action), state: PickupState::new() });
+    }
+    if value == DROP_KEYWORD {
+        return Ok(Action::Drop);
+    }
+    if let Some(action_spec) = strip_text_sink_alias(value) {
+        let text_sink_value = format!("{}{}", TEXT_SINK_PORT, action_spec);
+        return parse_field_rhs(field_id, &text_sink_value).map_err(|error| FieldParseError {
+            content: value.to_string(),
+            ..error
+        });
+    }
+    let (output_port, action_spec) = match value.split_once(ACTION_SEPARATOR) {
+        Some((output_port, action_spec)) => (output_port, Some(action_spec)),
+        None => (value, None),
+    };
+    let (output_port, channel) = split_channel(output_port).map_err(create_error)?;
+    let output_port = output_port.to_string();
+    if output_port.is_empty() {
+        Err(create_error(FieldFormatError::InvalidFormat))?
+    }
 
-    let start = get_match_as_i16("start")?.unwrap_or(default_start);
-    let end = get_match_as_i16("end")?.unwrap_or(default_end);
-    let lower_bound = get_match_as_i16("lower_bound")?.map(|b| b + 1).unwrap_or(default_start);
-    let upper_bound = get_match_as_i16("upper_bound")?.map(|b| b - 1).unwrap_or(default_end);
-    let exact_value = get_match_as_i16("exact_value")?;
+    match (channel, action_spec) {
+        (None, _) => {},
+        (Some(channel), None) => {
+            return Ok(Action::Transform { transforms: vec![Box::new(SetChannel { channel })], output_port });
+        },
+        (Some(channel), Some(action_spec)) => {
+            let transforms = action_spec.split(ACTION_SEPARATOR)
+                .map(|transform_spec| parse_transform(field_id, value, transform_spec));
+            let transforms = iter::once(Ok(Box::new(SetChannel { channel }) as Box<dyn Transform>))
+                .chain(transforms)
+                .collect::<Result<Vec<Box<dyn Transform>>, FieldParseError>>()?;
+            return Ok(Action::Transform { transforms, output_port });
+        },
+    }
+    match action_spec {
+        None => Ok(Action::ForwardTo { output_port }),
+        Some(REDIRECT_ACTION) => Ok(Action::Redirect { output_port }),
+        Some(action_spec) => match strip_call(action_spec, "send") {
+            Some(event_literal) if !action_spec.contains(ACTION_SEPARATOR) => {
+                let message = parse_event(event_literal).map_err(create_error)?;
+                Ok(Action::SendFixed { message, output_port })
+            },
+            _ => {
+                let transforms = action_spec.split(ACTION_SEPARATOR)
+                    .map(|transform_spec| parse_transform(field_id, value, transform_spec))
+                    .collect::<Result<Vec<Box<dyn Transform>>, FieldParseError>>()?;
+                Ok(Action::Transform { transforms, output_port })
+            },
+        },
+    }
+}
 
-    let start = exact_value.unwrap_or(max(start, lower_bound));
-    let end = exact_value.unwrap_or(min(end, upper_bound));
+/// Parses a stage of a transform pipeline, which is any action changing the event
+fn parse_transform(field_id: usize, value: &str, transform_spec: &str) -> Result<Box<dyn Transform>, FieldParseError> {
+    let create_error = |reason: FieldFormatError| FieldParseError {
+        field_id,
+        content: value.to_string(),
+        reason: Some(reason.into()),
+    };
 
-    if value_type_str != "" && !(0 <= start && start <= end && end <= 0xff) {
-        Err(FieldParseError {
-            field_id,
-            content: value.to_string(),
-            reason: Some(FieldFormatError::NumberOutOfRange { min: 0, max: 0xff }.into()),
-        })?
+    if let Some(mapping) = strip_call(transform_spec, "cc") {
+        let (from, to) = parse_controller_mapping(mapping).map_err(create_error)?;
+        Ok(Box::new(RemapController { from, to }))
+    } else if let Some(semitones) = strip_call(transform_spec, "transpose") {
+        let semitones = parse_semitones(semitones).map_err(create_error)?;
+        Ok(Box::new(Transpose { semitones }))
+    } else if let Some(control_no) = strip_call(transform_spec, "bend2cc") {
+        let control_no = parse_controller(control_no).map_err(create_error)?;
+        Ok(Box::new(BendToCc { control_no }))
+    } else if let Some(control_no) = strip_call(transform_spec, "cc2bend") {
+        let control_no = parse_controller(control_no).map_err(create_error)?;
+        Ok(Box::new(CcToBend { control_no }))
+    } else if let Some(scale) = strip_call(transform_spec, "quantize") {
+        let scale = scale.parse::<Scale>().map_err(|_| create_error(FieldFormatError::InvalidFormat))?;
+        Ok(Box::new(QuantizeToScale { scale }))
+    } else if let Some(step) = strip_call(transform_spec, "step") {
+        let step = parse_step(step).map_err(create_error)?;
+        Ok(Box::new(QuantizeValue { step }))
+    } else if let Some(control_no) = strip_call(transform_spec, "toggle") {
+        let control_no = parse_controller(control_no).map_err(create_error)?;
+        Ok(Box::new(ToggleSwitch { control_no, state: ToggleState::default() }))
+    } else if let Some(captures) = FIELD_PAT.captures(transform_spec) {
+        match parse_value_field(field_id, value, captures)? {
+            Field::VelocityField { start, end } if end <= 127 => Ok(Box::new(ClampVelocity { min: start, max: end })),
+            Field::VelocityField { .. } => Err(create_error(FieldFormatError::NumberOutOfRange { min: -1, max: 128 })),
+            _ => Err(create_error(FieldFormatError::UnknownAction)),
+        }
+    } else {
+        Err(create_error(FieldFormatError::UnknownAction))
     }
+}
 
-    Ok(match value_type_str {
-        "ch" => Field::ChannelField {start: start as u8, end: end as u8},
-        "vel" => Field::VelocityField {start: start as u8, end: end as u8},
-        "ctrl" => Field::ControlNoField {start: start as u8, end: end as u8},
-        _ => Field::ValueField { start, end },
-    })
+/// Parses a controller mapping of the form `<FROM>-><TO>`
+fn parse_controller_mapping(mapping: &str) -> Result<(u8, u8), FieldFormatError> {
+    let (from, to) = mapping.split_once("->").ok_or(FieldFormatError::InvalidFormat)?;
+    Ok((parse_controller(from)?, parse_controller(to)?))
 }
 
-fn switch_option_and_result<T, E>(item: Option<Result<T, E>>) -> Result<Option<T>, E> {
+/// Splits the channel given by `@<N>` off an output port, e.g. `out1@3`. Port names with other
+/// text after an `@` are kept as they are.
+fn split_channel(output_port: &str) -> Result<(&str, Option<u8>), FieldFormatError> {
+    match output_port.rsplit_once(CHANNEL_SEPARATOR) {
+        Some((port_name, channel)) if !channel.is_empty() && channel.bytes().all(|byte| byte.is_ascii_digit()) => {
+            match channel.parse::<u8>() {
+                Ok(channel) if (1..=16).contains(&channel) => Ok((port_name, Some(channel))),
+                _ => Err(FieldFormatError::NumberOutOfRange { min: 0, max: 17 }),
+            }
+        },
+        _ => Ok((output_port, None)),
+    }
+}
+
+/// Parses a controller number between 0 and 127
+fn parse_controller(controller: &str) -> Result<u8, FieldFormatError> {
+    match controller.trim().parse::<u8>() {
+        Ok(controller) if controller <= 127 => Ok(controller),
+        Ok(_) => Err(FieldFormatError::NumberOutOfRange { min: -1, max: 128 }),
+        Err(_) => Err(FieldFormatError::InvalidFormat),
+    }
+}
+
+/// Parses the step of a value quantization between 1 and 127
+fn parse_step(step: &str) -> Result<u8, FieldFormatError> {
+    match step.trim().parse::<u8>() {
+        Ok(step) if (1..=127).contains(&step) => Ok(step),
+        _ => Err(FieldFormatError::NumberOutOfRange { min: 0, max: 128 }),
+    }
+}
+
+/// Parses a signed number of semitones between -127 and 127, e.g. `+12`
+fn parse_semitones(semitones: &str) -> Result<i8, FieldFormatError> {
+    let semitones = semitones.trim();
+    match semitones.strip_prefix('+').unwrap_or(semitones).parse::<i8>() {
+        Ok(semitones) if semitones >= -127 => Ok(semitones),
+        _ => Err(FieldFormatError::NumberOutOfRange { min: -128, max: 128 }),
+    }
+}
+
+/// Returns the argument `x` of an action specification of the form `name(x)`
+fn strip_call<'a>(action_spec: &'a str, name: &str) -> Option<&'a str> {
+    action_spec.strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Parses an event name pattern, which is a regular expression unless prefixed with `=`
+fn parse_name_pattern_field(field_id: usize, value: &str, match_mode: MatchMode) -> Result<Field, FieldParseError> {
+    let pattern = match value.strip_prefix(LITERAL_NAME_PREFIX) {
+        Some(name) => format!("^{}$", regex::escape(name)),
+        None if match_mode.anchored => format!("^(?:{})$", value),
+        None => value.to_string(),
+    };
+    match RegexBuilder::new(&pattern).case_insensitive(match_mode.case_insensitive).build() {
+        Ok(name_pattern) => Ok(Field::NameField { name_pattern }),
+        Err(err) => Err(FieldParseError {
+            field_id,
+            content: value.to_string(),
+            reason: Some(err.into()),
+        }),
+    }
+}
+
+/// Writes a value field in canonical form, e.g. `ch>=3` as `ch>2`, or returns `None` if it is no
+/// valid value field or a musical dynamic, which is kept as it is
+pub(super) fn normalize_value_field(value: &str) -> Option<String> {
+    let captures = FIELD_PAT.captures(value)?;
+    if captures.name("dynamic").is_some() {
+        return None;
+    }
+    let u8_bounds = (u8::MIN.into(), u8::MAX.into());
+    let (prefix, start, end, (min, max)) = match parse_value_field(0, value, captures).ok()? {
+        Field::ChannelField { start, end } => ("ch", start.into(), end.into(), u8_bounds),
+        Field::VelocityField { start, end } => ("vel", start.into(), end.into(), u8_bounds),
+        Field::ReleaseVelocityField { start, end } => ("relvel", start.into(), end.into(), u8_bounds),
+        Field::ControlNoField { start, end } => ("ctrl", start.into(), end.into(), u8_bounds),
+        Field::SlewField { start, end } => ("slew", start.into(), end.into(), u8_bounds),
+        Field::TempoField { start, end } => ("tempo", start.into(), end.into(), (0, MAX_TEMPO_BPM.into())),
+        Field::BankField { start, end } => ("bank", start.into(), end.into(), (0, MAX_BANK.into())),
+        Field::LengthField { start, end } => ("len", start.into(), end.into(), (0, MAX_LENGTH.into())),
+        Field::VoiceField { start, end } => ("voice", start.into(), end.into(), u8_bounds),
+        Field::PitchBendMsbField { start, end } => ("pbmsb", start.into(), end.into(), u8_bounds),
+        Field::PitchBendLsbField { start, end } => ("pblsb", start.into(), end.into(), u8_bounds),
+        Field::ValueField { start, end } => ("", start.into(), end.into(), (i16::MIN.into(), i16::MAX.into())),
+        _ => return None,
+    };
+    Some(NumericRange::<i64> { start, end }.describe(prefix, min, max))
+}
+
+fn parse_value_field(field_id: usize, value: &str, captures: Captures) -> Result<Field, FieldParseError> {
+    let value_type_str = captures.name("type").map_or("", |m| m.as_str());
+
+    if let Some(dynamic) = captures.name("dynamic") {
+        return parse_dynamic(value_type_str, dynamic.as_str())
+            .map(|(start, end)| Field::VelocityField { start, end })
+            .map_err(|reason| FieldParseError {
+                field_id,
+                content: value.to_string(),
+                reason: Some(reason.into()),
+            });
+    }
+
+    let match_to_i16 = |m: Match| m.as_str()
+        .parse::<i16>()
+        .map_err(|err| FieldParseError {
+            field_id,
+            content: value.into(),
+            reason: Some(err.into()),
+        });
+    let get_match_as_i16 = |name: &str| {
+        let opt_value = captures.name(name).map(match_to_i16);
+        switch_option_and_result(opt_value)
+    };
+
+    let max_value = match value_type_str {
+        "tempo" => MAX_TEMPO_BPM,
+        "bank" => MAX_BANK as i16,
+        "len" => MAX_LENGTH as i16,
+        _ => u8::MAX as i16,
+    };
+    let default_start = if value_type_str == "" { i16::MIN } else { u8::MIN as i16 };
+    let default_end = if value_type_str == "" { i16::MAX } else { max_value };
+
+    let start = get_match_as_i16("start")?.unwrap_or(default_start);
+    let end = get_match_as_i16("end")?.unwrap_or(default_end);
+    let lower_bound = get_match_as_i16("lower_bound")?.map(|b| b + 1)
+        .or(get_match_as_i16("inclusive_lower_bound")?)
+        .unwrap_or(default_start);
+    let upper_bound = get_match_as_i16("upper_bound")?.map(|b| b - 1)
+        .or(get_match_as_i16("inclusive_upper_bound")?)
+        .unwrap_or(default_end);
+    let exact_value = get_match_as_i16("exact_value")?;
+
+    let start = exact_value.unwrap_or(max(start, lower_bound));
+    let end = exact_value.unwrap_or(min(end, upper_bound));
+
+    if value_type_str != "" && !(0 <= start && start <= end && end <= max_value) {
+        Err(FieldParseError {
+            field_id,
+            content: value.to_string(),
+            reason: Some(FieldFormatError::NumberOutOfRange { min: 0, max: max_value }.into()),
+        })?
+    }
+
+    Ok(match value_type_str {
+        "ch" => Field::ChannelField {start: start as u8, end: end as u8},
+        "tempo" => Field::TempoField {start: start as u16, end: end as u16},
+        "bank" => Field::BankField {start: start as u16, end: end as u16},
+        "len" => Field::LengthField {start: start as u16, end: end as u16},
+        "voice" => Field::VoiceField {start: start as u8, end: end as u8},
+        "pbmsb" => Field::PitchBendMsbField {start: start as u8, end: end as u8},
+        "pblsb" => Field::PitchBendLsbField {start: start as u8, end: end as u8},
+        "vel" => Field::VelocityField {start: start as u8, end: end as u8},
+        "relvel" => Field::ReleaseVelocityField {start: start as u8, end: end as u8},
+        "ctrl" => Field::ControlNoField {start: start as u8, end: end as u8},
+        "slew" => Field::SlewField {start: start as u8, end: end as u8},
+        _ => Field::ValueField { start, end },
+    })
+}
+
+/// Parses the complement of a velocity range which includes the lowest or the highest velocity,
+/// e.g. `velcomp>=100` for the velocities below 100. Other ranges have no contiguous complement.
+fn parse_velocity_complement_field(field_id: usize, value: &str, velocity_range: &str) -> Result<Field, FieldParseError> {
+    let invalid_format = || FieldParseError {
+        field_id,
+        content: value.to_string(),
+        reason: Some(FieldFormatError::InvalidFormat.into()),
+    };
+    let velocity_field = format!("vel{}", velocity_range);
+    let captures = FIELD_PAT.captures(&velocity_field).ok_or_else(invalid_format)?;
+    match parse_value_field(field_id, value, captures)? {
+        Field::VelocityField { start: 0, end } if end < 127 => {
+            Ok(Field::VelocityComplementField { start: end + 1, end: u8::MAX })
+        },
+        Field::VelocityField { start, end } if start > 0 && end >= 127 => {
+            Ok(Field::VelocityComplementField { start: 0, end: start - 1 })
+        },
+        _ => Err(invalid_format()),
+    }
+}
+
+/// Parses a daily time window of the form `<HH:MM>-<HH:MM>`, e.g. `22:00-06:00`
+fn parse_time_window(time_window: &str) -> Result<TimeWindow, FieldFormatError> {
+    let (start, end) = time_window.split_once('-').ok_or(FieldFormatError::InvalidFormat)?;
+    Ok(TimeWindow { start: parse_time_of_day(start)?, end: parse_time_of_day(end)? })
+}
+
+/// Parses a time of day of the form `<HH:MM>` into minutes since midnight
+fn parse_time_of_day(time_of_day: &str) -> Result<u16, FieldFormatError> {
+    let (hours, minutes) = time_of_day.split_once(':').ok_or(FieldFormatError::InvalidFormat)?;
+    let hours = hours.parse::<u16>().map_err(|_| FieldFormatError::InvalidFormat)?;
+    let minutes = minutes.parse::<u16>().map_err(|_| FieldFormatError::InvalidFormat)?;
+    if hours > 23 || minutes > 59 {
+        Err(FieldFormatError::InvalidFormat)?
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Parses a comma-separated list of controllers, e.g. `ctrl1,7,11`
+fn parse_controller_set_field(field_id: usize, value: &str, captures: Captures) -> Result<Field, FieldParseError> {
+    captures["controllers"].split(',')
+        .map(|controller| controller.parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .map(|controllers| Field::ControllerSetField { controller_set: ControllerSet::new(controllers) })
+        .map_err(|err| FieldParseError {
+            field_id,
+            content: value.to_string(),
+            reason: Some(err.into()),
+        })
+}
+
+/// Looks up the velocity range of a dynamic, e.g. `mf`, which is only valid for velocity fields
+fn parse_dynamic(value_type_str: &str, dynamic: &str) -> Result<(u8, u8), FieldFormatError> {
+    if !value_type_str.eq_ignore_ascii_case("vel") {
+        Err(FieldFormatError::InvalidFormat)?
+    }
+    DYNAMICS.iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(dynamic))
+        .map(|(_, start, end)| (*start, *end))
+        .ok_or(FieldFormatError::UnknownDynamic)
+}
+
+fn switch_option_and_result<T, E>(item: Option<Result<T, E>>) -> Result<Option<T>, E> {
     match item {
         None => Ok(None),
         Some(Ok(value)) => Ok(Some(value)),
@@ -248,6 +1511,49 @@ enum Field {
     NameField {
         name_pattern: Regex,
     },
+    CategoryField {
+        category: EventCategory,
+    },
+    ZeroVelocityOffField,
+    SustainedField,
+    StuckField,
+    ReleaseField,
+    RunningStatusField,
+    ControllerKindField {
+        controller_kind: ControllerKind,
+    },
+    ControllerSetField {
+        controller_set: ControllerSet,
+    },
+    TimeWindowField {
+        time_window: TimeWindow,
+    },
+    ScaleField {
+        scale: Scale,
+    },
+    TransportField {
+        transport_state: TransportState,
+    },
+    SysExField {
+        sysex_id: UniversalSysExId,
+    },
+    SilenceField {
+        min_silence_ms: u64,
+    },
+    HeldField {
+        start: u64,
+        end: u64,
+    },
+    SinceProgramChangeField {
+        start: u64,
+        end: u64,
+    },
+    EveryField {
+        every: u64,
+    },
+    AlternateField {
+        alternate: u8,
+    },
     ValueField {
         start: i16,
         end: i16,
@@ -256,14 +1562,51 @@ enum Field {
         start: u8,
         end: u8,
     },
+    TempoField {
+        start: u16,
+        end: u16,
+    },
+    BankField {
+        start: u16,
+        end: u16,
+    },
+    LengthField {
+        start: u16,
+        end: u16,
+    },
+    VoiceField {
+        start: u8,
+        end: u8,
+    },
+    PitchBendMsbField {
+        start: u8,
+        end: u8,
+    },
+    PitchBendLsbField {
+        start: u8,
+        end: u8,
+    },
     VelocityField {
         start: u8,
         end: u8,
     },
+    /// Velocity range given as the complement of another range with `velcomp`
+    VelocityComplementField {
+        start: u8,
+        end: u8,
+    },
+    ReleaseVelocityField {
+        start: u8,
+        end: u8,
+    },
     ControlNoField {
         start: u8,
         end: u8,
     },
+    SlewField {
+        start: u8,
+        end: u8,
+    },
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -274,6 +1617,8 @@ enum Field {
 mod tests {
     use std::io::Write;
     use tempfile::NamedTempFile;
+    use crate::midi::MidiEvent;
+    use crate::routing::{MatchContext, RoutedEvent};
     use super::*;
 
     #[test]
@@ -356,6 +1701,146 @@ mod tests {
         assert_eq!(rule.actions, expected_actions);
     }
 
+    #[test]
+    fn test_load_rules_from_file_with_comments() {
+        let file_content = r#"
+        # drums
+        note-.* ch10 => drums-out
+          # everything else
+        note-.* => kb-out
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let rules = load_rules_from_file(&file).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].actions, vec![Action::ForwardTo { output_port: "drums-out".into() }]);
+        assert_eq!(rules[1].actions, vec![Action::ForwardTo { output_port: "kb-out".into() }]);
+    }
+
+    #[test]
+    fn test_load_rules_from_file_with_descriptions() {
+        let file_content = r#"
+        # Drums go to the
+        # sampler
+        note-.* ch10 => drums-out
+        # detached comment
+
+        note-.* => kb-out
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let rules = load_rules_from_file(&file).unwrap();
+
+        assert_eq!(rules[0].description.as_deref(), Some("Drums go to the sampler"));
+        assert_eq!(rules[1].description, None);
+    }
+
+    #[test]
+    fn test_load_rules_from_file_with_options() {
+        let file_content = r#"
+        # Comment replaced by the label
+        note-.* ch10 => drums-out { label=Drums, enabled=true }
+        note-.* ch11 => pads { enabled=false, label=Pads }
+        note-.* => ${name} {label=Keyboard}
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let rules = load_rules_from_file(&file).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].description.as_deref(), Some("Drums"));
+        assert_eq!(rules[0].actions, vec![Action::ForwardTo { output_port: "drums-out".into() }]);
+        assert_eq!(rules[1].description.as_deref(), Some("Keyboard"));
+        assert_eq!(rules[1].actions, vec![Action::ForwardTo { output_port: "${name}".into() }]);
+    }
+
+    #[test]
+    fn test_load_routing_table_from_file_with_filter() {
+        let file_content = r#"
+        filter out1 ch1-4
+        note-on => out1 out2
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let routing_table = load_routing_table_from_file(&file).unwrap();
+
+        assert_eq!(routing_table.rules.len(), 1);
+        assert_eq!(routing_table.output_filters.len(), 1);
+        assert_eq!(
+            routing_table.output_filters["out1"],
+            OutputFilter { channel_pattern: NumericRange { start: 1, end: 4 } },
+        );
+
+        let note_on = |channel| MidiEvent::NoteOn { channel, note: 60, velocity: 100 };
+        assert!(routing_table.is_allowed_on_port("out1", &note_on(2)));
+        assert!(!routing_table.is_allowed_on_port("out1", &note_on(5)));
+        assert!(routing_table.is_allowed_on_port("out2", &note_on(5)));
+    }
+
+    #[test]
+    fn test_load_routing_table_from_file_with_note_cap() {
+        let file_content = r#"
+        cap mono 1
+        cap poly 8 steal
+        note-on => mono poly
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let routing_table = load_routing_table_from_file(&file).unwrap();
+
+        assert_eq!(routing_table.note_caps["mono"], NoteCap { max_notes: 1, policy: NoteCapPolicy::Drop });
+        assert_eq!(routing_table.note_caps["poly"], NoteCap { max_notes: 8, policy: NoteCapPolicy::Steal });
+    }
+
+    #[test]
+    fn test_load_routing_table_strict_ports() {
+        let file_content = r#"
+        ports: synth drums
+        note-on ch1 => synth
+        note-on ch10 => drums pads
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let strict = LoadOptions { strict_ports: true };
+
+        let Err(err) = load_routing_table_with_options(&file, &strict) else {
+            panic!("Expected an error for the undeclared port");
+        };
+        let rule_config_error = err.downcast_ref::<RuleConfigError>().unwrap();
+        assert_eq!(rule_config_error.errors.len(), 1);
+        let RuleParseError::InvalidFields { line_no, invalid_fields, .. } = &rule_config_error.errors[0];
+        assert_eq!(*line_no, 3);
+        assert_eq!(invalid_fields[0].content, "pads");
+        assert_eq!(load_routing_table_from_file(&file).unwrap().rules.len(), 2);
+    }
+
+    #[test]
+    fn test_load_routing_table_strict_ports_without_header() {
+        let file = write_tmp_file_content("note-on => synth\n");
+        let strict = LoadOptions { strict_ports: true };
+
+        assert!(load_routing_table_with_options(&file, &strict).is_err());
+        assert!(load_routing_table_from_file(&file).is_ok());
+    }
+
+    #[test]
+    fn test_parse_cap_directive_invalid() {
+        assert!(parse_cap_directive(0, "cap out1").is_err());
+        assert!(parse_cap_directive(0, "cap out1 -1").is_err());
+        assert!(parse_cap_directive(0, "cap out1 8 oldest").is_err());
+        assert!(parse_cap_directive(0, "cap out1 8 steal now").is_err());
+    }
+
+    #[test]
+    fn test_parse_filter_directive_invalid() {
+        let result = parse_filter_directive(4, "filter out1 vel1-4");
+        assert!(result.is_err());
+        if let Err(RuleParseError::InvalidFields { line_no, invalid_fields, .. }) = result {
+            assert_eq!(line_no, 4);
+            assert_eq!(invalid_fields[0].field_id, 2);
+            assert_eq!(invalid_fields[0].content, "vel1-4");
+        }
+
+        assert!(parse_filter_directive(0, "filter out1").is_err());
+        assert!(parse_filter_directive(0, "filter out1 ch1 ch2").is_err());
+        assert!(parse_filter_directive(0, "filter out1 ch300").is_err());
+    }
+
     #[test]
     fn test_load_rules_from_file_with_io_error() {
         let result = load_rules_from_file(&"/this/path/does/not/exist");
@@ -380,85 +1865,340 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_rule_valid_multi_forward() {
-        let line_no = 0;
-        let line = r"note-.* <64 ch0-8 vel>100 ctrl44 => out1 out2";
-        let result = parse_rule(line_no, line.into());
-
-        assert!(result.is_ok());
-        if let Ok(Rule { condition, actions }) = result {
-            assert!(condition.event_pattern.is_some());
-            if let Some(pattern) = condition.event_pattern {
-                assert!(pattern.is_match("note-on"));
-                assert!(!pattern.is_match("notey-on"));
-            }
-
-            assert_eq!(condition.channel_pattern, Some(NumericRange {
-                start: 0,
-                end: 8,
-            }));
+    fn test_load_rules_from_directory() {
+        let dir = tempfile::tempdir().unwrap();
 
-            assert_eq!(condition.value_pattern, Some(NumericRange {
-                start: i16::MIN,
-                end: 63,
-            }));
+        let error = load_rules_with_report(&dir.path()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.get_ref().is_some_and(|inner| inner.is::<IsDirectoryError>()));
+    }
 
-            assert_eq!(condition.velocity_pattern, Some(NumericRange {
-                start: 101,
-                end: u8::MAX,
-            }));
+    #[test]
+    fn test_load_rules_with_byte_order_mark() {
+        let file = write_tmp_file_content("\u{feff}note-on ch1 => out1\r\nnote-off => out2\r\n");
+        let rules = load_rules_from_file(&file).unwrap();
 
-            assert_eq!(condition.controller_pattern, Some(NumericRange {
-                start: 44,
-                end: 44,
-            }));
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].describe_condition(), "note-on ch1");
+        assert!(rules[0].condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+    }
 
-            assert_eq!(actions, vec![
-                Action::ForwardTo {
-                    output_port: "out1".into(),
-                },
-                Action::ForwardTo {
-                    output_port: "out2".into()
-                }
-            ]);
-        } else {
-            panic!("Unexpected result type {:?}", result);
-        }
+    #[test]
+    fn test_load_rules_with_invalid_utf8() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"note-on => out1\nnote-off => out\xff\xfe2\n").unwrap();
+
+        let error = load_rules_with_report(&file).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let inner = error.get_ref().and_then(|inner| inner.downcast_ref::<InvalidUtf8Error>()).unwrap();
+        assert_eq!(inner.line_no, 1);
+        assert_eq!(error.to_string(), "Invalid UTF-8 in line 2");
     }
 
     #[test]
-    fn test_parse_rule_valid_drop() {
-        let line_no = 0;
-        let line = r".*-aftertouch =>";
-        let result = parse_rule(line_no, line.into());
+    fn test_load_rules_with_report() {
+        let file_content = r#"
+        note-on ch1 => out1
+        note-off ch300 => out2
+        control-change ctrl7 => out3
+        *** => out4
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let report = load_rules_with_report(&file).unwrap();
 
-        assert!(result.is_ok());
-        if let Ok(Rule { condition, actions }) = result {
-            assert!(condition.event_pattern.is_some());
-            if let Some(pattern) = condition.event_pattern {
-                assert!(pattern.is_match("hello-aftertouch"));
-                assert!(!pattern.is_match("note-on"));
-            }
+        assert_eq!(report.rules.len(), 2);
+        assert_eq!(report.rules[0].actions, vec![Action::ForwardTo { output_port: "out1".into() }]);
+        assert_eq!(report.rules[1].actions, vec![Action::ForwardTo { output_port: "out3".into() }]);
 
-            assert!(condition.channel_pattern.is_none());
-            assert!(condition.velocity_pattern.is_none());
-            assert!(condition.value_pattern.is_none());
-            assert!(condition.controller_pattern.is_none());
+        assert_eq!(report.errors.len(), 2);
+        let RuleParseError::InvalidFields { line_no, invalid_fields, .. } = &report.errors[0];
+        assert_eq!(*line_no, 2);
+        assert_eq!(invalid_fields[0].field_id, 1);
+        let RuleParseError::InvalidFields { line_no, invalid_fields, .. } = &report.errors[1];
+        assert_eq!(*line_no, 4);
+        assert_eq!(invalid_fields[0].field_id, 0);
 
-            assert!(actions.is_empty());
-        } else {
-            panic!("Unexpected result type {:?}", result);
-        }
+        assert!(report.warnings.is_empty());
     }
 
     #[test]
-    fn test_parse_rule_invalid() {
-        let line_no = 127;
-        let line = r"*-aftertouch 300000 v0 ch-1";
-        let result = parse_rule(line_no, line.into());
+    fn test_load_rules_warns_about_drop_rules() {
+        let file_content = r#"
+        note-on ch1 => out1
+        active-sensing => drop
+        note-off ch2 => out2; note-off ch3 => drop
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let report = load_rules_with_report(&file).unwrap();
+
+        assert!(report.errors.is_empty());
+        assert_eq!(report.warnings, vec![
+            RuleWarning::DropsAllEvents { line_no: 2, rule_no: None },
+            RuleWarning::DropsAllEvents { line_no: 3, rule_no: Some(1) },
+        ]);
+        assert_eq!(
+            report.warnings[1].to_string(),
+            "Rule in line 4, rule 2 has no output port and drops all matching events",
+        );
 
+        let routing_table = load_routing_table_from_file(&file).unwrap();
+        assert_eq!(routing_table.rules.len(), 4);
+    }
+
+    #[test]
+    fn test_load_rules_separated_by_semicolons() {
+        let file_content = "note-on ch1 => out1; note-off ch1 => out2;\nnote-on ch2 => out3; note-off ch300 => out4\n";
+        let file = write_tmp_file_content(file_content);
+        let report = load_rules_with_report(&file).unwrap();
+
+        assert_eq!(report.rules.len(), 3);
+        assert_eq!(report.rules[1].actions, vec![Action::ForwardTo { output_port: "out2".into() }]);
+        assert_eq!(report.rules[2].actions, vec![Action::ForwardTo { output_port: "out3".into() }]);
+
+        assert_eq!(report.errors.len(), 1);
+        let RuleParseError::InvalidFields { line_no, rule_no, invalid_fields } = &report.errors[0];
+        assert_eq!(*line_no, 1);
+        assert_eq!(*rule_no, Some(1));
+        assert_eq!(invalid_fields[0].field_id, 1);
+        assert_eq!(invalid_fields[0].content, "ch300");
+        assert!(report.errors[0].to_string().starts_with("Invalid field in line 2, rule 2:"));
+    }
+
+    #[test]
+    fn test_load_rules_with_report_with_io_error() {
+        let result = load_rules_with_report(&"/this/path/does/not/exist");
         assert!(result.is_err());
-        if let Err(RuleParseError::InvalidFields { line_no: err_line_no, invalid_fields }) = result {
+    }
+
+    #[test]
+    fn test_load_rules_from_file_zone_transpose() {
+        let file_content = r#"
+        note-.* <60 => lower:transpose(+12)
+        note-.* >59 => upper
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let routing_table = load_routing_table_from_file(&file).unwrap();
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 48, velocity: 90 });
+        assert_eq!(outputs, vec![RoutedEvent {
+            output_port: "lower".into(),
+            event: Some(MidiEvent::NoteOn { channel: 1, note: 60, velocity: 90 }),
+        }]);
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOff { channel: 1, note: 59, velocity: 0 });
+        assert_eq!(outputs, vec![RoutedEvent {
+            output_port: "lower".into(),
+            event: Some(MidiEvent::NoteOff { channel: 1, note: 71, velocity: 0 }),
+        }]);
+
+        // notes transposed into the upper zone are not routed by the upper rule
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 90 });
+        assert_eq!(outputs, vec![RoutedEvent { output_port: "upper".into(), event: None }]);
+    }
+
+    #[test]
+    fn test_load_rules_from_file_zone_transpose_clamped() {
+        let file_content = r#"
+        note-on >100 => out:transpose(24)
+        note-on <10 => out:transpose(-24)
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let routing_table = load_routing_table_from_file(&file).unwrap();
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 110, velocity: 90 });
+        assert_eq!(outputs[0].event, Some(MidiEvent::NoteOn { channel: 1, note: 127, velocity: 90 }));
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 3, velocity: 90 });
+        assert_eq!(outputs[0].event, Some(MidiEvent::NoteOn { channel: 1, note: 0, velocity: 90 }));
+    }
+
+    #[test]
+    fn test_parse_rule_valid_clamp_velocity() {
+        let result = parse_rule(0, "note-.* => out1:vel30-110 out2:velmf".into());
+
+        assert!(result.is_ok());
+        let Rule { actions, .. } = result.unwrap();
+        assert_eq!(actions.len(), 2);
+        assert_pipeline(&actions[0], &[Box::new(ClampVelocity { min: 30, max: 110 })], "out1");
+        assert_pipeline(&actions[1], &[Box::new(ClampVelocity { min: 64, max: 79 })], "out2");
+    }
+
+    #[test]
+    fn test_parse_rule_invalid_clamp_velocity() {
+        assert!(parse_rule(0, "note-on => out1:vel30-200".into()).is_err());
+        assert!(parse_rule(0, "note-on => out1:ch1-4".into()).is_err());
+        assert!(parse_rule(0, "note-on => out1:vel110-30".into()).is_err());
+    }
+
+    #[test]
+    fn test_load_rules_from_file_with_sections() {
+        let file_content = r#"
+        with ch1 vel>0 {
+            note-on => lead
+            note-off ch2 => lead
+            with <60 {
+                note-on => bass
+            }
+        }
+        note-on => other
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let rules = load_rules_from_file(&file).unwrap();
+
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[0].condition.channel_pattern, Some(NumericRange { start: 1, end: 1 }));
+        assert_eq!(rules[0].condition.velocity_pattern, Some(NumericRange { start: 1, end: 255 }));
+        assert_eq!(rules[1].condition.channel_pattern, Some(NumericRange { start: 2, end: 2 }));
+        assert_eq!(rules[1].condition.velocity_pattern, Some(NumericRange { start: 1, end: 255 }));
+        assert_eq!(rules[2].condition.channel_pattern, Some(NumericRange { start: 1, end: 1 }));
+        assert_eq!(rules[2].condition.value_pattern, Some(NumericRange { start: i16::MIN, end: 59 }));
+        assert_eq!(rules[3].condition.channel_pattern, None);
+        assert_eq!(rules[3].condition.velocity_pattern, None);
+    }
+
+    #[test]
+    fn test_load_rules_with_report_unbalanced_sections() {
+        let file_content = r#"
+        }
+        with ch1 {
+        note-on => lead
+        with ch300 {
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let report = load_rules_with_report(&file).unwrap();
+
+        assert_eq!(report.rules.len(), 1);
+        let error_lines: Vec<usize> = report.errors.iter()
+            .map(|RuleParseError::InvalidFields { line_no, .. }| *line_no)
+            .collect();
+        assert_eq!(error_lines, vec![1, 4, 2, 4]);
+    }
+
+    #[test]
+    fn test_parse_section_start_invalid() {
+        assert!(parse_section_start(0, "with ch1").is_err());
+        assert!(parse_section_start(0, "with {").is_err());
+        assert!(parse_section_start(0, "with ch1 or ch2 {").is_err());
+        assert!(parse_section_start(0, "with xyz {").is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_valid_every() {
+        let result = parse_rule(0, "note-on every4 => hats".into());
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap().condition.every, Some(4));
+        assert!(parse_rule(0, "note-on every0 => hats".into()).is_err());
+        assert!(parse_rule(0, "note-on everyx => hats".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_valid_alternate() {
+        let alt0 = parse_rule(0, "note-on alt0 => left".into()).unwrap();
+        let alt1 = parse_rule(1, "note-on alt1 => right".into()).unwrap();
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+
+        assert_eq!(alt0.condition.alternate, Some(0));
+        assert_eq!(alt1.condition.alternate, Some(1));
+        assert_eq!(alt0.describe_condition(), "note-on alt0");
+        let matches: Vec<(bool, bool)> = (0..4)
+            .map(|_| (alt0.condition.matches(&note_on), alt1.condition.matches(&note_on)))
+            .collect();
+        assert_eq!(matches, vec![(true, false), (false, true), (true, false), (false, true)]);
+        assert!(parse_rule(0, "note-on alt2 => left".into()).is_err());
+        assert!(parse_rule(0, "note-on alt => left".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_semitones() {
+        assert_eq!(parse_semitones("+12").unwrap(), 12);
+        assert_eq!(parse_semitones("-7").unwrap(), -7);
+        assert_eq!(parse_semitones("0").unwrap(), 0);
+        assert!(parse_semitones("-128").is_err());
+        assert!(parse_semitones("200").is_err());
+        assert!(parse_semitones("twelve").is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_valid_multi_forward() {
+        let line_no = 0;
+        let line = r"note-.* <64 ch0-8 vel>100 ctrl44 => out1 out2";
+        let result = parse_rule(line_no, line.into());
+
+        assert!(result.is_ok());
+        if let Ok(Rule { condition, actions, .. }) = result {
+            assert!(condition.event_pattern.is_some());
+            if let Some(pattern) = condition.event_pattern {
+                assert!(pattern.is_match("note-on"));
+                assert!(!pattern.is_match("notey-on"));
+            }
+
+            assert_eq!(condition.channel_pattern, Some(NumericRange {
+                start: 0,
+                end: 8,
+            }));
+
+            assert_eq!(condition.value_pattern, Some(NumericRange {
+                start: i16::MIN,
+                end: 63,
+            }));
+
+            assert_eq!(condition.velocity_pattern, Some(NumericRange {
+                start: 101,
+                end: u8::MAX,
+            }));
+
+            assert_eq!(condition.controller_pattern, Some(NumericRange {
+                start: 44,
+                end: 44,
+            }));
+
+            assert_eq!(actions, vec![
+                Action::ForwardTo {
+                    output_port: "out1".into(),
+                },
+                Action::ForwardTo {
+                    output_port: "out2".into()
+                }
+            ]);
+        } else {
+            panic!("Unexpected result type {:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_parse_rule_valid_drop() {
+        let line_no = 0;
+        let line = r".*-aftertouch =>";
+        let result = parse_rule(line_no, line.into());
+
+        assert!(result.is_ok());
+        if let Ok(Rule { condition, actions, .. }) = result {
+            assert!(condition.event_pattern.is_some());
+            if let Some(pattern) = condition.event_pattern {
+                assert!(pattern.is_match("hello-aftertouch"));
+                assert!(!pattern.is_match("note-on"));
+            }
+
+            assert!(condition.channel_pattern.is_none());
+            assert!(condition.velocity_pattern.is_none());
+            assert!(condition.value_pattern.is_none());
+            assert!(condition.controller_pattern.is_none());
+
+            assert!(actions.is_empty());
+        } else {
+            panic!("Unexpected result type {:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_parse_rule_invalid() {
+        let line_no = 127;
+        let line = r"*-aftertouch 300000 v0 ch-1";
+        let result = parse_rule(line_no, line.into());
+
+        assert!(result.is_err());
+        if let Err(RuleParseError::InvalidFields { line_no: err_line_no, invalid_fields, .. }) = result {
             assert_eq!(err_line_no, line_no);
             assert_eq!(invalid_fields.len(), 4);
             assert_eq!(invalid_fields[0].field_id, 0);
@@ -528,7 +2268,7 @@ mod tests {
     fn test_parse_name_pattern_field_ok() {
         let field_id = 1;
         let value = r"no.*-(on|off)";
-        let result = parse_name_pattern_field(field_id, value);
+        let result = parse_name_pattern_field(field_id, value, MatchMode::default());
 
         assert!(result.is_ok());
         if let Ok(Field::NameField { name_pattern }) = result {
@@ -539,11 +2279,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_name_pattern_field_literal() {
+        let Ok(Field::NameField { name_pattern }) = parse_name_pattern_field(0, "=note.on", MatchMode::default()) else {
+            panic!("Expected NameField variant");
+        };
+        assert!(name_pattern.is_match("note.on"));
+        assert!(!name_pattern.is_match("noteXon"));
+        assert!(!name_pattern.is_match("note.on-extra"));
+
+        let Ok(Field::NameField { name_pattern }) = parse_name_pattern_field(0, "=note-on", MatchMode::default()) else {
+            panic!("Expected NameField variant");
+        };
+        assert!(name_pattern.is_match("note-on"));
+        assert!(!name_pattern.is_match("noteXon"));
+
+        let Ok(Field::NameField { name_pattern }) = parse_name_pattern_field(0, "note.on", MatchMode::default()) else {
+            panic!("Expected NameField variant");
+        };
+        assert!(name_pattern.is_match("noteXon"));
+    }
+
+    #[test]
+    fn test_parse_name_pattern_field_match_mode() {
+        let matches = |pattern: &str, match_mode: MatchMode, event_name: &str| {
+            let Ok(Field::NameField { name_pattern }) = parse_name_pattern_field(0, pattern, match_mode) else {
+                panic!("Expected NameField variant");
+            };
+            name_pattern.is_match(event_name)
+        };
+        let anchored = MatchMode { anchored: true, ..Default::default() };
+        let case_insensitive = MatchMode { case_insensitive: true, ..Default::default() };
+
+        assert!(matches("on", MatchMode::default(), "note-on"));
+        assert!(!matches("on", anchored, "note-on"));
+        assert!(matches("note-on|stop", anchored, "stop"));
+        assert!(!matches("note-on|stop", anchored, "note-one"));
+        assert!(matches("note-.*", anchored, "note-off"));
+
+        assert!(!matches("Note-On", MatchMode::default(), "note-on"));
+        assert!(matches("Note-On", case_insensitive, "note-on"));
+        assert!(matches("=NOTE-ON", case_insensitive, "note-on"));
+        assert!(!matches("ON", MatchMode { anchored: true, case_insensitive: true }, "note-on"));
+    }
+
+    #[test]
+    fn test_load_rules_with_match_mode() {
+        let file_content = r#"
+        match-mode anchored icase
+        on => out1
+        Note-On => out2
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let routing_table = load_routing_table_from_file(&file).unwrap();
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 });
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].output_port, "out2");
+
+        let file = write_tmp_file_content("match-mode fuzzy\nnote-on => out1\n");
+        let report = load_rules_with_report(&file).unwrap();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.rules.len(), 1);
+    }
+
     #[test]
     fn test_parse_name_pattern_field_invalid_pattern() {
         let field_id = 2;
         let value = r"no[te-*";
-        let result = parse_name_pattern_field(field_id, value);
+        let result = parse_name_pattern_field(field_id, value, MatchMode::default());
 
         assert!(result.is_err());
         if let Err(err) = result {
@@ -658,4 +2462,788 @@ mod tests {
             assert!(err.reason.is_some());
         }
     }
+
+    #[test]
+    fn test_parse_rule_valid_send_fixed() {
+        let line_no = 0;
+        let line = r"note-on 36 => out1:send(program-change ch1 5) out2";
+        let result = parse_rule(line_no, line.into());
+
+        assert!(result.is_ok());
+        let Rule { actions, .. } = result.unwrap();
+        assert_eq!(actions, vec![
+            Action::SendFixed {
+                message: MidiEvent::ProgramChange {
+                    channel: 1,
+                    program: 5,
+                },
+                output_port: "out1".into(),
+            },
+            Action::ForwardTo {
+                output_port: "out2".into(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_rule_invalid_actions() {
+        let line_no = 3;
+        let line = r"note-on 36 => out1:send(note-pikachu ch1 5) out2:explode() :send(start)";
+        let result = parse_rule(line_no, line.into());
+
+        assert!(result.is_err());
+        if let Err(RuleParseError::InvalidFields { invalid_fields, .. }) = result {
+            assert_eq!(invalid_fields.len(), 3);
+            assert_eq!(invalid_fields[0].content, "out1:send(note-pikachu ch1 5)");
+            assert_eq!(invalid_fields[1].content, "out2:explode()");
+            assert_eq!(invalid_fields[2].content, ":send(start)");
+        }
+    }
+
+    #[test]
+    fn test_split_fields() {
+        let fields = split_fields("note-on  36 =>\tout1:send(program-change ch1 5) out2");
+        assert_eq!(fields, vec!["note-on", "36", "=>", "out1:send(program-change ch1 5)", "out2"]);
+    }
+
+    #[test]
+    fn test_parse_rule_valid_or() {
+        let line_no = 0;
+        let line = r"note-on ch1 or note-off ch2 or control-change ctrl64 => out";
+        let result = parse_rule(line_no, line.into());
+
+        assert!(result.is_ok());
+        let Rule { condition, actions, .. } = result.unwrap();
+        assert_eq!(condition.channel_pattern, Some(NumericRange { start: 1, end: 1 }));
+        assert_eq!(condition.alternatives.len(), 2);
+        assert_eq!(condition.alternatives[0].channel_pattern, Some(NumericRange { start: 2, end: 2 }));
+        assert_eq!(condition.alternatives[1].controller_pattern, Some(NumericRange { start: 64, end: 64 }));
+        assert!(condition.alternatives.iter().all(|alternative| alternative.alternatives.is_empty()));
+
+        assert!(condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert!(condition.matches(&MidiEvent::NoteOff { channel: 2, note: 60, velocity: 0 }));
+        assert!(condition.matches(&MidiEvent::ControlChange { channel: 5, control_no: 64, value: 127 }));
+        assert!(!condition.matches(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert_eq!(actions, vec![Action::ForwardTo { output_port: "out".into() }]);
+    }
+
+    #[test]
+    fn test_parse_rule_invalid_or() {
+        let result = parse_rule(0, r"note-on ch1 or => out".into());
+        assert!(result.is_err());
+        if let Err(RuleParseError::InvalidFields { invalid_fields, .. }) = result {
+            assert_eq!(invalid_fields.len(), 1);
+            assert_eq!(invalid_fields[0].field_id, 2);
+            assert_eq!(invalid_fields[0].content, "or");
+        }
+
+        let result = parse_rule(0, r"note-on ch1 or".into());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_leading_or() {
+        for line in [r"or note-on ch1 => out", r"not or note-on ch1 => out"] {
+            let result = parse_rule(0, line.into());
+            assert!(result.is_err(), "Rule '{}' should be rejected", line);
+            if let Err(RuleParseError::InvalidFields { invalid_fields, .. }) = result {
+                assert_eq!(invalid_fields.len(), 1);
+                assert_eq!(invalid_fields[0].content, "or");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_rule_doubled_or() {
+        let result = parse_rule(0, r"note-on ch1 or or note-off ch2 => out".into());
+        assert!(result.is_err());
+        if let Err(RuleParseError::InvalidFields { invalid_fields, .. }) = result {
+            assert_eq!(invalid_fields.len(), 1);
+            assert_eq!(invalid_fields[0].field_id, 3);
+            assert_eq!(invalid_fields[0].content, "or");
+        }
+    }
+
+    #[test]
+    fn test_parse_rule_valid_any_note() {
+        let result = parse_rule(0, r"anynote ch1 => out".into());
+
+        assert!(result.is_ok());
+        let Rule { condition, .. } = result.unwrap();
+        assert!(condition.event_pattern.is_none());
+        assert_eq!(condition.category_pattern, Some(EventCategory::Note));
+        assert_eq!(condition.channel_pattern, Some(NumericRange { start: 1, end: 1 }));
+
+        assert!(condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert!(condition.matches(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert!(condition.matches(&MidiEvent::PolyphonicAftertouch { channel: 1, note: 60, pressure: 10 }));
+        assert!(!condition.matches(&MidiEvent::ControlChange { channel: 1, control_no: 1, value: 0 }));
+    }
+
+    #[test]
+    fn test_parse_rule_valid_remap_controller() {
+        let result = parse_rule(0, r"control-change => out1:cc(1->11)".into());
+
+        assert!(result.is_ok());
+        let Rule { actions, .. } = result.unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(RemapController { from: 1, to: 11 })], "out1");
+    }
+
+    #[test]
+    fn test_parse_rule_bend_to_cc() {
+        let Rule { actions, .. } = parse_rule(0, "pitch-bend-change => synth:bend2cc(74)".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(BendToCc { control_no: 74 })], "synth");
+
+        assert!(parse_rule(0, "pitch-bend-change => synth:bend2cc(128)".into()).is_err());
+        assert!(parse_rule(0, "pitch-bend-change => synth:bend2cc()".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_set_channel() {
+        let Rule { actions, .. } = parse_rule(0, "note-.* => out1@3 out1@5 out2".into()).unwrap();
+        assert_eq!(actions.len(), 3);
+        assert_pipeline(&actions[0], &[Box::new(SetChannel { channel: 3 })], "out1");
+        assert_pipeline(&actions[1], &[Box::new(SetChannel { channel: 5 })], "out1");
+        assert_eq!(actions[2], Action::ForwardTo { output_port: "out2".into() });
+
+        let Rule { actions, .. } = parse_rule(0, "note-.* => out1@16:transpose(+12)".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(SetChannel { channel: 16 }), Box::new(Transpose { semitones: 12 })], "out1");
+
+        let Rule { actions, .. } = parse_rule(0, "note-.* => user@host".into()).unwrap();
+        assert_eq!(actions, vec![Action::ForwardTo { output_port: "user@host".into() }]);
+
+        assert!(parse_rule(0, "note-.* => out1@17".into()).is_err());
+        assert!(parse_rule(0, "note-.* => out1@0".into()).is_err());
+        assert!(parse_rule(0, "note-.* => @3".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_cc_to_bend() {
+        let Rule { actions, .. } = parse_rule(0, "control-change ctrl1 => synth:cc2bend(1)".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(CcToBend { control_no: 1 })], "synth");
+
+        let Rule { actions, .. } = parse_rule(0, "control-change => synth:cc2bend(1):cc(2->3)".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(CcToBend { control_no: 1 }), Box::new(RemapController { from: 2, to: 3 })], "synth");
+
+        assert!(parse_rule(0, "control-change => synth:cc2bend(128)".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_quantize() {
+        let Rule { actions, .. } = parse_rule(0, "note-.* => synth:quantize(Amin)".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(QuantizeToScale { scale: "Amin".parse().unwrap() })], "synth");
+
+        assert!(parse_rule(0, "note-.* => synth:quantize(X)".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_quantize_value() {
+        let Rule { actions, .. } = parse_rule(0, "control-change => fx:step(16)".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(QuantizeValue { step: 16 })], "fx");
+
+        assert!(parse_rule(0, "control-change => fx:step(0)".into()).is_err());
+        assert!(parse_rule(0, "control-change => fx:step(128)".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_toggle() {
+        let Rule { actions, .. } = parse_rule(0, "control-change => fx:toggle(64)".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(ToggleSwitch { control_no: 64, state: ToggleState::default() })], "fx");
+
+        assert!(parse_rule(0, "control-change => fx:toggle(128)".into()).is_err());
+    }
+
+    /// Checks that the action is a pipeline of the given transforms, which are compared by their
+    /// parameters since transforms are only equal to themselves
+    fn assert_pipeline(action: &Action, expected_transforms: &[Box<dyn Transform>], expected_output_port: &str) {
+        let Action::Transform { transforms, output_port } = action else {
+            panic!("Expected a transform pipeline: {:?}", action);
+        };
+        assert_eq!(format!("{:?}", transforms), format!("{:?}", expected_transforms));
+        assert_eq!(output_port, expected_output_port);
+    }
+
+    #[test]
+    fn test_parse_rule_transform_pipeline() {
+        let Rule { actions, .. } = parse_rule(0, "note-.* => synth:transpose(+12):vel1-100".into()).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_pipeline(&actions[0], &[Box::new(Transpose { semitones: 12 }), Box::new(ClampVelocity { min: 1, max: 100 })], "synth");
+
+        assert!(parse_rule(0, "note-.* => synth:transpose(+12):redirect".into()).is_err());
+        assert!(parse_rule(0, "note-.* => synth:transpose(+12):vel1-200".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_controller_mapping_invalid() {
+        assert!(parse_controller_mapping("1-11").is_err());
+        assert!(parse_controller_mapping("1->128").is_err());
+        assert!(parse_controller_mapping("x->1").is_err());
+    }
+
+    #[test]
+    fn test_parse_value_field_vel_dynamic() {
+        let field_id = 1;
+        let value = "velmf";
+        let captures = FIELD_PAT.captures(value).unwrap();
+        let result = parse_value_field(field_id, value, captures);
+
+        assert!(result.is_ok());
+        if let Ok(Field::VelocityField { start, end }) = result {
+            assert_eq!(start, 64);
+            assert_eq!(end, 79);
+        } else {
+            panic!("Expected VelocityField variant");
+        }
+    }
+
+    #[test]
+    fn test_parse_field_lhs_vel_dynamics() {
+        for (dynamic, expected_start, expected_end) in [("velppp", 1, 15), ("velp", 32, 47), ("velFF", 96, 111)] {
+            match parse_field_lhs(1, dynamic) {
+                Ok(Field::VelocityField { start, end }) => {
+                    assert_eq!((start, end), (expected_start, expected_end), "Unexpected range for {}", dynamic);
+                },
+                _ => panic!("Expected VelocityField variant for {}", dynamic),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_field_lhs_vel_dynamic_unknown() {
+        let result = parse_field_lhs(1, "velzzz");
+
+        assert!(result.is_err());
+        if let Err(err) = result {
+            assert_eq!(err.content, "velzzz");
+            assert!(err.reason.is_some());
+        }
+    }
+
+    #[test]
+    fn test_parse_field_lhs_dynamic_without_vel() {
+        assert!(parse_field_lhs(1, "chmf").is_err());
+        assert!(parse_field_lhs(1, "mf").is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_valid_sysex() {
+        let result = parse_rule(0, r"system-exclusive sysex=7f,06,01 => transport".into());
+
+        assert!(result.is_ok());
+        let Rule { condition, .. } = result.unwrap();
+        assert_eq!(condition.sysex_sub_id, Some(UniversalSysExId {
+            universal_id: 0x7f,
+            sub_id1: 0x06,
+            sub_id2: Some(0x01),
+        }));
+
+        let mmc_stop = MidiEvent::SystemExclusive { data: vec![0xf0, 0x7f, 0x7f, 0x06, 0x01, 0xf7] };
+        assert!(condition.matches(&mmc_stop));
+    }
+
+    #[test]
+    fn test_parse_sysex_field() {
+        match parse_sysex_field("7E,09") {
+            Ok(Field::SysExField { sysex_id }) => assert_eq!(sysex_id, UniversalSysExId {
+                universal_id: 0x7e,
+                sub_id1: 0x09,
+                sub_id2: None,
+            }),
+            _ => panic!("Expected SysExField variant"),
+        }
+        assert!(parse_sysex_field("43,10").is_err());
+        assert!(parse_sysex_field("7f").is_err());
+        assert!(parse_sysex_field("7f,06,01,02").is_err());
+        assert!(parse_sysex_field("7f,xy").is_err());
+    }
+
+    #[test]
+    fn test_load_rules_from_file_with_buses() {
+        let file_content = r#"
+        note-on ch1 => @synths
+        bus synths = synthA synthB
+        note-off ch1 => drums @synths:transpose(+12)
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let rules = load_rules_from_file(&file).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].actions, vec![
+            Action::ForwardTo { output_port: "synthA".into() },
+            Action::ForwardTo { output_port: "synthB".into() },
+        ]);
+        assert_eq!(rules[1].actions.len(), 3);
+        assert_eq!(rules[1].actions[0], Action::ForwardTo { output_port: "drums".into() });
+        assert_pipeline(&rules[1].actions[1], &[Box::new(Transpose { semitones: 12 })], "synthA");
+        assert_pipeline(&rules[1].actions[2], &[Box::new(Transpose { semitones: 12 })], "synthB");
+    }
+
+    #[test]
+    fn test_load_rules_with_report_undefined_bus() {
+        let file_content = r#"
+        bus synths = synthA synthB
+        note-on ch1 => out1 @pads
+        bus synths = synthC
+        "#;
+        let file = write_tmp_file_content(file_content);
+        let report = load_rules_with_report(&file).unwrap();
+
+        assert!(report.rules.is_empty());
+        assert_eq!(report.errors.len(), 2);
+        let RuleParseError::InvalidFields { line_no, invalid_fields, .. } = &report.errors[0];
+        assert_eq!(*line_no, 3);
+        assert_eq!(invalid_fields[0].field_id, 1);
+        assert_eq!(invalid_fields[0].content, "synths");
+        let RuleParseError::InvalidFields { line_no, invalid_fields, .. } = &report.errors[1];
+        assert_eq!(*line_no, 2);
+        assert_eq!(invalid_fields[0].field_id, 4);
+        assert_eq!(invalid_fields[0].content, "@pads");
+    }
+
+    #[test]
+    fn test_parse_bus_directive() {
+        let result = parse_bus_directive(0, "bus synths = synthA synthB synthC");
+
+        assert!(result.is_ok());
+        let (name, output_ports) = result.unwrap();
+        assert_eq!(name, "synths");
+        assert_eq!(output_ports, vec!["synthA", "synthB", "synthC"]);
+
+        assert!(parse_bus_directive(0, "bus synths =").is_err());
+        assert!(parse_bus_directive(0, "bus synths synthA synthB").is_err());
+        assert!(parse_bus_directive(0, "bus synths = synthA @pads").is_err());
+        assert!(parse_bus_directive(0, "bus synths = synthA:transpose(12)").is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_valid_after_quiet() {
+        let result = parse_rule(0, "note-on afterquiet500 => scenes:send(program-change ch1 2)".into());
+
+        assert!(result.is_ok());
+        let Rule { condition, .. } = result.unwrap();
+        assert_eq!(condition.min_silence_ms, Some(500));
+
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let after_silence = |silence_ms| MatchContext { silence_ms, ..Default::default() };
+        assert!(condition.matches_in_context(&note_on, &after_silence(500)));
+        assert!(!condition.matches_in_context(&note_on, &after_silence(499)));
+    }
+
+    #[test]
+    fn test_parse_field_lhs_after_quiet_invalid() {
+        assert!(parse_field_lhs(1, "afterquiet").is_err());
+        assert!(parse_field_lhs(1, "afterquiet-5").is_err());
+        assert!(parse_field_lhs(1, "afterquiet5s").is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_valid_held() {
+        let rules = ["note-off heldover500 => pad", "note-off heldunder500 => pluck", "note-off heldover100 heldunder500 => pluck"]
+            .map(|line| parse_rule(0, line.into()).unwrap());
+
+        assert_eq!(rules[0].condition.held_pattern, Some(NumericRange { start: 500, end: u64::MAX }));
+        assert_eq!(rules[1].condition.held_pattern, Some(NumericRange { start: 0, end: 499 }));
+        assert_eq!(rules[2].condition.held_pattern, Some(NumericRange { start: 100, end: 499 }));
+        assert_eq!(rules[2].condition.describe(), "note-off heldover100 heldunder500");
+    }
+
+    #[test]
+    fn test_parse_rule_since_program_change() {
+        let rules = ["note-on sincepc>2000 => analytics", "note-on sincepc<500 => fx", "note-on sincepc>100 sincepc<500 => fx"]
+            .map(|line| parse_rule(0, line.into()).unwrap());
+
+        assert_eq!(rules[0].condition.since_program_change_pattern, Some(NumericRange { start: 2001, end: u64::MAX }));
+        assert_eq!(rules[1].condition.since_program_change_pattern, Some(NumericRange { start: 0, end: 499 }));
+        assert_eq!(rules[2].condition.since_program_change_pattern, Some(NumericRange { start: 101, end: 499 }));
+        assert_eq!(rules[0].condition.describe(), "note-on sincepc>2000");
+        assert_eq!(rules[2].condition.describe(), "note-on sincepc>100 sincepc<500");
+
+        assert!(parse_field_lhs(1, "sincepc").is_err());
+        assert!(parse_field_lhs(1, "sincepc2000").is_err());
+        assert!(parse_field_lhs(1, "sincepc<0").is_err());
+        assert!(parse_field_lhs(1, "sincepc>-5").is_err());
+    }
+
+    #[test]
+    fn test_parse_field_lhs_held_invalid() {
+        assert!(parse_field_lhs(1, "heldover").is_err());
+        assert!(parse_field_lhs(1, "heldunder0").is_err());
+        assert!(parse_field_lhs(1, "heldover-5").is_err());
+    }
+
+    #[test]
+    fn test_parse_value_field_inclusive_bounds() {
+        let parse_velocity = |value: &str| match parse_field_lhs(1, value) {
+            Ok(Field::VelocityField { start, end }) => NumericRange { start, end },
+            other => panic!("Expected VelocityField variant, got {:?}", other),
+        };
+
+        let inclusive = parse_velocity("vel>=100");
+        let exclusive = parse_velocity("vel>100");
+        assert!(inclusive.is_within(100));
+        assert!(!exclusive.is_within(100));
+        assert!(inclusive.is_within(101) && exclusive.is_within(101));
+
+        assert_eq!(parse_velocity("vel<=20"), NumericRange { start: 0, end: 20 });
+        assert_eq!(parse_velocity("vel<20"), NumericRange { start: 0, end: 19 });
+    }
+
+    #[test]
+    fn test_parse_value_field_inclusive_value_bounds() {
+        match parse_field_lhs(1, "<=60") {
+            Ok(Field::ValueField { start, end }) => {
+                assert_eq!(start, i16::MIN);
+                assert_eq!(end, 60);
+            },
+            other => panic!("Expected ValueField variant, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_rule_valid_tempo() {
+        let result = parse_rule(0, "note-on tempo120-140 => fast".into());
+
+        assert!(result.is_ok());
+        let Rule { condition, .. } = result.unwrap();
+        assert_eq!(condition.tempo_pattern, Some(NumericRange { start: 120, end: 140 }));
+
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let at_tempo = |tempo_bpm| MatchContext { tempo_bpm, ..Default::default() };
+        assert!(condition.matches_in_context(&note_on, &at_tempo(Some(128.4))));
+        assert!(!condition.matches_in_context(&note_on, &at_tempo(Some(100.0))));
+        assert!(!condition.matches_in_context(&note_on, &at_tempo(None)));
+    }
+
+    #[test]
+    fn test_parse_field_lhs_tempo_bounds() {
+        match parse_field_lhs(1, "tempo>200") {
+            Ok(Field::TempoField { start, end }) => assert_eq!((start, end), (201, 999)),
+            other => panic!("Expected TempoField variant, got {:?}", other),
+        }
+        assert!(parse_field_lhs(1, "tempo1000").is_err());
+        assert!(parse_field_lhs(1, "ch300").is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_zero_velocity_off() {
+        let zero_velocity_note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 };
+
+        let rule = parse_rule(0, "note-off vel0off => out".into()).unwrap();
+        assert!(rule.condition.treat_zero_vel_as_off);
+        assert!(rule.condition.matches(&zero_velocity_note_on));
+        assert!(!rule.condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 1 }));
+
+        let rule = parse_rule(0, "note-off => out".into()).unwrap();
+        assert!(!rule.condition.treat_zero_vel_as_off);
+        assert!(!rule.condition.matches(&zero_velocity_note_on));
+    }
+
+    #[test]
+    fn test_parse_rule_drop_and_redirect() {
+        let rule = parse_rule(0, "active-sensing => drop".into()).unwrap();
+        assert_eq!(rule.actions, vec![Action::Drop]);
+
+        let rule = parse_rule(0, "program-change => synth:redirect monitor".into()).unwrap();
+        assert_eq!(rule.actions, vec![
+            Action::Redirect { output_port: "synth".into() },
+            Action::ForwardTo { output_port: "monitor".into() },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_rule_release_velocity() {
+        let rule = parse_rule(0, "note-.* relvel>100 => out".into()).unwrap();
+
+        assert_eq!(rule.condition.release_velocity_pattern, Some(NumericRange { start: 101, end: 255 }));
+        assert_eq!(rule.condition.velocity_pattern, None);
+        assert!(rule.condition.matches(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 110 }));
+        assert!(!rule.condition.matches(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 50 }));
+        assert!(rule.condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 50 }));
+    }
+
+    #[test]
+    fn test_parse_rule_bank() {
+        let rule = parse_rule(0, "program-change bank256 5 => synth".into()).unwrap();
+        assert_eq!(rule.condition.bank_pattern, Some(NumericRange { start: 256, end: 256 }));
+
+        let rule = parse_rule(0, "program-change bank<128 => synth".into()).unwrap();
+        assert_eq!(rule.condition.bank_pattern, Some(NumericRange { start: 0, end: 127 }));
+
+        assert!(parse_rule(0, "program-change bank16384 => synth".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_sustained() {
+        let rule = parse_rule(0, "note-.* sustained => pad".into()).unwrap();
+        assert!(rule.condition.sustained);
+
+        let rule = parse_rule(0, "note-.* => pad".into()).unwrap();
+        assert!(!rule.condition.sustained);
+    }
+
+    #[test]
+    fn test_parse_rule_options() {
+        let rule = parse_rule(0, "note-on => synth { label=Lead synth }".into()).unwrap();
+        assert_eq!(rule.description.as_deref(), Some("Lead synth"));
+        assert_eq!(rule.actions, vec![Action::ForwardTo { output_port: "synth".into() }]);
+        let rule = parse_rule(0, "note-on => ${port}".into()).unwrap();
+        assert_eq!(rule.actions, vec![Action::ForwardTo { output_port: "${port}".into() }]);
+
+        assert!(parse_rule(0, "note-on => synth { enabled=maybe }".into()).is_err());
+        assert!(parse_rule(0, "note-on => synth { label }".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_dry_run_option() {
+        assert!(parse_rule(0, "note-on => synth { dryrun=true }".into()).unwrap().dry_run);
+        assert!(!parse_rule(0, "note-on => synth { dryrun=false }".into()).unwrap().dry_run);
+        assert!(!parse_rule(0, "note-on => synth".into()).unwrap().dry_run);
+        assert!(parse_rule(0, "note-on => synth { dryrun }".into()).is_err());
+        assert!(parse_rule(0, "note-on => synth { dryrun=yes }".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_options_unknown_key() {
+        let result = parse_rule(0, "note-on => synth { label=Lead, color=red }".into());
+
+        let Err(RuleParseError::InvalidFields { invalid_fields, .. }) = result else {
+            panic!("Expected an error for the unknown option");
+        };
+        assert_eq!(invalid_fields.len(), 1);
+        assert_eq!(invalid_fields[0].field_id, 3);
+        assert_eq!(invalid_fields[0].content, "color=red");
+    }
+
+    #[test]
+    fn test_parse_rule_priority_and_group_options() {
+        let rule = parse_rule(0, "note-on => synth { label=Lead, priority=-2, group=keys, dryrun=true }".into()).unwrap();
+        assert_eq!(rule.description.as_deref(), Some("Lead"));
+        assert_eq!(rule.priority, -2);
+        assert_eq!(rule.group.as_deref(), Some("keys"));
+        assert!(rule.dry_run);
+
+        let rule = parse_rule(0, "note-on => synth".into()).unwrap();
+        assert_eq!(rule.priority, 0);
+        assert_eq!(rule.group, None);
+
+        assert!(parse_rule(0, "note-on => synth { priority=high }".into()).is_err());
+        assert!(parse_rule(0, "note-on => synth { priority=40000 }".into()).is_err());
+        assert!(parse_rule(0, "note-on => synth { group= }".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_stuck() {
+        let rule = parse_rule(0, "note-on stuck => monitor".into()).unwrap();
+        assert!(rule.condition.stuck);
+        assert_eq!(rule.condition.describe(), "note-on stuck");
+    }
+
+    #[test]
+    fn test_parse_rule_running_status() {
+        let rule = parse_rule(0, "note-.* running-status => monitor".into()).unwrap();
+        assert!(rule.condition.running_status);
+        assert_eq!(rule.condition.describe(), "note-.* running-status");
+    }
+
+    #[test]
+    fn test_parse_rule_release() {
+        let rule = parse_rule(0, "release vel0off => synth".into()).unwrap();
+        assert!(rule.condition.release);
+        assert!(rule.condition.treat_zero_vel_as_off);
+        assert!(rule.condition.event_pattern.is_none());
+        assert!(rule.condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 }));
+        assert!(rule.condition.matches(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert_eq!(rule.condition.describe(), "vel0off release");
+    }
+
+    #[test]
+    fn test_parse_rule_transport() {
+        let rule = parse_rule(0, "note-.* transport=rolling => recorder".into()).unwrap();
+        assert_eq!(rule.condition.transport_state, Some(TransportState::Rolling));
+
+        let rule = parse_rule(0, "note-.* transport=stopped => monitor".into()).unwrap();
+        assert_eq!(rule.condition.transport_state, Some(TransportState::Stopped));
+
+        assert!(parse_rule(0, "note-.* transport=paused => monitor".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_scale() {
+        let rule = parse_rule(0, "note-on scale=Cmaj => synth".into()).unwrap();
+        let note_on = |note| MidiEvent::NoteOn { channel: 1, note, velocity: 100 };
+        for note in [60, 62, 64, 65, 67, 69, 71] {
+            assert!(rule.condition.matches(&note_on(note)));
+        }
+        assert!(!rule.condition.matches(&note_on(61)));
+
+        assert!(parse_rule(0, "note-on scale=Cdorian => synth".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_time_window() {
+        let rule = parse_rule(0, "note-on time09:00-17:00 => synth".into()).unwrap();
+        assert_eq!(rule.condition.time_window, Some(TimeWindow { start: 9 * 60, end: 17 * 60 }));
+
+        let rule = parse_rule(0, "note-on time22:30-06:00 => synth".into()).unwrap();
+        assert_eq!(rule.condition.time_window, Some(TimeWindow { start: 22 * 60 + 30, end: 6 * 60 }));
+
+        assert!(parse_rule(0, "note-on time24:00-06:00 => synth".into()).is_err());
+        assert!(parse_rule(0, "note-on time09:00 => synth".into()).is_err());
+        assert!(parse_rule(0, "note-on time9-17 => synth".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_controller_set() {
+        let rule = parse_rule(0, "control-change ctrl1,7,11 => mixer".into()).unwrap();
+        let control_change = |control_no| MidiEvent::ControlChange { channel: 1, control_no, value: 64 };
+        assert!(rule.condition.matches(&control_change(1)));
+        assert!(rule.condition.matches(&control_change(7)));
+        assert!(rule.condition.matches(&control_change(11)));
+        assert!(!rule.condition.matches(&control_change(2)));
+        assert!(!rule.condition.matches(&control_change(64)));
+        assert!(rule.condition.controller_pattern.is_none());
+
+        let rule = parse_rule(0, "control-change ctrl1-7 => mixer".into()).unwrap();
+        assert_eq!(rule.condition.controller_pattern, Some(NumericRange { start: 1, end: 7 }));
+        assert!(rule.condition.controller_set.is_none());
+
+        assert!(parse_rule(0, "control-change ctrl1,256 => mixer".into()).is_err());
+        assert!(parse_rule(0, "control-change ctrl1, => mixer".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_controller_kind() {
+        let rule = parse_rule(0, "control-change ctrl64 switch => pedal".into()).unwrap();
+        assert_eq!(rule.condition.controller_kind, Some(ControllerKind::Switch));
+        let control_change = |value| MidiEvent::ControlChange { channel: 1, control_no: 64, value };
+        assert!(rule.condition.matches(&control_change(0)));
+        assert!(rule.condition.matches(&control_change(127)));
+        assert!(!rule.condition.matches(&control_change(64)));
+
+        let rule = parse_rule(0, "control-change continuous => knobs".into()).unwrap();
+        assert_eq!(rule.condition.controller_kind, Some(ControllerKind::Continuous));
+    }
+
+    #[test]
+    fn test_parse_rule_slew() {
+        let rule = parse_rule(0, "control-change ctrl1 slew>20 => sweep".into()).unwrap();
+
+        assert_eq!(rule.condition.slew_pattern, Some(NumericRange { start: 21, end: 255 }));
+    }
+
+    #[test]
+    fn test_parse_rule_length() {
+        let rule = parse_rule(0, ".* len2 => debug".into()).unwrap();
+        assert_eq!(rule.condition.length_pattern, Some(NumericRange { start: 2, end: 2 }));
+
+        let rule = parse_rule(0, "system-exclusive len>100 => debug".into()).unwrap();
+        assert_eq!(rule.condition.length_pattern, Some(NumericRange { start: 101, end: MAX_LENGTH }));
+    }
+
+    #[test]
+    fn test_parse_rule_velocity_complement() {
+        let rule = parse_rule(0, "note-on velcomp>=100 => soft".into()).unwrap();
+        assert_eq!(rule.condition.velocity_pattern, Some(NumericRange { start: 0, end: 99 }));
+        assert!(rule.condition.velocity_complement);
+
+        let rule = parse_rule(0, "note-on velcomp<40 => loud".into()).unwrap();
+        assert_eq!(rule.condition.velocity_pattern, Some(NumericRange { start: 40, end: 255 }));
+        let rule = parse_rule(0, "note-on vel<40 => soft".into()).unwrap();
+        assert!(!rule.condition.velocity_complement);
+
+        assert!(parse_rule(0, "note-on velcomp40-80 => soft".into()).is_err());
+        assert!(parse_rule(0, "note-on velcomp* => soft".into()).is_err());
+        assert!(parse_rule(0, "note-on velcomp => soft".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_negated() {
+        let rule = parse_rule(0, "not note-on ch1 => out".into()).unwrap();
+        assert!(rule.negated);
+        assert_eq!(rule.condition.channel_pattern, Some(NumericRange { start: 1, end: 1 }));
+        assert_eq!(rule.describe_condition(), "not note-on ch1");
+
+        let rule = parse_rule(0, "note-on ch1 => out".into()).unwrap();
+        assert!(!rule.negated);
+
+        let Err(RuleParseError::InvalidFields { invalid_fields, .. }) = parse_rule(0, "not => out".into()) else {
+            panic!("Expected an error for the missing condition");
+        };
+        assert_eq!(invalid_fields.len(), 1);
+        assert_eq!(invalid_fields[0].field_id, 0);
+        assert_eq!(invalid_fields[0].content, "not");
+
+        let Err(RuleParseError::InvalidFields { invalid_fields, .. }) = parse_rule(0, "not note-(on|off) => out-$1 all".into()) else {
+            panic!("Expected an error for the capture reference");
+        };
+        assert_eq!(invalid_fields.len(), 1);
+        assert_eq!(invalid_fields[0].field_id, 3);
+        assert_eq!(invalid_fields[0].reason.as_ref().unwrap().to_string(), FieldFormatError::CaptureInNegatedRule.to_string());
+    }
+
+    #[test]
+    fn test_parse_rule_voice() {
+        let rule = parse_rule(0, "note-.* voice2 => tenor".into()).unwrap();
+        assert_eq!(rule.condition.voice_pattern, Some(NumericRange { start: 2, end: 2 }));
+
+        let rule = parse_rule(0, "note-.* voice>2 => soprano".into()).unwrap();
+        assert_eq!(rule.condition.voice_pattern, Some(NumericRange { start: 3, end: 255 }));
+        assert_eq!(rule.condition.describe(), "note-.* voice>2");
+    }
+
+    #[test]
+    fn test_parse_rule_pitch_bend_bytes() {
+        let rule = parse_rule(0, "pitch-bend-change pbmsb>64 => coarse".into()).unwrap();
+        assert_eq!(rule.condition.pitch_bend_msb_pattern, Some(NumericRange { start: 65, end: 255 }));
+        assert_eq!(rule.condition.describe(), "pitch-bend-change pbmsb>64");
+        let bend = |msb: i16, lsb: i16| MidiEvent::PitchBendChange { channel: 1, value: (msb << 7) + lsb - 8192 };
+        assert!(rule.condition.matches(&bend(65, 0)));
+        assert!(rule.condition.matches(&bend(65, 127)));
+        assert!(!rule.condition.matches(&bend(64, 127)));
+
+        let rule = parse_rule(0, "pitch-bend-change pblsb0-15 => fine".into()).unwrap();
+        assert_eq!(rule.condition.pitch_bend_lsb_pattern, Some(NumericRange { start: 0, end: 15 }));
+        assert_eq!(normalize_value_field("pbmsb>=65"), Some("pbmsb>64".to_string()));
+        assert!(parse_rule(0, "pitch-bend-change pbmsb300 => coarse".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_log_modifier() {
+        let rule = parse_rule(0, "note-on => out1:log out2:transpose(+12):log".into()).unwrap();
+
+        assert_eq!(rule.actions.len(), 2);
+        assert_eq!(rule.actions[0], Action::Logged { action: Box::new(Action::ForwardTo { output_port: "out1".into() }) });
+        let Action::Logged { action } = &rule.actions[1] else {
+            panic!("Expected a logged action: {:?}", rule.actions[1]);
+        };
+        assert_pipeline(action, &[Box::new(Transpose { semitones: 12 })], "out2");
+        assert!(parse_rule(0, "note-on => :log".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_pickup_modifier() {
+        let rule = parse_rule(0, "control-change ch1-2 => synth@1:cc(1->7):pickup synth:pickup:log".into()).unwrap();
+
+        let Action::Pickup { action, .. } = &rule.actions[0] else {
+            panic!("Unexpected action: {:?}", rule.actions[0]);
+        };
+        assert_pipeline(action, &[Box::new(SetChannel { channel: 1 }), Box::new(RemapController { from: 1, to: 7 })], "synth");
+        assert_eq!(rule.actions[1..], [
+            Action::Logged {
+                action: Box::new(Action::Pickup {
+                    action: Box::new(Action::ForwardTo { output_port: "synth".into() }),
+                    state: PickupState::new(),
+                }),
+            },
+        ]);
+        assert!(parse_rule(0, "control-change => :pickup".into()).is_err());
+    }
 }
@@ -0,0 +1,54 @@
+/*
+ * Track program changes to know how long the current preset of a channel has been selected
+ */
+
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+
+pub struct PresetTracker {
+    /// Time in milliseconds of the last program change by channel
+    program_change_ms: [Option<u64>; CHANNEL_COUNT],
+}
+
+impl PresetTracker {
+    pub fn new() -> Self {
+        PresetTracker {
+            program_change_ms: [None; CHANNEL_COUNT],
+        }
+    }
+
+    /// Records program changes and returns the time in milliseconds since the last program change
+    /// on the event's channel, which is unknown until a program change was received. For a
+    /// program change, this is the time since the previous one.
+    pub fn track(&mut self, midi_event: &MidiEvent, event_ms: u64) -> Option<u64> {
+        let channel = midi_event.channel()?;
+        let program_change_ms = self.program_change_ms.get_mut((channel as usize).checked_sub(1)?)?;
+        let since_program_change_ms = program_change_ms.map(|program_change_ms| event_ms.saturating_sub(program_change_ms));
+        if let MidiEvent::ProgramChange { .. } = midi_event {
+            *program_change_ms = Some(event_ms);
+        }
+        since_program_change_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_tracker() {
+        let mut preset_tracker = PresetTracker::new();
+        let note_on = |channel| MidiEvent::NoteOn { channel, note: 60, velocity: 100 };
+        let program_change = |channel| MidiEvent::ProgramChange { channel, program: 5 };
+
+        assert_eq!(preset_tracker.track(&note_on(1), 100), None);
+        assert_eq!(preset_tracker.track(&program_change(1), 1000), None);
+        assert_eq!(preset_tracker.track(&note_on(1), 1000), Some(0));
+        assert_eq!(preset_tracker.track(&note_on(1), 3500), Some(2500));
+        assert_eq!(preset_tracker.track(&note_on(2), 3500), None);
+        assert_eq!(preset_tracker.track(&MidiEvent::TimingClock {}, 3500), None);
+        assert_eq!(preset_tracker.track(&program_change(1), 4000), Some(3000));
+        assert_eq!(preset_tracker.track(&note_on(1), 4200), Some(200));
+    }
+}
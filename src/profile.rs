@@ -0,0 +1,127 @@
+/*
+ * Measure the duration of the process callback
+ */
+
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Durations of the process callbacks since the last summary, which are recorded by the process
+/// handler and collected by another thread
+#[derive(Debug)]
+pub struct ProcessProfile {
+    min_ns: AtomicU64,
+    max_ns: AtomicU64,
+    total_ns: AtomicU64,
+    count: AtomicU64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ProfileSummary {
+    pub min: Duration,
+    pub max: Duration,
+    pub avg: Duration,
+    pub count: u64,
+}
+
+impl Display for ProfileSummary {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            formatter,
+            "Process callback: min {:?}, max {:?}, avg {:?} over {} cycles",
+            self.min,
+            self.max,
+            self.avg,
+            self.count,
+        )
+    }
+}
+
+impl ProcessProfile {
+    pub fn new() -> Self {
+        ProcessProfile {
+            min_ns: AtomicU64::new(u64::MAX),
+            max_ns: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, duration: Duration) {
+        let duration_ns = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.min_ns.fetch_min(duration_ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(duration_ns, Ordering::Relaxed);
+        self.total_ns.fetch_add(duration_ns, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Summarizes all durations recorded since the last summary and starts a new window, or
+    /// returns `None` if nothing was recorded
+    pub fn take_summary(&self) -> Option<ProfileSummary> {
+        let count = self.count.swap(0, Ordering::Relaxed);
+        let min_ns = self.min_ns.swap(u64::MAX, Ordering::Relaxed);
+        let max_ns = self.max_ns.swap(0, Ordering::Relaxed);
+        let total_ns = self.total_ns.swap(0, Ordering::Relaxed);
+        if count == 0 {
+            return None;
+        }
+        Some(ProfileSummary {
+            min: Duration::from_nanos(min_ns),
+            max: Duration::from_nanos(max_ns),
+            avg: Duration::from_nanos(total_ns / count),
+            count,
+        })
+    }
+
+    /// Starts a thread which passes a summary to `on_summary` after each interval
+    pub fn spawn_reporter<F: Fn(ProfileSummary) + Send + 'static>(self: Arc<Self>,
+                                                                  interval: Duration,
+                                                                  on_summary: F) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if let Some(summary) = self.take_summary() {
+                on_summary(summary);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_profile_summary() {
+        let profile = ProcessProfile::new();
+        assert_eq!(profile.take_summary(), None);
+
+        profile.record(Duration::from_micros(20));
+        profile.record(Duration::from_micros(50));
+        profile.record(Duration::from_micros(110));
+
+        assert_eq!(profile.take_summary(), Some(ProfileSummary {
+            min: Duration::from_micros(20),
+            max: Duration::from_micros(110),
+            avg: Duration::from_micros(60),
+            count: 3,
+        }));
+    }
+
+    #[test]
+    fn test_process_profile_window_reset() {
+        let profile = ProcessProfile::new();
+        profile.record(Duration::from_micros(500));
+        profile.take_summary();
+
+        profile.record(Duration::from_micros(7));
+
+        let summary = profile.take_summary().unwrap();
+        assert_eq!(summary.min, Duration::from_micros(7));
+        assert_eq!(summary.max, Duration::from_micros(7));
+        assert_eq!(summary.count, 1);
+        assert_eq!(profile.take_summary(), None);
+    }
+}
@@ -0,0 +1,159 @@
+/*
+ * Queue of events which could not be written because the buffer of their output port was full,
+ * e.g. when one event fans out to many ports, so that they are sent in the next cycle
+ */
+
+use std::collections::VecDeque;
+use jack::Error as JackError;
+use log::{debug, warn};
+
+/// Largest number of queued events, beyond which the oldest ones are dropped
+const MAX_QUEUED_EVENTS: usize = 1024;
+
+#[derive(Debug, Default)]
+pub struct RetryQueue {
+    /// Output port and raw bytes of the queued events in the order they were sent
+    events: VecDeque<(String, Vec<u8>)>,
+}
+
+impl RetryQueue {
+    pub fn new() -> Self {
+        RetryQueue::default()
+    }
+
+    /// Checks whether events for the port are queued, so that later events must be queued too to
+    /// keep their order
+    pub fn has_pending(&self, port_name: &str) -> bool {
+        self.events.iter().any(|(queued_port_name, _)| queued_port_name == port_name)
+    }
+
+    pub fn push(&mut self, port_name: &str, bytes: &[u8]) {
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            if let Some((dropped_port_name, dropped_bytes)) = self.events.pop_front() {
+                warn!("Retry queue is full, dropping event {:?} for port {}", dropped_bytes, dropped_port_name);
+            }
+        }
+        self.events.push_back((port_name.to_string(), bytes.to_vec()));
+    }
+
+    /// Writes the queued events with `write` in their order, and keeps those which fail again
+    /// because the buffer is still full. Returns the ports of the events which were written.
+    pub fn flush<F>(&mut self, mut write: F) -> Vec<String>
+    where F: FnMut(&str, &[u8]) -> Result<(), JackError> {
+        let mut written_ports = Vec::new();
+        let mut retained = VecDeque::new();
+        for (port_name, bytes) in self.events.drain(..) {
+            let is_blocked = retained.iter().any(|(retained_port_name, _)| *retained_port_name == port_name);
+            if is_blocked {
+                retained.push_back((port_name, bytes));
+                continue;
+            }
+            match write(&port_name, &bytes) {
+                Ok(()) => {
+                    debug!("Sent queued event {:?} to port {}", bytes, port_name);
+                    written_ports.push(port_name);
+                },
+                Err(JackError::NotEnoughSpace) => retained.push_back((port_name, bytes)),
+                Err(err) => warn!("Could not send queued event {:?} to port {}: {}", bytes, port_name, err),
+            }
+        }
+        self.events = retained;
+        written_ports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_queue_flush() {
+        let mut retry_queue = RetryQueue::new();
+        retry_queue.push("synth", &[0x90, 60, 100]);
+        retry_queue.push("drums", &[0x99, 36, 100]);
+        retry_queue.push("synth", &[0x80, 60, 0]);
+
+        let mut written = Vec::new();
+        let written_ports = retry_queue.flush(|port_name, bytes| {
+            written.push((port_name.to_string(), bytes.to_vec()));
+            Ok(())
+        });
+
+        assert_eq!(written_ports, vec!["synth", "drums", "synth"]);
+        assert_eq!(written, vec![
+            ("synth".to_string(), vec![0x90, 60, 100]),
+            ("drums".to_string(), vec![0x99, 36, 100]),
+            ("synth".to_string(), vec![0x80, 60, 0]),
+        ]);
+        assert_eq!(retry_queue.events.len(), 0);
+    }
+
+    #[test]
+    fn test_retry_queue_flush_buffer_still_full() {
+        let mut retry_queue = RetryQueue::new();
+        retry_queue.push("synth", &[0x90, 60, 100]);
+        retry_queue.push("drums", &[0x99, 36, 100]);
+        retry_queue.push("synth", &[0x80, 60, 0]);
+
+        let written_ports = retry_queue.flush(|port_name, _| match port_name {
+            "synth" => Err(JackError::NotEnoughSpace),
+            _ => Ok(()),
+        });
+
+        assert_eq!(written_ports, vec!["drums"]);
+        assert_eq!(retry_queue.events.len(), 2);
+        assert!(retry_queue.has_pending("synth"));
+        assert!(!retry_queue.has_pending("drums"));
+
+        let mut written = Vec::new();
+        retry_queue.flush(|_, bytes| {
+            written.push(bytes.to_vec());
+            Ok(())
+        });
+        assert_eq!(written, vec![vec![0x90, 60, 100], vec![0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn test_retry_queue_keeps_order_after_partial_flush() {
+        let mut retry_queue = RetryQueue::new();
+        retry_queue.push("synth", &[0x90, 60, 100]);
+        retry_queue.push("synth", &[0x90, 62, 100]);
+
+        let mut free_space = 1;
+        retry_queue.flush(|_, _| match free_space {
+            0 => Err(JackError::NotEnoughSpace),
+            _ => {
+                free_space -= 1;
+                Ok(())
+            },
+        });
+
+        assert_eq!(retry_queue.events.len(), 1);
+        let mut written = Vec::new();
+        retry_queue.flush(|_, bytes| {
+            written.push(bytes.to_vec());
+            Ok(())
+        });
+        assert_eq!(written, vec![vec![0x90, 62, 100]]);
+    }
+
+    #[test]
+    fn test_retry_queue_drops_other_errors_and_oldest_events() {
+        let mut retry_queue = RetryQueue::new();
+        retry_queue.push("synth", &[0x90, 60, 100]);
+        let written_ports = retry_queue.flush(|_, _| Err(JackError::PortAliasError));
+        assert!(written_ports.is_empty());
+        assert_eq!(retry_queue.events.len(), 0);
+
+        for note in 0..(MAX_QUEUED_EVENTS + 1) {
+            retry_queue.push("synth", &[0x90, note as u8, 100]);
+        }
+        assert_eq!(retry_queue.events.len(), MAX_QUEUED_EVENTS);
+        let mut first_written = None;
+        retry_queue.flush(|_, bytes| {
+            first_written.get_or_insert(bytes.to_vec());
+            Ok(())
+        });
+        assert_eq!(first_written, Some(vec![0x90, 1, 100]));
+    }
+}
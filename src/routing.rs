@@ -2,12 +2,31 @@
  * Core MIDI signal routing logic
  */
 
-use crate::midi::MidiEvent;
-use regex::Regex;
-use std::collections::HashSet;
-use log::debug;
+use crate::bank::MAX_BANK;
+use crate::note_cap::NoteCap;
+use crate::scale::Scale;
+use crate::stats::RuleStats;
+use crate::time_window::TimeWindow;
+use crate::transform::{apply_all, preview_all, PickupState, Transform};
+use crate::midi::{EventCategory, EventTypeMask, MidiEvent, UniversalSysExId};
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::fmt::Debug;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
 
-#[derive(Debug, PartialEq)]
+/// Highest message length that can be given in a condition, which is also used for longer messages
+pub const MAX_LENGTH: u16 = i16::MAX as u16;
+
+/// Starts a reference to a capture group of the event pattern in an output port name, e.g. `out-$1`
+pub const CAPTURE_REF_PREFIX: char = '$';
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct NumericRange<T> {
     pub start: T,
     pub end: T,
@@ -19,24 +38,492 @@ impl<T: PartialOrd> NumericRange<T> {
     }
 }
 
+impl<T: Copy + Into<i64>> NumericRange<T> {
+    /// Writes the range in config file syntax, e.g. `ch1-8` or `<60`, given the bounds of its type
+    pub fn describe(&self, prefix: &str, min: i64, max: i64) -> String {
+        let (start, end) = (self.start.into(), self.end.into());
+        if start == end {
+            format!("{}{}", prefix, start)
+        } else if start <= min && end >= max {
+            format!("{}*", prefix)
+        } else if start <= min {
+            format!("{}<{}", prefix, end + 1)
+        } else if end >= max {
+            format!("{}>{}", prefix, start - 1)
+        } else {
+            format!("{}{}-{}", prefix, start, end)
+        }
+    }
+}
+
+/// Kind of controller distinguished by the values of its control changes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControllerKind {
+    /// Only sends 0 or 127, e.g. a footswitch
+    Switch,
+    /// Sends values between 0 and 127, e.g. a knob
+    Continuous,
+}
+
+impl ControllerKind {
+    pub fn contains(&self, value: u8) -> bool {
+        let is_switch_value = value == 0 || value == 127;
+        match self {
+            ControllerKind::Switch => is_switch_value,
+            ControllerKind::Continuous => !is_switch_value,
+        }
+    }
+}
+
+/// State of the JACK transport, where starting counts as stopped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportState {
+    Stopped,
+    Rolling,
+}
+
+/// Set of controller numbers, e.g. `ctrl1,7,11`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControllerSet {
+    /// Sorted controller numbers without duplicates
+    controllers: Vec<u8>,
+}
+
+impl ControllerSet {
+    pub fn new(mut controllers: Vec<u8>) -> Self {
+        controllers.sort_unstable();
+        controllers.dedup();
+        ControllerSet { controllers }
+    }
+
+    pub fn contains(&self, controller: u8) -> bool {
+        self.controllers.binary_search(&controller).is_ok()
+    }
+
+    /// Writes the set in config file syntax, e.g. `ctrl1,7,11`
+    pub fn describe(&self) -> String {
+        let controllers: Vec<String> = self.controllers.iter().map(u8::to_string).collect();
+        format!("ctrl{}", controllers.join(","))
+    }
+}
+
+/// Timing of an event relative to previous events, which is required by time-based conditions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchContext {
+    /// Time in milliseconds since the previous event
+    pub silence_ms: u64,
+    /// Time in milliseconds a note was held, which is only known when the note is released
+    pub held_ms: Option<u64>,
+    /// Time in milliseconds since the last program change on the channel of the event, if any
+    pub since_program_change_ms: Option<u64>,
+    /// Tempo in beats per minute derived from timing clock events, if any
+    pub tempo_bpm: Option<f64>,
+    /// Bank selected on the channel of the event, if any
+    pub bank: Option<u16>,
+    /// Whether the sustain pedal is down on the channel of the event
+    pub sustain_pedal_down: bool,
+    /// Whether the event is a note-on of a note which is already held without a note-off between
+    pub repeated_note_on: bool,
+    /// Change of the value since the previous control change of the same controller, if any
+    pub slew: Option<u8>,
+    /// Number of bytes of the raw message, if it was received from a port
+    pub raw_len: Option<usize>,
+    /// Whether the status byte of the message was omitted and restored from the running status
+    pub running_status: bool,
+    /// Position of a note among the held notes of its channel from the lowest note, starting at 1
+    pub voice: Option<u8>,
+    /// Minutes since midnight when the event was received, if known
+    pub minute_of_day: Option<u16>,
+    /// State of the JACK transport when the event was received, if known
+    pub transport: Option<TransportState>,
+}
+
+impl Default for MatchContext {
+    fn default() -> Self {
+        MatchContext {
+            silence_ms: u64::MAX,
+            held_ms: None,
+            since_program_change_ms: None,
+            tempo_bpm: None,
+            bank: None,
+            sustain_pedal_down: false,
+            repeated_note_on: false,
+            slew: None,
+            raw_len: None,
+            running_status: false,
+            voice: None,
+            minute_of_day: None,
+            transport: None,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Condition {
     pub event_pattern: Option<Regex>,
+    pub category_pattern: Option<EventCategory>,
+    /// Match note-on events with velocity 0 as note-off events, which they are by convention
+    pub treat_zero_vel_as_off: bool,
+    /// Only match note events while the sustain pedal is down
+    pub sustained: bool,
+    /// Only match note-on events of notes which are already held, which are likely stuck notes
+    pub stuck: bool,
+    /// Only match events releasing a note, i.e. note-off events and, with `treat_zero_vel_as_off`,
+    /// note-on events with velocity 0
+    pub release: bool,
+    /// Only match events received with running status, i.e. without their status byte
+    pub running_status: bool,
     pub channel_pattern: Option<NumericRange<u8>>,
     pub value_pattern: Option<NumericRange<i16>>,
+    /// Range of the most significant 7 bits of the value of pitch bends
+    pub pitch_bend_msb_pattern: Option<NumericRange<u8>>,
+    /// Range of the least significant 7 bits of the value of pitch bends
+    pub pitch_bend_lsb_pattern: Option<NumericRange<u8>>,
     pub velocity_pattern: Option<NumericRange<u8>>,
+    /// Whether the velocity range was given with `velcomp` as the complement of another range,
+    /// which another rule is expected to match
+    pub velocity_complement: bool,
+    /// Range of the release velocity, which only applies to note-off events
+    pub release_velocity_pattern: Option<NumericRange<u8>>,
     pub controller_pattern: Option<NumericRange<u8>>,
+    /// Controllers of control changes given as a list instead of a range
+    pub controller_set: Option<ControllerSet>,
+    /// Kind of controller of control changes, given by their value
+    pub controller_kind: Option<ControllerKind>,
+    /// Range of the change of a controller's value since its previous control change
+    pub slew_pattern: Option<NumericRange<u8>>,
+    /// Range of the number of bytes of the raw message
+    pub length_pattern: Option<NumericRange<u16>>,
+    /// Range of the voice of a note within the held chord, where voice 1 is the lowest note
+    pub voice_pattern: Option<NumericRange<u8>>,
+    pub sysex_sub_id: Option<UniversalSysExId>,
+    /// Minimum time in milliseconds without any incoming event before the event
+    pub min_silence_ms: Option<u64>,
+    /// Range of the time in milliseconds a released note was held
+    pub held_pattern: Option<NumericRange<u64>>,
+    /// Range of the time in milliseconds since the last program change on the channel
+    pub since_program_change_pattern: Option<NumericRange<u64>>,
+    /// Range of the tempo in beats per minute
+    pub tempo_pattern: Option<NumericRange<u16>>,
+    /// Range of the bank selected before a program change
+    pub bank_pattern: Option<NumericRange<u16>>,
+    /// Time of day during which the condition applies
+    pub time_window: Option<TimeWindow>,
+    /// Scale which the note of note events must be in
+    pub scale: Option<Scale>,
+    /// State the JACK transport must be in
+    pub transport_state: Option<TransportState>,
+    /// Only every n-th event that matches all other patterns matches
+    pub every: Option<u64>,
+    /// Only events at odd (0) or even (1) positions among the events that match all other
+    /// patterns match, so that two rules can split a stream alternately
+    pub alternate: Option<u8>,
+    /// Number of events that matched all other patterns so far
+    pub match_count: AtomicU64,
+    /// Cache of `possible_event_types`, which is computed once the condition is first matched
+    pub event_types: OnceLock<EventTypeMask>,
+    /// Further conditions of which any may match instead of this one
+    pub alternatives: Vec<Condition>,
 }
 
 impl Condition {
+    #[cfg(test)]
     pub fn matches(&self, midi_event: &MidiEvent) -> bool {
+        self.matches_in_context(midi_event, &MatchContext::default())
+    }
+
+    /// Matches an event including time-based conditions, which require the timing of the event
+    pub fn matches_in_context(&self, midi_event: &MidiEvent, context: &MatchContext) -> bool {
+        self.matches_self(midi_event, context)
+            || self.alternatives.iter().any(|alternative| alternative.matches_in_context(midi_event, context))
+    }
+
+    /// Whether this condition or any alternative depends on the time of day
+    pub fn uses_time_window(&self) -> bool {
+        self.time_window.is_some() || self.alternatives.iter().any(Condition::uses_time_window)
+    }
+
+    /// Takes over all patterns of `outer` which are not set in this condition and its alternatives
+    pub fn inherit(&mut self, outer: &Condition) {
+        fn inherit_pattern<T: Clone>(pattern: &mut Option<T>, outer_pattern: &Option<T>) {
+            if pattern.is_none() {
+                pattern.clone_from(outer_pattern);
+            }
+        }
+        inherit_pattern(&mut self.event_pattern, &outer.event_pattern);
+        inherit_pattern(&mut self.category_pattern, &outer.category_pattern);
+        inherit_pattern(&mut self.channel_pattern, &outer.channel_pattern);
+        inherit_pattern(&mut self.value_pattern, &outer.value_pattern);
+        inherit_pattern(&mut self.pitch_bend_msb_pattern, &outer.pitch_bend_msb_pattern);
+        inherit_pattern(&mut self.pitch_bend_lsb_pattern, &outer.pitch_bend_lsb_pattern);
+        if self.velocity_pattern.is_none() {
+            self.velocity_complement = outer.velocity_complement;
+        }
+        inherit_pattern(&mut self.velocity_pattern, &outer.velocity_pattern);
+        inherit_pattern(&mut self.release_velocity_pattern, &outer.release_velocity_pattern);
+        inherit_pattern(&mut self.controller_pattern, &outer.controller_pattern);
+        inherit_pattern(&mut self.controller_set, &outer.controller_set);
+        inherit_pattern(&mut self.controller_kind, &outer.controller_kind);
+        inherit_pattern(&mut self.slew_pattern, &outer.slew_pattern);
+        inherit_pattern(&mut self.length_pattern, &outer.length_pattern);
+        inherit_pattern(&mut self.voice_pattern, &outer.voice_pattern);
+        inherit_pattern(&mut self.sysex_sub_id, &outer.sysex_sub_id);
+        inherit_pattern(&mut self.min_silence_ms, &outer.min_silence_ms);
+        inherit_pattern(&mut self.held_pattern, &outer.held_pattern);
+        inherit_pattern(&mut self.since_program_change_pattern, &outer.since_program_change_pattern);
+        inherit_pattern(&mut self.tempo_pattern, &outer.tempo_pattern);
+        inherit_pattern(&mut self.bank_pattern, &outer.bank_pattern);
+        inherit_pattern(&mut self.time_window, &outer.time_window);
+        inherit_pattern(&mut self.scale, &outer.scale);
+        inherit_pattern(&mut self.transport_state, &outer.transport_state);
+        inherit_pattern(&mut self.every, &outer.every);
+        inherit_pattern(&mut self.alternate, &outer.alternate);
+        self.treat_zero_vel_as_off |= outer.treat_zero_vel_as_off;
+        self.sustained |= outer.sustained;
+        self.stuck |= outer.stuck;
+        self.release |= outer.release;
+        self.running_status |= outer.running_status;
+        for alternative in &mut self.alternatives {
+            alternative.inherit(outer);
+        }
+        self.event_types = OnceLock::new();
+    }
+
+    /// Types of the events this condition or any alternative can match, so that it can be skipped
+    /// for other events without matching all patterns
+    pub fn possible_event_types(&self) -> EventTypeMask {
+        *self.event_types.get_or_init(|| {
+            self.alternatives.iter()
+                .map(Condition::possible_event_types)
+                .fold(self.own_event_types(), EventTypeMask::union)
+        })
+    }
+
+    fn own_event_types(&self) -> EventTypeMask {
+        let mut event_types = match &self.event_pattern {
+            Some(event_pattern) => EventTypeMask::matching(|event_name| event_pattern.is_match(event_name)),
+            None => EventTypeMask::ALL,
+        };
+        if let Some(category) = self.category_pattern {
+            event_types = event_types.intersection(category.event_types());
+        }
+        let note_off_types = EventTypeMask::matching(|event_name| event_name == "note-off");
+        if self.release {
+            event_types = event_types.intersection(note_off_types);
+        }
+        if self.treat_zero_vel_as_off && event_types.intersects(note_off_types) {
+            // note-on events with velocity 0 are matched as note-off events
+            event_types = event_types.union(EventTypeMask::matching(|event_name| event_name == "note-on"));
+        }
+        event_types
+    }
+
+    /// Summarizes the condition in config file syntax, e.g. `note-on ch1 <60`
+    pub fn describe(&self) -> String {
+        let mut fields = Vec::new();
+        if let Some(event_pattern) = &self.event_pattern {
+            fields.push(event_pattern.as_str().to_string());
+        }
+        if let Some(EventCategory::Note) = self.category_pattern {
+            fields.push("anynote".to_string());
+        }
+        if self.treat_zero_vel_as_off {
+            fields.push("vel0off".to_string());
+        }
+        if self.sustained {
+            fields.push("sustained".to_string());
+        }
+        if self.stuck {
+            fields.push("stuck".to_string());
+        }
+        if self.release {
+            fields.push("release".to_string());
+        }
+        if self.running_status {
+            fields.push("running-status".to_string());
+        }
+        if let Some(tempo_pattern) = &self.tempo_pattern {
+            fields.push(tempo_pattern.describe("tempo", u16::MIN.into(), u16::MAX.into()));
+        }
+        if let Some(length_pattern) = &self.length_pattern {
+            fields.push(length_pattern.describe("len", u16::MIN.into(), MAX_LENGTH.into()));
+        }
+        if let Some(voice_pattern) = &self.voice_pattern {
+            fields.push(voice_pattern.describe("voice", u8::MIN.into(), u8::MAX.into()));
+        }
+        if let Some(pitch_bend_msb_pattern) = &self.pitch_bend_msb_pattern {
+            fields.push(pitch_bend_msb_pattern.describe("pbmsb", u8::MIN.into(), u8::MAX.into()));
+        }
+        if let Some(pitch_bend_lsb_pattern) = &self.pitch_bend_lsb_pattern {
+            fields.push(pitch_bend_lsb_pattern.describe("pblsb", u8::MIN.into(), u8::MAX.into()));
+        }
+        if let Some(bank_pattern) = &self.bank_pattern {
+            fields.push(bank_pattern.describe("bank", u16::MIN.into(), MAX_BANK.into()));
+        }
+        if let Some(time_window) = &self.time_window {
+            fields.push(time_window.describe());
+        }
+        if let Some(scale) = &self.scale {
+            fields.push(format!("scale={}", scale.describe()));
+        }
+        match self.transport_state {
+            Some(TransportState::Stopped) => fields.push("transport=stopped".to_string()),
+            Some(TransportState::Rolling) => fields.push("transport=rolling".to_string()),
+            None => {},
+        }
+        let u8_ranges = [
+            ("ch", &self.channel_pattern),
+            ("vel", &self.velocity_pattern),
+            ("relvel", &self.release_velocity_pattern),
+            ("ctrl", &self.controller_pattern),
+            ("slew", &self.slew_pattern),
+        ];
+        if let Some(value_pattern) = &self.value_pattern {
+            fields.push(value_pattern.describe("", i16::MIN.into(), i16::MAX.into()));
+        }
+        for (prefix, range) in u8_ranges {
+            if let Some(range) = range {
+                fields.push(range.describe(prefix, u8::MIN.into(), u8::MAX.into()));
+            }
+        }
+        if let Some(controller_set) = &self.controller_set {
+            fields.push(controller_set.describe());
+        }
+        match self.controller_kind {
+            Some(ControllerKind::Switch) => fields.push("switch".to_string()),
+            Some(ControllerKind::Continuous) => fields.push("continuous".to_string()),
+            None => {},
+        }
+        if let Some(sysex_sub_id) = &self.sysex_sub_id {
+            let sub_id2 = sysex_sub_id.sub_id2.map(|sub_id2| format!(",{:02x}", sub_id2)).unwrap_or_default();
+            fields.push(format!("sysex={:02x},{:02x}{}", sysex_sub_id.universal_id, sysex_sub_id.sub_id1, sub_id2));
+        }
+        if let Some(min_silence_ms) = self.min_silence_ms {
+            fields.push(format!("afterquiet{}", min_silence_ms));
+        }
+        if let Some(held_pattern) = &self.held_pattern {
+            if held_pattern.start > 0 {
+                fields.push(format!("heldover{}", held_pattern.start));
+            }
+            if held_pattern.end < u64::MAX {
+                fields.push(format!("heldunder{}", held_pattern.end + 1));
+            }
+        }
+        if let Some(since_program_change_pattern) = &self.since_program_change_pattern {
+            if since_program_change_pattern.start > 0 {
+                fields.push(format!("sincepc>{}", since_program_change_pattern.start - 1));
+            }
+            if since_program_change_pattern.end < u64::MAX {
+                fields.push(format!("sincepc<{}", since_program_change_pattern.end + 1));
+            }
+        }
+        if let Some(every) = self.every {
+            fields.push(format!("every{}", every));
+        }
+        if let Some(alternate) = self.alternate {
+            fields.push(format!("alt{}", alternate));
+        }
+        if fields.is_empty() {
+            fields.push("*".to_string());
+        }
+
+        let mut description = fields.join(" ");
+        for alternative in &self.alternatives {
+            description = format!("{} or {}", description, alternative.describe());
+        }
+        description
+    }
+
+    /// Capture groups of the event pattern of the first of this condition and its alternatives
+    /// which matches the event
+    fn captures(&self, midi_event: &MidiEvent, context: &MatchContext) -> Option<Captures<'static>> {
+        let event_name: &'static str = midi_event.into();
+        iter::once(self)
+            .chain(&self.alternatives)
+            .find(|condition| condition.matches_patterns(midi_event, context))?
+            .event_pattern.as_ref()?
+            .captures(event_name)
+    }
+
+    fn matches_self(&self, midi_event: &MidiEvent, context: &MatchContext) -> bool {
+        self.matches_patterns(midi_event, context) && self.match_count()
+    }
+
+    fn matches_patterns(&self, midi_event: &MidiEvent, context: &MatchContext) -> bool {
+        let midi_event = &*self.normalize_zero_velocity(midi_event);
+        if !self.min_silence_ms.map(|min_silence_ms| context.silence_ms >= min_silence_ms).unwrap_or(true) {
+            return false
+        }
+        if !self.match_held(context.held_ms) {
+            return false
+        }
+        if !self.match_since_program_change(context.since_program_change_ms) {
+            return false
+        }
+        if !self.match_tempo(context.tempo_bpm) {
+            return false
+        }
+        if !self.match_bank(midi_event, context.bank) {
+            return false
+        }
+        if !self.match_controller_kind(midi_event) {
+            return false
+        }
+        if !self.match_slew(context.slew) {
+            return false
+        }
+        if !self.match_length(context.raw_len) {
+            return false
+        }
+        if !self.match_voice(context.voice) {
+            return false
+        }
+        if !self.match_pitch_bend_bytes(midi_event) {
+            return false
+        }
+        if !self.match_time_of_day(context.minute_of_day) {
+            return false
+        }
+        if !self.match_scale(midi_event) {
+            return false
+        }
+        if !self.match_transport(context.transport) {
+            return false
+        }
+        if self.sustained && !(EventCategory::Note.contains(midi_event) && context.sustain_pedal_down) {
+            return false
+        }
+        if self.stuck && !context.repeated_note_on {
+            return false
+        }
+        if self.release && !midi_event.is_release(self.treat_zero_vel_as_off) {
+            return false
+        }
+        if self.running_status && !context.running_status {
+            return false
+        }
         let event_name: &'static str = midi_event.into();
         if !self.event_pattern.as_ref().map(|p| p.is_match(event_name)).unwrap_or(true) {
             return false
         }
+        if !self.category_pattern.map(|c| c.contains(midi_event)).unwrap_or(true) {
+            return false
+        }
+        if !self.match_sysex_sub_id(midi_event) {
+            return false
+        }
+        if self.channel_pattern.is_some() && midi_event.channel().is_none() {
+            // system messages have no channel, so a channel condition can never apply to them
+            return false
+        }
 
         match midi_event {
-            MidiEvent::NoteOff { channel, note, velocity } |
+            MidiEvent::NoteOff { channel, note, velocity } => {
+                self.match_velocity(*velocity)
+                    && self.match_release_velocity(*velocity)
+                    && self.match_value_u8(*note)
+                    && self.match_channel(*channel)
+            },
             MidiEvent::NoteOn { channel, note, velocity } |
             MidiEvent::PolyphonicAftertouch { channel, note, pressure: velocity } => {
                 self.match_velocity(*velocity)
@@ -62,6 +549,16 @@ impl Condition {
         }
     }
 
+    /// Turns a note-on event with velocity 0 into a note-off event if requested by the condition
+    fn normalize_zero_velocity<'e>(&self, midi_event: &'e MidiEvent) -> Cow<'e, MidiEvent> {
+        match *midi_event {
+            MidiEvent::NoteOn { channel, note, velocity: 0 } if self.treat_zero_vel_as_off => {
+                Cow::Owned(MidiEvent::NoteOff { channel, note, velocity: 0 })
+            },
+            _ => Cow::Borrowed(midi_event),
+        }
+    }
+
     fn match_channel(&self, channel: u8) -> bool {
         self.match_range(&self.channel_pattern, channel)
     }
@@ -78,8 +575,149 @@ impl Condition {
         self.match_range(&self.velocity_pattern, velocity)
     }
 
+    fn match_release_velocity(&self, velocity: u8) -> bool {
+        self.match_range(&self.release_velocity_pattern, velocity)
+    }
+
     fn match_control_no(&self, controller: u8) -> bool {
-        self.match_range(&self.controller_pattern, controller)
+        let in_set = match &self.controller_set {
+            Some(controller_set) => controller_set.contains(controller),
+            None => true,
+        };
+        in_set && self.match_range(&self.controller_pattern, controller)
+    }
+
+    /// Counts the matching event and checks whether it is the n-th one and at the alternate's
+    /// position
+    fn match_count(&self) -> bool {
+        if self.every.is_none() && self.alternate.is_none() {
+            return true
+        }
+        let match_count = self.match_count.fetch_add(1, Ordering::Relaxed);
+        self.every.map(|every| match_count % every == every - 1).unwrap_or(true)
+            && self.alternate.map(|alternate| match_count % 2 == alternate as u64).unwrap_or(true)
+    }
+
+    /// Only pitch bends have bytes of a bend value
+    fn match_pitch_bend_bytes(&self, midi_event: &MidiEvent) -> bool {
+        if self.pitch_bend_msb_pattern.is_none() && self.pitch_bend_lsb_pattern.is_none() {
+            return true
+        }
+        match midi_event.pitch_bend_bytes() {
+            Some((lsb, msb)) => {
+                self.match_range(&self.pitch_bend_msb_pattern, msb) && self.match_range(&self.pitch_bend_lsb_pattern, lsb)
+            },
+            None => false,
+        }
+    }
+
+    fn match_held(&self, held_ms: Option<u64>) -> bool {
+        match (&self.held_pattern, held_ms) {
+            (Some(held_pattern), Some(held_ms)) => held_pattern.is_within(held_ms),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    fn match_since_program_change(&self, since_program_change_ms: Option<u64>) -> bool {
+        match (&self.since_program_change_pattern, since_program_change_ms) {
+            (Some(pattern), Some(since_program_change_ms)) => pattern.is_within(since_program_change_ms),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    fn match_tempo(&self, tempo_bpm: Option<f64>) -> bool {
+        match (&self.tempo_pattern, tempo_bpm) {
+            (Some(tempo_pattern), Some(tempo_bpm)) => {
+                tempo_pattern.is_within(tempo_bpm.round().clamp(0.0, u16::MAX as f64) as u16)
+            },
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Only program changes have a bank, which must be known to match
+    fn match_bank(&self, midi_event: &MidiEvent, bank: Option<u16>) -> bool {
+        match (&self.bank_pattern, midi_event, bank) {
+            (None, _, _) => true,
+            (Some(bank_pattern), MidiEvent::ProgramChange { .. }, Some(bank)) => bank_pattern.is_within(bank),
+            (Some(_), _, _) => false,
+        }
+    }
+
+    /// Only control changes have a controller kind
+    fn match_controller_kind(&self, midi_event: &MidiEvent) -> bool {
+        match (&self.controller_kind, midi_event) {
+            (None, _) => true,
+            (Some(controller_kind), MidiEvent::ControlChange { value, .. }) => controller_kind.contains(*value),
+            (Some(_), _) => false,
+        }
+    }
+
+    /// The slew is only known for control changes following another one of the same controller
+    fn match_slew(&self, slew: Option<u8>) -> bool {
+        match (&self.slew_pattern, slew) {
+            (Some(slew_pattern), Some(slew)) => slew_pattern.is_within(slew),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Only held notes have a voice
+    fn match_voice(&self, voice: Option<u8>) -> bool {
+        match (&self.voice_pattern, voice) {
+            (Some(voice_pattern), Some(voice)) => voice_pattern.is_within(voice),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// The length is unknown for events which were not received from a port
+    fn match_length(&self, raw_len: Option<usize>) -> bool {
+        match (&self.length_pattern, raw_len) {
+            (Some(length_pattern), Some(raw_len)) => {
+                length_pattern.is_within(raw_len.min(MAX_LENGTH as usize) as u16)
+            },
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Only note events have a note which can be in a scale
+    fn match_scale(&self, midi_event: &MidiEvent) -> bool {
+        match (&self.scale, midi_event) {
+            (None, _) => true,
+            (Some(scale), MidiEvent::NoteOff { note, .. }) |
+            (Some(scale), MidiEvent::NoteOn { note, .. }) |
+            (Some(scale), MidiEvent::PolyphonicAftertouch { note, .. }) => scale.contains(*note),
+            (Some(_), _) => false,
+        }
+    }
+
+    fn match_transport(&self, transport: Option<TransportState>) -> bool {
+        match (&self.transport_state, transport) {
+            (Some(transport_state), Some(transport)) => *transport_state == transport,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    fn match_time_of_day(&self, minute_of_day: Option<u16>) -> bool {
+        match (&self.time_window, minute_of_day) {
+            (Some(time_window), Some(minute_of_day)) => time_window.contains(minute_of_day),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    fn match_sysex_sub_id(&self, midi_event: &MidiEvent) -> bool {
+        match self.sysex_sub_id {
+            Some(pattern) => midi_event.universal_sysex_id()
+                .map(|sysex_id| pattern.includes(&sysex_id))
+                .unwrap_or(false),
+            None => true,
+        }
     }
 
     fn match_range<T: PartialOrd>(&self, range: &Option<NumericRange<T>>, value: T) -> bool {
@@ -92,63 +730,379 @@ pub enum Action {
     ForwardTo {
         output_port: String,
     },
+    SendFixed {
+        message: MidiEvent,
+        output_port: String,
+    },
+    /// Passes the event through a pipeline of one or more transforms, e.g. `synth:transpose(+12)`
+    /// or `synth:transpose(+12):vel1-100`
+    Transform {
+        transforms: Vec<Box<dyn Transform>>,
+        output_port: String,
+    },
+    /// Forwards the event to `output_port` instead of the passthrough port
+    Redirect {
+        output_port: String,
+    },
+    /// Keeps the event from being forwarded to the passthrough port
+    Drop,
+    /// Applies `action` and logs the incoming and the resulting event
+    Logged {
+        action: Box<Action>,
+    },
+    /// Applies `action`, but drops resulting control changes until the incoming values reach the
+    /// value last sent for the controller, so that switching banks does not make values jump
+    Pickup {
+        action: Box<Action>,
+        state: PickupState,
+    },
+}
+
+impl Action {
+    /// Port the action sends events to, or `None` if it does not send anything
+    pub fn output_port(&self) -> Option<&str> {
+        match self {
+            Action::ForwardTo { output_port } |
+            Action::SendFixed { output_port, .. } |
+            Action::Transform { output_port, .. } |
+            Action::Redirect { output_port } => Some(output_port),
+            Action::Drop => None,
+            Action::Logged { action } | Action::Pickup { action, .. } => action.output_port(),
+        }
+    }
+
+    /// Checks whether the output port name refers to capture groups of the event pattern, so that
+    /// it is only known once an event matched
+    pub fn has_dynamic_output_port(&self) -> bool {
+        self.output_port().is_some_and(|output_port| output_port.contains(CAPTURE_REF_PREFIX))
+    }
+
+    /// Checks whether the event must not be forwarded to the passthrough port
+    pub fn suppresses_passthrough(&self) -> bool {
+        match self {
+            Action::Redirect { .. } | Action::Drop => true,
+            Action::Logged { action } | Action::Pickup { action, .. } => action.suppresses_passthrough(),
+            _ => false,
+        }
+    }
+
+    /// Forwards the event unchanged to the output port of the action, bypassing its transformation
+    fn forward(&self, captures: Option<&Captures>) -> Option<RoutedEvent<'_>> {
+        Some(RoutedEvent {
+            output_port: resolve_output_port(self.output_port()?, captures),
+            event: None,
+        })
+    }
+
+    fn apply(&self, midi_event: &MidiEvent, captures: Option<&Captures>) -> Option<RoutedEvent<'_>> {
+        self.apply_or_preview(midi_event, captures, false)
+    }
+
+    /// Returns the event the action would send without changing its state, e.g. of a toggle
+    fn preview(&self, midi_event: &MidiEvent, captures: Option<&Captures>) -> Option<RoutedEvent<'_>> {
+        self.apply_or_preview(midi_event, captures, true)
+    }
+
+    fn apply_or_preview(&self,
+                        midi_event: &MidiEvent,
+                        captures: Option<&Captures>,
+                        is_preview: bool) -> Option<RoutedEvent<'_>> {
+        let output_port = self.output_port()?;
+        let event = match self {
+            Action::Logged { action } if is_preview => return action.preview(midi_event, captures),
+            Action::Pickup { action, .. } if is_preview => return action.preview(midi_event, captures),
+            Action::Logged { action } => {
+                let routed_event = action.apply(midi_event, captures)?;
+                info!(
+                    "Routing {:?} to port {} as {:?}",
+                    midi_event,
+                    routed_event.output_port,
+                    routed_event.event.as_ref().unwrap_or(midi_event),
+                );
+                return Some(routed_event);
+            },
+            Action::Pickup { action, state } => {
+                let routed_event = action.apply(midi_event, captures)?;
+                let output_event = routed_event.event.as_ref().unwrap_or(midi_event);
+                if let (
+                    MidiEvent::ControlChange { channel, control_no, .. },
+                    MidiEvent::ControlChange { channel: output_channel, control_no: output_control_no, value },
+                ) = (midi_event, output_event) {
+                    if !state.pick_up((*channel, *control_no), (*output_channel, *output_control_no), *value) {
+                        return None;
+                    }
+                }
+                return Some(routed_event);
+            },
+            Action::ForwardTo { .. } | Action::Redirect { .. } | Action::Drop => None,
+            Action::SendFixed { message, .. } => Some(message.clone()),
+            Action::Transform { transforms, .. } if is_preview => Some(preview_all(transforms, midi_event.clone())?),
+            Action::Transform { transforms, .. } => Some(apply_all(transforms, midi_event.clone())?),
+        };
+        Some(RoutedEvent {
+            output_port: resolve_output_port(output_port, captures),
+            event,
+        })
+    }
+}
+
+/// Substitutes references like `$1` or `${name}` in the output port name by the given capture
+/// groups. Unknown groups are replaced by an empty string.
+fn resolve_output_port<'a>(output_port: &'a str, captures: Option<&Captures>) -> Cow<'a, str> {
+    match captures {
+        Some(captures) if output_port.contains(CAPTURE_REF_PREFIX) => {
+            let mut resolved_output_port = String::new();
+            captures.expand(output_port, &mut resolved_output_port);
+            Cow::Owned(resolved_output_port)
+        },
+        _ => Cow::Borrowed(output_port),
+    }
+}
+
+/// Event to be sent to an output port
+#[derive(Debug, PartialEq)]
+pub struct RoutedEvent<'a> {
+    /// Name of the output port, which is only owned if it was resolved from capture groups
+    pub output_port: Cow<'a, str>,
+    /// Event to send instead of the incoming event, or `None` to forward the incoming event unchanged
+    pub event: Option<MidiEvent>,
 }
 
 #[derive(Debug)]
 pub struct Rule {
     pub condition: Condition,
     pub actions: Vec<Action>,
+    /// Comment directly above the rule in the config file, if any
+    pub description: Option<String>,
+    /// Applies the actions to all events which do not match the condition instead
+    pub negated: bool,
+    /// Only logs the events the rule would send instead of sending them
+    pub dry_run: bool,
+    /// Rules with a higher priority are applied before those with a lower one
+    pub priority: i16,
+    /// Only the first matching rule of a group is applied
+    pub group: Option<String>,
+}
+
+impl Rule {
+    /// Names the rule by its number in the config file and its description, e.g. `Rule 2: Drums`
+    pub fn label(&self, rule_no: usize) -> String {
+        match &self.description {
+            Some(description) => format!("Rule {}: {}", rule_no, description),
+            None => format!("Rule {}", rule_no),
+        }
+    }
+
+    /// Writes the condition in config file syntax, including the `not` of negated rules
+    pub fn describe_condition(&self) -> String {
+        match self.negated {
+            true => format!("not {}", self.condition.describe()),
+            false => self.condition.describe(),
+        }
+    }
+
+    pub fn matches_in_context(&self, midi_event: &MidiEvent, context: &MatchContext) -> bool {
+        self.condition.matches_in_context(midi_event, context) != self.negated
+    }
+}
+
+/// Restriction applied to all events sent to an output port, regardless of the rule
+#[derive(Debug, PartialEq)]
+pub struct OutputFilter {
+    pub channel_pattern: NumericRange<u8>,
+}
+
+impl OutputFilter {
+    /// Checks whether the event may be sent; system messages without channel always pass
+    pub fn allows(&self, midi_event: &MidiEvent) -> bool {
+        midi_event.channel()
+            .map(|channel| self.channel_pattern.is_within(channel))
+            .unwrap_or(true)
+    }
+}
+
+/// Restricts routing to a single rule, which can be changed from another thread at runtime
+#[derive(Clone, Debug, Default)]
+pub struct SoloSwitch {
+    /// Index of the soloed rule plus one, or zero if no rule is soloed
+    soloed_rule: Arc<AtomicUsize>,
+}
+
+impl SoloSwitch {
+    /// Only applies the rule with the given index if `Some`, or all rules again if `None`
+    pub fn solo(&self, index: Option<usize>) {
+        self.soloed_rule.store(index.map(|index| index + 1).unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn soloed_rule(&self) -> Option<usize> {
+        self.soloed_rule.load(Ordering::Relaxed).checked_sub(1)
+    }
 }
 
+#[derive(Default)]
 pub struct RoutingTable {
     pub rules: Vec<Rule>,
+    pub output_filters: HashMap<String, OutputFilter>,
+    /// Maximum number of simultaneous notes by output port
+    pub note_caps: HashMap<String, NoteCap>,
+    pub solo_switch: SoloSwitch,
+    /// Number of events matched by each rule
+    pub rule_stats: Arc<RuleStats>,
+    /// Maximum time to spend on matching a single event, or `None` for no limit
+    pub match_budget: Option<Duration>,
+    /// Number of events for which matching was abandoned because the budget was exceeded
+    pub abandoned_match_count: AtomicU64,
+    /// Port receiving all events unless a matching rule drops or redirects them
+    pub passthrough_port: Option<String>,
+    /// Port receiving events of matching rules whose output ports are all unavailable
+    pub orphan_port: Option<String>,
+    /// Forward events unchanged to the output ports of all actions instead of transforming them,
+    /// e.g. to check whether a transformation causes a problem
+    pub bypass_transforms: bool,
+    /// Indices of the rules in the order in which they are applied, or empty for file order
+    pub rule_order: Vec<usize>,
 }
 
 impl RoutingTable {
-    pub fn get_all_output_ports(&self) -> HashSet<&String> {
-        let output_port_names = self.rules.iter()
-            .flat_map(|rule| &rule.actions)
-            .map(|action| match action {
-                Action::ForwardTo { output_port } => output_port,
-            });
-        HashSet::from_iter(output_port_names)
+    /// Applies the rules by descending priority, and rules with the same priority in file order
+    pub fn order_rules_by_priority(&mut self) {
+        let mut rule_order: Vec<usize> = (0..self.rules.len()).collect();
+        rule_order.sort_by_key(|index| Reverse(self.rules[*index].priority));
+        self.rule_order = rule_order;
     }
 
-    pub fn get_output_ports(&self, midi_event: MidiEvent) -> Vec<&str> {
-        let mut ports = Vec::new();
-        for rule in &self.rules {
-            if rule.condition.matches(&midi_event) {
-                debug!("Rule {:?} matches event {:?}", rule, midi_event);
-                let p = self.get_ports_from_actions(&rule.actions);
-                ports.extend(p);
-            } else {
-                debug!("Rule {:?} does not match event {:?}", rule, midi_event);
-            }
-        }
-        ports
+    /// Indices of the rules in the order in which they are applied
+    fn rule_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.rules.len()).map(|position| self.rule_order.get(position).copied().unwrap_or(position))
     }
 
-    fn get_ports_from_actions<'a>(&self, actions: &'a Vec<Action>) -> Vec<&'a str> {
-        let mut ports = Vec::new();
-        for action in actions {
-            if let Some(port) = self.get_port_from_action(action) {
-                ports.push(port);
-            }
-        }
-        ports
+    /// Switch to solo a rule while the routing table is used by the router
+    pub fn solo_switch(&self) -> SoloSwitch {
+        self.solo_switch.clone()
     }
 
-    fn get_port_from_action<'a>(&self, action: &'a Action) -> Option<&'a str> {
-        match action {
-            Action::ForwardTo { output_port } => {
-                Some(&output_port)
-            },
-        }
+    /// Checks whether `midi_event` passes the filter of `output_port`, if any
+    pub fn is_allowed_on_port(&self, output_port: &str, midi_event: &MidiEvent) -> bool {
+        self.output_filters.get(output_port)
+            .map(|filter| filter.allows(midi_event))
+            .unwrap_or(true)
+    }
+
+    /// Names of all output ports except dynamic ones, which cannot be known before events arrive
+    pub fn get_all_output_ports(&self) -> HashSet<&str> {
+        let output_port_names = self.rules.iter()
+            .flat_map(|rule| &rule.actions)
+            .filter(|action| !action.has_dynamic_output_port())
+            .filter_map(|action| action.output_port())
+            .chain(self.passthrough_port.as_deref())
+            .chain(self.orphan_port.as_deref());
+        HashSet::from_iter(output_port_names)
+    }
+
+    /// Whether any rule depends on the time of day, which must then be published to the process
+    /// handler
+    pub fn uses_time_windows(&self) -> bool {
+        self.rules.iter().any(|rule| rule.condition.uses_time_window())
+    }
+
+    #[cfg(test)]
+    pub fn get_output_ports(&self, midi_event: MidiEvent) -> Vec<String> {
+        self.get_outputs(&midi_event).into_iter()
+            .map(|routed_event| routed_event.output_port.into_owned())
+            .collect()
+    }
+
+    pub fn get_outputs(&self, midi_event: &MidiEvent) -> Vec<RoutedEvent<'_>> {
+        self.get_outputs_in_context(midi_event, &MatchContext::default())
+    }
+
+    /// Same as `get_outputs`, but including time-based conditions. The events are ordered by the
+    /// rules in the order they are applied, i.e. by priority and then file order, and the actions
+    /// of each rule in the order given, followed by the passthrough port, which is the order in
+    /// which they are sent.
+    pub fn get_outputs_in_context(&self, midi_event: &MidiEvent, context: &MatchContext) -> Vec<RoutedEvent<'_>> {
+        let mut outputs = Vec::new();
+        let mut passthrough = self.passthrough_port.is_some();
+        let soloed_rule = self.solo_switch.soloed_rule();
+        let match_start = Instant::now();
+        let event_types = EventTypeMask::of_event(midi_event);
+        // Groups of which a rule was applied, which skips the other rules of the group
+        let mut applied_groups: Vec<&str> = Vec::new();
+        for index in self.rule_indices() {
+            let rule = &self.rules[index];
+            if self.is_match_budget_exceeded(match_start) {
+                let abandoned_match_count = self.abandoned_match_count.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    "Matching budget exceeded, skipping rules from rule {} on for event {:?} ({} events so far)",
+                    index + 1,
+                    midi_event,
+                    abandoned_match_count,
+                );
+                break;
+            }
+            if soloed_rule.map(|soloed_rule| soloed_rule != index).unwrap_or(false) {
+                continue;
+            }
+            if !rule.negated && !rule.condition.possible_event_types().intersects(event_types) {
+                continue;
+            }
+            if rule.group.as_deref().is_some_and(|group| applied_groups.contains(&group)) {
+                continue;
+            }
+            if rule.matches_in_context(midi_event, context) {
+                debug!("Rule {:?} matches event {:?}", rule, midi_event);
+                self.rule_stats.record_hit(index);
+                let captures = match rule.actions.iter().any(Action::has_dynamic_output_port) {
+                    true => rule.condition.captures(midi_event, context),
+                    false => None,
+                };
+                let routed_events = rule.actions.iter().filter_map(|action| match (self.bypass_transforms, rule.dry_run) {
+                    (true, _) => action.forward(captures.as_ref()),
+                    (false, true) => action.preview(midi_event, captures.as_ref()),
+                    (false, false) => action.apply(midi_event, captures.as_ref()),
+                });
+                if rule.dry_run {
+                    for routed_event in routed_events {
+                        info!(
+                            "Dry run of rule {}: would send {:?} to port {}",
+                            index + 1,
+                            routed_event.event.as_ref().unwrap_or(midi_event),
+                            routed_event.output_port,
+                        );
+                    }
+                    continue;
+                }
+                outputs.extend(routed_events);
+                applied_groups.extend(rule.group.as_deref());
+                passthrough &= !rule.actions.iter().any(Action::suppresses_passthrough);
+            } else {
+                debug!("Rule {:?} does not match event {:?}", rule, midi_event);
+            }
+        }
+        if let Some(passthrough_port) = self.passthrough_port.as_deref().filter(|_| passthrough) {
+            outputs.push(RoutedEvent { output_port: Cow::Borrowed(passthrough_port), event: None });
+        }
+        outputs
+    }
+
+    fn is_match_budget_exceeded(&self, match_start: Instant) -> bool {
+        self.match_budget
+            .map(|match_budget| match_start.elapsed() >= match_budget)
+            .unwrap_or(false)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::mem;
+    use std::sync::{Mutex, Once};
+    use crate::note_tracker::NoteTracker;
+    use crate::parser::parse_rule;
+    use crate::preset::PresetTracker;
+    use crate::slew::SlewTracker;
+    use crate::sustain::SustainTracker;
+    use crate::transform::{RemapController, SetChannel, ToggleState, ToggleSwitch, Transpose};
+    use crate::voice::VoiceTracker;
     use super::*;
     
     #[test]
@@ -159,6 +1113,7 @@ mod tests {
             value_pattern: Some(NumericRange {start: -16, end: 15}),
             velocity_pattern: Some(NumericRange {start: 20, end: 40}), // a.k.a. pressure
             controller_pattern: Some(NumericRange {start: 5, end: 10}),
+            ..Default::default()
         };
         
         let note_off_event_ch0 = MidiEvent::NoteOff {
@@ -310,6 +1265,192 @@ mod tests {
         assert!(!condition.matches(&polyphonic_aftertouch_event));
     }
 
+    #[test]
+    fn test_condition_matches_category() {
+        let condition = Condition {
+            category_pattern: Some(EventCategory::Note),
+            channel_pattern: Some(NumericRange { start: 1, end: 1 }),
+            ..Default::default()
+        };
+
+        assert!(condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert!(condition.matches(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert!(condition.matches(&MidiEvent::PolyphonicAftertouch { channel: 1, note: 60, pressure: 10 }));
+        assert!(!condition.matches(&MidiEvent::NoteOn { channel: 2, note: 60, velocity: 100 }));
+        assert!(!condition.matches(&MidiEvent::ControlChange { channel: 1, control_no: 1, value: 0 }));
+    }
+
+    #[test]
+    fn test_condition_matches_sysex_sub_id() {
+        let condition = Condition {
+            sysex_sub_id: Some(UniversalSysExId { universal_id: 0x7f, sub_id1: 0x06, sub_id2: Some(0x01) }),
+            ..Default::default()
+        };
+
+        let mmc_stop = MidiEvent::SystemExclusive { data: vec![0xf0, 0x7f, 0x7f, 0x06, 0x01, 0xf7] };
+        assert!(condition.matches(&mmc_stop));
+        let mmc_play = MidiEvent::SystemExclusive { data: vec![0xf0, 0x7f, 0x7f, 0x06, 0x02, 0xf7] };
+        assert!(!condition.matches(&mmc_play));
+        let manufacturer_specific = MidiEvent::SystemExclusive { data: vec![0xf0, 0x43, 0x10, 0x4c, 0xf7] };
+        assert!(!condition.matches(&manufacturer_specific));
+        assert!(!condition.matches(&MidiEvent::Stop {}));
+    }
+
+    #[test]
+    fn test_condition_matches_after_silence() {
+        let condition = Condition {
+            min_silence_ms: Some(500),
+            ..Default::default()
+        };
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+
+        let event_times_ms = [1000, 1100, 1599, 2100, 2200, 2700];
+        let mut last_event_ms = 0;
+        let matching_times_ms: Vec<u64> = event_times_ms.into_iter()
+            .filter(|&event_ms| {
+                let silence_ms = event_ms - mem::replace(&mut last_event_ms, event_ms);
+                condition.matches_in_context(&note_on, &MatchContext { silence_ms, ..Default::default() })
+            })
+            .collect();
+
+        assert_eq!(matching_times_ms, vec![1000, 2100, 2700]);
+        assert!(condition.matches(&note_on));
+    }
+
+    #[test]
+    fn test_condition_matches_pitch_bend_msb() {
+        let condition = Condition {
+            pitch_bend_msb_pattern: Some(NumericRange { start: 65, end: u8::MAX }),
+            ..Default::default()
+        };
+        let bend = |msb: i16, lsb: i16| MidiEvent::PitchBendChange { channel: 1, value: (msb << 7) + lsb - 8192 };
+
+        assert!(condition.matches(&bend(65, 0)));
+        assert!(condition.matches(&bend(65, 127)));
+        assert!(condition.matches(&bend(127, 127)));
+        assert!(!condition.matches(&bend(64, 0)));
+        assert!(!condition.matches(&bend(64, 127)));
+        assert!(!condition.matches(&MidiEvent::ControlChange { channel: 1, control_no: 1, value: 100 }));
+
+        let condition = Condition {
+            pitch_bend_lsb_pattern: Some(NumericRange { start: 0, end: 0 }),
+            ..Default::default()
+        };
+        assert!(condition.matches(&bend(64, 0)));
+        assert!(!condition.matches(&bend(64, 1)));
+    }
+
+    #[test]
+    fn test_condition_matches_every() {
+        let condition = Condition {
+            event_pattern: Some(Regex::new("note-on").unwrap()),
+            every: Some(4),
+            ..Default::default()
+        };
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+
+        let matching_events: Vec<usize> = (1..=12)
+            .filter(|_| !condition.matches(&note_off))
+            .filter(|_| condition.matches(&note_on))
+            .collect();
+
+        assert_eq!(matching_events, vec![4, 8, 12]);
+    }
+
+    #[test]
+    fn test_condition_matches_alternate() {
+        let condition = |alternate| Condition {
+            event_pattern: Some(Regex::new("note-on").unwrap()),
+            alternate: Some(alternate),
+            ..Default::default()
+        };
+        let (alt0, alt1) = (condition(0), condition(1));
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+
+        let mut matching_events = (Vec::new(), Vec::new());
+        for event_no in 1..=6 {
+            assert!(!alt0.matches(&note_off) && !alt1.matches(&note_off));
+            if alt0.matches(&note_on) {
+                matching_events.0.push(event_no);
+            }
+            if alt1.matches(&note_on) {
+                matching_events.1.push(event_no);
+            }
+        }
+
+        assert_eq!(matching_events, (vec![1, 3, 5], vec![2, 4, 6]));
+    }
+
+    #[test]
+    fn test_condition_inherit() {
+        let outer = Condition {
+            channel_pattern: Some(NumericRange { start: 1, end: 1 }),
+            velocity_pattern: Some(NumericRange { start: 1, end: 127 }),
+            ..Default::default()
+        };
+        let mut condition = Condition {
+            event_pattern: Some(Regex::new("note-on").unwrap()),
+            velocity_pattern: Some(NumericRange { start: 100, end: 127 }),
+            alternatives: vec![Condition {
+                channel_pattern: Some(NumericRange { start: 2, end: 2 }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        condition.inherit(&outer);
+
+        assert_eq!(condition.event_pattern.unwrap().as_str(), "note-on");
+        assert_eq!(condition.channel_pattern, Some(NumericRange { start: 1, end: 1 }));
+        assert_eq!(condition.velocity_pattern, Some(NumericRange { start: 100, end: 127 }));
+        assert_eq!(condition.alternatives[0].channel_pattern, Some(NumericRange { start: 2, end: 2 }));
+        assert_eq!(condition.alternatives[0].velocity_pattern, Some(NumericRange { start: 1, end: 127 }));
+    }
+
+    #[test]
+    fn test_condition_describe() {
+        let condition = Condition {
+            event_pattern: Some(Regex::new("note-.*").unwrap()),
+            channel_pattern: Some(NumericRange { start: 1, end: 8 }),
+            value_pattern: Some(NumericRange { start: i16::MIN, end: 59 }),
+            velocity_pattern: Some(NumericRange { start: 0, end: 255 }),
+            alternatives: vec![Condition {
+                category_pattern: Some(EventCategory::Note),
+                controller_pattern: Some(NumericRange { start: 8, end: 255 }),
+                min_silence_ms: Some(500),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(condition.describe(), "note-.* <60 ch1-8 vel* or anynote ctrl>7 afterquiet500");
+        assert_eq!(Condition::default().describe(), "*");
+    }
+
+    #[test]
+    fn test_condition_matches_alternatives() {
+        let condition = Condition {
+            event_pattern: Some(Regex::new("note-on").unwrap()),
+            channel_pattern: Some(NumericRange { start: 1, end: 1 }),
+            alternatives: vec![
+                Condition {
+                    event_pattern: Some(Regex::new("note-off").unwrap()),
+                    channel_pattern: Some(NumericRange { start: 2, end: 2 }),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        assert!(condition.matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert!(condition.matches(&MidiEvent::NoteOff { channel: 2, note: 60, velocity: 0 }));
+        assert!(!condition.matches(&MidiEvent::NoteOn { channel: 2, note: 60, velocity: 100 }));
+        assert!(!condition.matches(&MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert!(!condition.matches(&MidiEvent::ControlChange { channel: 1, control_no: 1, value: 0 }));
+    }
+
     #[test]
     fn test_routing_table_get_all_output_ports() {
         let create_condition = || {
@@ -329,6 +1470,11 @@ mod tests {
                             output_port: "lead".to_string(),
                         }
                     ],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
                 },
                 Rule {
                     condition: create_condition(),
@@ -340,10 +1486,20 @@ mod tests {
                             output_port: "pads".to_string(),
                         }
                     ],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
                 },
                 Rule {
                     condition: create_condition(),
                     actions: Vec::new(),
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
                 },
                 Rule {
                     condition: create_condition(),
@@ -352,15 +1508,19 @@ mod tests {
                             output_port: "pads".to_string()
                         }
                     ],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
                 },
             ],
+            ..Default::default()
         };
         let output_ports = routing_table.get_all_output_ports();
 
-        let expected: Vec<_> = vec!["drums", "lead", "pads"].into_iter()
-            .map(String::from)
-            .collect();
-        assert_eq!(output_ports, expected.iter().collect());
+        let expected: HashSet<_> = vec!["drums", "lead", "pads"].into_iter().collect();
+        assert_eq!(output_ports, expected);
     }
     
     #[test]
@@ -369,14 +1529,16 @@ mod tests {
             Rule {
                 condition: Condition {
                     event_pattern: Some(Regex::new(pattern).unwrap()),
-                    channel_pattern: None,
-                    value_pattern: None,
-                    velocity_pattern: None,
-                    controller_pattern: None,
+                    ..Default::default()
                 },
                 actions: output_ports.iter()
                     .map(|p| Action::ForwardTo { output_port: p.to_string() })
                     .collect(),
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
             }
         };
         
@@ -387,6 +1549,7 @@ mod tests {
                 create_rule("note-on", vec!["a", "b", "c"]),
                 create_rule("note-*", vec!["x", "y", "z"]),
             ],
+            ..Default::default()
         };
         let output_ports = routing_table.get_output_ports(MidiEvent::NoteOff {
             channel: 0,
@@ -397,4 +1560,1003 @@ mod tests {
         let expected: Vec<_> = vec!["x", "xx", "xxx", "x", "y", "z"];
         assert_eq!(output_ports, expected);
     }
+
+    #[test]
+    fn test_routing_table_get_outputs_send_fixed() {
+        let program_change = MidiEvent::ProgramChange {
+            channel: 1,
+            program: 5,
+        };
+        let routing_table = RoutingTable {
+            rules: vec![
+                Rule {
+                    condition: Condition {
+                        event_pattern: Some(Regex::new("note-on").unwrap()),
+                        value_pattern: Some(NumericRange { start: 36, end: 36 }),
+                        ..Default::default()
+                    },
+                    actions: vec![
+                        Action::SendFixed {
+                            message: program_change.clone(),
+                            output_port: "out1".to_string(),
+                        },
+                        Action::ForwardTo {
+                            output_port: "out2".to_string(),
+                        },
+                    ],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn {
+            channel: 1,
+            note: 36,
+            velocity: 100,
+        });
+        assert_eq!(outputs, vec![
+            RoutedEvent { output_port: "out1".into(), event: Some(program_change) },
+            RoutedEvent { output_port: "out2".into(), event: None },
+        ]);
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn {
+            channel: 1,
+            note: 37,
+            velocity: 100,
+        });
+        assert!(outputs.is_empty());
+    }
+
+    #[test]
+    fn test_routing_table_get_outputs_remap_controller() {
+        let routing_table = RoutingTable {
+            rules: vec![
+                Rule {
+                    condition: Condition {
+                        event_pattern: Some(Regex::new("control-change").unwrap()),
+                        ..Default::default()
+                    },
+                    actions: vec![
+                        Action::Transform {
+                            transforms: vec![Box::new(RemapController { from: 1, to: 11 })],
+                            output_port: "out1".to_string(),
+                        },
+                    ],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let outputs = routing_table.get_outputs(&MidiEvent::ControlChange {
+            channel: 3,
+            control_no: 1,
+            value: 99,
+        });
+        assert_eq!(outputs, vec![
+            RoutedEvent {
+                output_port: "out1".into(),
+                event: Some(MidiEvent::ControlChange { channel: 3, control_no: 11, value: 99 }),
+            },
+        ]);
+
+        let outputs = routing_table.get_outputs(&MidiEvent::ControlChange {
+            channel: 3,
+            control_no: 7,
+            value: 99,
+        });
+        let unchanged = MidiEvent::ControlChange { channel: 3, control_no: 7, value: 99 };
+        assert_eq!(outputs, vec![RoutedEvent { output_port: "out1".into(), event: Some(unchanged) }]);
+    }
+
+    #[test]
+    fn test_routing_table_is_allowed_on_port() {
+        let routing_table = RoutingTable {
+            output_filters: HashMap::from([
+                ("out1".to_string(), OutputFilter { channel_pattern: NumericRange { start: 1, end: 4 } }),
+            ]),
+            ..Default::default()
+        };
+        let note_on = |channel| MidiEvent::NoteOn { channel, note: 60, velocity: 100 };
+
+        assert!(routing_table.is_allowed_on_port("out1", &note_on(2)));
+        assert!(routing_table.is_allowed_on_port("out1", &note_on(4)));
+        assert!(!routing_table.is_allowed_on_port("out1", &note_on(5)));
+        assert!(routing_table.is_allowed_on_port("out1", &MidiEvent::TimingClock {}));
+        assert!(routing_table.is_allowed_on_port("out2", &note_on(5)));
+    }
+
+    #[test]
+    fn test_routing_table_negated_rule() {
+        let routing_table = RoutingTable {
+            rules: vec![Rule {
+                condition: Condition {
+                    event_pattern: Some(Regex::new("^note-on$").unwrap()),
+                    channel_pattern: Some(NumericRange { start: 1, end: 1 }),
+                    ..Default::default()
+                },
+                actions: vec![Action::ForwardTo { output_port: "out".to_string() }],
+                description: None,
+                negated: true,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            }],
+            ..Default::default()
+        };
+
+        assert!(routing_table.get_output_ports(MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }).is_empty());
+        assert_eq!(routing_table.get_output_ports(MidiEvent::NoteOn { channel: 2, note: 60, velocity: 100 }), vec!["out"]);
+        assert_eq!(
+            routing_table.get_output_ports(MidiEvent::ControlChange { channel: 1, control_no: 7, value: 5 }),
+            vec!["out"],
+        );
+    }
+
+    #[test]
+    fn test_routing_table_set_channel_on_same_port() {
+        let routing_table = RoutingTable {
+            rules: vec![Rule {
+                condition: Condition::default(),
+                actions: vec![
+                    Action::Transform { transforms: vec![Box::new(SetChannel { channel: 3 })], output_port: "out1".to_string() },
+                    Action::Transform { transforms: vec![Box::new(SetChannel { channel: 5 })], output_port: "out1".to_string() },
+                ],
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            }],
+            ..Default::default()
+        };
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 });
+        assert_eq!(outputs, vec![
+            RoutedEvent { output_port: "out1".into(), event: Some(MidiEvent::NoteOn { channel: 3, note: 60, velocity: 100 }) },
+            RoutedEvent { output_port: "out1".into(), event: Some(MidiEvent::NoteOn { channel: 5, note: 60, velocity: 100 }) },
+        ]);
+        assert_eq!(routing_table.get_all_output_ports(), HashSet::from(["out1"]));
+    }
+
+    #[test]
+    fn test_routing_table_bypass_transforms() {
+        let mut routing_table = RoutingTable {
+            rules: vec![Rule {
+                condition: Condition::default(),
+                actions: vec![
+                    Action::Transform { transforms: vec![Box::new(Transpose { semitones: 12 })], output_port: "out1".to_string() },
+                    Action::SendFixed { message: MidiEvent::Stop {}, output_port: "out2".to_string() },
+                    Action::Drop,
+                ],
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            }],
+            ..Default::default()
+        };
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert_eq!(routing_table.get_outputs(&note_on), vec![
+            RoutedEvent { output_port: "out1".into(), event: Some(MidiEvent::NoteOn { channel: 1, note: 72, velocity: 100 }) },
+            RoutedEvent { output_port: "out2".into(), event: Some(MidiEvent::Stop {}) },
+        ]);
+
+        routing_table.bypass_transforms = true;
+        assert_eq!(routing_table.get_outputs(&note_on), vec![
+            RoutedEvent { output_port: "out1".into(), event: None },
+            RoutedEvent { output_port: "out2".into(), event: None },
+        ]);
+    }
+
+    #[test]
+    fn test_routing_table_emission_order() {
+        let routing_table = RoutingTable {
+            rules: vec![
+                parse_rule(0, "note-on => synth:transpose(+12) pad synth:transpose(+7)".into()).unwrap(),
+                parse_rule(1, "control-change => synth".into()).unwrap(),
+                parse_rule(2, "note-.* => pad:vel1-50 synth@2".into()).unwrap(),
+                parse_rule(3, "note-on ch1 => synth:send(program-change ch1 5)".into()).unwrap(),
+            ],
+            passthrough_port: Some("thru".to_string()),
+            ..Default::default()
+        };
+        let note_on = |channel, note, velocity| MidiEvent::NoteOn { channel, note, velocity };
+
+        let outputs: Vec<(String, MidiEvent)> = routing_table.get_outputs(&note_on(1, 60, 100)).into_iter()
+            .map(|routed_event| (routed_event.output_port.to_string(), routed_event.event.unwrap_or(note_on(1, 60, 100))))
+            .collect();
+        assert_eq!(outputs, vec![
+            ("synth".to_string(), note_on(1, 72, 100)),
+            ("pad".to_string(), note_on(1, 60, 100)),
+            ("synth".to_string(), note_on(1, 67, 100)),
+            ("pad".to_string(), note_on(1, 60, 50)),
+            ("synth".to_string(), note_on(2, 60, 100)),
+            ("synth".to_string(), MidiEvent::ProgramChange { channel: 1, program: 5 }),
+            ("thru".to_string(), note_on(1, 60, 100)),
+        ]);
+    }
+
+    #[test]
+    fn test_routing_table_priority_and_group() {
+        let mut routing_table = RoutingTable {
+            rules: vec![
+                parse_rule(0, "note-on => low { priority=-1 }".into()).unwrap(),
+                parse_rule(1, "note-on ch1 => lead { group=keys }".into()).unwrap(),
+                parse_rule(2, "note-on => first".into()).unwrap(),
+                parse_rule(3, "note-on ch2 => split { group=keys, priority=5 }".into()).unwrap(),
+                parse_rule(4, "note-on => pad { group=keys }".into()).unwrap(),
+                parse_rule(5, "note-on ch3 => muted { group=keys, dryrun=true, priority=9 }".into()).unwrap(),
+            ],
+            rule_stats: Arc::new(RuleStats::new(6)),
+            ..Default::default()
+        };
+        routing_table.order_rules_by_priority();
+        let note_on = |channel| MidiEvent::NoteOn { channel, note: 60, velocity: 100 };
+
+        assert_eq!(routing_table.get_output_ports(note_on(1)), vec!["lead", "first", "low"]);
+        assert_eq!(routing_table.get_output_ports(note_on(2)), vec!["split", "first", "low"]);
+        assert_eq!(routing_table.get_output_ports(note_on(3)), vec!["first", "pad", "low"]);
+        assert_eq!(routing_table.rule_stats.snapshot(), vec![3, 1, 3, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_routing_table_solo() {
+        let rule = |output_port: &str| Rule {
+            condition: Condition::default(),
+            actions: vec![Action::ForwardTo { output_port: output_port.to_string() }],
+            description: None,
+            negated: false,
+            dry_run: false,
+            priority: 0,
+            group: None,
+        };
+        let routing_table = RoutingTable {
+            rules: vec![rule("out0"), rule("out1"), rule("out2")],
+            ..Default::default()
+        };
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+
+        routing_table.solo_switch().solo(Some(1));
+        assert_eq!(routing_table.get_output_ports(note_on.clone()), vec!["out1"]);
+
+        routing_table.solo_switch().solo(Some(5));
+        assert!(routing_table.get_output_ports(note_on.clone()).is_empty());
+
+        routing_table.solo_switch().solo(None);
+        assert_eq!(routing_table.get_output_ports(note_on), vec!["out0", "out1", "out2"]);
+    }
+
+    #[test]
+    fn test_routing_table_match_budget_heavy_patterns() {
+        let rules = (0..1000)
+            .map(|rule_no| Rule {
+                condition: Condition {
+                    event_pattern: Some(Regex::new(&format!("^(note|control)-(on|off|change){{1,3}}(x{{0,{}}})*$", rule_no % 10)).unwrap()),
+                    ..Default::default()
+                },
+                actions: vec![Action::ForwardTo { output_port: format!("out{}", rule_no) }],
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            })
+            .collect();
+        let routing_table = RoutingTable {
+            rules,
+            match_budget: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 });
+
+        assert_eq!(outputs.len(), 1000);
+        assert_eq!(routing_table.abandoned_match_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_routing_table_match_budget_exceeded() {
+        let routing_table = RoutingTable {
+            rules: vec![Rule {
+                condition: Condition::default(),
+                actions: vec![Action::ForwardTo { output_port: "out".to_string() }],
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            }],
+            match_budget: Some(Duration::ZERO),
+            ..Default::default()
+        };
+
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 });
+
+        assert!(outputs.is_empty());
+        assert_eq!(routing_table.abandoned_match_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_routing_table_held_notes() {
+        let rule = |held_pattern, output_port: &str| Rule {
+            condition: Condition {
+                event_pattern: Some(Regex::new("note-off").unwrap()),
+                held_pattern: Some(held_pattern),
+                ..Default::default()
+            },
+            actions: vec![Action::ForwardTo { output_port: output_port.to_string() }],
+            description: None,
+            negated: false,
+            dry_run: false,
+            priority: 0,
+            group: None,
+        };
+        let routing_table = RoutingTable {
+            rules: vec![
+                rule(NumericRange { start: 500, end: u64::MAX }, "pad"),
+                rule(NumericRange { start: 0, end: 499 }, "pluck"),
+            ],
+            ..Default::default()
+        };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+        let outputs_after_hold = |held_ms| routing_table
+            .get_outputs_in_context(&note_off, &MatchContext { held_ms, ..Default::default() })
+            .into_iter()
+            .map(|routed_event| routed_event.output_port.into_owned())
+            .collect::<Vec<String>>();
+
+        assert_eq!(outputs_after_hold(Some(120)), vec!["pluck"]);
+        assert_eq!(outputs_after_hold(Some(499)), vec!["pluck"]);
+        assert_eq!(outputs_after_hold(Some(500)), vec!["pad"]);
+        assert!(outputs_after_hold(None).is_empty());
+    }
+
+    #[test]
+    fn test_routing_table_capture_groups_in_output_port() {
+        let routing_table = RoutingTable {
+            rules: vec![
+                Rule {
+                    condition: Condition {
+                        event_pattern: Some(Regex::new("note-(on|off)").unwrap()),
+                        ..Default::default()
+                    },
+                    actions: vec![
+                        Action::ForwardTo { output_port: "out-$1".to_string() },
+                        Action::ForwardTo { output_port: "all".to_string() },
+                    ],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
+                },
+                Rule {
+                    condition: Condition {
+                        event_pattern: Some(Regex::new("(?P<kind>control|program)-change").unwrap()),
+                        ..Default::default()
+                    },
+                    actions: vec![Action::ForwardTo { output_port: "${kind}-$2".to_string() }],
+                    description: None,
+                    negated: false,
+                    dry_run: false,
+                    priority: 0,
+                    group: None,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert_eq!(routing_table.get_output_ports(note_on), vec!["out-on", "all"]);
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+        assert_eq!(routing_table.get_output_ports(note_off), vec!["out-off", "all"]);
+        let program_change = MidiEvent::ProgramChange { channel: 1, program: 5 };
+        assert_eq!(routing_table.get_output_ports(program_change), vec!["program-"]);
+        assert_eq!(routing_table.get_all_output_ports(), HashSet::from(["all"]));
+    }
+
+    #[test]
+    fn test_routing_table_capture_groups_of_alternative() {
+        let routing_table = RoutingTable {
+            rules: vec![parse_rule(0, "note-(on) ch1 or (control|program)-change ch2 => out-$1".into()).unwrap()],
+            rule_stats: Arc::new(RuleStats::new(1)),
+            ..Default::default()
+        };
+
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert_eq!(routing_table.get_output_ports(note_on), vec!["out-on"]);
+        let control_change = MidiEvent::ControlChange { channel: 2, control_no: 1, value: 64 };
+        assert_eq!(routing_table.get_output_ports(control_change), vec!["out-control"]);
+        let program_change = MidiEvent::ProgramChange { channel: 2, program: 5 };
+        assert_eq!(routing_table.get_output_ports(program_change), vec!["out-program"]);
+    }
+
+    #[test]
+    fn test_condition_channel_rejects_system_messages() {
+        let condition = Condition {
+            channel_pattern: Some(NumericRange { start: 1, end: 1 }),
+            ..Default::default()
+        };
+
+        assert!(!condition.matches(&MidiEvent::TimingClock {}));
+        assert!(!condition.matches(&MidiEvent::SongSelect { song_num: 1 }));
+        assert!(condition.matches(&MidiEvent::ProgramChange { channel: 1, program: 1 }));
+        assert!(Condition::default().matches(&MidiEvent::TimingClock {}));
+    }
+
+    #[test]
+    fn test_condition_treat_zero_velocity_as_off() {
+        let condition = |treat_zero_vel_as_off| Condition {
+            event_pattern: Some(Regex::new("note-off").unwrap()),
+            velocity_pattern: Some(NumericRange { start: 0, end: 0 }),
+            treat_zero_vel_as_off,
+            ..Default::default()
+        };
+        let zero_velocity_note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 };
+
+        assert!(condition(true).matches(&zero_velocity_note_on));
+        assert!(!condition(false).matches(&zero_velocity_note_on));
+        assert!(!condition(true).matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert_eq!(condition(true).describe(), "note-off vel0off vel0");
+    }
+
+    #[test]
+    fn test_routing_table_passthrough() {
+        let rule = |pattern: &str, actions| Rule {
+            condition: Condition {
+                event_pattern: Some(Regex::new(pattern).unwrap()),
+                ..Default::default()
+            },
+            actions,
+            description: None,
+            negated: false,
+            dry_run: false,
+            priority: 0,
+            group: None,
+        };
+        let routing_table = RoutingTable {
+            rules: vec![
+                rule("control-change", vec![Action::ForwardTo { output_port: "monitor".to_string() }]),
+                rule("active-sensing", vec![Action::Drop]),
+                rule("program-change", vec![Action::Redirect { output_port: "synth".to_string() }]),
+            ],
+            passthrough_port: Some("thru".to_string()),
+            ..Default::default()
+        };
+
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert_eq!(routing_table.get_output_ports(note_on), vec!["thru"]);
+        let control_change = MidiEvent::ControlChange { channel: 1, control_no: 7, value: 100 };
+        assert_eq!(routing_table.get_output_ports(control_change), vec!["monitor", "thru"]);
+        assert!(routing_table.get_output_ports(MidiEvent::ActiveSensing {}).is_empty());
+        let program_change = MidiEvent::ProgramChange { channel: 1, program: 5 };
+        assert_eq!(routing_table.get_output_ports(program_change), vec!["synth"]);
+        assert_eq!(routing_table.get_all_output_ports(), HashSet::from(["monitor", "synth", "thru"]));
+    }
+
+    #[test]
+    fn test_condition_matches_bank() {
+        let condition = Condition {
+            bank_pattern: Some(NumericRange { start: 256, end: 256 }),
+            value_pattern: Some(NumericRange { start: 5, end: 5 }),
+            ..Default::default()
+        };
+        let program_change = MidiEvent::ProgramChange { channel: 1, program: 5 };
+        let in_bank = |bank| MatchContext { bank, ..Default::default() };
+
+        assert!(condition.matches_in_context(&program_change, &in_bank(Some(256))));
+        assert!(!condition.matches_in_context(&program_change, &in_bank(Some(257))));
+        assert!(!condition.matches_in_context(&program_change, &in_bank(None)));
+        let control_change = MidiEvent::ControlChange { channel: 1, control_no: 7, value: 5 };
+        assert!(!condition.matches_in_context(&control_change, &in_bank(Some(256))));
+    }
+
+    #[test]
+    fn test_condition_matches_since_program_change() {
+        let condition = Condition {
+            since_program_change_pattern: Some(NumericRange { start: 2001, end: u64::MAX }),
+            ..Default::default()
+        };
+        let mut preset_tracker = PresetTracker::new();
+        let mut matches = |midi_event: MidiEvent, event_ms: u64| {
+            let context = MatchContext {
+                since_program_change_ms: preset_tracker.track(&midi_event, event_ms),
+                ..Default::default()
+            };
+            condition.matches_in_context(&midi_event, &context)
+        };
+        let note_on = |channel| MidiEvent::NoteOn { channel, note: 60, velocity: 100 };
+
+        assert!(!matches(note_on(1), 5000));
+        assert!(!matches(MidiEvent::ProgramChange { channel: 1, program: 3 }, 10_000));
+        assert!(!matches(note_on(1), 10_500));
+        assert!(!matches(note_on(1), 12_000));
+        assert!(matches(note_on(1), 12_001));
+        assert!(!matches(note_on(2), 20_000));
+        assert!(matches(MidiEvent::ProgramChange { channel: 1, program: 4 }, 20_000));
+        assert!(!matches(note_on(1), 20_100));
+    }
+
+    #[test]
+    fn test_condition_matches_stuck() {
+        let condition = Condition {
+            stuck: true,
+            ..Default::default()
+        };
+        let mut note_tracker = NoteTracker::new();
+        let mut matches = |midi_event: MidiEvent| {
+            let context = MatchContext {
+                repeated_note_on: note_tracker.is_repeated_note_on(&midi_event),
+                ..Default::default()
+            };
+            note_tracker.track(&midi_event, 0);
+            condition.matches_in_context(&midi_event, &context)
+        };
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+
+        assert!(!matches(note_on.clone()));
+        assert!(matches(note_on.clone()));
+        assert!(!matches(MidiEvent::NoteOn { channel: 2, note: 60, velocity: 100 }));
+        assert!(!matches(MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert!(!matches(note_on));
+    }
+
+    #[test]
+    fn test_condition_matches_running_status() {
+        let condition = Condition {
+            running_status: true,
+            ..Default::default()
+        };
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+
+        assert!(condition.matches_in_context(&note_on, &MatchContext { running_status: true, ..Default::default() }));
+        assert!(!condition.matches_in_context(&note_on, &MatchContext::default()));
+        assert!(Condition::default().matches_in_context(&note_on, &MatchContext { running_status: true, ..Default::default() }));
+    }
+
+    #[test]
+    fn test_condition_possible_event_types() {
+        let condition = |pattern: &str| Condition {
+            event_pattern: Some(Regex::new(pattern).unwrap()),
+            ..Default::default()
+        };
+        let event_types = |event| EventTypeMask::of_event(&event);
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 };
+
+        assert_eq!(condition("^note-on$").possible_event_types(), event_types(note_on.clone()));
+        assert_eq!(
+            condition("note-.*").possible_event_types(),
+            event_types(note_on.clone()).union(event_types(note_off.clone())),
+        );
+        assert_eq!(Condition::default().possible_event_types(), EventTypeMask::ALL);
+        assert_eq!(
+            Condition { category_pattern: Some(EventCategory::Note), ..Default::default() }.possible_event_types(),
+            EventCategory::Note.event_types(),
+        );
+
+        let zero_velocity_off = Condition {
+            treat_zero_vel_as_off: true,
+            ..condition("^note-off$")
+        };
+        assert!(zero_velocity_off.possible_event_types().intersects(event_types(note_on.clone())));
+
+        let mut alternatives = condition("^stop$");
+        alternatives.alternatives.push(condition("^note-on$"));
+        assert_eq!(
+            alternatives.possible_event_types(),
+            event_types(MidiEvent::Stop {}).union(event_types(note_on)),
+        );
+    }
+
+    #[test]
+    fn test_condition_matches_release() {
+        let condition = |treat_zero_vel_as_off| Condition {
+            release: true,
+            treat_zero_vel_as_off,
+            ..Default::default()
+        };
+        let note_off = MidiEvent::NoteOff { channel: 1, note: 60, velocity: 64 };
+        let zero_velocity_note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 };
+
+        assert!(condition(true).matches(&note_off));
+        assert!(condition(true).matches(&zero_velocity_note_on));
+        assert!(!condition(true).matches(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert!(!condition(true).matches(&MidiEvent::ControlChange { channel: 1, control_no: 64, value: 0 }));
+        assert!(condition(false).matches(&note_off));
+        assert!(!condition(false).matches(&zero_velocity_note_on));
+        assert_eq!(condition(true).describe(), "vel0off release");
+    }
+
+    #[test]
+    fn test_condition_matches_sustained() {
+        let condition = Condition {
+            sustained: true,
+            ..Default::default()
+        };
+        let mut sustain_tracker = SustainTracker::new();
+        let mut matches = |midi_event: MidiEvent| {
+            let context = MatchContext {
+                sustain_pedal_down: sustain_tracker.track(&midi_event),
+                ..Default::default()
+            };
+            condition.matches_in_context(&midi_event, &context)
+        };
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        let sustain = |value| MidiEvent::ControlChange { channel: 1, control_no: 64, value };
+
+        assert!(!matches(note_on.clone()));
+        assert!(!matches(sustain(127)));
+        assert!(matches(note_on.clone()));
+        assert!(matches(MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert!(!matches(MidiEvent::NoteOn { channel: 2, note: 60, velocity: 100 }));
+        assert!(!matches(sustain(0)));
+        assert!(!matches(note_on));
+    }
+
+    #[test]
+    fn test_condition_matches_controller_kind() {
+        let condition = |controller_kind| Condition {
+            controller_kind: Some(controller_kind),
+            ..Default::default()
+        };
+        let control_change = |value| MidiEvent::ControlChange { channel: 1, control_no: 64, value };
+
+        assert!(condition(ControllerKind::Switch).matches(&control_change(0)));
+        assert!(condition(ControllerKind::Switch).matches(&control_change(127)));
+        assert!(!condition(ControllerKind::Switch).matches(&control_change(64)));
+        assert!(condition(ControllerKind::Continuous).matches(&control_change(64)));
+        assert!(!condition(ControllerKind::Continuous).matches(&control_change(127)));
+        assert!(!condition(ControllerKind::Switch).matches(&MidiEvent::ProgramChange { channel: 1, program: 0 }));
+    }
+
+    #[test]
+    fn test_condition_matches_transport() {
+        let condition = Condition {
+            transport_state: Some(TransportState::Rolling),
+            ..Default::default()
+        };
+        let matches_while = |transport| condition.matches_in_context(
+            &MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 },
+            &MatchContext { transport, ..Default::default() },
+        );
+
+        assert!(matches_while(Some(TransportState::Rolling)));
+        assert!(!matches_while(Some(TransportState::Stopped)));
+        assert!(!matches_while(None));
+        assert_eq!(condition.describe(), "transport=rolling");
+    }
+
+    #[test]
+    fn test_condition_matches_scale() {
+        let condition = Condition {
+            scale: Some("Cmaj".parse().unwrap()),
+            ..Default::default()
+        };
+        let note_on = |note| MidiEvent::NoteOn { channel: 1, note, velocity: 100 };
+
+        assert!(condition.matches(&note_on(60)));
+        assert!(condition.matches(&note_on(62)));
+        assert!(condition.matches(&note_on(64)));
+        assert!(!condition.matches(&note_on(61)));
+        assert!(condition.matches(&MidiEvent::NoteOff { channel: 1, note: 48, velocity: 0 }));
+        assert!(!condition.matches(&MidiEvent::ControlChange { channel: 1, control_no: 60, value: 1 }));
+        assert_eq!(condition.describe(), "scale=Cmaj");
+    }
+
+    #[test]
+    fn test_condition_matches_time_of_day() {
+        let condition = Condition {
+            time_window: Some(TimeWindow { start: 22 * 60, end: 6 * 60 }),
+            ..Default::default()
+        };
+        let matches_at = |minute_of_day| condition.matches_in_context(
+            &MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 },
+            &MatchContext { minute_of_day, ..Default::default() },
+        );
+
+        assert!(matches_at(Some(23 * 60)));
+        assert!(matches_at(Some(60)));
+        assert!(!matches_at(Some(12 * 60)));
+        assert!(!matches_at(None));
+    }
+
+    #[test]
+    fn test_routing_table_uses_time_windows() {
+        let routing_table = RoutingTable {
+            rules: vec![parse_rule(0, "note-on ch1 => synth".into()).unwrap()],
+            ..Default::default()
+        };
+        let routing_table_at_night = RoutingTable {
+            rules: vec![parse_rule(0, "note-on ch1 or note-on time22:00-06:00 => synth".into()).unwrap()],
+            ..Default::default()
+        };
+
+        assert!(!routing_table.uses_time_windows());
+        assert!(routing_table_at_night.uses_time_windows());
+    }
+
+    #[test]
+    fn test_condition_matches_controller_set() {
+        let condition = Condition {
+            controller_set: Some(ControllerSet::new(vec![11, 1, 7])),
+            ..Default::default()
+        };
+        let control_change = |control_no| MidiEvent::ControlChange { channel: 1, control_no, value: 64 };
+
+        assert!(condition.matches(&control_change(1)));
+        assert!(condition.matches(&control_change(11)));
+        assert!(!condition.matches(&control_change(8)));
+        assert_eq!(condition.describe(), "ctrl1,7,11");
+    }
+
+    #[test]
+    fn test_condition_matches_slew() {
+        let condition = Condition {
+            slew_pattern: Some(NumericRange { start: 21, end: 255 }),
+            ..Default::default()
+        };
+        let mut slew_tracker = SlewTracker::new();
+        let mut matches = |value| {
+            let control_change = MidiEvent::ControlChange { channel: 1, control_no: 1, value };
+            let context = MatchContext { slew: slew_tracker.track(&control_change), ..Default::default() };
+            condition.matches_in_context(&control_change, &context)
+        };
+
+        assert!(!matches(0));
+        assert!(!matches(5));
+        assert!(!matches(25));
+        assert!(matches(90));
+        assert!(matches(30));
+        assert!(!matches(31));
+    }
+
+    #[test]
+    fn test_condition_matches_length() {
+        let condition = Condition {
+            length_pattern: Some(NumericRange { start: 2, end: 2 }),
+            ..Default::default()
+        };
+        let with_length = |raw_len| MatchContext { raw_len: Some(raw_len), ..Default::default() };
+
+        let program_change = MidiEvent::ProgramChange { channel: 1, program: 5 };
+        assert!(condition.matches_in_context(&program_change, &with_length(2)));
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert!(!condition.matches_in_context(&note_on, &with_length(3)));
+        assert!(!condition.matches(&program_change));
+    }
+
+    #[test]
+    fn test_routing_table_voices_of_chord() {
+        let voice_rule = |voice, output_port: &str| Rule {
+            condition: Condition {
+                voice_pattern: Some(NumericRange { start: voice, end: voice }),
+                ..Default::default()
+            },
+            actions: vec![Action::ForwardTo { output_port: output_port.to_string() }],
+            description: None,
+            negated: false,
+            dry_run: false,
+            priority: 0,
+            group: None,
+        };
+        let routing_table = RoutingTable {
+            rules: vec![voice_rule(1, "bass"), voice_rule(2, "tenor"), voice_rule(3, "soprano")],
+            ..Default::default()
+        };
+        let mut voice_tracker = VoiceTracker::new();
+        let mut output_ports = |midi_event: MidiEvent| {
+            let context = MatchContext { voice: voice_tracker.track(&midi_event), ..Default::default() };
+            routing_table.get_outputs_in_context(&midi_event, &context).into_iter()
+                .map(|routed_event| routed_event.output_port.into_owned())
+                .collect::<Vec<String>>()
+        };
+        let note_on = |note| MidiEvent::NoteOn { channel: 1, note, velocity: 100 };
+        let note_off = |note| MidiEvent::NoteOff { channel: 1, note, velocity: 0 };
+
+        assert_eq!(output_ports(note_on(60)), vec!["bass"]);
+        assert_eq!(output_ports(note_on(64)), vec!["tenor"]);
+        assert_eq!(output_ports(note_on(67)), vec!["soprano"]);
+        assert_eq!(output_ports(note_off(64)), vec!["tenor"]);
+        assert_eq!(output_ports(note_off(60)), vec!["bass"]);
+        assert_eq!(output_ports(note_off(67)), vec!["soprano"]);
+        assert_eq!(output_ports(MidiEvent::ProgramChange { channel: 1, program: 1 }), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_toggle_switch_action() {
+        let routing_table = RoutingTable {
+            rules: vec![Rule {
+                condition: Condition::default(),
+                actions: vec![Action::Transform {
+                    transforms: vec![Box::new(ToggleSwitch { control_no: 64, state: ToggleState::default() })],
+                    output_port: "out1".to_string(),
+                }],
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            }],
+            ..Default::default()
+        };
+        let footswitch = |value| MidiEvent::ControlChange { channel: 1, control_no: 64, value };
+        let output = |value| vec![RoutedEvent { output_port: "out1".into(), event: Some(footswitch(value)) }];
+
+        assert_eq!(routing_table.get_outputs(&footswitch(127)), output(127));
+        assert_eq!(routing_table.get_outputs(&footswitch(0)), vec![]);
+        assert_eq!(routing_table.get_outputs(&footswitch(127)), output(0));
+        assert_eq!(routing_table.get_outputs(&footswitch(0)), vec![]);
+        assert_eq!(routing_table.get_outputs(&footswitch(127)), output(127));
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert_eq!(routing_table.get_outputs(&note_on), vec![RoutedEvent { output_port: "out1".into(), event: Some(note_on) }]);
+    }
+
+    #[test]
+    fn test_pickup_action() {
+        let routing_table = RoutingTable {
+            rules: vec![Rule {
+                condition: Condition::default(),
+                actions: vec![Action::Pickup {
+                    action: Box::new(Action::Transform {
+                        transforms: vec![Box::new(SetChannel { channel: 1 })],
+                        output_port: "out1".to_string(),
+                    }),
+                    state: PickupState::new(),
+                }],
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            }],
+            ..Default::default()
+        };
+        let volume = |channel, value| MidiEvent::ControlChange { channel, control_no: 7, value };
+        let output = |value| vec![RoutedEvent { output_port: "out1".into(), event: Some(volume(1, value)) }];
+
+        assert_eq!(routing_table.get_outputs(&volume(1, 100)), output(100));
+        assert_eq!(routing_table.get_outputs(&volume(2, 10)), vec![]);
+        assert_eq!(routing_table.get_outputs(&volume(2, 90)), vec![]);
+        assert_eq!(routing_table.get_outputs(&volume(2, 110)), output(110));
+        assert_eq!(routing_table.get_outputs(&volume(2, 120)), output(120));
+        assert_eq!(routing_table.get_outputs(&volume(1, 110)), vec![]);
+        let note_on = MidiEvent::NoteOn { channel: 2, note: 60, velocity: 100 };
+        let moved_note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+        assert_eq!(routing_table.get_outputs(&note_on), vec![RoutedEvent { output_port: "out1".into(), event: Some(moved_note_on) }]);
+    }
+
+    static LOG_RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Records the messages of all log records in `LOG_RECORDS`, since a logger can only be set
+    /// once for all tests
+    fn record_logs() {
+        struct RecordingLogger;
+        impl log::Log for RecordingLogger {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                LOG_RECORDS.lock().unwrap().push(record.args().to_string());
+            }
+
+            fn flush(&self) {}
+        }
+        static SET_LOGGER: Once = Once::new();
+        SET_LOGGER.call_once(|| {
+            log::set_logger(&RecordingLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Info);
+        });
+    }
+
+    #[test]
+    fn test_dry_run_rule() {
+        record_logs();
+        let routing_table = RoutingTable {
+            rules: vec![
+                Rule { dry_run: true, ..parse_rule(0, "note-on => staging:transpose(+12)".into()).unwrap() },
+                parse_rule(1, "note-on => synth".into()).unwrap(),
+                Rule { dry_run: true, ..parse_rule(2, "note-on => staging:redirect".into()).unwrap() },
+            ],
+            passthrough_port: Some("thru".to_string()),
+            rule_stats: Arc::new(RuleStats::new(3)),
+            ..Default::default()
+        };
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 7, note: 60, velocity: 100 });
+
+        assert_eq!(outputs, vec![
+            RoutedEvent { output_port: "synth".into(), event: None },
+            RoutedEvent { output_port: "thru".into(), event: None },
+        ]);
+        assert_eq!(routing_table.rule_stats.snapshot(), vec![1, 1, 1]);
+        let log_records = LOG_RECORDS.lock().unwrap();
+        for expected_record in [
+            "Dry run of rule 1: would send NoteOn { channel: 7, note: 72, velocity: 100 } to port staging",
+            "Dry run of rule 3: would send NoteOn { channel: 7, note: 60, velocity: 100 } to port staging",
+        ] {
+            assert!(
+                log_records.contains(&expected_record.to_string()),
+                "Unexpected log records: {:?}",
+                log_records,
+            );
+        }
+    }
+
+    #[test]
+    fn test_dry_run_rule_keeps_state() {
+        record_logs();
+        let routing_table = RoutingTable {
+            rules: vec![
+                Rule { dry_run: true, ..parse_rule(0, "control-change ch9 ctrl64 => pedal:toggle(64)".into()).unwrap() },
+                Rule { dry_run: true, ..parse_rule(1, "control-change ch9 ctrl64 => pedal:transpose(+12):toggle(64)".into()).unwrap() },
+            ],
+            rule_stats: Arc::new(RuleStats::new(2)),
+            ..Default::default()
+        };
+        let press = MidiEvent::ControlChange { channel: 9, control_no: 64, value: 127 };
+        for _ in 0..3 {
+            assert_eq!(routing_table.get_outputs(&press), vec![]);
+        }
+
+        let Action::Transform { transforms, .. } = &routing_table.rules[0].actions[0] else {
+            panic!("Unexpected action: {:?}", routing_table.rules[0].actions[0]);
+        };
+        assert_eq!(transforms[0].preview(press.clone()), Some(press));
+        let log_records = LOG_RECORDS.lock().unwrap();
+        for rule_no in 1..=2 {
+            let record = |value: u8| format!(
+                "Dry run of rule {}: would send ControlChange {{ channel: 9, control_no: 64, value: {} }} to port pedal",
+                rule_no,
+                value,
+            );
+            assert!(log_records.contains(&record(127)), "Unexpected log records: {:?}", log_records);
+            assert!(!log_records.contains(&record(0)), "Unexpected log records: {:?}", log_records);
+        }
+    }
+
+    #[test]
+    fn test_logged_action() {
+        record_logs();
+
+        let routing_table = RoutingTable {
+            rules: vec![Rule {
+                condition: Condition::default(),
+                actions: vec![Action::Logged {
+                    action: Box::new(Action::Transform {
+                        transforms: vec![Box::new(Transpose { semitones: 12 })],
+                        output_port: "out1".to_string(),
+                    }),
+                }],
+                description: None,
+                negated: false,
+                dry_run: false,
+                priority: 0,
+                group: None,
+            }],
+            ..Default::default()
+        };
+        let outputs = routing_table.get_outputs(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 });
+
+        let transposed = MidiEvent::NoteOn { channel: 1, note: 72, velocity: 100 };
+        assert_eq!(outputs, vec![RoutedEvent { output_port: "out1".into(), event: Some(transposed) }]);
+        let log_records = LOG_RECORDS.lock().unwrap();
+        assert!(
+            log_records.contains(&"Routing NoteOn { channel: 1, note: 60, velocity: 100 } to port out1 as \
+                NoteOn { channel: 1, note: 72, velocity: 100 }".to_string()),
+            "Unexpected log records: {:?}",
+            log_records,
+        );
+    }
 }
@@ -0,0 +1,61 @@
+/*
+ * Check a single rule against a single event without config file or Jack
+ */
+
+use crate::parser::{parse_event, parse_rule, FieldFormatError, RuleParseError};
+use crate::routing::RoutingTable;
+
+#[derive(Debug)]
+pub enum RuleTestError {
+    InvalidRule(RuleParseError),
+    InvalidEvent(FieldFormatError),
+}
+
+/// Parses `rule_text` and `event_text` and returns a line for each output port the event is
+/// routed to. The list is empty if the rule does not match the event.
+pub fn test_rule(rule_text: &str, event_text: &str) -> Result<Vec<String>, RuleTestError> {
+    let rule = parse_rule(0, rule_text.to_string()).map_err(RuleTestError::InvalidRule)?;
+    let event = parse_event(event_text).map_err(RuleTestError::InvalidEvent)?;
+    let routing_table = RoutingTable {
+        rules: vec![rule],
+        ..Default::default()
+    };
+
+    let outputs = routing_table.get_outputs(&event).into_iter()
+        .map(|routed_event| match routed_event.event {
+            Some(replacement) => format!("{} <- {:?}", routed_event.output_port, replacement),
+            None => format!("{} <- {:?}", routed_event.output_port, event),
+        })
+        .collect();
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_test_rule_match() {
+        let result = test_rule("note-.* ch1 => out1 out2", "note-on ch1 note60 vel100");
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![
+            "out1 <- NoteOn { channel: 1, note: 60, velocity: 100 }",
+            "out2 <- NoteOn { channel: 1, note: 60, velocity: 100 }",
+        ]);
+    }
+
+    #[test]
+    fn test_test_rule_no_match() {
+        let result = test_rule("note-.* ch1 => out", "note-on ch2 note60 vel100");
+
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_test_rule_invalid_input() {
+        assert!(matches!(test_rule("note-on ch300 => out", "note-on"), Err(RuleTestError::InvalidRule(_))));
+        assert!(matches!(test_rule("note-on => out", "note-pikachu"), Err(RuleTestError::InvalidEvent(_))));
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * Musical scales to match notes by their pitch class, e.g. all notes of C major
+ */
+
+use std::str::FromStr;
+
+const NOTES_PER_OCTAVE: u8 = 12;
+/// Names of the pitch classes starting at C, which are used to name the root of a scale
+const PITCH_CLASS_NAMES: [&str; NOTES_PER_OCTAVE as usize] =
+    ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleKind {
+    Major,
+    /// Natural minor
+    Minor,
+}
+
+impl ScaleKind {
+    /// Semitones of the scale's notes above the root
+    fn intervals(&self) -> &'static [u8] {
+        match self {
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::Minor => &[0, 2, 3, 5, 7, 8, 10],
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            ScaleKind::Major => "maj",
+            ScaleKind::Minor => "min",
+        }
+    }
+}
+
+/// Scale given by its root pitch class (0 for C to 11 for B) and its kind, e.g. `Cmaj` or `F#min`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scale {
+    pub root: u8,
+    pub kind: ScaleKind,
+}
+
+impl Scale {
+    /// Checks whether the note is in the scale in any octave
+    pub fn contains(&self, note: u8) -> bool {
+        let interval = (note + NOTES_PER_OCTAVE - self.root % NOTES_PER_OCTAVE) % NOTES_PER_OCTAVE;
+        self.kind.intervals().contains(&interval)
+    }
+
+    /// Returns the nearest note in the scale. Notes between two notes of the scale, e.g. C# in C
+    /// major, are rounded down.
+    pub fn quantize(&self, note: u8) -> u8 {
+        (0..NOTES_PER_OCTAVE)
+            .flat_map(|distance| [note.checked_sub(distance), note.checked_add(distance).filter(|note| *note <= 127)])
+            .flatten()
+            .find(|candidate| self.contains(*candidate))
+            .unwrap_or(note)
+    }
+
+    /// Writes the scale in config file syntax, e.g. `Cmaj`
+    pub fn describe(&self) -> String {
+        format!("{}{}", PITCH_CLASS_NAMES[(self.root % NOTES_PER_OCTAVE) as usize], self.kind.suffix())
+    }
+}
+
+impl FromStr for Scale {
+    type Err = ();
+
+    /// Parses a root note name with an optional `#` or `b` followed by `maj` or `min`, e.g. `Bbmin`
+    fn from_str(scale: &str) -> Result<Self, Self::Err> {
+        let (root_name, kind) = if let Some(root_name) = scale.strip_suffix(ScaleKind::Major.suffix()) {
+            (root_name, ScaleKind::Major)
+        } else if let Some(root_name) = scale.strip_suffix(ScaleKind::Minor.suffix()) {
+            (root_name, ScaleKind::Minor)
+        } else {
+            return Err(());
+        };
+        let mut chars = root_name.chars();
+        let natural = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some(letter) => PITCH_CLASS_NAMES.iter().position(|name| *name == letter.to_string()).ok_or(())?,
+            None => return Err(()),
+        } as u8;
+        let root = match chars.as_str() {
+            "" => natural,
+            "#" => (natural + 1) % NOTES_PER_OCTAVE,
+            "b" => (natural + NOTES_PER_OCTAVE - 1) % NOTES_PER_OCTAVE,
+            _ => return Err(()),
+        };
+        Ok(Scale { root, kind })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_contains() {
+        let c_major: Scale = "Cmaj".parse().unwrap();
+
+        for note in [60, 62, 64, 65, 67, 69, 71, 72, 0, 127] {
+            assert!(c_major.contains(note), "note {}", note);
+        }
+        for note in [61, 63, 66, 68, 70, 1] {
+            assert!(!c_major.contains(note), "note {}", note);
+        }
+    }
+
+    #[test]
+    fn test_scale_quantize() {
+        let c_major: Scale = "Cmaj".parse().unwrap();
+
+        assert_eq!(c_major.quantize(60), 60);
+        assert_eq!(c_major.quantize(61), 60);
+        assert_eq!(c_major.quantize(63), 62);
+        assert_eq!(c_major.quantize(66), 65);
+        assert_eq!(c_major.quantize(127), 127);
+        assert_eq!("Dmaj".parse::<Scale>().unwrap().quantize(0), 1);
+    }
+
+    #[test]
+    fn test_scale_from_str() {
+        assert_eq!("Amin".parse(), Ok(Scale { root: 9, kind: ScaleKind::Minor }));
+        assert_eq!("F#maj".parse(), Ok(Scale { root: 6, kind: ScaleKind::Major }));
+        assert_eq!("Bbmin".parse(), Ok(Scale { root: 10, kind: ScaleKind::Minor }));
+        assert_eq!("cmaj".parse(), Ok(Scale { root: 0, kind: ScaleKind::Major }));
+        assert_eq!("Hmaj".parse::<Scale>(), Err(()));
+        assert_eq!("C".parse::<Scale>(), Err(()));
+        assert_eq!("C##maj".parse::<Scale>(), Err(()));
+        assert_eq!("Bbmin".parse::<Scale>().unwrap().describe(), "A#min");
+    }
+}
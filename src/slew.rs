@@ -0,0 +1,53 @@
+/*
+ * Track the last value of each controller to detect how fast it changes
+ */
+
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const CONTROLLER_COUNT: usize = 128;
+
+pub struct SlewTracker {
+    /// Value of the last control change by channel and controller
+    last_values: [[Option<u8>; CONTROLLER_COUNT]; CHANNEL_COUNT],
+}
+
+impl SlewTracker {
+    pub fn new() -> Self {
+        SlewTracker {
+            last_values: [[None; CONTROLLER_COUNT]; CHANNEL_COUNT],
+        }
+    }
+
+    /// Records control changes and returns by how much the value changed since the previous
+    /// control change of the same controller, which is unknown for its first control change
+    pub fn track(&mut self, midi_event: &MidiEvent) -> Option<u8> {
+        let MidiEvent::ControlChange { channel, control_no, value } = *midi_event else {
+            return None;
+        };
+        let last_value = self.last_values
+            .get_mut((channel as usize).checked_sub(1)?)?
+            .get_mut(control_no as usize)?
+            .replace(value)?;
+        Some(value.abs_diff(last_value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slew_tracker() {
+        let mut slew_tracker = SlewTracker::new();
+        let control_change = |channel, control_no, value| MidiEvent::ControlChange { channel, control_no, value };
+
+        assert_eq!(slew_tracker.track(&control_change(1, 7, 10)), None);
+        assert_eq!(slew_tracker.track(&control_change(1, 7, 12)), Some(2));
+        assert_eq!(slew_tracker.track(&control_change(1, 7, 100)), Some(88));
+        assert_eq!(slew_tracker.track(&control_change(1, 7, 40)), Some(60));
+        assert_eq!(slew_tracker.track(&control_change(2, 7, 40)), None);
+        assert_eq!(slew_tracker.track(&control_change(1, 8, 40)), None);
+        assert_eq!(slew_tracker.track(&MidiEvent::ProgramChange { channel: 1, program: 40 }), None);
+    }
+}
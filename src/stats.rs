@@ -0,0 +1,134 @@
+/*
+ * Traffic statistics of the output ports and rules
+ */
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of events sent to each output port, which is shared between the process handler and the
+/// main thread
+#[derive(Debug, Default)]
+pub struct PortStats {
+    sent_events: HashMap<String, AtomicU64>,
+}
+
+impl PortStats {
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(port_names: I) -> Self {
+        PortStats {
+            sent_events: port_names.into_iter()
+                .map(|port_name| (port_name.to_string(), AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    pub fn record_sent(&self, port_name: &str) {
+        if let Some(counter) = self.sent_events.get(port_name) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Names of all ports which did not receive any event, sorted by name
+    pub fn unused_ports(&self) -> Vec<&str> {
+        let mut port_names: Vec<&str> = self.sent_events.iter()
+            .filter(|(_, counter)| counter.load(Ordering::Relaxed) == 0)
+            .map(|(port_name, _)| port_name.as_str())
+            .collect();
+        port_names.sort_unstable();
+        port_names
+    }
+}
+
+/// Number of events matched by each rule, which is shared between the process handler and the main
+/// thread
+#[derive(Debug, Default)]
+pub struct RuleStats {
+    hits: Vec<AtomicU64>,
+}
+
+impl RuleStats {
+    pub fn new(rule_count: usize) -> Self {
+        RuleStats {
+            hits: (0..rule_count).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn record_hit(&self, rule_index: usize) {
+        if let Some(counter) = self.hits.get(rule_index) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Current number of hits by rule index
+    pub fn snapshot(&self) -> Vec<u64> {
+        self.hits.iter().map(|counter| counter.load(Ordering::Relaxed)).collect()
+    }
+}
+
+/// Writes a CSV table with the number, description, condition, and hits of each rule, given the
+/// description and condition summary of each rule and a snapshot of their hits
+pub fn rule_stats_csv(rule_summaries: &[(Option<String>, String)], hits: &[u64]) -> String {
+    let mut csv = String::from("rule,label,condition,hits\n");
+    for (rule_index, ((description, condition), hits)) in rule_summaries.iter().zip(hits).enumerate() {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            rule_index + 1,
+            escape_csv(description.as_deref().unwrap_or_default()),
+            escape_csv(condition),
+            hits,
+        ));
+    }
+    csv
+}
+
+/// Quotes a CSV field if it contains separators, quotes, or line breaks
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_stats_unused_ports() {
+        let stats = PortStats::new(["pads", "lead", "drums", "bass"]);
+
+        stats.record_sent("lead");
+        stats.record_sent("lead");
+        stats.record_sent("drums");
+        stats.record_sent("unknown");
+
+        assert_eq!(stats.unused_ports(), vec!["bass", "pads"]);
+    }
+
+    #[test]
+    fn test_rule_stats() {
+        let stats = RuleStats::new(2);
+
+        stats.record_hit(1);
+        stats.record_hit(1);
+        stats.record_hit(5);
+
+        assert_eq!(stats.snapshot(), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_rule_stats_csv() {
+        let rule_summaries = vec![
+            (Some("Drums".to_string()), "note-.* ch10".to_string()),
+            (None, "control-change ctrl1,7".to_string()),
+            (Some("Say \"hi\"".to_string()), "*".to_string()),
+        ];
+
+        assert_eq!(rule_stats_csv(&rule_summaries, &[42, 0, 7]), "\
+rule,label,condition,hits
+1,Drums,note-.* ch10,42
+2,,\"control-change ctrl1,7\",0
+3,\"Say \"\"hi\"\"\",*,7
+");
+    }
+}
@@ -0,0 +1,73 @@
+/*
+ * Release stuck notes by sending a note-off before a note-on of a note which is already playing
+ */
+
+use std::collections::HashMap;
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const NOTE_COUNT: usize = 128;
+
+type HeldNotes = [[bool; NOTE_COUNT]; CHANNEL_COUNT];
+
+pub struct StuckNoteFixer {
+    /// Notes playing on each output port by channel and note
+    held_notes: HashMap<String, Box<HeldNotes>>,
+}
+
+impl StuckNoteFixer {
+    /// Creates the state of all output ports in advance to avoid allocations while routing
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(port_names: I) -> Self {
+        StuckNoteFixer {
+            held_notes: port_names.into_iter()
+                .map(|port_name| (port_name.to_string(), Box::new([[false; NOTE_COUNT]; CHANNEL_COUNT])))
+                .collect(),
+        }
+    }
+
+    /// Records the event sent to the port and returns a note-off to send before it if the event
+    /// is a note-on of a note which is still playing on the port
+    pub fn check(&mut self, port_name: &str, midi_event: &MidiEvent) -> Option<MidiEvent> {
+        let (channel, note, note_on) = match *midi_event {
+            MidiEvent::NoteOn { channel, note, velocity } => (channel, note, velocity > 0),
+            MidiEvent::NoteOff { channel, note, .. } => (channel, note, false),
+            _ => return None,
+        };
+        let held = self.held_notes.get_mut(port_name)?
+            .get_mut((channel as usize).checked_sub(1)?)?
+            .get_mut(note as usize)?;
+        let was_held = std::mem::replace(held, note_on);
+        (note_on && was_held).then_some(MidiEvent::NoteOff { channel, note, velocity: 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stuck_note_fixer_double_note_on() {
+        let mut fixer = StuckNoteFixer::new(["out1", "out2"]);
+        let note_on = MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 };
+
+        assert_eq!(fixer.check("out1", &note_on), None);
+        assert_eq!(fixer.check("out1", &note_on), Some(MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert_eq!(fixer.check("out1", &note_on), Some(MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }));
+        assert_eq!(fixer.check("out2", &note_on), None);
+        assert_eq!(fixer.check("unknown", &note_on), None);
+        assert_eq!(fixer.check("unknown", &note_on), None);
+    }
+
+    #[test]
+    fn test_stuck_note_fixer_released_note() {
+        let mut fixer = StuckNoteFixer::new(["out1"]);
+        let note_on = MidiEvent::NoteOn { channel: 3, note: 64, velocity: 90 };
+
+        assert_eq!(fixer.check("out1", &note_on), None);
+        assert_eq!(fixer.check("out1", &MidiEvent::NoteOff { channel: 3, note: 64, velocity: 0 }), None);
+        assert_eq!(fixer.check("out1", &note_on), None);
+        assert_eq!(fixer.check("out1", &MidiEvent::NoteOn { channel: 3, note: 64, velocity: 0 }), None);
+        assert_eq!(fixer.check("out1", &note_on), None);
+        assert_eq!(fixer.check("out1", &MidiEvent::NoteOn { channel: 4, note: 64, velocity: 90 }), None);
+    }
+}
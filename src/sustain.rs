@@ -0,0 +1,64 @@
+/*
+ * Track the sustain pedal of each channel
+ */
+
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const SUSTAIN_PEDAL: u8 = 64;
+/// Lowest value of the sustain pedal control change which means the pedal is down
+const PEDAL_DOWN_THRESHOLD: u8 = 64;
+
+pub struct SustainTracker {
+    pedal_down: [bool; CHANNEL_COUNT],
+}
+
+impl SustainTracker {
+    pub fn new() -> Self {
+        SustainTracker {
+            pedal_down: [false; CHANNEL_COUNT],
+        }
+    }
+
+    /// Records sustain pedal control changes and returns whether the pedal is down on the
+    /// event's channel, which is never the case for events without channel. A system reset
+    /// releases the pedal on all channels.
+    pub fn track(&mut self, midi_event: &MidiEvent) -> bool {
+        if let MidiEvent::SystemReset {} = midi_event {
+            self.pedal_down = [false; CHANNEL_COUNT];
+        }
+        let Some(channel) = midi_event.channel() else {
+            return false;
+        };
+        let Some(pedal_down) = (channel as usize).checked_sub(1).and_then(|index| self.pedal_down.get_mut(index)) else {
+            return false;
+        };
+        if let MidiEvent::ControlChange { control_no: SUSTAIN_PEDAL, value, .. } = *midi_event {
+            *pedal_down = value >= PEDAL_DOWN_THRESHOLD;
+        }
+        *pedal_down
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sustain_tracker() {
+        let mut sustain_tracker = SustainTracker::new();
+        let note_on = |channel| MidiEvent::NoteOn { channel, note: 60, velocity: 100 };
+        let sustain = |value| MidiEvent::ControlChange { channel: 1, control_no: SUSTAIN_PEDAL, value };
+
+        assert!(!sustain_tracker.track(&note_on(1)));
+        assert!(sustain_tracker.track(&sustain(127)));
+        assert!(sustain_tracker.track(&note_on(1)));
+        assert!(!sustain_tracker.track(&note_on(2)));
+        assert!(!sustain_tracker.track(&sustain(10)));
+        assert!(!sustain_tracker.track(&note_on(1)));
+
+        sustain_tracker.track(&sustain(64));
+        sustain_tracker.track(&MidiEvent::SystemReset {});
+        assert!(!sustain_tracker.track(&note_on(1)));
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * Derive the tempo from incoming timing clock events
+ */
+
+use std::collections::VecDeque;
+use crate::midi::MidiEvent;
+
+/// Timing clock events are sent 24 times per quarter note
+const CLOCKS_PER_BEAT: usize = 24;
+/// Longest interval between two clocks (about 10 BPM) before the tempo is considered unknown
+const MAX_CLOCK_INTERVAL_MS: u64 = 250;
+const MS_PER_MINUTE: f64 = 60_000.0;
+
+pub struct TempoTracker {
+    /// Times of the timing clocks within the last beat in milliseconds of the JACK `Clock`
+    clock_times_ms: VecDeque<u64>,
+}
+
+impl TempoTracker {
+    pub fn new() -> Self {
+        TempoTracker {
+            clock_times_ms: VecDeque::with_capacity(CLOCKS_PER_BEAT + 1),
+        }
+    }
+
+    /// Records timing clocks and resets the tempo if the clock was stopped
+    pub fn track(&mut self, midi_event: &MidiEvent, event_ms: u64) {
+        match midi_event {
+            MidiEvent::TimingClock {} => {
+                let is_interrupted = self.clock_times_ms.back()
+                    .map(|last_clock_ms| {
+                        event_ms < *last_clock_ms || event_ms - *last_clock_ms > MAX_CLOCK_INTERVAL_MS
+                    })
+                    .unwrap_or(false);
+                if is_interrupted {
+                    self.clock_times_ms.clear();
+                }
+                if self.clock_times_ms.len() > CLOCKS_PER_BEAT {
+                    self.clock_times_ms.pop_front();
+                }
+                self.clock_times_ms.push_back(event_ms);
+            },
+            MidiEvent::Stop {} | MidiEvent::SystemReset {} => self.clock_times_ms.clear(),
+            _ => {},
+        }
+    }
+
+    /// Tempo in beats per minute averaged over the clock intervals of the last beat
+    pub fn bpm(&self) -> Option<f64> {
+        let (first_ms, last_ms) = (self.clock_times_ms.front()?, self.clock_times_ms.back()?);
+        let interval_count = self.clock_times_ms.len() - 1;
+        if interval_count == 0 || last_ms <= first_ms {
+            return None;
+        }
+        let beat_ms = last_ms.saturating_sub(*first_ms) as f64 * CLOCKS_PER_BEAT as f64 / interval_count as f64;
+        Some(MS_PER_MINUTE / beat_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    fn feed_clocks(tracker: &mut TempoTracker, start_ms: f64, interval_ms: f64, count: usize) {
+        for clock_no in 0..count {
+            let event_ms = (start_ms + clock_no as f64 * interval_ms).round() as u64;
+            tracker.track(&MidiEvent::TimingClock {}, event_ms);
+        }
+    }
+
+    #[test]
+    fn test_tempo_tracker_bpm() {
+        let mut tracker = TempoTracker::new();
+        assert_eq!(tracker.bpm(), None);
+
+        // 120 BPM: 500 ms per beat, i.e. 20.83 ms per clock
+        feed_clocks(&mut tracker, 1000.0, 500.0 / 24.0, 100);
+        let bpm = tracker.bpm().unwrap();
+        assert!((bpm - 120.0).abs() < 0.5, "Unexpected tempo: {}", bpm);
+    }
+
+    #[test]
+    fn test_tempo_tracker_tempo_change() {
+        let mut tracker = TempoTracker::new();
+
+        feed_clocks(&mut tracker, 0.0, 500.0 / 24.0, 48);
+        feed_clocks(&mut tracker, 1000.0, 400.0 / 24.0, 48);
+
+        let bpm = tracker.bpm().unwrap();
+        assert!((bpm - 150.0).abs() < 0.5, "Unexpected tempo: {}", bpm);
+    }
+
+    #[test]
+    fn test_tempo_tracker_reset() {
+        let mut tracker = TempoTracker::new();
+
+        feed_clocks(&mut tracker, 0.0, 20.0, 10);
+        tracker.track(&MidiEvent::Stop {}, 200);
+        assert_eq!(tracker.bpm(), None);
+
+        feed_clocks(&mut tracker, 1000.0, 20.0, 10);
+        tracker.track(&MidiEvent::TimingClock {}, 5000);
+        assert_eq!(tracker.bpm(), None);
+    }
+
+    #[test]
+    fn test_tempo_tracker_sample_rate_change() {
+        let mut tracker = TempoTracker::new();
+        let mut clock = Clock::new(44100);
+        let sample_rate = clock.shared_sample_rate();
+        let mut frames = 44100;
+        let mut last_event_ms = 0;
+
+        // 120 BPM: 20.83 ms per clock, while the sample rate changes after every 30 clocks
+        for (block_no, rate) in [44100, 96000, 22050, 48000].iter().cycle().take(8).enumerate() {
+            sample_rate.set(*rate);
+            clock.update_sample_rate(frames);
+            for _ in 0..30 {
+                frames += *rate as u64 * 500 / 24 / 1000;
+                let event_ms = clock.frames_to_ms(frames);
+                assert!(event_ms >= last_event_ms, "Time went backwards after {} clocks", block_no * 30);
+                last_event_ms = event_ms;
+                tracker.track(&MidiEvent::TimingClock {}, event_ms);
+                if let Some(bpm) = tracker.bpm() {
+                    assert!((bpm - 120.0).abs() < 1.0, "Unexpected tempo: {}", bpm);
+                }
+            }
+        }
+        assert!(tracker.bpm().is_some());
+    }
+
+    #[test]
+    fn test_tempo_tracker_time_goes_backwards() {
+        let mut tracker = TempoTracker::new();
+
+        feed_clocks(&mut tracker, 1000.0, 20.0, 10);
+        tracker.track(&MidiEvent::TimingClock {}, 500);
+        assert_eq!(tracker.bpm(), None);
+
+        feed_clocks(&mut tracker, 520.0, 20.0, 10);
+        assert!(tracker.bpm().is_some());
+    }
+}
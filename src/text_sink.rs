@@ -0,0 +1,88 @@
+/*
+ * Print routed events as text lines instead of sending them as MIDI, e.g. to pipe them into
+ * other tools
+ */
+
+use std::io::Write;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use log::{error, warn};
+use crate::midi::MidiEvent;
+use crate::parser::format_event;
+
+/// Name of the output port which prints events to stdout instead of sending them to a JACK port
+pub const TEXT_SINK_PORT: &str = "-";
+/// Alternative name of the port `-` in rules
+pub const TEXT_SINK_ALIAS: &str = "stdout:";
+/// Maximum number of events waiting to be printed, further events are dropped
+const TEXT_SINK_QUEUE_LEN: usize = 1024;
+
+/// Passes events to a thread which writes each as a line in the format of event literals, e.g.
+/// `note-on ch1 60 vel100`, so that the process cycle never waits for the output
+pub struct TextSink {
+    sender: SyncSender<MidiEvent>,
+}
+
+impl TextSink {
+    /// Starts the thread writing to `writer`, which ends and returns the writer when the sink is
+    /// dropped
+    pub fn spawn<W: Write + Send + 'static>(mut writer: W) -> (Self, JoinHandle<W>) {
+        let (sender, receiver) = mpsc::sync_channel::<MidiEvent>(TEXT_SINK_QUEUE_LEN);
+        let join_handle = thread::spawn(move || {
+            for midi_event in receiver {
+                if let Err(err) = writeln!(writer, "{}", format_event(&midi_event)) {
+                    error!("Could not print event {:?}: {}", midi_event, err);
+                }
+            }
+            writer
+        });
+        (TextSink { sender }, join_handle)
+    }
+
+    pub fn write(&self, midi_event: &MidiEvent) {
+        if let Err(err) = self.sender.try_send(midi_event.clone()) {
+            warn!("Could not print event {:?}: {}", midi_event, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_rule;
+    use crate::routing::RoutingTable;
+    use super::*;
+
+    #[test]
+    fn test_text_sink_prints_routed_events() {
+        let routing_table = RoutingTable {
+            rules: vec![
+                parse_rule(0, "note-.* ch1 => - synth".into()).unwrap(),
+                parse_rule(1, "control-change => -:cc(1->2)".into()).unwrap(),
+                parse_rule(2, "program-change => stdout:".into()).unwrap(),
+                parse_rule(3, "note-on ch2 => stdout::transpose(+12)".into()).unwrap(),
+            ],
+            ..Default::default()
+        };
+        let (text_sink, join_handle) = TextSink::spawn(Vec::new());
+        let events = [
+            MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 },
+            MidiEvent::NoteOn { channel: 2, note: 60, velocity: 100 },
+            MidiEvent::ControlChange { channel: 3, control_no: 1, value: 64 },
+            MidiEvent::ProgramChange { channel: 1, program: 5 },
+            MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 },
+        ];
+        for midi_event in &events {
+            for routed_event in routing_table.get_outputs(midi_event) {
+                if routed_event.output_port == TEXT_SINK_PORT {
+                    text_sink.write(routed_event.event.as_ref().unwrap_or(midi_event));
+                }
+            }
+        }
+        drop(text_sink);
+
+        assert_eq!(
+            String::from_utf8(join_handle.join().unwrap()).unwrap(),
+            "note-on ch1 60 vel100\nnote-on ch2 72 vel100\ncontrol-change ch3 ctrl2 64\nprogram-change ch1 5\nnote-off ch1 60 vel0\n",
+        );
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * Match events by the time of day, e.g. to change the routing of an installation at night
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+/// Interval at which `MinuteOfDayClock` reads the local time
+const MINUTE_OF_DAY_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Daily time window in minutes since midnight, which includes `start` and excludes `end`. The
+/// window crosses midnight if `end` is before `start`, e.g. 22:00-06:00.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeWindow {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, minute_of_day: u16) -> bool {
+        if self.start <= self.end {
+            minute_of_day >= self.start && minute_of_day < self.end
+        } else {
+            minute_of_day >= self.start || minute_of_day < self.end
+        }
+    }
+
+    /// Writes the window in config file syntax, e.g. `time09:00-17:00`
+    pub fn describe(&self) -> String {
+        format!(
+            "time{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60,
+        )
+    }
+}
+
+/// Local time of day in minutes since midnight, which a thread updates about once a second, so
+/// that the process cycle never calls into the time zone handling of libc
+pub struct MinuteOfDayClock {
+    minute_of_day: Arc<AtomicU16>,
+}
+
+impl MinuteOfDayClock {
+    /// Starts the thread updating the time, which ends when the clock is dropped
+    pub fn spawn() -> Self {
+        let minute_of_day = Arc::new(AtomicU16::new(current_minute_of_day()));
+        let published_minute = Arc::downgrade(&minute_of_day);
+        thread::spawn(move || loop {
+            thread::sleep(MINUTE_OF_DAY_UPDATE_INTERVAL);
+            let Some(published_minute) = published_minute.upgrade() else {
+                return;
+            };
+            published_minute.store(current_minute_of_day(), Ordering::Relaxed);
+        });
+        MinuteOfDayClock { minute_of_day }
+    }
+
+    pub fn minute_of_day(&self) -> u16 {
+        self.minute_of_day.load(Ordering::Relaxed)
+    }
+}
+
+/// Current local time of day in minutes since midnight, which falls back to UTC if the local
+/// time is unknown
+pub fn current_minute_of_day() -> u16 {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|since_epoch| since_epoch.as_secs()).unwrap_or(0);
+    local_minute_of_day(seconds).unwrap_or(((seconds / 60) % MINUTES_PER_DAY as u64) as u16)
+}
+
+#[cfg(unix)]
+fn local_minute_of_day(seconds: u64) -> Option<u16> {
+    let seconds = libc::time_t::try_from(seconds).ok()?;
+    // SAFETY: `tm` is plain data for which all zeros are valid, and `localtime_r` only writes to
+    // the given struct, unlike `localtime`, which shares one between threads
+    let mut local_time: libc::tm = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::localtime_r(&seconds, &mut local_time) };
+    if result.is_null() {
+        return None;
+    }
+    u16::try_from(local_time.tm_hour * 60 + local_time.tm_min).ok()
+}
+
+#[cfg(not(unix))]
+fn local_minute_of_day(_seconds: u64) -> Option<u16> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_window_contains() {
+        let office_hours = TimeWindow { start: 9 * 60, end: 17 * 60 };
+
+        assert!(office_hours.contains(9 * 60));
+        assert!(office_hours.contains(12 * 60 + 30));
+        assert!(!office_hours.contains(17 * 60));
+        assert!(!office_hours.contains(3 * 60));
+    }
+
+    #[test]
+    fn test_time_window_contains_across_midnight() {
+        let night = TimeWindow { start: 22 * 60, end: 6 * 60 };
+
+        assert!(night.contains(23 * 60));
+        assert!(night.contains(0));
+        assert!(night.contains(5 * 60 + 59));
+        assert!(!night.contains(6 * 60));
+        assert!(!night.contains(12 * 60));
+        assert_eq!(night.describe(), "time22:00-06:00");
+    }
+
+    #[test]
+    fn test_minute_of_day_clock() {
+        let clock = MinuteOfDayClock::spawn();
+
+        assert!(clock.minute_of_day() < MINUTES_PER_DAY);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_minute_of_day() {
+        let noon_utc = 19_000 * 24 * 60 * 60 + 12 * 60 * 60;
+        let minute_of_day = local_minute_of_day(noon_utc).unwrap();
+
+        assert!(minute_of_day < MINUTES_PER_DAY);
+        // time zones are offset from UTC by whole quarter hours
+        assert_eq!((minute_of_day + MINUTES_PER_DAY - 12 * 60) % 15, 0);
+    }
+}
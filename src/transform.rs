@@ -0,0 +1,627 @@
+/*
+ * Transformations of events applied by actions, which can be chained into a pipeline
+ */
+
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use crate::midi::MidiEvent;
+use crate::scale::Scale;
+
+const CHANNEL_COUNT: usize = 16;
+const CONTROLLER_COUNT: usize = 128;
+/// Marks a controller of which no value is known yet, since controller values are at most 127
+const NO_VALUE: u8 = u8::MAX;
+/// Smallest value of a switch controller which means on, e.g. a pressed footswitch
+const SWITCH_ON_MIN_VALUE: u8 = 64;
+
+/// Stage of a transform pipeline. Implement it to add transforms beyond the built-in ones.
+pub trait Transform: Debug + Send + Sync {
+    /// Returns the transformed event, or `None` to drop it. Events which the transform does not
+    /// apply to, e.g. control changes for a transposition, are returned unchanged.
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent>;
+
+    /// Returns the event `apply` would return without changing any state, e.g. for a dry run.
+    /// Transforms with state must override it.
+    fn preview(&self, event: MidiEvent) -> Option<MidiEvent> {
+        self.apply(event)
+    }
+}
+
+impl PartialEq for dyn Transform {
+    /// Transforms are only equal to themselves, since they may have state
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+#[derive(Debug)]
+pub struct Transpose {
+    pub semitones: i8,
+}
+
+impl Transform for Transpose {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(transpose(&event, self.semitones).unwrap_or(event))
+    }
+}
+
+#[derive(Debug)]
+pub struct RemapController {
+    pub from: u8,
+    pub to: u8,
+}
+
+impl Transform for RemapController {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(remap_controller(&event, self.from, self.to).unwrap_or(event))
+    }
+}
+
+#[derive(Debug)]
+pub struct BendToCc {
+    pub control_no: u8,
+}
+
+impl Transform for BendToCc {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(bend_to_cc(&event, self.control_no).unwrap_or(event))
+    }
+}
+
+#[derive(Debug)]
+pub struct CcToBend {
+    pub control_no: u8,
+}
+
+impl Transform for CcToBend {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(cc_to_bend(&event, self.control_no).unwrap_or(event))
+    }
+}
+
+#[derive(Debug)]
+pub struct SetChannel {
+    pub channel: u8,
+}
+
+impl Transform for SetChannel {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(set_channel(&event, self.channel).unwrap_or(event))
+    }
+}
+
+/// Scales the velocity of notes into a range by clamping it
+#[derive(Debug)]
+pub struct ClampVelocity {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl Transform for ClampVelocity {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(clamp_velocity(&event, self.min, self.max).unwrap_or(event))
+    }
+}
+
+#[derive(Debug)]
+pub struct QuantizeToScale {
+    pub scale: Scale,
+}
+
+impl Transform for QuantizeToScale {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(quantize_to_scale(&event, &self.scale).unwrap_or(event))
+    }
+}
+
+/// Reduces the resolution of control change values to multiples of `step`
+#[derive(Debug)]
+pub struct QuantizeValue {
+    pub step: u8,
+}
+
+impl Transform for QuantizeValue {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        Some(quantize_value(&event, self.step).unwrap_or(event))
+    }
+}
+
+/// Latched state of a momentary switch on each channel, which is flipped by each press
+#[derive(Debug, Default)]
+pub struct ToggleState {
+    latched: [AtomicBool; CHANNEL_COUNT],
+}
+
+impl ToggleState {
+    /// Flips the state of the channel if `value` is a press and returns the new value 127 or 0.
+    /// Returns `None` for releases and invalid channels.
+    fn press(&self, channel: u8, value: u8) -> Option<u8> {
+        let was_latched = self.latched_for_press(channel, value)?.fetch_xor(true, Ordering::Relaxed);
+        Some(if was_latched { 0 } else { 127 })
+    }
+
+    /// Returns the value which `press` would return without flipping the state
+    fn peek(&self, channel: u8, value: u8) -> Option<u8> {
+        let was_latched = self.latched_for_press(channel, value)?.load(Ordering::Relaxed);
+        Some(if was_latched { 0 } else { 127 })
+    }
+
+    fn latched_for_press(&self, channel: u8, value: u8) -> Option<&AtomicBool> {
+        let latched = self.latched.get((channel as usize).checked_sub(1)?)?;
+        (value >= SWITCH_ON_MIN_VALUE).then_some(latched)
+    }
+}
+
+impl PartialEq for ToggleState {
+    fn eq(&self, other: &Self) -> bool {
+        self.latched.iter()
+            .zip(&other.latched)
+            .all(|(latched, other_latched)| latched.load(Ordering::Relaxed) == other_latched.load(Ordering::Relaxed))
+    }
+}
+
+/// Values of each channel and controller for soft takeover, which are only forwarded once the
+/// incoming values reach the value last sent, i.e. the pickup point
+pub struct PickupState {
+    /// Last value sent by output channel and controller
+    sent: Box<[AtomicU8]>,
+    /// Last value received by incoming channel and controller, after the inner action
+    received: Box<[AtomicU8]>,
+}
+
+impl PickupState {
+    pub fn new() -> Self {
+        let no_values = || (0..CHANNEL_COUNT * CONTROLLER_COUNT).map(|_| AtomicU8::new(NO_VALUE)).collect();
+        PickupState { sent: no_values(), received: no_values() }
+    }
+
+    /// Checks whether `value` is to be sent as `output` controller, which is the case if nothing
+    /// was sent yet, or if the values received from the `input` controller reached or crossed the
+    /// value last sent since the previous one. Returns `false` for invalid channels.
+    pub fn pick_up(&self, input: (u8, u8), output: (u8, u8), value: u8) -> bool {
+        let (Some(received), Some(sent)) = (Self::value_of(&self.received, input), Self::value_of(&self.sent, output)) else {
+            return false;
+        };
+        let previous = received.swap(value, Ordering::Relaxed);
+        let pickup_point = sent.load(Ordering::Relaxed);
+        let picked_up = pickup_point == NO_VALUE || value == pickup_point
+            || (previous != NO_VALUE && (previous.min(value)..=previous.max(value)).contains(&pickup_point));
+        if picked_up {
+            sent.store(value, Ordering::Relaxed);
+        }
+        picked_up
+    }
+
+    fn value_of(values: &[AtomicU8], (channel, control_no): (u8, u8)) -> Option<&AtomicU8> {
+        let channel_idx = (channel as usize).checked_sub(1).filter(|&channel_idx| channel_idx < CHANNEL_COUNT)?;
+        values.get(channel_idx * CONTROLLER_COUNT + control_no as usize)
+    }
+}
+
+impl Debug for PickupState {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_struct("PickupState").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for PickupState {
+    fn eq(&self, other: &Self) -> bool {
+        let values_eq = |values: &[AtomicU8], other_values: &[AtomicU8]| values.iter()
+            .zip(other_values)
+            .all(|(value, other_value)| value.load(Ordering::Relaxed) == other_value.load(Ordering::Relaxed));
+        values_eq(&self.sent, &other.sent) && values_eq(&self.received, &other.received)
+    }
+}
+
+/// Turns a momentary switch into a latching one: each press of controller `control_no` sends
+/// 127 and 0 alternately, and releases are dropped
+#[derive(Debug)]
+pub struct ToggleSwitch {
+    pub control_no: u8,
+    pub state: ToggleState,
+}
+
+impl ToggleSwitch {
+    /// Replaces the value of presses of the switch by the one `latch` returns and drops releases
+    fn latch(&self, event: MidiEvent, latch: fn(&ToggleState, u8, u8) -> Option<u8>) -> Option<MidiEvent> {
+        match event {
+            MidiEvent::ControlChange { channel, control_no, value } if control_no == self.control_no => {
+                Some(MidiEvent::ControlChange { channel, control_no, value: latch(&self.state, channel, value)? })
+            },
+            event => Some(event),
+        }
+    }
+}
+
+impl Transform for ToggleSwitch {
+    fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+        self.latch(event, ToggleState::press)
+    }
+
+    fn preview(&self, event: MidiEvent) -> Option<MidiEvent> {
+        self.latch(event, ToggleState::peek)
+    }
+}
+
+/// Passes the event through all transforms in order, or drops it if any transform drops it
+pub fn apply_all(transforms: &[Box<dyn Transform>], event: MidiEvent) -> Option<MidiEvent> {
+    transforms.iter().try_fold(event, |event, transform| transform.apply(event))
+}
+
+/// Same as `apply_all`, but without changing the state of any transform
+pub fn preview_all(transforms: &[Box<dyn Transform>], event: MidiEvent) -> Option<MidiEvent> {
+    transforms.iter().try_fold(event, |event, transform| transform.preview(event))
+}
+
+/// Shifts the note of note events by the given number of semitones, clamped to the valid note range
+fn transpose(midi_event: &MidiEvent, semitones: i8) -> Option<MidiEvent> {
+    let shift = |note: u8| (note as i16 + semitones as i16).clamp(0, 127) as u8;
+    match *midi_event {
+        MidiEvent::NoteOff { channel, note, velocity } => {
+            Some(MidiEvent::NoteOff { channel, note: shift(note), velocity })
+        },
+        MidiEvent::NoteOn { channel, note, velocity } => {
+            Some(MidiEvent::NoteOn { channel, note: shift(note), velocity })
+        },
+        MidiEvent::PolyphonicAftertouch { channel, note, pressure } => {
+            Some(MidiEvent::PolyphonicAftertouch { channel, note: shift(note), pressure })
+        },
+        _ => None,
+    }
+}
+
+/// Snaps the note of note events to the nearest note in the scale. Since a note always snaps to
+/// the same note, the note-off event releases the snapped note.
+fn quantize_to_scale(midi_event: &MidiEvent, scale: &Scale) -> Option<MidiEvent> {
+    match *midi_event {
+        MidiEvent::NoteOff { channel, note, velocity } => {
+            Some(MidiEvent::NoteOff { channel, note: scale.quantize(note), velocity })
+        },
+        MidiEvent::NoteOn { channel, note, velocity } => {
+            Some(MidiEvent::NoteOn { channel, note: scale.quantize(note), velocity })
+        },
+        MidiEvent::PolyphonicAftertouch { channel, note, pressure } => {
+            Some(MidiEvent::PolyphonicAftertouch { channel, note: scale.quantize(note), pressure })
+        },
+        _ => None,
+    }
+}
+
+/// Rounds the value of control changes down to a multiple of `step`
+fn quantize_value(midi_event: &MidiEvent, step: u8) -> Option<MidiEvent> {
+    match *midi_event {
+        MidiEvent::ControlChange { channel, control_no, value } if step > 0 => {
+            Some(MidiEvent::ControlChange { channel, control_no, value: value - value % step })
+        },
+        _ => None,
+    }
+}
+
+/// Changes the controller number of control changes of controller `from` to `to`
+fn remap_controller(midi_event: &MidiEvent, from: u8, to: u8) -> Option<MidiEvent> {
+    match *midi_event {
+        MidiEvent::ControlChange { channel, control_no, value } if control_no == from => {
+            Some(MidiEvent::ControlChange { channel, control_no: to, value })
+        },
+        _ => None,
+    }
+}
+
+/// Converts a pitch bend to a control change, so that the center maps to 64 and the extremes to 0
+/// and 127
+fn bend_to_cc(midi_event: &MidiEvent, control_no: u8) -> Option<MidiEvent> {
+    match *midi_event {
+        MidiEvent::PitchBendChange { channel, value } => {
+            let value = ((value.clamp(-8192, 8191) + 8192) >> 7) as u8;
+            Some(MidiEvent::ControlChange { channel, control_no, value })
+        },
+        _ => None,
+    }
+}
+
+/// Converts a control change of `control_no` to a pitch bend, so that 64 maps to the center and 0
+/// and 127 to the extremes. This is the inverse of `bend_to_cc`.
+fn cc_to_bend(midi_event: &MidiEvent, control_no: u8) -> Option<MidiEvent> {
+    match *midi_event {
+        MidiEvent::ControlChange { channel, control_no: event_control_no, value } if event_control_no == control_no => {
+            let offset = value.min(127) as i32 - 64;
+            let value = match offset < 0 {
+                true => offset * 128,
+                false => offset * 8191 / 63,
+            } as i16;
+            Some(MidiEvent::PitchBendChange { channel, value })
+        },
+        _ => None,
+    }
+}
+
+/// Moves channel messages to the given channel. System messages have no channel and are not
+/// changed.
+fn set_channel(midi_event: &MidiEvent, channel: u8) -> Option<MidiEvent> {
+    let mut midi_event = midi_event.clone();
+    match &mut midi_event {
+        MidiEvent::NoteOff { channel: event_channel, .. } |
+        MidiEvent::NoteOn { channel: event_channel, .. } |
+        MidiEvent::PolyphonicAftertouch { channel: event_channel, .. } |
+        MidiEvent::ControlChange { channel: event_channel, .. } |
+        MidiEvent::ProgramChange { channel: event_channel, .. } |
+        MidiEvent::ChannelAftertouch { channel: event_channel, .. } |
+        MidiEvent::PitchBendChange { channel: event_channel, .. } => *event_channel = channel,
+        _ => return None,
+    }
+    Some(midi_event)
+}
+
+/// Limits the velocity of note events to the given range. Note-on events with velocity 0 are kept
+/// unchanged, because they release the note.
+fn clamp_velocity(midi_event: &MidiEvent, min: u8, max: u8) -> Option<MidiEvent> {
+    match *midi_event {
+        MidiEvent::NoteOff { channel, note, velocity } => {
+            Some(MidiEvent::NoteOff { channel, note, velocity: velocity.clamp(min, max) })
+        },
+        MidiEvent::NoteOn { channel, note, velocity } if velocity > 0 => {
+            Some(MidiEvent::NoteOn { channel, note, velocity: velocity.clamp(min, max) })
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transpose() {
+        let note_on = |note| MidiEvent::NoteOn { channel: 1, note, velocity: 100 };
+        assert_eq!(transpose(&note_on(60), 12), Some(note_on(72)));
+        assert_eq!(transpose(&note_on(60), -12), Some(note_on(48)));
+        assert_eq!(transpose(&note_on(120), 12), Some(note_on(127)));
+        assert_eq!(transpose(&note_on(5), -12), Some(note_on(0)));
+        assert_eq!(
+            transpose(&MidiEvent::PolyphonicAftertouch { channel: 2, note: 40, pressure: 3 }, 1),
+            Some(MidiEvent::PolyphonicAftertouch { channel: 2, note: 41, pressure: 3 }),
+        );
+        assert_eq!(transpose(&MidiEvent::ControlChange { channel: 1, control_no: 1, value: 60 }, 12), None);
+    }
+
+    #[test]
+    fn test_bend_to_cc() {
+        let pitch_bend = |value| MidiEvent::PitchBendChange { channel: 2, value };
+        let control_change = |value| MidiEvent::ControlChange { channel: 2, control_no: 1, value };
+        assert_eq!(bend_to_cc(&pitch_bend(-8192), 1), Some(control_change(0)));
+        assert_eq!(bend_to_cc(&pitch_bend(0), 1), Some(control_change(64)));
+        assert_eq!(bend_to_cc(&pitch_bend(8191), 1), Some(control_change(127)));
+        assert_eq!(bend_to_cc(&MidiEvent::ProgramChange { channel: 2, program: 5 }, 1), None);
+    }
+
+    #[test]
+    fn test_cc_to_bend() {
+        let control_change = |control_no, value| MidiEvent::ControlChange { channel: 2, control_no, value };
+        let pitch_bend = |value| MidiEvent::PitchBendChange { channel: 2, value };
+        assert_eq!(cc_to_bend(&control_change(1, 0), 1), Some(pitch_bend(-8192)));
+        assert_eq!(cc_to_bend(&control_change(1, 64), 1), Some(pitch_bend(0)));
+        assert_eq!(cc_to_bend(&control_change(1, 127), 1), Some(pitch_bend(8191)));
+        assert_eq!(cc_to_bend(&control_change(1, 32), 1), Some(pitch_bend(-4096)));
+        assert_eq!(cc_to_bend(&control_change(7, 64), 1), None);
+        assert_eq!(cc_to_bend(&MidiEvent::ProgramChange { channel: 2, program: 5 }, 1), None);
+        for value in 0..=127 {
+            assert_eq!(bend_to_cc(&cc_to_bend(&control_change(1, value), 1).unwrap(), 1), Some(control_change(1, value)));
+        }
+    }
+
+    #[test]
+    fn test_set_channel() {
+        assert_eq!(
+            set_channel(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }, 3),
+            Some(MidiEvent::NoteOn { channel: 3, note: 60, velocity: 100 }),
+        );
+        assert_eq!(
+            set_channel(&MidiEvent::PitchBendChange { channel: 16, value: -5 }, 1),
+            Some(MidiEvent::PitchBendChange { channel: 1, value: -5 }),
+        );
+        assert_eq!(set_channel(&MidiEvent::TimingClock {}, 3), None);
+    }
+
+    #[test]
+    fn test_clamp_velocity() {
+        let note_on = |velocity| MidiEvent::NoteOn { channel: 1, note: 60, velocity };
+        assert_eq!(clamp_velocity(&note_on(10), 30, 110), Some(note_on(30)));
+        assert_eq!(clamp_velocity(&note_on(127), 30, 110), Some(note_on(110)));
+        assert_eq!(clamp_velocity(&note_on(64), 30, 110), Some(note_on(64)));
+        assert_eq!(clamp_velocity(&note_on(0), 30, 110), None);
+        assert_eq!(
+            clamp_velocity(&MidiEvent::NoteOff { channel: 3, note: 60, velocity: 5 }, 30, 110),
+            Some(MidiEvent::NoteOff { channel: 3, note: 60, velocity: 30 }),
+        );
+        assert_eq!(clamp_velocity(&MidiEvent::ControlChange { channel: 1, control_no: 7, value: 5 }, 30, 110), None);
+    }
+
+    #[test]
+    fn test_transpose_transform() {
+        let note_on = |note| MidiEvent::NoteOn { channel: 1, note, velocity: 100 };
+        let control_change = MidiEvent::ControlChange { channel: 1, control_no: 1, value: 60 };
+        let transform = Transpose { semitones: 12 };
+
+        assert_eq!(transform.apply(note_on(60)), Some(note_on(72)));
+        assert_eq!(transform.apply(control_change.clone()), Some(control_change));
+    }
+
+    #[test]
+    fn test_remap_controller_transform() {
+        let control_change = |control_no| MidiEvent::ControlChange { channel: 1, control_no, value: 60 };
+        let transform = RemapController { from: 1, to: 11 };
+
+        assert_eq!(transform.apply(control_change(1)), Some(control_change(11)));
+        assert_eq!(transform.apply(control_change(7)), Some(control_change(7)));
+    }
+
+    #[test]
+    fn test_bend_to_cc_transform() {
+        let transform = BendToCc { control_no: 74 };
+
+        assert_eq!(
+            transform.apply(MidiEvent::PitchBendChange { channel: 1, value: 0 }),
+            Some(MidiEvent::ControlChange { channel: 1, control_no: 74, value: 64 }),
+        );
+        assert_eq!(
+            transform.apply(MidiEvent::ProgramChange { channel: 1, program: 5 }),
+            Some(MidiEvent::ProgramChange { channel: 1, program: 5 }),
+        );
+    }
+
+    #[test]
+    fn test_clamp_velocity_transform() {
+        let note_on = |velocity| MidiEvent::NoteOn { channel: 1, note: 60, velocity };
+        let transform = ClampVelocity { min: 30, max: 110 };
+
+        assert_eq!(transform.apply(note_on(10)), Some(note_on(30)));
+        assert_eq!(transform.apply(note_on(0)), Some(note_on(0)));
+    }
+
+    #[test]
+    fn test_quantize_to_scale() {
+        let c_major: Scale = "Cmaj".parse().unwrap();
+
+        assert_eq!(
+            quantize_to_scale(&MidiEvent::NoteOn { channel: 1, note: 61, velocity: 100 }, &c_major),
+            Some(MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }),
+        );
+        assert_eq!(
+            quantize_to_scale(&MidiEvent::NoteOff { channel: 1, note: 61, velocity: 0 }, &c_major),
+            Some(MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }),
+        );
+        assert_eq!(quantize_to_scale(&MidiEvent::ControlChange { channel: 1, control_no: 61, value: 1 }, &c_major), None);
+    }
+
+    #[test]
+    fn test_quantize_to_scale_transform() {
+        let transform = QuantizeToScale { scale: "Dmaj".parse().unwrap() };
+
+        assert_eq!(
+            transform.apply(MidiEvent::NoteOn { channel: 1, note: 65, velocity: 100 }),
+            Some(MidiEvent::NoteOn { channel: 1, note: 64, velocity: 100 }),
+        );
+        assert_eq!(
+            transform.apply(MidiEvent::ProgramChange { channel: 1, program: 65 }),
+            Some(MidiEvent::ProgramChange { channel: 1, program: 65 }),
+        );
+    }
+
+    #[test]
+    fn test_toggle_state() {
+        let state = ToggleState::default();
+
+        assert_eq!(state.press(1, 127), Some(127));
+        assert_eq!(state.press(1, 0), None);
+        assert_eq!(state.press(1, 127), Some(0));
+        assert_eq!(state.press(1, 0), None);
+        assert_eq!(state.press(1, 64), Some(127));
+        assert_eq!(state.press(2, 127), Some(127));
+        assert_eq!(state.press(0, 127), None);
+        assert_eq!(state.press(17, 127), None);
+    }
+
+    #[test]
+    fn test_pickup_state_below_crossing_and_above() {
+        let state = PickupState::new();
+        let (bank_a, bank_b, bank_c, output) = ((1, 7), (2, 7), (3, 7), (1, 7));
+
+        assert!(state.pick_up(bank_a, output, 64));
+        // below the pickup point 64
+        assert!(!state.pick_up(bank_b, output, 20));
+        assert!(!state.pick_up(bank_b, output, 40));
+        // crossing it
+        assert!(state.pick_up(bank_b, output, 70));
+        assert!(state.pick_up(bank_b, output, 75));
+        // bank A is still below the new pickup point 75 until it crosses it
+        assert!(!state.pick_up(bank_a, output, 70));
+        assert!(state.pick_up(bank_a, output, 80));
+        // above the pickup point 80 until reaching it
+        assert!(!state.pick_up(bank_c, output, 100));
+        assert!(!state.pick_up(bank_c, output, 90));
+        assert!(state.pick_up(bank_c, output, 80));
+        assert!(state.pick_up(bank_c, output, 60));
+    }
+
+    #[test]
+    fn test_pickup_state_invalid_channel() {
+        let state = PickupState::new();
+
+        assert!(!state.pick_up((0, 7), (1, 7), 64));
+        assert!(!state.pick_up((1, 7), (17, 7), 64));
+        assert!(state.pick_up((16, 127), (16, 127), 64));
+    }
+
+    #[test]
+    fn test_toggle_switch_transform() {
+        let control_change = |control_no, value| MidiEvent::ControlChange { channel: 1, control_no, value };
+        let transform = ToggleSwitch { control_no: 64, state: ToggleState::default() };
+
+        assert_eq!(transform.apply(control_change(64, 127)), Some(control_change(64, 127)));
+        assert_eq!(transform.apply(control_change(64, 0)), None);
+        assert_eq!(transform.apply(control_change(64, 127)), Some(control_change(64, 0)));
+        assert_eq!(transform.apply(control_change(7, 0)), Some(control_change(7, 0)));
+    }
+
+    #[test]
+    fn test_quantize_value() {
+        let control_change = |value| MidiEvent::ControlChange { channel: 1, control_no: 7, value };
+
+        for value in 0..16 {
+            assert_eq!(quantize_value(&control_change(value), 16), Some(control_change(0)));
+        }
+        for value in 16..32 {
+            assert_eq!(quantize_value(&control_change(value), 16), Some(control_change(16)));
+        }
+        assert_eq!(quantize_value(&control_change(127), 16), Some(control_change(112)));
+        assert_eq!(quantize_value(&MidiEvent::ProgramChange { channel: 1, program: 5 }, 16), None);
+    }
+
+    #[test]
+    fn test_quantize_value_transform() {
+        let transform = QuantizeValue { step: 8 };
+
+        assert_eq!(
+            transform.apply(MidiEvent::ControlChange { channel: 1, control_no: 7, value: 13 }),
+            Some(MidiEvent::ControlChange { channel: 1, control_no: 7, value: 8 }),
+        );
+        assert_eq!(
+            transform.apply(MidiEvent::NoteOn { channel: 1, note: 13, velocity: 100 }),
+            Some(MidiEvent::NoteOn { channel: 1, note: 13, velocity: 100 }),
+        );
+    }
+
+    #[test]
+    fn test_transform_equality() {
+        let toggle: Box<dyn Transform> = Box::new(ToggleSwitch { control_no: 64, state: ToggleState::default() });
+        let other_toggle: Box<dyn Transform> = Box::new(ToggleSwitch { control_no: 64, state: ToggleState::default() });
+
+        assert!(*toggle == *toggle);
+        assert!(*toggle != *other_toggle);
+    }
+
+    #[test]
+    fn test_apply_all() {
+        #[derive(Debug)]
+        struct DropNoteOff;
+        impl Transform for DropNoteOff {
+            fn apply(&self, event: MidiEvent) -> Option<MidiEvent> {
+                match event {
+                    MidiEvent::NoteOff { .. } => None,
+                    event => Some(event),
+                }
+            }
+        }
+        let transforms: Vec<Box<dyn Transform>> = vec![
+            Box::new(Transpose { semitones: -12 }),
+            Box::new(ClampVelocity { min: 1, max: 64 }),
+            Box::new(DropNoteOff),
+        ];
+
+        assert_eq!(
+            apply_all(&transforms, MidiEvent::NoteOn { channel: 1, note: 60, velocity: 100 }),
+            Some(MidiEvent::NoteOn { channel: 1, note: 48, velocity: 64 }),
+        );
+        assert_eq!(apply_all(&transforms, MidiEvent::NoteOff { channel: 1, note: 60, velocity: 0 }), None);
+    }
+}
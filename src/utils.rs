@@ -2,6 +2,13 @@
  * Utilities
  */
 
+use std::time::Instant;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
 /// Indents every line of a string s by n spaces
 pub fn indent(s: String, n: usize) -> String {
     let spaces = " ".repeat(n);
@@ -9,6 +16,11 @@ pub fn indent(s: String, n: usize) -> String {
     s.replace("\n", replacement.as_str())
 }
 
+/// Milliseconds elapsed on a monotonic clock since the first call of this function
+pub fn now_ms() -> u64 {
+    START_TIME.elapsed().as_millis() as u64
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::indent;
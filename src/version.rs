@@ -0,0 +1,37 @@
+/*
+ * Version and build information
+ */
+
+use crate::parser::SUPPORTED_CONFIG_VERSIONS;
+
+/// Cargo features which are enabled in this build
+const ENABLED_FEATURES: [&str; 0] = [];
+
+pub fn version_info() -> String {
+    let features = if ENABLED_FEATURES.is_empty() {
+        "none".to_string()
+    } else {
+        ENABLED_FEATURES.join(", ")
+    };
+    format!(
+        "{} {}\nSupported config versions: {}\nEnabled features: {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        SUPPORTED_CONFIG_VERSIONS.join(", "),
+        features,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_info() {
+        let info = version_info();
+
+        assert!(info.starts_with(&format!("midi_router {}\n", env!("CARGO_PKG_VERSION"))), "Unexpected info: {}", info);
+        assert!(info.contains("Supported config versions: 1\n"), "Unexpected info: {}", info);
+        assert!(info.ends_with("Enabled features: none"), "Unexpected info: {}", info);
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * Track held notes per channel to number the voices of a chord from the lowest note upwards
+ */
+
+use crate::midi::MidiEvent;
+
+const CHANNEL_COUNT: usize = 16;
+const NOTE_COUNT: usize = 128;
+
+pub struct VoiceTracker {
+    /// Voice assigned to each held note by channel and note
+    voices: [[Option<u8>; NOTE_COUNT]; CHANNEL_COUNT],
+}
+
+impl VoiceTracker {
+    pub fn new() -> Self {
+        VoiceTracker {
+            voices: [[None; NOTE_COUNT]; CHANNEL_COUNT],
+        }
+    }
+
+    /// Records note events and returns the voice of the note, which is its position among the
+    /// held notes of the channel counted from the lowest note, starting at 1. A note keeps its
+    /// voice until it is released, so that the note-off follows the note-on.
+    pub fn track(&mut self, midi_event: &MidiEvent) -> Option<u8> {
+        match *midi_event {
+            MidiEvent::NoteOn { channel, note, velocity } if velocity > 0 => {
+                let voices = self.voices.get_mut((channel as usize).checked_sub(1)?)?;
+                let lower_note_count = voices.get(..note as usize)?.iter().filter(|voice| voice.is_some()).count();
+                let voice = lower_note_count as u8 + 1;
+                *voices.get_mut(note as usize)? = Some(voice);
+                Some(voice)
+            },
+            MidiEvent::NoteOn { channel, note, .. } |
+            MidiEvent::NoteOff { channel, note, .. } => self.voices
+                .get_mut((channel as usize).checked_sub(1)?)?
+                .get_mut(note as usize)?
+                .take(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_tracker_chord() {
+        let mut tracker = VoiceTracker::new();
+        let note_on = |note| MidiEvent::NoteOn { channel: 1, note, velocity: 100 };
+        let note_off = |note| MidiEvent::NoteOff { channel: 1, note, velocity: 0 };
+
+        assert_eq!(tracker.track(&note_on(64)), Some(1));
+        assert_eq!(tracker.track(&note_on(67)), Some(2));
+        assert_eq!(tracker.track(&note_on(60)), Some(1));
+        assert_eq!(tracker.track(&note_off(67)), Some(2));
+        assert_eq!(tracker.track(&note_off(67)), None);
+        assert_eq!(tracker.track(&note_on(72)), Some(3));
+        assert_eq!(tracker.track(&MidiEvent::NoteOn { channel: 1, note: 60, velocity: 0 }), Some(1));
+        assert_eq!(tracker.track(&MidiEvent::NoteOn { channel: 2, note: 72, velocity: 100 }), Some(1));
+        assert_eq!(tracker.track(&MidiEvent::ProgramChange { channel: 1, program: 5 }), None);
+    }
+
+    #[test]
+    fn test_voice_tracker_invalid_note() {
+        let mut tracker = VoiceTracker::new();
+
+        assert_eq!(tracker.track(&MidiEvent::NoteOn { channel: 0, note: 60, velocity: 1 }), None);
+        assert_eq!(tracker.track(&MidiEvent::NoteOn { channel: 1, note: 200, velocity: 1 }), None);
+    }
+}
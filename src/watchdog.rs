@@ -0,0 +1,179 @@
+/*
+ * Watchdogs terminating the router after a period without MIDI activity or without a
+ * connection to the input port
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use log::{debug, info, warn};
+use crate::utils::now_ms;
+
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct IdleWatchdog {
+    last_event_ms: Arc<AtomicU64>,
+    timeout: Duration,
+}
+
+impl IdleWatchdog {
+    pub fn new(last_event_ms: Arc<AtomicU64>, timeout: Duration) -> Self {
+        IdleWatchdog {
+            last_event_ms,
+            timeout,
+        }
+    }
+
+    /// Checks whether no event was received within the timeout before `now_ms`
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        let last_event_ms = self.last_event_ms.load(Ordering::Relaxed);
+        now_ms.saturating_sub(last_event_ms) >= self.timeout.as_millis() as u64
+    }
+
+    fn poll_interval(&self) -> Duration {
+        (self.timeout / 4).clamp(Duration::from_millis(1), MAX_POLL_INTERVAL)
+    }
+
+    /// Starts a thread which calls `on_expired` once the idle timeout is reached
+    pub fn spawn<F: FnOnce() + Send + 'static>(self, on_expired: F) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let poll_interval = self.poll_interval();
+            while !self.is_expired(now_ms()) {
+                thread::sleep(poll_interval);
+            }
+            info!("No MIDI events received for {:?}", self.timeout);
+            on_expired();
+        })
+    }
+}
+
+/// Check whether the input port is connected once a grace period after the start has passed,
+/// which gives auto-connect scripts and session managers time to connect it
+pub struct InputConnectionCheck<F: Fn() -> usize> {
+    connection_count: F,
+    grace_period: Duration,
+}
+
+impl<F: Fn() -> usize + Send + 'static> InputConnectionCheck<F> {
+    pub fn new(connection_count: F, grace_period: Duration) -> Self {
+        InputConnectionCheck {
+            connection_count,
+            grace_period,
+        }
+    }
+
+    /// Checks whether the input port has any connections and warns if not
+    pub fn check(&self) -> bool {
+        let connection_count = (self.connection_count)();
+        if connection_count == 0 {
+            warn!("The MIDI input port has no connections after {:?}, so no events are routed", self.grace_period);
+            false
+        } else {
+            debug!("The MIDI input port has {} connections", connection_count);
+            true
+        }
+    }
+
+    /// Starts a thread which checks the connections after the grace period and calls
+    /// `on_unconnected` if there are none. The check is skipped if the handle is cancelled before.
+    pub fn spawn<G: FnOnce() + Send + 'static>(self, on_unconnected: G) -> InputCheckHandle {
+        let (cancel_sender, cancel_receiver) = mpsc::channel::<()>();
+        let join_handle = thread::spawn(move || {
+            if cancel_receiver.recv_timeout(self.grace_period) == Err(RecvTimeoutError::Timeout) && !self.check() {
+                on_unconnected();
+            }
+        });
+        InputCheckHandle { cancel_sender, join_handle }
+    }
+}
+
+pub struct InputCheckHandle {
+    cancel_sender: Sender<()>,
+    join_handle: JoinHandle<()>,
+}
+
+impl InputCheckHandle {
+    /// Skips the check if it is still pending and waits for the thread, so that the JACK client
+    /// can be closed afterwards
+    pub fn cancel(self) {
+        drop(self.cancel_sender);
+        self.join_handle.join().unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use super::*;
+
+    fn create_watchdog(last_event_ms: u64, timeout_ms: u64) -> IdleWatchdog {
+        IdleWatchdog::new(
+            Arc::new(AtomicU64::new(last_event_ms)),
+            Duration::from_millis(timeout_ms),
+        )
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let watchdog = create_watchdog(1000, 500);
+        assert!(!watchdog.is_expired(1000));
+        assert!(!watchdog.is_expired(1499));
+        assert!(watchdog.is_expired(1500));
+        assert!(watchdog.is_expired(10000));
+    }
+
+    #[test]
+    fn test_is_expired_reset_by_new_event() {
+        let watchdog = create_watchdog(1000, 500);
+        assert!(watchdog.is_expired(1600));
+
+        watchdog.last_event_ms.store(1400, Ordering::Relaxed);
+        assert!(!watchdog.is_expired(1600));
+    }
+
+    #[test]
+    fn test_is_expired_event_after_now() {
+        let watchdog = create_watchdog(2000, 500);
+        assert!(!watchdog.is_expired(1000));
+    }
+
+    #[test]
+    fn test_spawn_calls_on_expired() {
+        let watchdog = create_watchdog(now_ms(), 10);
+        let (sender, receiver) = mpsc::channel();
+        let handle = watchdog.spawn(move || sender.send(()).unwrap());
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_input_connection_check() {
+        assert!(!InputConnectionCheck::new(|| 0, Duration::ZERO).check());
+        assert!(InputConnectionCheck::new(|| 1, Duration::ZERO).check());
+        assert!(InputConnectionCheck::new(|| 3, Duration::ZERO).check());
+    }
+
+    #[test]
+    fn test_input_connection_check_spawn_calls_on_unconnected() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = InputConnectionCheck::new(|| 0, Duration::from_millis(10))
+            .spawn(move || sender.send(()).unwrap());
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        handle.cancel();
+    }
+
+    #[test]
+    fn test_input_connection_check_cancelled() {
+        let (sender, receiver) = mpsc::channel();
+        InputConnectionCheck::new(|| 0, Duration::from_secs(60))
+            .spawn(move || sender.send(()).unwrap())
+            .cancel();
+
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,162 @@
+/*
+ * Round-trip tests sending MIDI events through a running router with a real JACK server
+ *
+ * These tests are skipped unless MIDI_ROUTER_JACK_TEST is set, since they need a JACK server,
+ * e.g. one started with the dummy backend:
+ *
+ *     jackd -d dummy &
+ *     MIDI_ROUTER_JACK_TEST=1 cargo test --test jack_roundtrip -- --test-threads 1
+ */
+
+use std::env;
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use jack::{AsyncClient, Client, ClientOptions, ClosureProcessHandler, Control, MidiIn, MidiOut, ProcessHandler, ProcessScope, RawMidi};
+use tempfile::NamedTempFile;
+
+const ENABLE_VAR: &str = "MIDI_ROUTER_JACK_TEST";
+const ROUTER_NAME: &str = "midi_router";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn jack_tests_enabled() -> bool {
+    let enabled = env::var_os(ENABLE_VAR).is_some();
+    if !enabled {
+        eprintln!("Skipping JACK round-trip test, set {} to run it", ENABLE_VAR);
+    }
+    enabled
+}
+
+/// Router process running with the given config, which is stopped when dropped
+struct RouterProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    _config_file: NamedTempFile,
+}
+
+impl RouterProcess {
+    fn start(config: &str) -> Self {
+        let mut config_file = NamedTempFile::new().unwrap();
+        config_file.write_all(config.as_bytes()).unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_midi_router"))
+            .arg(config_file.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to run midi_router");
+        let stdin = child.stdin.take();
+        RouterProcess { child, stdin, _config_file: config_file }
+    }
+}
+
+impl Drop for RouterProcess {
+    fn drop(&mut self) {
+        if let Some(mut stdin) = self.stdin.take() {
+            writeln!(stdin, "q").unwrap_or_default();
+        }
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        self.child.kill().unwrap_or_default();
+    }
+}
+
+/// JACK client with one output port feeding the router and one input port receiving from it
+struct TestClient<P> {
+    client: AsyncClient<(), P>,
+    outgoing: Arc<Mutex<Vec<Vec<u8>>>>,
+    incoming: mpsc::Receiver<Vec<u8>>,
+}
+
+/// Starts a test client with the given name, which must be unique on the JACK server
+fn start_test_client(name: &str) -> TestClient<impl ProcessHandler> {
+    let (client, _status) = Client::new(name, ClientOptions::NO_START_SERVER)
+        .expect("Failed to connect to the JACK server");
+    let mut out_port = client.register_port("out", MidiOut::default()).unwrap();
+    let in_port = client.register_port("in", MidiIn::default()).unwrap();
+    let outgoing: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+    let (sender, incoming) = mpsc::channel();
+
+    let process_outgoing = Arc::clone(&outgoing);
+    let process = move |_: &Client, ps: &ProcessScope| -> Control {
+        let mut writer = out_port.writer(ps);
+        if let Ok(mut outgoing) = process_outgoing.try_lock() {
+            for bytes in outgoing.drain(..) {
+                writer.write(&RawMidi { time: 0, bytes: &bytes }).unwrap_or_default();
+            }
+        }
+        for raw_event in in_port.iter(ps) {
+            sender.send(raw_event.bytes.to_vec()).unwrap_or_default();
+        }
+        Control::Continue
+    };
+    let client = client.activate_async((), ClosureProcessHandler::new(process)).unwrap();
+    TestClient { client, outgoing, incoming }
+}
+
+impl<P> TestClient<P> {
+    fn name(&self) -> String {
+        self.client.as_client().name().to_string()
+    }
+
+    /// Connects this client to the input port and the given output port of the router
+    fn connect_router(&self, router_output: &str) {
+        let client = self.client.as_client();
+        let router_input = format!("{}:midi_in", ROUTER_NAME);
+        let router_output = format!("{}:{}", ROUTER_NAME, router_output);
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while client.port_by_name(&router_input).is_none() || client.port_by_name(&router_output).is_none() {
+            assert!(Instant::now() < deadline, "Router ports did not appear");
+            thread::sleep(Duration::from_millis(50));
+        }
+        client.connect_ports_by_name(&format!("{}:out", self.name()), &router_input).unwrap();
+        client.connect_ports_by_name(&router_output, &format!("{}:in", self.name())).unwrap();
+    }
+
+    fn send(&self, bytes: &[u8]) {
+        self.outgoing.lock().unwrap().push(bytes.to_vec());
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        self.incoming.recv_timeout(RECEIVE_TIMEOUT).ok()
+    }
+}
+
+#[test]
+fn test_roundtrip_forward() {
+    if !jack_tests_enabled() {
+        return;
+    }
+    let _router = RouterProcess::start("note-.* ch1 => synth\n");
+    let test_client = start_test_client("roundtrip_forward");
+    test_client.connect_router("synth");
+
+    test_client.send(&[0x90, 60, 100]);
+    test_client.send(&[0x80, 60, 0]);
+
+    assert_eq!(test_client.receive(), Some(vec![0x90, 60, 100]));
+    assert_eq!(test_client.receive(), Some(vec![0x80, 60, 0]));
+}
+
+#[test]
+fn test_roundtrip_drop() {
+    if !jack_tests_enabled() {
+        return;
+    }
+    let _router = RouterProcess::start("note-on ch1 => synth\nnote-on ch2 => drop\n");
+    let test_client = start_test_client("roundtrip_drop");
+    test_client.connect_router("synth");
+
+    test_client.send(&[0x91, 60, 100]);
+    assert_eq!(test_client.receive(), None);
+
+    test_client.send(&[0x90, 62, 100]);
+    assert_eq!(test_client.receive(), Some(vec![0x90, 62, 100]));
+}
@@ -0,0 +1,44 @@
+use std::io::Write;
+use std::process::{Command, Output};
+use tempfile::NamedTempFile;
+
+fn run_lint(config: &str, args: &[&str]) -> Output {
+    let mut config_file = NamedTempFile::new().unwrap();
+    config_file.write_all(config.as_bytes()).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_midi_router"))
+        .arg("lint")
+        .arg(config_file.path())
+        .args(args)
+        .output()
+        .expect("Failed to run midi_router")
+}
+
+#[test]
+fn test_lint_clean_config() {
+    let output = run_lint("note-.* ch1 => synth\n", &["--deny-warnings"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0 errors, 0 warnings\n");
+}
+
+#[test]
+fn test_lint_warning_only_config() {
+    let config = "note-.* => synth\nnote-.* => synth\n";
+
+    assert_eq!(run_lint(config, &[]).status.code(), Some(0));
+    let output = run_lint(config, &["--deny-warnings", "--format", "json"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{\"errors\":0,\"warnings\":1,\"findings\":[{\"severity\":\"warning\",\
+\"message\":\"Rule 2 duplicates rule 1, so matching events are sent twice\"}]}\n",
+    );
+}
+
+#[test]
+fn test_lint_error_config() {
+    let output = run_lint("note-.* ch300 => synth\n", &[]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("error: Invalid field in line 1"));
+}
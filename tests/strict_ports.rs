@@ -0,0 +1,38 @@
+use std::io::Write;
+use std::process::{Command, Output};
+use tempfile::{NamedTempFile, TempDir};
+
+fn export_dot(config: &str, args: &[&str]) -> Output {
+    let mut config_file = NamedTempFile::new().unwrap();
+    config_file.write_all(config.as_bytes()).unwrap();
+    let dot_dir = TempDir::new().unwrap();
+    Command::new(env!("CARGO_BIN_EXE_midi_router"))
+        .arg(config_file.path())
+        .arg("--export-dot")
+        .arg(dot_dir.path().join("routing.dot"))
+        .args(args)
+        .output()
+        .expect("Failed to run midi_router")
+}
+
+#[test]
+fn test_undeclared_port_with_strict_ports() {
+    let output = export_dot("ports: synth\nnote-on => synth drums\n", &["--strict-ports"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Port is not declared"));
+}
+
+#[test]
+fn test_undeclared_port_without_strict_ports() {
+    let output = export_dot("ports: synth\nnote-on => synth drums\n", &[]);
+
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_declared_ports_with_strict_ports() {
+    let output = export_dot("ports: synth\nports: drums\nnote-on => synth drums\n", &["--strict-ports"]);
+
+    assert_eq!(output.status.code(), Some(0));
+}
@@ -0,0 +1,35 @@
+use std::process::{Command, Output};
+
+fn run_test_rule(rule: &str, event: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_midi_router"))
+        .args(["--test-rule", rule, "--event", event])
+        .output()
+        .expect("Failed to run midi_router")
+}
+
+#[test]
+fn test_rule_matching_event() {
+    let output = run_test_rule("note-.* ch1 => out", "note-on ch1 note60 vel100");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "out <- NoteOn { channel: 1, note: 60, velocity: 100 }\n",
+    );
+}
+
+#[test]
+fn test_rule_non_matching_event() {
+    let output = run_test_rule("note-.* ch1 => out", "note-on ch2 note60 vel100");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "No match, the event is dropped\n");
+}
+
+#[test]
+fn test_rule_invalid_rule() {
+    let output = run_test_rule("note-.* ch300 => out", "note-on ch1 note60 vel100");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ch300"));
+}